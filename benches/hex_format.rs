@@ -0,0 +1,35 @@
+//! Compares `hex::encode` with the reusable `HexBuf` formatter.
+//!
+//! Run with `cargo bench --bench hex_format`.
+
+use hid_cmd::hexfmt::HexBuf;
+use std::hint::black_box;
+use std::time::Instant;
+
+const REPORTS: usize = 200_000;
+const REPORT_SIZE: usize = 64;
+
+fn report(i: usize) -> [u8; REPORT_SIZE] {
+    let mut r = [0u8; REPORT_SIZE];
+    for (j, b) in r.iter_mut().enumerate() {
+        *b = (i + j) as u8;
+    }
+    r
+}
+
+fn run(name: &str, mut f: impl FnMut(&[u8]) -> usize) {
+    let started = Instant::now();
+    let mut total = 0;
+    for i in 0..REPORTS {
+        total += f(black_box(&report(i)));
+    }
+    let secs = started.elapsed().as_secs_f64();
+    black_box(total);
+    println!("{:<12} {:>12.0} reports/s", name, REPORTS as f64 / secs);
+}
+
+fn main() {
+    run("hex::encode", |r| hex::encode(r).len());
+    let mut buf = HexBuf::new();
+    run("HexBuf", |r| buf.encode(r).len());
+}
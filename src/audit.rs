@@ -0,0 +1,326 @@
+//! Append-only audit log of everything written to a device.
+//!
+//! Each write is one tab-separated line:
+//!
+//! ```text
+//! seq  time  user  device  serial  operation  payload  result  prev  hash
+//! ```
+//!
+//! `hash` is the SHA-256 of the rest of the line, which includes `prev`, the
+//! hash of the line before (all zeros for the first). Editing, removing or
+//! reordering entries breaks the chain from that line on, which [`verify`]
+//! detects. Entries removed from the end leave a shorter chain that is still
+//! intact; only a [`Verified::head`] kept elsewhere and passed back to
+//! [`verify`] as the anchor catches that.
+//!
+//! Several processes, such as the stations of a production line, can share one
+//! log: each append locks the file and continues the chain from whatever entry
+//! is last at that moment.
+
+use crate::{sha256, timestamp};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const FIELDS: usize = 10;
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How much of the end of the log is read at a time to find its last entry.
+const TAIL_CHUNK: u64 = 4096;
+
+pub struct AuditLog {
+    file: File,
+    path: PathBuf,
+}
+
+/// One write, as recorded.
+pub struct AuditEntry<'a> {
+    pub user: &'a str,
+    /// `vid:pid` of the device.
+    pub device: &'a str,
+    pub serial: &'a str,
+    /// What was written, e.g. `output` or `feature`.
+    pub operation: &'a str,
+    pub payload: &'a [u8],
+    /// `ok`, or the error.
+    pub result: &'a str,
+}
+
+/// Where a log stops verifying.
+#[derive(Debug, PartialEq, Eq)]
+pub struct VerifyError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// An intact log.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Verified {
+    pub entries: usize,
+    /// Hash of the last entry, all zeros for an empty log.
+    pub head: String,
+}
+
+impl AuditLog {
+    /// Open a log for appending, creating it if needed. Fails if the log ends
+    /// in something that isn't an entry, since the chain can't continue from it.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).read(true).append(true).open(path)
+            .map_err(|e| anyhow::anyhow!("Cannot open audit log {}: {}", path.display(), e))?;
+        let mut log = AuditLog { file, path: path.to_path_buf() };
+        log.file.lock_shared().and_then(|()| log.head()).map_err(|e| anyhow::anyhow!("Audit log {}: {}", path.display(), e))?;
+        log.file.unlock()?;
+        Ok(log)
+    }
+
+    /// Append an entry and flush it to disk. The log is locked meanwhile, so
+    /// entries from other processes sharing it are chained, not forked.
+    pub fn record(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        self.file.lock()?;
+        let result = self.append(entry);
+        let unlocked = self.file.unlock();
+        result.and(unlocked)
+    }
+
+    fn append(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        let (seq, last_hash) = self.head()?;
+        let body = [
+            (seq + 1).to_string(),
+            timestamp::now_utc(),
+            clean(entry.user),
+            clean(entry.device),
+            clean(entry.serial),
+            clean(entry.operation),
+            hex::encode(entry.payload),
+            clean(entry.result),
+            last_hash,
+        ].join("\t");
+        let hash = hex::encode(sha256::digest(body.as_bytes()));
+        writeln!(self.file, "{}\t{}", body, hash)?;
+        self.file.sync_data()
+    }
+
+    /// Sequence number and hash of the last entry, read from the file.
+    fn head(&mut self) -> io::Result<(u64, String)> {
+        let Some(line) = self.last_line()? else {
+            return Ok((0, GENESIS.to_string()));
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        match (fields.len(), fields[0].parse::<u64>()) {
+            (FIELDS, Ok(seq)) => Ok((seq, fields[FIELDS - 1].to_string())),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} ends in a malformed entry; not appending to it", self.path.display()))),
+        }
+    }
+
+    /// The last non-empty line, read backwards from the end so that long logs
+    /// aren't read whole for every entry.
+    fn last_line(&mut self) -> io::Result<Option<String>> {
+        let len = self.file.seek(SeekFrom::End(0))?;
+        let mut start = len;
+        let mut tail = Vec::new();
+        loop {
+            let text = tail.trim_ascii_end();
+            if let Some(newline) = text.iter().rposition(|&b| b == b'\n') {
+                return Ok(Some(String::from_utf8_lossy(&text[newline + 1..]).into_owned()));
+            }
+            if start == 0 {
+                return Ok((!text.is_empty()).then(|| String::from_utf8_lossy(text).into_owned()));
+            }
+            let chunk = start.min(TAIL_CHUNK);
+            start -= chunk;
+            let mut buf = vec![0u8; chunk as usize];
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.read_exact(&mut buf)?;
+            buf.extend_from_slice(&tail);
+            tail = buf;
+        }
+    }
+}
+
+/// Tabs and line breaks would split an entry.
+fn clean(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+/// Check a whole log's hash chain. With an `anchor`, the head of an earlier
+/// check, the log must still contain that entry, so it can't have been cut
+/// short since.
+pub fn verify(text: &str, anchor: Option<&str>) -> Result<Verified, VerifyError> {
+    let mut prev = GENESIS.to_string();
+    let mut count = 0;
+    let mut anchored = anchor.is_none_or(|anchor| anchor == GENESIS);
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| VerifyError { line: i + 1, message };
+        if line.is_empty() {
+            continue;
+        }
+        let Some((body, hash)) = line.rsplit_once('\t') else {
+            return Err(error("not an audit entry".to_string()));
+        };
+        let fields: Vec<&str> = body.split('\t').collect();
+        if fields.len() != FIELDS - 1 {
+            return Err(error(format!("expected {} fields, found {}", FIELDS, fields.len() + 1)));
+        }
+        if fields[0] != (count + 1).to_string() {
+            return Err(error(format!("sequence number {} where {} was expected", fields[0], count + 1)));
+        }
+        if fields[FIELDS - 2] != prev {
+            return Err(error("previous hash does not match the entry before; entries were removed or reordered".to_string()));
+        }
+        let expected = hex::encode(sha256::digest(body.as_bytes()));
+        if hash != expected {
+            return Err(error("hash does not match the entry; it was modified".to_string()));
+        }
+        anchored |= anchor == Some(expected.as_str());
+        prev = expected;
+        count += 1;
+    }
+    if !anchored {
+        return Err(VerifyError {
+            line: text.lines().count() + 1,
+            message: "the log ends before the anchored entry; entries were removed from the end".to_string(),
+        });
+    }
+    Ok(Verified { entries: count, head: prev })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A log of `count` entries, written through [`AuditLog`].
+    fn log(name: &str, count: usize) -> String {
+        let path = std::env::temp_dir().join(format!("hid-cmd-audit-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut log = AuditLog::open(&path).unwrap();
+        for i in 0..count {
+            log.record(&AuditEntry {
+                user: "alice",
+                device: "1234:5678",
+                serial: "SN\t1",
+                operation: "output",
+                payload: &[i as u8, 0x02],
+                result: "ok",
+            }).unwrap();
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text
+    }
+
+    #[test]
+    fn intact_chain() {
+        let text = log("intact", 3);
+        let verified = verify(&text, None).unwrap();
+        assert_eq!(verified.entries, 3);
+        assert_eq!(Some(verified.head.as_str()), text.lines().last().unwrap().rsplit('\t').next());
+        // Still anchored to itself and to any earlier head
+        assert!(verify(&text, Some(&verified.head)).is_ok());
+        let first = text.lines().next().unwrap().rsplit('\t').next().unwrap();
+        assert!(verify(&text, Some(first)).is_ok());
+    }
+
+    #[test]
+    fn empty_log() {
+        assert_eq!(verify("", None), Ok(Verified { entries: 0, head: GENESIS.to_string() }));
+        assert_eq!(verify("\n\n", Some(GENESIS)).unwrap().entries, 0);
+    }
+
+    #[test]
+    fn modified_entry() {
+        let text = log("modified", 3).replacen("\toutput\t0102", "\toutput\t0103", 1);
+        let error = verify(&text, None).unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("modified"), "{}", error.message);
+    }
+
+    #[test]
+    fn reordered_entries() {
+        let text = log("reordered", 3);
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines.swap(1, 2);
+        let error = verify(&lines.join("\n"), None).unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn removed_entry() {
+        let text = log("removed", 3);
+        let lines: Vec<&str> = text.lines().collect();
+        let error = verify(&[lines[0], lines[2]].join("\n"), None).unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn truncated_tail_needs_an_anchor() {
+        let text = log("truncated", 3);
+        let head = verify(&text, None).unwrap().head;
+        let truncated: String = text.lines().take(2).map(|l| format!("{}\n", l)).collect();
+        assert_eq!(verify(&truncated, None).unwrap().entries, 2);
+        let error = verify(&truncated, Some(&head)).unwrap_err();
+        assert_eq!(error.line, 3);
+        assert!(verify("", Some(&head)).is_err());
+    }
+
+    #[test]
+    fn shared_logs_keep_one_chain() {
+        let path = std::env::temp_dir().join(format!("hid-cmd-audit-shared-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // Both open before either writes, like two stations started together
+        let mut first = AuditLog::open(&path).unwrap();
+        let mut second = AuditLog::open(&path).unwrap();
+        let entry = AuditEntry { user: "line", device: "1:2", serial: "", operation: "output", payload: &[0x01], result: "ok" };
+        std::thread::scope(|scope| {
+            scope.spawn(|| (0..20).for_each(|_| first.record(&entry).unwrap()));
+            scope.spawn(|| (0..20).for_each(|_| second.record(&entry).unwrap()));
+        });
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(verify(&text, None).unwrap().entries, 40);
+    }
+
+    #[test]
+    fn long_entries_across_tail_chunks() {
+        let path = std::env::temp_dir().join(format!("hid-cmd-audit-long-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let payload = vec![0xab; TAIL_CHUNK as usize];
+        let entry = AuditEntry { user: "bob", device: "1:2", serial: "", operation: "output", payload: &payload, result: "ok" };
+        let mut log = AuditLog::open(&path).unwrap();
+        for _ in 0..3 {
+            log.record(&entry).unwrap();
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(verify(&text, None).unwrap().entries, 3);
+    }
+
+    #[test]
+    fn refuses_to_continue_a_malformed_log() {
+        let path = std::env::temp_dir().join(format!("hid-cmd-audit-malformed-{}.log", std::process::id()));
+        std::fs::write(&path, "not an entry\n").unwrap();
+        let error = AuditLog::open(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("malformed"), "{}", error);
+    }
+
+    #[test]
+    fn open_continues_the_chain() {
+        let path = std::env::temp_dir().join(format!("hid-cmd-audit-reopen-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let entry = AuditEntry { user: "bob", device: "1:2", serial: "", operation: "feature", payload: &[], result: "ok" };
+        AuditLog::open(&path).unwrap().record(&entry).unwrap();
+        AuditLog::open(&path).unwrap().record(&entry).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(verify(&text, None).unwrap().entries, 2);
+    }
+}
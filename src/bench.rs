@@ -0,0 +1,112 @@
+//! Round-trip timing for `hid-cmd bench`: the same request sent over and over,
+//! each time waiting for its response.
+
+use crate::exchange::{self, Observer, ReportFormat};
+use crate::latency::LatencyStats;
+use crate::pattern::HexPattern;
+use crate::transport::HidTransport;
+use std::time::{Duration, Instant};
+
+/// What to time.
+#[derive(Clone, Copy, Debug)]
+pub struct Bench<'a> {
+    /// The complete output report, as [`ReportFormat::output_report`] builds it.
+    pub request: &'a [u8],
+    /// The response to wait for; any input report with a valid payload without it.
+    pub expect: Option<&'a HexPattern>,
+    /// Round trips to run before measuring.
+    pub warmup: u32,
+    /// Round trips to measure.
+    pub count: u32,
+}
+
+/// Results of the measured round trips.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub latencies: LatencyStats,
+    /// Requests whose response didn't come within the response timeout.
+    pub lost: u32,
+    /// From the first measured request to the last response.
+    pub elapsed: Duration,
+    /// Bytes written and read, responses only.
+    pub bytes: usize,
+}
+
+impl Bench<'_> {
+    /// Run the warmup and the measured round trips. Fails if a write fails;
+    /// responses that don't come are counted as lost.
+    pub fn run(&self, transport: &dyn HidTransport, format: &ReportFormat, observer: &mut dyn Observer) -> anyhow::Result<Summary> {
+        // Reports queued before the run would be taken for the first responses
+        let mut input_report = vec![0u8; format.input_buffer];
+        while transport.read(&mut input_report, Some(Duration::ZERO))? > 0 {}
+
+        let mut summary = Summary::default();
+        let mut bytes_in = 0;
+        let mut start = Instant::now();
+        for i in 0..self.warmup + self.count {
+            if i == self.warmup {
+                start = Instant::now();
+            }
+            // Latency includes the write
+            let sent = Instant::now();
+            exchange::write(transport, self.request, observer)
+                .map_err(|_| anyhow::anyhow!("Sending request {} failed", i + 1))?;
+            let response = exchange::read_until(transport, format.input_buffer, sent + format.response_timeout(), observer, |report| {
+                format.payload(report).and_then(Result::ok)
+                    .is_some_and(|p| self.expect.is_none_or(|pattern| pattern.matches_prefix(&p)))
+            })?;
+            if i < self.warmup {
+                continue;
+            }
+            match response {
+                Some(response) => {
+                    summary.latencies.record(response.received - sent);
+                    bytes_in += response.report.len();
+                }
+                None => summary.lost += 1,
+            }
+        }
+        summary.elapsed = start.elapsed();
+        summary.bytes = self.request.len() * self.count as usize + bytes_in;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::Framing;
+    use crate::mock::MockTransport;
+
+    fn format() -> ReportFormat {
+        ReportFormat { report_id: Some(1), input_buffer: 65, read_timeout: Some(Duration::from_millis(20)), framing: Framing::Raw }
+    }
+
+    #[test]
+    fn times_round_trips() {
+        let mock = MockTransport::new();
+        mock.push_input(&[0x01, 0xee]);
+        mock.respond(HexPattern::parse("01 10").unwrap(), [vec![0x02, 0x00], vec![0x01, 0x90]]);
+        let expect = HexPattern::parse("90").unwrap();
+        let bench = Bench { request: &[0x01, 0x10, 0x00, 0x00], expect: Some(&expect), warmup: 2, count: 3 };
+
+        let summary = bench.run(&mock, &format(), &mut ()).unwrap();
+        assert_eq!((summary.latencies.count(), summary.lost), (3, 0));
+        assert_eq!(summary.bytes, 3 * 4 + 3 * 2);
+        assert_eq!(mock.written().len(), 5);
+        // The report queued before the run was drained rather than timed
+        assert_eq!(mock.pending_inputs(), 0);
+    }
+
+    #[test]
+    fn counts_lost_responses() {
+        let mock = MockTransport::new();
+        let bench = Bench { request: &[0x01, 0x10], expect: None, warmup: 0, count: 2 };
+        let summary = bench.run(&mock, &format(), &mut ()).unwrap();
+        assert_eq!((summary.latencies.count(), summary.lost, summary.bytes), (0, 2, 4));
+
+        mock.fail_writes(1);
+        let e = bench.run(&mock, &format(), &mut ()).unwrap_err();
+        assert_eq!(e.to_string(), "Sending request 1 failed");
+    }
+}
@@ -0,0 +1,58 @@
+//! Field-by-field comparison of two units' responses, for `hid-cmd compare`.
+//!
+//! Responses are compared as lists of labelled values, such as the fields the
+//! report descriptor declares or the bytes of the payload. Values may differ
+//! by up to a tolerance, set per field or for all of them.
+
+use std::collections::BTreeMap;
+
+/// How far values may differ before they count as a difference.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tolerances {
+    /// For fields without their own tolerance.
+    pub default: i64,
+    pub fields: BTreeMap<String, i64>,
+}
+
+impl Tolerances {
+    /// Add a tolerance; field `*` sets the default.
+    pub fn insert(&mut self, field: &str, tolerance: i64) {
+        if field == "*" {
+            self.default = tolerance;
+        } else {
+            self.fields.insert(field.to_string(), tolerance);
+        }
+    }
+
+    pub fn of(&self, field: &str) -> i64 {
+        self.fields.get(field).copied().unwrap_or(self.default)
+    }
+}
+
+/// One value that differs beyond its tolerance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub field: String,
+    /// `None` if the golden unit's response has no such field.
+    pub golden: Option<i64>,
+    /// `None` if the suspect unit's response has no such field.
+    pub suspect: Option<i64>,
+    pub tolerance: i64,
+}
+
+/// Compare two responses value by value, in order. A field that only one of
+/// them has is always a difference.
+pub fn diff(golden: &[(String, i64)], suspect: &[(String, i64)], tolerances: &Tolerances) -> Vec<Difference> {
+    (0..golden.len().max(suspect.len()))
+        .filter_map(|i| {
+            let (g, s) = (golden.get(i), suspect.get(i));
+            let field = g.or(s).map(|(label, _)| label.clone())?;
+            let tolerance = tolerances.of(&field);
+            let within = match (g, s) {
+                (Some((_, g)), Some((_, s))) => g.abs_diff(*s) <= tolerance.unsigned_abs(),
+                _ => false,
+            };
+            (!within).then(|| Difference { field, golden: g.map(|(_, v)| *v), suspect: s.map(|(_, v)| *v), tolerance })
+        })
+        .collect()
+}
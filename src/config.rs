@@ -0,0 +1,561 @@
+//! A small TOML subset for profiles and other configuration files.
+//!
+//! Supported: comments, `[table]` and `[[array.of.tables]]` headers, bare,
+//! quoted and dotted keys, basic and literal strings, integers (decimal, `0x`,
+//! `0o`, `0b`, with `_` separators), floats, booleans, arrays (which may span
+//! lines) and inline tables. Multi-line strings and dates are not.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            Value::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+/// A parse error with the 1-based line it occurred on.
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse a whole document into its root table.
+pub fn parse(text: &str) -> Result<Table, ParseError> {
+    Parser { chars: text.chars().collect(), pos: 0, line: 1 }.document()
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError { line: self.line, message: message.into() })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", c))
+        }
+    }
+
+    /// Skip spaces and tabs on the current line.
+    fn skip_inline_ws(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Skip whitespace, newlines and comments (inside arrays and between lines).
+    fn skip_ws_and_comments(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.bump();
+                }
+                Some('#') => self.skip_comment(),
+                _ => break,
+            }
+        }
+    }
+
+    /// After a value or header: only a comment may follow on the line.
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_inline_ws();
+        self.skip_comment();
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some('\r') if self.chars.get(self.pos + 1) == Some(&'\n') => {
+                self.bump();
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("unexpected '{}' after value", c)),
+        }
+    }
+
+    fn document(mut self) -> Result<Table, ParseError> {
+        let mut root = Table::new();
+        // Path of the table that key/value lines currently go into
+        let mut current: Vec<String> = Vec::new();
+
+        loop {
+            self.skip_ws_and_comments();
+            let Some(c) = self.peek() else {
+                break;
+            };
+            // Errors found once the line is read still belong to it
+            let line = self.line;
+            let at = |message: String| ParseError { line, message };
+
+            if c == '[' {
+                self.bump();
+                let array = self.eat('[');
+                self.skip_inline_ws();
+                let path = self.key_path()?;
+                self.skip_inline_ws();
+                self.expect(']')?;
+                if array {
+                    self.expect(']')?;
+                }
+                self.end_of_line()?;
+
+                if array {
+                    push_array_table(&mut root, &path).map_err(at)?;
+                } else {
+                    table_at(&mut root, &path).map_err(at)?;
+                }
+                current = path;
+                continue;
+            }
+
+            let path = self.key_path()?;
+            self.skip_inline_ws();
+            self.expect('=')?;
+            self.skip_inline_ws();
+            let value = self.value()?;
+            self.end_of_line()?;
+
+            let (last, parents) = path.split_last().expect("key path is never empty");
+            let table = table_at(&mut root, &current).map_err(at)?;
+            let table = table_at(table, parents).map_err(at)?;
+            if table.contains_key(last) {
+                return Err(at(format!("duplicate key '{}'", last)));
+            }
+            table.insert(last.clone(), value);
+        }
+        Ok(root)
+    }
+
+    fn key_path(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut path = vec![self.key()?];
+        loop {
+            self.skip_inline_ws();
+            if !self.eat('.') {
+                break;
+            }
+            self.skip_inline_ws();
+            path.push(self.key()?);
+        }
+        Ok(path)
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                    self.bump();
+                }
+                if start == self.pos {
+                    return self.error("expected a key");
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some('t' | 'f') => self.boolean(),
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' => self.number(),
+            Some(c) => self.error(format!("unexpected '{}' at start of value", c)),
+            None => self.error("expected a value"),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            if matches!(self.peek(), None | Some('\n')) {
+                return self.error("unterminated string");
+            }
+            match self.bump() {
+                None => return self.error("unterminated string"),
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => self.unicode_escape()?,
+                        other => return self.error(format!("invalid escape {:?}", other)),
+                    };
+                    s.push(c);
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        let digits: String = (0..4).filter_map(|_| self.bump()).collect();
+        u32::from_str_radix(&digits, 16).ok()
+            .and_then(char::from_u32)
+            .map_or_else(|| self.error(format!("invalid unicode escape '{}'", digits)), Ok)
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            if matches!(self.peek(), None | Some('\n')) {
+                return self.error("unterminated string");
+            }
+            match self.bump() {
+                None => return self.error("unterminated string"),
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn boolean(&mut self) -> Result<Value, ParseError> {
+        for (word, value) in [("true", true), ("false", false)] {
+            let end = self.pos + word.len();
+            if self.chars.get(self.pos..end).is_some_and(|w| w.iter().copied().eq(word.chars())) {
+                self.pos = end;
+                return Ok(Value::Boolean(value));
+            }
+        }
+        self.error("expected 'true' or 'false'")
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.')) {
+            self.bump();
+        }
+        let text: String = self.chars[start..self.pos].iter().filter(|&&c| c != '_').collect();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(&text)),
+        };
+
+        let radix = match digits.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+        let parsed = match radix {
+            Some(radix) => i64::from_str_radix(&digits[2..], radix).ok().map(Value::Integer),
+            None if digits.contains(['.', 'e', 'E']) => digits.parse::<f64>().ok().map(Value::Float),
+            None => digits.parse::<i64>().ok().map(Value::Integer),
+        };
+        match parsed {
+            Some(Value::Integer(i)) if negative => Ok(Value::Integer(-i)),
+            Some(Value::Float(f)) if negative => Ok(Value::Float(-f)),
+            Some(v) => Ok(v),
+            None => self.error(format!("invalid number '{}'", text)),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws_and_comments();
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_ws_and_comments();
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, ParseError> {
+        self.expect('{')?;
+        let mut table = Table::new();
+        self.skip_inline_ws();
+        if self.eat('}') {
+            return Ok(Value::Table(table));
+        }
+        loop {
+            self.skip_inline_ws();
+            let path = self.key_path()?;
+            self.skip_inline_ws();
+            self.expect('=')?;
+            self.skip_inline_ws();
+            let value = self.value()?;
+
+            let (last, parents) = path.split_last().expect("key path is never empty");
+            let target = table_at(&mut table, parents).or_else(|message| self.error(message))?;
+            if target.contains_key(last) {
+                return self.error(format!("duplicate key '{}'", last));
+            }
+            target.insert(last.clone(), value);
+
+            self.skip_inline_ws();
+            if self.eat('}') {
+                return Ok(Value::Table(table));
+            }
+            self.expect(',')?;
+        }
+    }
+}
+
+/// The table at `path` below `root`, creating missing tables. For an array of
+/// tables, the last element is used.
+fn table_at<'t>(root: &'t mut Table, path: &[String]) -> Result<&'t mut Table, String> {
+    let mut table = root;
+    for key in path {
+        let entry = table.entry(key.clone()).or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(t) => t,
+            Value::Array(a) => match a.last_mut() {
+                Some(Value::Table(t)) => t,
+                _ => return Err(format!("'{}' is not a table", key)),
+            },
+            _ => return Err(format!("'{}' is not a table", key)),
+        };
+    }
+    Ok(table)
+}
+
+fn push_array_table(root: &mut Table, path: &[String]) -> Result<(), String> {
+    let (last, parents) = path.split_last().expect("key path is never empty");
+    let table = table_at(root, parents)?;
+    match table.entry(last.clone()).or_insert_with(|| Value::Array(Vec::new())) {
+        Value::Array(a) => {
+            a.push(Value::Table(Table::new()));
+            Ok(())
+        }
+        _ => Err(format!("'{}' is not an array of tables", last)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get<'a>(table: &'a Table, path: &str) -> &'a Value {
+        let mut parts = path.split('.');
+        let mut value = &table[parts.next().unwrap()];
+        for part in parts {
+            value = &value.as_table().unwrap()[part];
+        }
+        value
+    }
+
+    fn error(text: &str) -> (usize, String) {
+        let e = parse(text).unwrap_err();
+        (e.line, e.message)
+    }
+
+    #[test]
+    fn scalars() {
+        let t = parse("s = \"a\\tb\\u00e9\"\nl = 'C:\\path'\nt = true\nf = false\nx = 1.5\ne = -2e3\n").unwrap();
+        assert_eq!(t["s"], Value::String("a\tbé".to_string()));
+        assert_eq!(t["l"], Value::String("C:\\path".to_string()));
+        assert_eq!(t["t"], Value::Boolean(true));
+        assert_eq!(t["f"], Value::Boolean(false));
+        assert_eq!(t["x"], Value::Float(1.5));
+        assert_eq!(t["e"], Value::Float(-2000.0));
+    }
+
+    #[test]
+    fn integer_radixes() {
+        let t = parse("d = 1_000\nn = -42\np = +7\nh = 0xFF_42\no = 0o755\nb = 0b1010\nnh = -0x10\n").unwrap();
+        let ints: Vec<i64> = ["d", "n", "p", "h", "o", "b", "nh"].iter().map(|k| t[*k].as_integer().unwrap()).collect();
+        assert_eq!(ints, [1000, -42, 7, 0xff42, 0o755, 0b1010, -16]);
+    }
+
+    #[test]
+    fn dotted_and_quoted_keys() {
+        let t = parse("a.b.c = 1\na.b.d = 2\n\"quoted key\".x = 3\n'lit'.y = 4\n[t]\nsub . key = 5\n").unwrap();
+        assert_eq!(get(&t, "a.b.c"), &Value::Integer(1));
+        assert_eq!(get(&t, "a.b.d"), &Value::Integer(2));
+        assert_eq!(get(&t, "quoted key.x"), &Value::Integer(3));
+        assert_eq!(get(&t, "lit.y"), &Value::Integer(4));
+        assert_eq!(get(&t, "t.sub.key"), &Value::Integer(5));
+    }
+
+    #[test]
+    fn tables_and_arrays_of_tables() {
+        let text = "\
+# A comment
+[device]
+vid = 0x1234 # trailing comment
+
+[[deny]]
+pattern = \"ee\"
+
+[[deny]]
+pattern = \"ff\"
+[deny.extra]
+note = \"belongs to the second entry\"
+
+[a.b]
+c = 1
+";
+        let t = parse(text).unwrap();
+        assert_eq!(get(&t, "device.vid"), &Value::Integer(0x1234));
+        let deny = t["deny"].as_array().unwrap();
+        assert_eq!(deny.len(), 2);
+        assert_eq!(deny[0].as_table().unwrap()["pattern"].as_str(), Some("ee"));
+        let second = deny[1].as_table().unwrap();
+        assert_eq!(second["pattern"].as_str(), Some("ff"));
+        assert_eq!(get(second, "extra.note").as_str(), Some("belongs to the second entry"));
+        assert_eq!(get(&t, "a.b.c"), &Value::Integer(1));
+    }
+
+    #[test]
+    fn arrays_and_inline_tables() {
+        let text = "ids = [\n  0x05, # first\n  0x06,\n]\nempty = []\nnested = [[1, 2], ['x']]\n\
+                    users = { alice = \"engineer\", lang.de = { d = 1 } }\nnone = {}\n";
+        let t = parse(text).unwrap();
+        assert_eq!(t["ids"], Value::Array(vec![Value::Integer(5), Value::Integer(6)]));
+        assert_eq!(t["empty"], Value::Array(Vec::new()));
+        assert_eq!(t["nested"].as_array().unwrap()[1], Value::Array(vec![Value::String("x".to_string())]));
+        assert_eq!(get(&t, "users.alice").as_str(), Some("engineer"));
+        assert_eq!(get(&t, "users.lang.de.d"), &Value::Integer(1));
+        assert_eq!(t["none"], Value::Table(Table::new()));
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let t = parse("a = 1\r\n[b]\r\nc = 2\r\n").unwrap();
+        assert_eq!(get(&t, "b.c"), &Value::Integer(2));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("a = 1\nb = 2\nc = \"open\n"), (3, "unterminated string".to_string()));
+        assert_eq!(error("a = 1\n\na = 2\n"), (3, "duplicate key 'a'".to_string()));
+        assert_eq!(error("a = 1\na.b = 2\n"), (2, "'a' is not a table".to_string()));
+        assert_eq!(error("x = 1\n[x]\ny = 2\n"), (2, "'x' is not a table".to_string()));
+        assert_eq!(error("[t]\nk = 1\n[[t]]\n"), (3, "'t' is not an array of tables".to_string()));
+        assert_eq!(error("# c\nk = ?\n"), (2, "unexpected '?' at start of value".to_string()));
+        assert_eq!(error("k = 1 2\n"), (1, "unexpected '2' after value".to_string()));
+        assert_eq!(error("k = 0xZZ\n"), (1, "invalid number '0xZZ'".to_string()));
+        assert_eq!(error("k = \"\\q\"\n"), (1, "invalid escape Some('q')".to_string()));
+        assert_eq!(error("a = [\n1,\n2\n"), (4, "expected ','".to_string()));
+        assert_eq!(error("t = { a = 1, a = 2 }\n"), (1, "duplicate key 'a'".to_string()));
+        assert_eq!(error("= 1\n"), (1, "expected a key".to_string()));
+    }
+}
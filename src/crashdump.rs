@@ -0,0 +1,308 @@
+//! Crash dumps of Cortex-M firmware, for `hid-cmd crashdump`.
+//!
+//! After a panic or fault the firmware keeps a snapshot in memory that
+//! survives the reset, and sends it on request as [file
+//! transfer](crate::transfer) chunks. All values are little-endian:
+//!
+//! ```text
+//! offset  size
+//!  0       4   magic "CDMP"
+//!  4       1   version, 1
+//!  5       1   reserved
+//!  6       2   n, length of the message
+//!  8      68   r0-r12, sp, lr, pc, xpsr (u32 each)
+//! 76      16   CFSR, HFSR, MMFAR, BFAR (u32 each)
+//! 92       n   panic message or other reason, UTF-8; may be empty
+//! 92+n         stack words from sp upwards (u32 each)
+//! ```
+//!
+//! With the firmware's ELF file, [`Symbols`] names the function and, if the
+//! image has line tables, the source line of each code address. Cortex-M has
+//! no frame pointers to follow without unwind tables, so the backtrace is the
+//! faulting pc, lr, and the stack words that look like return addresses:
+//! Thumb code addresses (bit 0 set) inside a function. Some of those may be
+//! left over from calls that already returned.
+
+use crate::demangle::demangle;
+use crate::dwarf::{self, LineTable};
+use crate::elf::{self, Elf};
+use std::path::Path;
+
+pub use crate::dwarf::Location;
+
+pub const MAGIC: &[u8; 4] = b"CDMP";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 92;
+
+pub const REGISTER_NAMES: [&str; 17] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc", "xpsr",
+];
+const LR: usize = 14;
+const PC: usize = 15;
+
+/// Fault status bits of CFSR (MemManage, BusFault and UsageFault) and HFSR.
+const CFSR_BITS: [(u32, &str); 19] = [
+    (0, "IACCVIOL"), (1, "DACCVIOL"), (3, "MUNSTKERR"), (4, "MSTKERR"), (5, "MLSPERR"), (7, "MMARVALID"),
+    (8, "IBUSERR"), (9, "PRECISERR"), (10, "IMPRECISERR"), (11, "UNSTKERR"), (12, "STKERR"), (13, "LSPERR"), (15, "BFARVALID"),
+    (16, "UNDEFINSTR"), (17, "INVSTATE"), (18, "INVPC"), (19, "NOCP"), (24, "UNALIGNED"), (25, "DIVBYZERO"),
+];
+const HFSR_BITS: [(u32, &str); 3] = [(1, "VECTTBL"), (30, "FORCED"), (31, "DEBUGEVT")];
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+
+/// A parsed crash dump.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dump {
+    /// In [`REGISTER_NAMES`] order.
+    pub registers: [u32; 17],
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub message: String,
+    pub stack: Vec<u32>,
+}
+
+impl Dump {
+    pub fn parse(blob: &[u8]) -> anyhow::Result<Self> {
+        if blob.len() < HEADER_LEN || &blob[..4] != MAGIC {
+            return Err(anyhow::anyhow!("not a crash dump (no CDMP header)"));
+        }
+        if blob[4] != VERSION {
+            return Err(anyhow::anyhow!("crash dump version {} is not supported", blob[4]));
+        }
+        let word = |at: usize| u32::from_le_bytes([blob[at], blob[at + 1], blob[at + 2], blob[at + 3]]);
+        let message_len = usize::from(u16::from_le_bytes([blob[6], blob[7]]));
+        let message = blob.get(HEADER_LEN..HEADER_LEN + message_len)
+            .ok_or_else(|| anyhow::anyhow!("crash dump message of {} bytes runs past its end", message_len))?;
+        let stack = &blob[HEADER_LEN + message_len..];
+        Ok(Dump {
+            registers: std::array::from_fn(|i| word(8 + 4 * i)),
+            cfsr: word(76),
+            hfsr: word(80),
+            mmfar: word(84),
+            bfar: word(88),
+            message: String::from_utf8_lossy(message).into_owned(),
+            stack: stack.chunks_exact(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect(),
+        })
+    }
+
+    /// The fault status bits that are set, e.g. `["DACCVIOL", "MMARVALID",
+    /// "FORCED"]`, and the fault address if a valid one was recorded.
+    pub fn fault(&self) -> (Vec<&'static str>, Option<u32>) {
+        let cfsr = CFSR_BITS.iter().filter(|(bit, _)| self.cfsr & (1 << bit) != 0);
+        let hfsr = HFSR_BITS.iter().filter(|(bit, _)| self.hfsr & (1 << bit) != 0);
+        let bits = cfsr.chain(hfsr).map(|&(_, name)| name).collect();
+        let address = if self.cfsr & MMARVALID != 0 {
+            Some(self.mmfar)
+        } else if self.cfsr & BFARVALID != 0 {
+            Some(self.bfar)
+        } else {
+            None
+        };
+        (bits, address)
+    }
+
+    /// The backtrace: pc, lr, then the return addresses found on the stack,
+    /// which takes `symbols` to tell them from data.
+    pub fn backtrace(&self, symbols: Option<&Symbols>) -> Vec<Frame> {
+        let mut frames = vec![Frame::new(FrameSource::Pc, self.registers[PC], 0, symbols)];
+        // EXC_RETURN values are not code addresses
+        let lr = self.registers[LR];
+        if lr < 0xf000_0000 {
+            frames.push(Frame::new(FrameSource::Lr, lr, 1, symbols));
+        }
+        let Some(symbols) = symbols else {
+            return frames;
+        };
+        for (i, &word) in self.stack.iter().enumerate() {
+            if word & 1 == 0 || word >= 0xf000_0000 {
+                continue;
+            }
+            let frame = Frame::new(FrameSource::Stack(self.registers[13].wrapping_add(4 * i as u32)), word, 1, Some(symbols));
+            if frame.function.is_some() {
+                frames.push(frame);
+            }
+        }
+        frames
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameSource {
+    Pc,
+    Lr,
+    /// Found on the stack at this address.
+    Stack(u32),
+}
+
+/// One code address of a backtrace and what is known about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub source: FrameSource,
+    pub address: u32,
+    /// Function name and the offset into it.
+    pub function: Option<(String, u32)>,
+    pub location: Option<Location>,
+}
+
+impl Frame {
+    /// `back` is subtracted before looking up return addresses, so that they
+    /// land on the call rather than on whatever follows it.
+    fn new(source: FrameSource, address: u32, back: u32, symbols: Option<&Symbols>) -> Self {
+        let code = (address & !1).wrapping_sub(back);
+        let function = symbols.and_then(|s| s.function(code)).map(|(name, start)| (name.to_string(), (address & !1) - start));
+        let location = symbols.and_then(|s| s.lines.as_ref()?.find(u64::from(code)));
+        Frame { source, address, function, location }
+    }
+}
+
+/// Function symbols and line tables of one firmware image.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    /// Start, end and demangled name, by start address.
+    functions: Vec<(u32, u32, String)>,
+    lines: Option<LineTable>,
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let elf = std::fs::read(path).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+        Self::from_elf(&elf).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_elf(data: &[u8]) -> anyhow::Result<Self> {
+        let elf = Elf::parse(data)?;
+        let mut functions: Vec<(u32, u32, String)> = elf.symbols()?.into_iter()
+            .filter(|s| s.kind == elf::FUNCTION && s.value != 0)
+            .map(|s| {
+                // Thumb function symbols have bit 0 set
+                let start = s.value as u32 & !1;
+                (start, start.wrapping_add(s.size.max(1) as u32), demangle(s.name))
+            })
+            .collect();
+        functions.sort();
+        functions.dedup_by_key(|f| f.0);
+
+        let section = |name| match elf.section(name) {
+            Some((_, section)) => elf.section_data(section),
+            None => Ok(&[][..]),
+        };
+        let lines = match elf.section(".debug_line") {
+            Some(_) => Some(LineTable::parse(&dwarf::Sections {
+                line: section(".debug_line")?,
+                line_str: section(".debug_line_str")?,
+                str: section(".debug_str")?,
+            })?),
+            None => None,
+        };
+        Ok(Symbols { functions, lines })
+    }
+
+    /// Whether source lines can be shown; not for images built without debug info.
+    pub fn has_lines(&self) -> bool {
+        self.lines.is_some()
+    }
+
+    /// The function containing `address`, and its start.
+    fn function(&self, address: u32) -> Option<(&str, u32)> {
+        let i = self.functions.partition_point(|f| f.0 <= address).checked_sub(1)?;
+        let (start, end, name) = &self.functions[i];
+        (address < *end).then_some((name.as_str(), *start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Built from `tests/fixtures/crash.c`, which says how. x86-64 code, but
+    /// symbols and line tables work the same.
+    const FIXTURE: &[u8] = include_bytes!("../tests/fixtures/crash-dwarf5.elf");
+
+    fn blob(registers: [u32; 17], [cfsr, hfsr, mmfar, bfar]: [u32; 4], message: &str, stack: &[u32]) -> Vec<u8> {
+        let mut blob = MAGIC.to_vec();
+        blob.extend_from_slice(&[VERSION, 0]);
+        blob.extend_from_slice(&(message.len() as u16).to_le_bytes());
+        for word in registers.iter().chain(&[cfsr, hfsr, mmfar, bfar]).chain(stack) {
+            blob.extend_from_slice(&word.to_le_bytes());
+        }
+        blob.splice(HEADER_LEN..HEADER_LEN, message.bytes());
+        blob
+    }
+
+    fn registers(sp: u32, lr: u32, pc: u32) -> [u32; 17] {
+        let mut registers = std::array::from_fn(|i| i as u32);
+        registers[13] = sp;
+        registers[LR] = lr;
+        registers[PC] = pc;
+        registers
+    }
+
+    #[test]
+    fn parses() {
+        let data = blob(registers(0x2000_0ff0, 0x0800_0101, 0x0800_0200), [0, 0, 0, 0], "oops", &[1, 2]);
+        let dump = Dump::parse(&data).unwrap();
+        assert_eq!(dump.registers[..13], std::array::from_fn::<u32, 13, _>(|i| i as u32));
+        assert_eq!((dump.registers[13], dump.registers[LR], dump.registers[PC]), (0x2000_0ff0, 0x0800_0101, 0x0800_0200));
+        assert_eq!(dump.message, "oops");
+        assert_eq!(dump.stack, [1, 2]);
+
+        // No message, and a partial stack word at the end
+        let mut data = blob(registers(0, 0, 0), [0, 0, 0, 0], "", &[7]);
+        data.push(0xaa);
+        let dump = Dump::parse(&data).unwrap();
+        assert_eq!((dump.message.as_str(), dump.stack.as_slice()), ("", &[7][..]));
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_records() {
+        let data = blob(registers(0, 0, 0), [0, 0, 0, 0], "panicked", &[]);
+        for cut in [0, 3, HEADER_LEN - 1] {
+            assert_eq!(Dump::parse(&data[..cut]).unwrap_err().to_string(), "not a crash dump (no CDMP header)");
+        }
+        assert_eq!(Dump::parse(&data[..HEADER_LEN + 3]).unwrap_err().to_string(), "crash dump message of 8 bytes runs past its end");
+        let mut oversized = data.clone();
+        oversized[6..8].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(Dump::parse(&oversized).unwrap_err().to_string(), "crash dump message of 65535 bytes runs past its end");
+        let mut version = data;
+        version[4] = 2;
+        assert_eq!(Dump::parse(&version).unwrap_err().to_string(), "crash dump version 2 is not supported");
+    }
+
+    #[test]
+    fn fault() {
+        let precise = blob(registers(0, 0, 0), [(1 << 9) | (1 << 15), 1 << 30, 0xdead, 0x4000_0000], "", &[]);
+        assert_eq!(Dump::parse(&precise).unwrap().fault(), (vec!["PRECISERR", "BFARVALID", "FORCED"], Some(0x4000_0000)));
+        let mem = blob(registers(0, 0, 0), [(1 << 1) | (1 << 7), 0, 0x10, 0], "", &[]);
+        assert_eq!(Dump::parse(&mem).unwrap().fault(), (vec!["DACCVIOL", "MMARVALID"], Some(0x10)));
+        let none = blob(registers(0, 0, 0), [1 << 25, 0, 0, 0], "", &[]);
+        assert_eq!(Dump::parse(&none).unwrap().fault(), (vec!["DIVBYZERO"], None));
+    }
+
+    #[test]
+    fn backtrace() {
+        let symbols = Symbols::from_elf(FIXTURE).unwrap();
+        assert!(symbols.has_lines());
+        // pc in crash_here, lr just after the call in middle, and on the stack
+        // data, the return address into _start, an odd address outside any
+        // function and an EXC_RETURN value
+        let stack = [0x1234, 0x4000f9, 0x0050_0001, 0xffff_fff9];
+        let dump = Dump::parse(&blob(registers(0x2000_0ff0, 0x4000e1, 0x4000bc), [0, 0, 0, 0], "", &stack)).unwrap();
+        let frames: Vec<_> = dump.backtrace(Some(&symbols)).into_iter()
+            .map(|f| (f.source, f.function, f.location.map(|l| l.line)))
+            .collect();
+        assert_eq!(frames, [
+            (FrameSource::Pc, Some(("crash_here".to_string(), 0xc)), Some(13)),
+            // middle is at 0x4000cb, which reads as a Thumb symbol for 0x4000ca
+            (FrameSource::Lr, Some(("middle".to_string(), 0x16)), Some(18)),
+            (FrameSource::Stack(0x2000_0ff4), Some(("_start".to_string(), 0xe)), Some(23)),
+        ]);
+
+        // Without symbols, only pc and lr, and no lr if it holds EXC_RETURN
+        let dump = Dump::parse(&blob(registers(0, 0xffff_fff9, 0x4000bc), [0, 0, 0, 0], "", &stack)).unwrap();
+        let frames = dump.backtrace(None);
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].address, &frames[0].function, &frames[0].location), (0x4000bc, &None, &None));
+    }
+}
@@ -0,0 +1,29 @@
+//! CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xffff, no reflection),
+//! the checksum most vendor HID protocols use for framing.
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        // The catalogue check value for CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn empty_input_is_the_initial_value() {
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+}
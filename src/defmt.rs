@@ -0,0 +1,667 @@
+//! Decoding of [defmt](https://defmt.ferrous-systems.com) logs streamed over
+//! HID, for `monitor --defmt-elf`.
+//!
+//! defmt keeps format strings out of the firmware: each log frame carries the
+//! index of its string and the raw arguments, and the strings live in the
+//! `.defmt` section of the ELF file, as names of symbols whose address is the
+//! index. The names are JSON objects such as
+//! `{"package":"app","tag":"defmt_info","data":"x={=u8}","disambiguator":"1"}`.
+//!
+//! Frames are rzCOBS encoded and end with a zero byte, as `defmt-rtt` sends
+//! them, so zero padding at the end of a report is harmless as long as a frame
+//! ends first. Supported are defmt 0.3's wire format, primitive and string
+//! arguments, byte slices and arrays, `Format` values (structs, enums, slices)
+//! and `Debug2Format`. File and line locations, which come from DWARF, are not.
+
+use crate::elf::Elf;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The format strings of one firmware build.
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    strings: BTreeMap<u16, Entry>,
+    /// Format string of `defmt::timestamp!`, if the firmware has one.
+    timestamp: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    tag: String,
+    data: String,
+}
+
+/// One decoded log frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    /// `None` for `println!`.
+    pub level: Option<&'static str>,
+    pub timestamp: Option<String>,
+    pub message: String,
+}
+
+impl Table {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let elf = std::fs::read(path).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+        Self::from_elf(&elf).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Collect the defmt symbols from a little-endian ELF file.
+    pub fn from_elf(elf: &[u8]) -> anyhow::Result<Self> {
+        let elf = Elf::parse(elf)?;
+        let (defmt, _) = elf.section(".defmt")
+            .ok_or_else(|| anyhow::anyhow!("no .defmt section; is the firmware built with defmt?"))?;
+
+        let mut table = Table::default();
+        for symbol in elf.symbols()? {
+            if symbol.section != defmt {
+                continue;
+            }
+            let Some(fields) = json_fields(symbol.name) else {
+                continue;
+            };
+            let (Some(tag), Some(data)) = (fields.get("tag"), fields.get("data")) else {
+                continue;
+            };
+            if tag == "defmt_timestamp" {
+                table.timestamp = Some(data.clone());
+            }
+            table.strings.insert(symbol.value as u16, Entry { tag: tag.clone(), data: data.clone() });
+        }
+        if table.strings.is_empty() {
+            return Err(anyhow::anyhow!("the .defmt section has no format strings"));
+        }
+        Ok(table)
+    }
+
+    /// Decode one frame, after its rzCOBS encoding is undone.
+    pub fn decode(&self, frame: &[u8]) -> anyhow::Result<Line> {
+        let mut reader = Reader { data: frame };
+        let index = reader.u16()?;
+        let entry = self.strings.get(&index).ok_or_else(|| anyhow::anyhow!("unknown format string {}", index))?;
+        let level = match entry.tag.as_str() {
+            "defmt_trace" => Some("TRACE"),
+            "defmt_debug" => Some("DEBUG"),
+            "defmt_info" => Some("INFO"),
+            "defmt_warn" => Some("WARN"),
+            "defmt_error" => Some("ERROR"),
+            "defmt_println" => None,
+            other => return Err(anyhow::anyhow!("format string {} is a {}, not a log message", index, other)),
+        };
+        let timestamp = match &self.timestamp {
+            Some(format) => Some(self.render(format, &mut reader, 0)?),
+            None => None,
+        };
+        let message = self.render(&entry.data, &mut reader, 0)?;
+        Ok(Line { level, timestamp, message })
+    }
+
+    /// Read the arguments of `format` and fill them in.
+    fn render(&self, format: &str, reader: &mut Reader, depth: usize) -> anyhow::Result<String> {
+        if depth > 16 {
+            return Err(anyhow::anyhow!("Format values nested too deeply"));
+        }
+        let pieces = parse_format(format)?;
+        // Arguments are encoded once each, in index order, whichever order they are used in
+        let mut kinds: BTreeMap<usize, &str> = BTreeMap::new();
+        for piece in &pieces {
+            if let Piece::Param { index, kind, .. } = piece {
+                kinds.entry(*index).or_insert(kind);
+            }
+        }
+        let mut values = BTreeMap::new();
+        for (index, kind) in kinds {
+            values.insert(index, self.argument(kind, reader, depth)?);
+        }
+
+        let mut text = String::new();
+        for piece in &pieces {
+            match piece {
+                Piece::Literal(literal) => text.push_str(literal),
+                Piece::Param { index, hint, .. } => text.push_str(&values[index].display(hint)),
+            }
+        }
+        Ok(text)
+    }
+
+    fn argument(&self, kind: &str, reader: &mut Reader, depth: usize) -> anyhow::Result<Value> {
+        Ok(match kind {
+            "u8" => Value::Unsigned(reader.uint(1)?),
+            "u16" => Value::Unsigned(reader.uint(2)?),
+            "u32" | "usize" => Value::Unsigned(reader.uint(4)?),
+            "u64" => Value::Unsigned(reader.uint(8)?),
+            "u128" => Value::Unsigned(reader.uint(16)?),
+            "i8" => Value::Signed(reader.uint(1)? as i8 as i128, 8),
+            "i16" => Value::Signed(reader.uint(2)? as i16 as i128, 16),
+            "i32" | "isize" => Value::Signed(reader.uint(4)? as i32 as i128, 32),
+            "i64" => Value::Signed(reader.uint(8)? as i64 as i128, 64),
+            "i128" => Value::Signed(reader.uint(16)? as i128, 128),
+            "f32" => Value::Text(f32::from_bits(reader.uint(4)? as u32).to_string()),
+            "f64" => Value::Text(f64::from_bits(reader.uint(8)? as u64).to_string()),
+            "bool" => Value::Text((reader.uint(1)? != 0).to_string()),
+            "char" => Value::Text(char::from_u32(reader.uint(4)? as u32).unwrap_or(char::REPLACEMENT_CHARACTER).to_string()),
+            "str" => {
+                let len = reader.uint(4)? as usize;
+                Value::Text(String::from_utf8_lossy(reader.take(len)?).into_owned())
+            }
+            "istr" => {
+                let index = reader.u16()?;
+                Value::Text(self.strings.get(&index).map(|e| e.data.clone()).unwrap_or_else(|| format!("<string {}>", index)))
+            }
+            "__internal_Debug" | "__internal_Display" => {
+                let end = reader.data.iter().position(|&b| b == 0xff).ok_or_else(|| anyhow::anyhow!("unterminated Debug2Format text"))?;
+                let text = String::from_utf8_lossy(reader.take(end)?).into_owned();
+                reader.take(1)?;
+                Value::Text(text)
+            }
+            "[u8]" => {
+                let len = reader.uint(4)? as usize;
+                Value::Bytes(reader.take(len)?.to_vec())
+            }
+            "?" => Value::Text(self.format_value(reader, None, depth)?),
+            "[?]" => {
+                let len = reader.uint(4)? as usize;
+                Value::Text(self.format_slice(reader, len, depth)?)
+            }
+            _ => {
+                if let Some(len) = array_len(kind, "[u8;") {
+                    Value::Bytes(reader.take(len)?.to_vec())
+                } else if let Some(len) = array_len(kind, "[?;") {
+                    Value::Text(self.format_slice(reader, len, depth)?)
+                } else if let Some((low, high)) = kind.split_once("..").and_then(|(l, h)| Some((l.parse::<u32>().ok()?, h.parse::<u32>().ok()?))) {
+                    // A bit field, sent in the smallest integer that holds its highest bit
+                    let bytes = match high {
+                        0..=8 => 1,
+                        9..=16 => 2,
+                        17..=32 => 4,
+                        _ => 8,
+                    };
+                    let raw = reader.uint(bytes)?;
+                    let width = high.saturating_sub(low).min(127);
+                    Value::Bits((raw >> low) & ((1u128 << width) - 1), width)
+                } else {
+                    return Err(anyhow::anyhow!("unsupported argument type {{={}}}", kind));
+                }
+            }
+        })
+    }
+
+    /// A `Format` value: the index of its format string, unless the caller
+    /// already knows it, then its arguments. Enums start with a discriminant
+    /// choosing one of the `|`-separated variants.
+    fn format_value(&self, reader: &mut Reader, index: Option<u16>, depth: usize) -> anyhow::Result<String> {
+        let index = match index {
+            Some(index) => index,
+            None => reader.u16()?,
+        };
+        let entry = self.strings.get(&index).ok_or_else(|| anyhow::anyhow!("unknown format string {}", index))?;
+        let variants = split_variants(&entry.data);
+        if variants.len() == 1 {
+            return self.render(&entry.data, reader, depth + 1);
+        }
+        let discriminant = if variants.len() <= 256 { reader.uint(1)? } else { reader.uint(2)? } as usize;
+        let variant = variants.get(discriminant).ok_or_else(|| anyhow::anyhow!("variant {} of a {}-variant enum", discriminant, variants.len()))?;
+        self.render(variant, reader, depth + 1)
+    }
+
+    /// `len` `Format` values of one type: only the first carries the index.
+    fn format_slice(&self, reader: &mut Reader, len: usize, depth: usize) -> anyhow::Result<String> {
+        let mut index = None;
+        let mut items = Vec::new();
+        for _ in 0..len {
+            let tag = match index {
+                Some(tag) => tag,
+                None => *index.insert(reader.u16()?),
+            };
+            items.push(self.format_value(reader, Some(tag), depth)?);
+        }
+        Ok(format!("[{}]", items.join(", ")))
+    }
+}
+
+/// Argument values, before the display hint is applied.
+enum Value {
+    Unsigned(u128),
+    /// The value and its width in bits, which hex shows the two's complement of.
+    Signed(i128, u32),
+    Bits(u128, u32),
+    Bytes(Vec<u8>),
+    Text(String),
+}
+
+impl Value {
+    fn display(&self, hint: &str) -> String {
+        match (self, hint) {
+            (Value::Unsigned(n), "x") => format!("{:x}", n),
+            (Value::Unsigned(n), "#x") => format!("{:#x}", n),
+            (Value::Unsigned(n), "X") => format!("{:X}", n),
+            (Value::Unsigned(n), "#X") => format!("{:#X}", n),
+            (Value::Unsigned(n), "b") => format!("{:b}", n),
+            (Value::Unsigned(n), "#b") => format!("{:#b}", n),
+            (Value::Unsigned(n), "us" | "tus") => format!("{}.{:06}", n / 1_000_000, n % 1_000_000),
+            (Value::Unsigned(n), "ms" | "tms") => format!("{}.{:03}", n / 1000, n % 1000),
+            (Value::Unsigned(n), _) => n.to_string(),
+            (Value::Signed(n, bits), "x") => format!("{:x}", *n as u128 & (u128::MAX >> (128 - bits))),
+            (Value::Signed(n, bits), "#x") => format!("{:#x}", *n as u128 & (u128::MAX >> (128 - bits))),
+            (Value::Signed(n, _), _) => n.to_string(),
+            (Value::Bits(n, width), _) => format!("{:#0w$b}", n, w = *width as usize + 2),
+            (Value::Bytes(bytes), "a") => format!("b\"{}\"", bytes.escape_ascii()),
+            (Value::Bytes(bytes), _) => {
+                let mut text = String::from("[");
+                for (i, b) in bytes.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    let _ = match hint {
+                        "x" | "#x" => write!(text, "{}{:#04x}", separator, b),
+                        _ => write!(text, "{}{}", separator, b),
+                    };
+                }
+                text.push(']');
+                text
+            }
+            (Value::Text(text), _) => text.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Piece<'a> {
+    Literal(String),
+    Param { index: usize, kind: &'a str, hint: &'a str },
+}
+
+/// Split a format string into literal text and `{index=type:hint}` parameters.
+/// `{}` and `{:?}` are `Format` values; `{{` and `}}` are braces.
+fn parse_format(format: &str) -> anyhow::Result<Vec<Piece<'_>>> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut next_index = 0;
+    let mut rest = format;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            literal.push(c);
+            rest = &rest[2..];
+            continue;
+        }
+        if c != '{' {
+            literal.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let end = rest.find('}').ok_or_else(|| anyhow::anyhow!("unclosed parameter in '{}'", format))?;
+        let param = &rest[1..end];
+        rest = &rest[end + 1..];
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+        }
+        let (spec, hint) = match param.find(':').filter(|&i| !param[i..].starts_with("::")) {
+            Some(i) => (&param[..i], &param[i + 1..]),
+            None => (param, ""),
+        };
+        let (position, kind) = spec.split_once('=').unwrap_or((spec, "?"));
+        let index = match position {
+            "" => {
+                next_index += 1;
+                next_index - 1
+            }
+            n => n.parse().map_err(|_| anyhow::anyhow!("invalid parameter '{{{}}}'", param))?,
+        };
+        let hint = if hint == "?" { "" } else { hint };
+        pieces.push(Piece::Param { index, kind, hint });
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+/// The variants of a derived enum format string, `A|B({=u8})`, split at the
+/// `|` that aren't inside parameters.
+fn split_variants(format: &str) -> Vec<&str> {
+    let mut variants = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in format.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '|' if depth == 0 => {
+                variants.push(&format[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    variants.push(&format[start..]);
+    variants
+}
+
+/// `N` of an array type such as `[u8; 16]`.
+fn array_len(kind: &str, prefix: &str) -> Option<usize> {
+    kind.strip_prefix(prefix)?.strip_suffix(']')?.trim().parse().ok()
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(anyhow::anyhow!("frame ends {} bytes early", len - self.data.len()));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// A little-endian unsigned integer of `len` bytes.
+    fn uint(&mut self, len: usize) -> anyhow::Result<u128> {
+        Ok(self.take(len)?.iter().rev().fold(0u128, |n, &b| n << 8 | u128::from(b)))
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(self.uint(2)? as u16)
+    }
+}
+
+/// Undo rzCOBS: groups are read from the end, each header byte saying which
+/// of the bytes before it are zeros that were left out.
+pub fn rzcobs_decode(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let truncated = || anyhow::anyhow!("truncated rzCOBS frame");
+    let mut decoded = Vec::new();
+    let mut bytes = data.iter().rev().copied();
+    while let Some(header) = bytes.next() {
+        match header {
+            0x00 => return Err(anyhow::anyhow!("zero byte inside an rzCOBS frame")),
+            0x01..=0x7f => {
+                for bit in (0..7).rev() {
+                    if header & (1 << bit) == 0 {
+                        decoded.push(bytes.next().ok_or_else(truncated)?);
+                    } else {
+                        decoded.push(0);
+                    }
+                }
+            }
+            0x80..=0xfe => {
+                decoded.push(0);
+                for _ in 0..(header & 0x7f) + 7 {
+                    decoded.push(bytes.next().ok_or_else(truncated)?);
+                }
+            }
+            0xff => {
+                for _ in 0..134 {
+                    decoded.push(bytes.next().ok_or_else(truncated)?);
+                }
+            }
+        }
+    }
+    decoded.reverse();
+    Ok(decoded)
+}
+
+/// Collects the byte stream from successive reports into frames.
+#[derive(Debug, Default)]
+pub struct Frames {
+    pending: Vec<u8>,
+}
+
+impl Frames {
+    pub fn new() -> Self {
+        Frames::default()
+    }
+
+    /// Add bytes of the stream; returns the frames they complete, still
+    /// rzCOBS encoded.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        for &b in bytes {
+            if b != 0 {
+                self.pending.push(b);
+            } else if !self.pending.is_empty() {
+                frames.push(std::mem::take(&mut self.pending));
+            }
+        }
+        frames
+    }
+}
+
+/// The string fields of a flat JSON object, as defmt writes symbol names.
+fn json_fields(text: &str) -> Option<BTreeMap<String, String>> {
+    let mut chars = text.trim().strip_prefix('{')?.strip_suffix('}')?.chars().peekable();
+    let mut fields = BTreeMap::new();
+    loop {
+        let key = json_string(&mut chars)?;
+        if skip_whitespace(&mut chars).next()? != ':' {
+            return None;
+        }
+        let value = json_string(&mut chars)?;
+        fields.insert(key, value);
+        match skip_whitespace(&mut chars).next() {
+            Some(',') => {}
+            None => return Some(fields),
+            Some(_) => return None,
+        }
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_whitespace<'a, 'b>(chars: &'b mut Chars<'a>) -> &'b mut Chars<'a> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars
+}
+
+fn json_string(chars: &mut Chars) -> Option<String> {
+    if skip_whitespace(chars).next()? != '"' {
+        return None;
+    }
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(s),
+            '\\' => match chars.next()? {
+                'n' => s.push('\n'),
+                't' => s.push('\t'),
+                'r' => s.push('\r'),
+                'u' => {
+                    let code: String = chars.take(4).collect();
+                    s.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => s.push(c),
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembled from `tests/fixtures/defmt.s`, which says how; a 32-bit ELF
+    /// file, as firmware images are.
+    const FIXTURE: &[u8] = include_bytes!("../tests/fixtures/defmt.elf");
+
+    /// Strings the test messages refer to, after the message itself at index 0.
+    const STRINGS: &[(u16, &str, &str)] = &[
+        (1, "defmt_derived", "Point {{ x: {=u8}, y: {=u8} }}"),
+        (2, "defmt_derived", "None|Some({=u8})"),
+        (3, "defmt_str", "interned"),
+        (4, "defmt_derived", "{=?}"),
+    ];
+
+    fn table(message: &str) -> Table {
+        let strings = [(0, "defmt_info", message)].iter().chain(STRINGS)
+            .map(|&(index, tag, data)| (index, Entry { tag: tag.to_string(), data: data.to_string() }))
+            .collect();
+        Table { strings, timestamp: None }
+    }
+
+    fn frame(args: &[u8]) -> Vec<u8> {
+        [&[0, 0][..], args].concat()
+    }
+
+    /// rzCOBS encoding, as the `rzcobs` crate does it.
+    fn rzcobs_encode(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut run, mut header) = (0u8, 0u8);
+        for &b in data {
+            if run < 7 {
+                if b == 0 {
+                    header |= 1 << run;
+                } else {
+                    out.push(b);
+                }
+                run += 1;
+                if run == 7 && header != 0 {
+                    out.push(header);
+                    (run, header) = (0, 0);
+                }
+            } else if b == 0 {
+                out.push((run - 7) | 0x80);
+                (run, header) = (0, 0);
+            } else {
+                out.push(b);
+                run += 1;
+                if run == 134 {
+                    out.push(0xff);
+                    (run, header) = (0, 0);
+                }
+            }
+        }
+        match run {
+            0 => {}
+            1..=6 => out.push(header | ((0x7f << run) & 0x7f)),
+            _ => out.push((run - 7) | 0x80),
+        }
+        out
+    }
+
+    #[test]
+    fn parameter_types() {
+        let cases: &[(&str, &[u8], &str)] = &[
+            ("{=u8}", &[0xff], "255"),
+            ("{=u16}", &[0x34, 0x12], "4660"),
+            ("{=u32}", &[1, 0, 0, 0x80], "2147483649"),
+            ("{=usize}", &[2, 0, 0, 0], "2"),
+            ("{=u64}", &[0xff; 8], "18446744073709551615"),
+            ("{=u128}", &[0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0], "18446744073709551616"),
+            ("{=i8}", &[0x80], "-128"),
+            ("{=i16}", &[0xfe, 0xff], "-2"),
+            ("{=i32}", &[0xff, 0xff, 0xff, 0x7f], "2147483647"),
+            ("{=isize}", &[0xfd, 0xff, 0xff, 0xff], "-3"),
+            ("{=i64}", &[0xff; 8], "-1"),
+            ("{=i128}", &[0xff; 16], "-1"),
+            ("{=f32}", &[0x00, 0x00, 0xc0, 0x3f], "1.5"),
+            ("{=f64}", &[0, 0, 0, 0, 0, 0, 0xd0, 0xbf], "-0.25"),
+            ("{=bool} {=bool}", &[1, 0], "true false"),
+            ("{=char}", &[0xe9, 0, 0, 0], "é"),
+            ("{=str}", &[3, 0, 0, 0, b'a', b'b', b'c'], "abc"),
+            ("{=istr} {=istr}", &[3, 0, 99, 0], "interned <string 99>"),
+            ("{=[u8]}", &[2, 0, 0, 0, 1, 2], "[1, 2]"),
+            ("{=[u8]:x}", &[2, 0, 0, 0, 1, 0xab], "[0x01, 0xab]"),
+            ("{=[u8]:a}", &[3, 0, 0, 0, b'h', b'i', b'\n'], "b\"hi\\n\""),
+            ("{=[u8; 3]}", &[1, 2, 3], "[1, 2, 3]"),
+            ("{=__internal_Debug}!", &[b'S', b'o', b'm', b'e', b'(', b'3', b')', 0xff], "Some(3)!"),
+            ("at {}", &[1, 0, 1, 2], "at Point { x: 1, y: 2 }"),
+            ("{=?} {=?}", &[2, 0, 0, 2, 0, 1, 7], "None Some(7)"),
+            ("{=[?]}", &[2, 0, 0, 0, 1, 0, 1, 2, 3, 4], "[Point { x: 1, y: 2 }, Point { x: 3, y: 4 }]"),
+            ("{=[?; 2]}", &[2, 0, 1, 5, 0], "[Some(5), None]"),
+            ("{=0..4} {=8..12}", &[0xab, 0xcd, 0x0e], "0b1011 0b1110"),
+            ("{=u32:us} {=u16:ms}", &[0x41, 0x42, 0x0f, 0x00, 0x0a, 0x00], "1.000001 0.010"),
+            ("{=u8:x} {=u8:#X} {=u8:b}", &[0xab, 0xab, 5], "ab 0xAB 101"),
+            ("{=i8:x} {=i16:#x} {=i128:x} {=i32:x}", &[0xff, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x10, 0, 0, 0],
+                "ff 0xfffe ffffffffffffffffffffffffffffffff 10"),
+            ("{1=u8} {0=u16} {1=u8}", &[0x01, 0x02, 0x03], "3 513 3"),
+            ("{{literal}}", &[], "{literal}"),
+        ];
+        for &(format, args, expected) in cases {
+            let table = table(format);
+            let line = table.decode(&frame(args)).unwrap_or_else(|e| panic!("{}: {}", format, e));
+            assert_eq!(line, Line { level: Some("INFO"), timestamp: None, message: expected.to_string() }, "{}", format);
+            for cut in 0..args.len() + 2 {
+                assert!(table.decode(&frame(args)[..cut]).is_err(), "{} cut at {}", format, cut);
+            }
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let mut table = table("x={=u8}");
+        table.timestamp = Some("{=u32:us}".to_string());
+        let line = table.decode(&frame(&[0x40, 0x42, 0x0f, 0x00, 7])).unwrap();
+        assert_eq!((line.timestamp.as_deref(), line.message.as_str()), (Some("1.000000"), "x=7"));
+        assert_eq!(table.decode(&frame(&[0x40, 0x42])).unwrap_err().to_string(), "frame ends 2 bytes early");
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        let error = |format: &str, frame: &[u8]| table(format).decode(frame).unwrap_err().to_string();
+        assert_eq!(error("", &[9, 0]), "unknown format string 9");
+        assert_eq!(error("", &[1, 0]), "format string 1 is a defmt_derived, not a log message");
+        assert_eq!(error("{=u7}", &frame(&[0])), "unsupported argument type {=u7}");
+        assert_eq!(error("{=?}", &frame(&[2, 0, 2])), "variant 2 of a 2-variant enum");
+        assert_eq!(error("{=?}", &frame(&[5, 0])), "unknown format string 5");
+        assert_eq!(error("{=__internal_Debug}", &frame(b"Some")), "unterminated Debug2Format text");
+        assert_eq!(error("{=str}", &frame(&[0xff, 0xff, 0xff, 0xff, b'a'])), format!("frame ends {} bytes early", 0xffff_ffffu32 - 1));
+        assert_eq!(error("x={=u8", &frame(&[0])), "unclosed parameter in 'x={=u8'");
+        // String 4 is a Format value of itself
+        assert_eq!(error("{}", &frame(&[4, 0].repeat(20))), "Format values nested too deeply");
+    }
+
+    #[test]
+    fn loads_strings_from_elf() {
+        let table = Table::from_elf(FIXTURE).unwrap();
+        assert_eq!(table.timestamp.as_deref(), Some("{=u32:us}"));
+        assert_eq!(table.decode(&[1, 0, 0x40, 0x42, 0x0f, 0x00, 0x2a]).unwrap(),
+            Line { level: Some("INFO"), timestamp: Some("1.000000".to_string()), message: "x=0x2a".to_string() });
+        assert_eq!(table.decode(&[2, 0, 0, 0, 0, 0, 2, 0, 0, 0, b'o', b'k']).unwrap(),
+            Line { level: None, timestamp: Some("0.000000".to_string()), message: "say \"hi\"\tok".to_string() });
+        // Symbols whose names aren't defmt's JSON are not strings
+        assert_eq!(table.decode(&[3, 0]).unwrap_err().to_string(), "unknown format string 3");
+
+        let not_defmt = include_bytes!("../tests/fixtures/crash-dwarf5.elf");
+        assert_eq!(Table::from_elf(not_defmt).unwrap_err().to_string(), "no .defmt section; is the firmware built with defmt?");
+    }
+
+    #[test]
+    fn rzcobs() {
+        assert_eq!(rzcobs_decode(&[0x05, 0x2a, 0x7a]).unwrap(), [0x05, 0, 0x2a, 0, 0, 0, 0]);
+        assert_eq!(rzcobs_decode(&[1, 2, 3, 4, 5, 6, 7, 0x80]).unwrap(), [1, 2, 3, 4, 5, 6, 7, 0]);
+        assert_eq!(rzcobs_decode(&[]).unwrap(), []);
+        for len in [1, 6, 7, 8, 133, 134, 135, 300] {
+            let data: Vec<u8> = (0..len).map(|i| if i % 5 == 3 { 0 } else { i as u8 | 1 }).collect();
+            let runs: Vec<u8> = (0..len).map(|i| i as u8 | 1).collect();
+            for data in [data, runs] {
+                let encoded = rzcobs_encode(&data);
+                assert!(!encoded.contains(&0), "{} bytes", len);
+                let decoded = rzcobs_decode(&encoded).unwrap();
+                // Decoding may leave zeros after the data, which frames ignore
+                assert!(decoded.starts_with(&data) && decoded[data.len()..].iter().all(|&b| b == 0), "{} bytes", len);
+            }
+        }
+        assert_eq!(rzcobs_decode(&[0x7a]).unwrap_err().to_string(), "truncated rzCOBS frame");
+        assert_eq!(rzcobs_decode(&[1, 0x80]).unwrap_err().to_string(), "truncated rzCOBS frame");
+        assert_eq!(rzcobs_decode(&[0xff]).unwrap_err().to_string(), "truncated rzCOBS frame");
+        assert_eq!(rzcobs_decode(&[0, 1, 0x7e]).unwrap_err().to_string(), "zero byte inside an rzCOBS frame");
+    }
+
+    #[test]
+    fn frames_across_reports() {
+        let table = table("x={=u8} y={=u16}");
+        let first = rzcobs_encode(&frame(&[1, 0, 0]));
+        let second = rzcobs_encode(&frame(&[2, 0x34, 0x12]));
+        let stream = [&first[..], &[0], &second, &[0]].concat();
+        let mut frames = Frames::new();
+        // Zero padding after a frame, then a frame split across reports
+        let (start, end) = stream.split_at(first.len() + 3);
+        let mut decoded = frames.push(&[&start[..first.len() + 1], &[0; 4]].concat());
+        assert_eq!(decoded.len(), 1);
+        assert!(frames.push(&start[first.len() + 1..]).is_empty());
+        decoded.extend(frames.push(end));
+        let messages: Vec<String> = decoded.iter()
+            .map(|f| table.decode(&rzcobs_decode(f).unwrap()).unwrap().message)
+            .collect();
+        assert_eq!(messages, ["x=1 y=0", "x=2 y=4660"]);
+    }
+}
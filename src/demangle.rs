@@ -0,0 +1,457 @@
+//! Demangling of Rust symbol names, both the legacy scheme
+//! (`_ZN4core9panicking5panic17h0123456789abcdefE`) and v0
+//! (`_RNvNtCs1234_4core9panicking5panic`), without the crate hashes.
+//!
+//! Anything else, such as C names, is passed through. So is any v0 name with
+//! parts this doesn't know, like punycode identifiers.
+
+pub fn demangle(name: &str) -> String {
+    // Both parsers stop before suffixes such as the `.llvm.1234` of local copies
+    let decoded = if let Some(rest) = name.strip_prefix("_ZN").or_else(|| name.strip_prefix("__ZN")) {
+        legacy(rest)
+    } else if let Some(rest) = name.strip_prefix("_R").or_else(|| name.strip_prefix("__R")) {
+        V0::new(rest).symbol()
+    } else {
+        None
+    };
+    decoded.unwrap_or_else(|| name.to_string())
+}
+
+/// `<len><part>...E`, the last part being `h` and a 16-digit hash.
+fn legacy(mut rest: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = rest[..digits].parse().ok()?;
+        let after = rest.get(digits..)?;
+        parts.push(after.get(..len)?);
+        rest = &after[len..];
+    }
+    if parts.last().is_some_and(|p| p.len() == 17 && p.starts_with('h') && p[1..].bytes().all(|b| b.is_ascii_hexdigit())) {
+        parts.pop();
+    }
+    Some(parts.iter().map(|p| unescape(p)).collect::<Vec<_>>().join("::"))
+}
+
+/// Undo the `$LT$`-style escapes and `..` of a legacy name part.
+fn unescape(part: &str) -> String {
+    // Parts that start with an escape get a leading underscore
+    let part = if part.starts_with("_$") { &part[1..] } else { part };
+    let mut text = String::new();
+    let mut rest = part;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("..") {
+            text.push_str("::");
+            rest = &rest[2..];
+            continue;
+        }
+        let escape = rest.strip_prefix('$').and_then(|r| r.split_once('$')).and_then(|(escape, after)| {
+            let decoded = match escape {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                _ => char::from_u32(u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?)?,
+            };
+            Some((decoded, after))
+        });
+        match escape {
+            Some((decoded, after)) => {
+                text.push(decoded);
+                rest = after;
+            }
+            None => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    text
+}
+
+/// A parser for the v0 grammar of RFC 2603, printing as it goes.
+struct V0<'a> {
+    s: &'a [u8],
+    pos: usize,
+    depth: u32,
+}
+
+impl<'a> V0<'a> {
+    fn new(s: &'a str) -> Self {
+        V0 { s: s.as_bytes(), pos: 0, depth: 0 }
+    }
+
+    fn symbol(&mut self) -> Option<String> {
+        // An optional encoding version
+        while self.peek()?.is_ascii_digit() {
+            self.pos += 1;
+        }
+        let path = self.path(true)?;
+        // What follows is the instantiating crate, which isn't shown
+        Some(path)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        let matched = self.peek() == Some(b);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    /// `_` for 0, otherwise digits and letters for the number minus one, then `_`.
+    fn base62(&mut self) -> Option<u64> {
+        if self.eat(b'_') {
+            return Some(0);
+        }
+        let mut n: u64 = 0;
+        loop {
+            let digit = match self.next()? {
+                b @ b'0'..=b'9' => b - b'0',
+                b @ b'a'..=b'z' => b - b'a' + 10,
+                b @ b'A'..=b'Z' => b - b'A' + 36,
+                b'_' => return n.checked_add(1),
+                _ => return None,
+            };
+            n = n.checked_mul(62)?.checked_add(u64::from(digit))?;
+        }
+    }
+
+    /// `s<base62>`, telling apart items of the same name; 0 if absent.
+    fn disambiguator(&mut self) -> Option<u64> {
+        if self.eat(b's') { self.base62().map(|n| n + 1) } else { Some(0) }
+    }
+
+    fn decimal(&mut self) -> Option<usize> {
+        let start = self.pos;
+        // No leading zeros, so `00` is two zeros
+        if self.eat(b'0') {
+            return Some(0);
+        }
+        while self.peek()?.is_ascii_digit() {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.s[start..self.pos]).ok()?.parse().ok()
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        if self.eat(b'u') {
+            // Punycode
+            return None;
+        }
+        let len = self.decimal()?;
+        self.eat(b'_');
+        let bytes = self.s.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    /// Parse whatever is at backref target `B<base62>` with `f`.
+    fn backref(&mut self, f: impl FnOnce(&mut Self) -> Option<String>) -> Option<String> {
+        let target = self.base62()? as usize;
+        if target >= self.pos {
+            return None;
+        }
+        let resume = self.pos;
+        self.pos = target;
+        let result = f(self);
+        self.pos = resume;
+        result
+    }
+
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        if self.depth > 64 {
+            return None;
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// A path; generic arguments get `::` in front when `in_value`, as in
+    /// expressions.
+    fn path(&mut self, in_value: bool) -> Option<String> {
+        self.nested(|p| match p.next()? {
+            b'C' => {
+                p.disambiguator()?;
+                p.ident().map(str::to_string)
+            }
+            b'M' => {
+                p.disambiguator()?;
+                p.path(false)?;
+                Some(format!("<{}>", p.ty()?))
+            }
+            b'X' => {
+                p.disambiguator()?;
+                p.path(false)?;
+                let ty = p.ty()?;
+                Some(format!("<{} as {}>", ty, p.path(false)?))
+            }
+            b'Y' => {
+                let ty = p.ty()?;
+                Some(format!("<{} as {}>", ty, p.path(false)?))
+            }
+            b'N' => {
+                let namespace = p.next()?;
+                let parent = p.path(in_value)?;
+                let disambiguator = p.disambiguator()?;
+                let name = p.ident()?;
+                Some(match namespace {
+                    b'C' if name.is_empty() => format!("{}::{{closure#{}}}", parent, disambiguator),
+                    b'C' => format!("{}::{{closure:{}#{}}}", parent, name, disambiguator),
+                    b'S' => format!("{}::{{shim:{}#{}}}", parent, name, disambiguator),
+                    b'A'..=b'Z' => format!("{}::{{{}:{}#{}}}", parent, namespace as char, name, disambiguator),
+                    _ => format!("{}::{}", parent, name),
+                })
+            }
+            b'I' => {
+                let path = p.path(in_value)?;
+                let args = p.list(Self::generic_arg)?;
+                let separator = if in_value { "::" } else { "" };
+                Some(format!("{}{}<{}>", path, separator, args.join(", ")))
+            }
+            b'B' => p.backref(|p| p.path(in_value)),
+            _ => None,
+        })
+    }
+
+    /// Items parsed with `f` up to an `E`.
+    fn list(&mut self, mut f: impl FnMut(&mut Self) -> Option<String>) -> Option<Vec<String>> {
+        let mut items = Vec::new();
+        while !self.eat(b'E') {
+            items.push(f(self)?);
+        }
+        Some(items)
+    }
+
+    fn generic_arg(&mut self) -> Option<String> {
+        if self.eat(b'L') {
+            self.base62()?;
+            return Some("'_".to_string());
+        }
+        if self.eat(b'K') {
+            return self.constant();
+        }
+        self.ty()
+    }
+
+    fn lifetime(&mut self) -> Option<()> {
+        if self.eat(b'L') {
+            self.base62()?;
+        }
+        Some(())
+    }
+
+    fn binder(&mut self) -> Option<()> {
+        if self.eat(b'G') {
+            self.base62()?;
+        }
+        Some(())
+    }
+
+    fn ty(&mut self) -> Option<String> {
+        self.nested(|p| {
+            let basic = match p.peek()? {
+                b'a' => "i8",
+                b'b' => "bool",
+                b'c' => "char",
+                b'd' => "f64",
+                b'e' => "str",
+                b'f' => "f32",
+                b'h' => "u8",
+                b'i' => "isize",
+                b'j' => "usize",
+                b'l' => "i32",
+                b'm' => "u32",
+                b'n' => "i128",
+                b'o' => "u128",
+                b's' => "i16",
+                b't' => "u16",
+                b'u' => "()",
+                b'v' => "...",
+                b'x' => "i64",
+                b'y' => "u64",
+                b'z' => "!",
+                b'p' => "_",
+                _ => "",
+            };
+            if !basic.is_empty() {
+                p.pos += 1;
+                return Some(basic.to_string());
+            }
+            match p.next()? {
+                b'A' => {
+                    let ty = p.ty()?;
+                    Some(format!("[{}; {}]", ty, p.constant()?))
+                }
+                b'S' => Some(format!("[{}]", p.ty()?)),
+                b'T' => {
+                    let items = p.list(Self::ty)?;
+                    Some(match items.len() {
+                        1 => format!("({},)", items[0]),
+                        _ => format!("({})", items.join(", ")),
+                    })
+                }
+                b'R' => {
+                    p.lifetime()?;
+                    Some(format!("&{}", p.ty()?))
+                }
+                b'Q' => {
+                    p.lifetime()?;
+                    Some(format!("&mut {}", p.ty()?))
+                }
+                b'P' => Some(format!("*const {}", p.ty()?)),
+                b'O' => Some(format!("*mut {}", p.ty()?)),
+                b'F' => {
+                    p.binder()?;
+                    let unsafety = if p.eat(b'U') { "unsafe " } else { "" };
+                    let abi = if p.eat(b'K') {
+                        match p.eat(b'C') {
+                            true => "extern \"C\" ".to_string(),
+                            false => format!("extern \"{}\" ", p.ident()?.replace('_', "-")),
+                        }
+                    } else {
+                        String::new()
+                    };
+                    let args = p.list(Self::ty)?;
+                    let ret = p.ty()?;
+                    let ret = if ret == "()" { String::new() } else { format!(" -> {}", ret) };
+                    Some(format!("{}{}fn({}){}", unsafety, abi, args.join(", "), ret))
+                }
+                b'D' => {
+                    p.binder()?;
+                    let bounds = p.list(|p| {
+                        let mut bound = p.path(false)?;
+                        let mut bindings = Vec::new();
+                        while p.eat(b'p') {
+                            let name = p.ident()?;
+                            bindings.push(format!("{} = {}", name, p.ty()?));
+                        }
+                        if !bindings.is_empty() {
+                            // Into the trait's generic arguments if it has any
+                            match bound.strip_suffix('>') {
+                                Some(open) => bound = format!("{}, {}>", open, bindings.join(", ")),
+                                None => bound = format!("{}<{}>", bound, bindings.join(", ")),
+                            }
+                        }
+                        Some(bound)
+                    })?;
+                    p.lifetime()?;
+                    Some(format!("dyn {}", bounds.join(" + ")))
+                }
+                b'B' => p.backref(Self::ty),
+                _ => {
+                    p.pos -= 1;
+                    p.path(false)
+                }
+            }
+        })
+    }
+
+    fn constant(&mut self) -> Option<String> {
+        if self.eat(b'p') {
+            return Some("_".to_string());
+        }
+        if self.eat(b'B') {
+            return self.backref(Self::constant);
+        }
+        let ty = self.ty()?;
+        let negative = self.eat(b'n');
+        let start = self.pos;
+        while self.peek()? != b'_' {
+            self.pos += 1;
+        }
+        let digits = std::str::from_utf8(&self.s[start..self.pos]).ok()?;
+        self.pos += 1;
+        let value = if digits.is_empty() { 0 } else { u128::from_str_radix(digits, 16).ok()? };
+        Some(match ty.as_str() {
+            "bool" => (value != 0).to_string(),
+            "char" => format!("{:?}", char::from_u32(value as u32)?),
+            _ if negative => format!("-{}", value),
+            _ => value.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(cases: &[(&str, &str)]) {
+        for (mangled, demangled) in cases {
+            assert_eq!(demangle(mangled), *demangled, "{}", mangled);
+        }
+    }
+
+    #[test]
+    fn legacy_names() {
+        check(&[
+            ("_ZN4core9panicking5panic17h0123456789abcdefE", "core::panicking::panic"),
+            ("__ZN3std2rt10lang_start17hba297e1bbc5faa8dE", "std::rt::lang_start"),
+            ("_ZN3std2rt10lang_start28_$u7b$$u7b$closure$u7d$$u7d$17ha86af84d9cc65291E", "std::rt::lang_start::{{closure}}"),
+            ("_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h527d7013781da89cE",
+                "core::ptr::drop_in_place<alloc::vec::Vec<u8>>"),
+            ("_ZN54_$LT$$LP$$RP$$u20$as$u20$std..process..Termination$GT$6report17hb14abffc65974b5aE",
+                "<() as std::process::Termination>::report"),
+            ("_ZN4core3ptr9const_ptr33_$LT$impl$u20$$BP$const$u20$T$GT$13is_aligned_to17h5590ef23eab063c9E",
+                "core::ptr::const_ptr::<impl *const T>::is_aligned_to"),
+            ("_ZN5alloc3vec16Vec$LT$T$C$A$GT$7set_len18precondition_check17hc9bd9ce9682b0dc7E",
+                "alloc::vec::Vec<T,A>::set_len::precondition_check"),
+            // Without a hash, and with the suffix of an LLVM-local copy
+            ("_ZN8firmware4main3runE", "firmware::main::run"),
+            ("_ZN8firmware4main17h0123456789abcdefE.llvm.123", "firmware::main"),
+        ]);
+    }
+
+    #[test]
+    fn v0_names() {
+        check(&[
+            ("_RNvNtCs1234_4core9panicking5panic", "core::panicking::panic"),
+            ("_RNCNCNCNvNtCsjrHSEGnQ3l9_3std2rt19lang_start_internal00s_0B9_",
+                "std::rt::lang_start_internal::{closure#0}::{closure#0}::{closure#1}"),
+            ("_RNvXsh_NtCsgEmfK2I1SDS_4core3fmteNtB5_5Debug3fmt", "<str as core::fmt::Debug>::fmt"),
+            ("_RNvXs1g_NtCsgEmfK2I1SDS_4core3fmtRDNtB6_5DebugEL_Bx_3fmtB8_", "<&dyn core::fmt::Debug as core::fmt::Debug>::fmt"),
+            ("_RNvXs1h_NtCsgEmfK2I1SDS_4core3fmtQShNtB6_5Debug3fmtCsgY6Mt91CT9J_14rustc_demangle", "<&mut [u8] as core::fmt::Debug>::fmt"),
+            ("_RNvXsq_NtCsgEmfK2I1SDS_4core3fmtONtNtB7_3ffi6c_voidNtB5_5Debug3fmtCsjrHSEGnQ3l9_3std",
+                "<*mut core::ffi::c_void as core::fmt::Debug>::fmt"),
+            ("_RNvMs4_NtCsgY6Mt91CT9J_14rustc_demangle2v0NtB5_7Printer11print_const", "<rustc_demangle::v0::Printer>::print_const"),
+            ("_RINvNtCsgEmfK2I1SDS_4core9panicking13assert_failedyyEB4_", "core::panicking::assert_failed::<u64, u64>"),
+            ("_RINvNtCsgEmfK2I1SDS_4core3ptr13drop_in_placeTjNtNtNtNtCsjrHSEGnQ3l9_3std12backtrace_rs9symbolize5gimli7MappingEEBQ_",
+                "core::ptr::drop_in_place::<(usize, std::backtrace_rs::symbolize::gimli::Mapping)>"),
+            ("_RINvNtNtCsjrHSEGnQ3l9_3std3sys9backtrace26___rust_end_short_backtraceNCNvNtB6_5alloc8rust_oom0zEB6_",
+                "std::sys::backtrace::__rust_end_short_backtrace::<std::alloc::rust_oom::{closure#0}, !>"),
+            ("_RNvMs3_NtCslNYArtu3iFV_5alloc7raw_vecINtB5_6RawVecTOhFUKCBN_EuENtNtCsjrHSEGnQ3l9_3std5alloc6SystemE8grow_oneB13_",
+                "<alloc::raw_vec::RawVec<(*mut u8, unsafe extern \"C\" fn(*mut u8)), std::alloc::System>>::grow_one"),
+            ("_RNSNvYNCNvNtNtNtCsjrHSEGnQ3l9_3std3sys11personality3gcc14find_eh_action0INtNtNtCsgEmfK2I1SDS_4core3ops8function6FnOnceuE9call_once6vtableBe_",
+                "<std::sys::personality::gcc::find_eh_action::{closure#0} as core::ops::function::FnOnce<()>>::call_once::{shim:vtable#0}"),
+        ]);
+    }
+
+    #[test]
+    fn passes_through_what_it_cannot_read() {
+        check(&[
+            ("crash_here", "crash_here"),
+            ("_ZN3foo3barv", "_ZN3foo3barv"),
+            ("_ZN3foo", "_ZN3foo"),
+            ("_ZN18446744073709551615xE", "_ZN18446744073709551615xE"),
+            ("_RNvNtCs1234_4core", "_RNvNtCs1234_4core"),
+            ("_RNvB99_3foo", "_RNvB99_3foo"),
+            ("_R", "_R"),
+        ]);
+    }
+}
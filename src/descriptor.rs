@@ -0,0 +1,577 @@
+//! HID report descriptor parsing.
+//!
+//! A report descriptor is a sequence of short items (a prefix byte holding the
+//! tag, type and data size, followed by 0, 1, 2 or 4 data bytes) and rare long
+//! items. [`items`] walks them; the helpers below answer the questions the rest
+//! of the crate needs, and [`decode`] renders a descriptor for people.
+
+use std::collections::BTreeMap;
+
+/// Item type from bits 2-3 of the prefix byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemType {
+    Main,
+    Global,
+    Local,
+    Reserved,
+}
+
+/// One item of a report descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Item<'a> {
+    /// Byte offset of the prefix within the descriptor.
+    pub offset: usize,
+    pub item_type: ItemType,
+    /// Tag within its type (bits 4-7 of the prefix); for long items, the long tag.
+    pub tag: u8,
+    pub data: &'a [u8],
+    pub long: bool,
+}
+
+impl Item<'_> {
+    /// Data as an unsigned little-endian value.
+    pub fn unsigned(&self) -> u32 {
+        self.data.iter().rev().fold(0, |acc, &b| (acc << 8) | u32::from(b))
+    }
+
+    /// Data as a signed little-endian value, as used by logical and physical extents.
+    pub fn signed(&self) -> i32 {
+        match self.data.len() {
+            1 => i32::from(self.data[0] as i8),
+            2 => i32::from(i16::from_le_bytes([self.data[0], self.data[1]])),
+            _ => self.unsigned() as i32,
+        }
+    }
+
+    /// Length of the item in the descriptor, prefix included.
+    pub fn encoded_len(&self) -> usize {
+        self.data.len() + if self.long { 3 } else { 1 }
+    }
+}
+
+/// Main item tags.
+pub const TAG_INPUT: u8 = 0x8;
+pub const TAG_OUTPUT: u8 = 0x9;
+pub const TAG_FEATURE: u8 = 0xb;
+pub const TAG_COLLECTION: u8 = 0xa;
+pub const TAG_END_COLLECTION: u8 = 0xc;
+/// Global item tags.
+pub const TAG_USAGE_PAGE: u8 = 0x0;
+pub const TAG_REPORT_SIZE: u8 = 0x7;
+pub const TAG_REPORT_ID: u8 = 0x8;
+pub const TAG_REPORT_COUNT: u8 = 0x9;
+pub const TAG_PUSH: u8 = 0xa;
+pub const TAG_POP: u8 = 0xb;
+/// Local item tags.
+pub const TAG_USAGE: u8 = 0x0;
+
+/// Iterator over the items of a descriptor; stops at the first truncated item.
+pub struct Items<'a> {
+    desc: &'a [u8],
+    pos: usize,
+}
+
+pub fn items(desc: &[u8]) -> Items<'_> {
+    Items { desc, pos: 0 }
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = Item<'a>;
+
+    fn next(&mut self) -> Option<Item<'a>> {
+        let offset = self.pos;
+        let prefix = *self.desc.get(offset)?;
+
+        if prefix == 0xfe {
+            // Long item: size and tag follow the prefix
+            let size = *self.desc.get(offset + 1)? as usize;
+            let tag = *self.desc.get(offset + 2)?;
+            let data = self.desc.get(offset + 3..offset + 3 + size)?;
+            self.pos = offset + 3 + size;
+            return Some(Item { offset, item_type: ItemType::Reserved, tag, data, long: true });
+        }
+
+        let size = [0, 1, 2, 4][(prefix & 0x03) as usize];
+        let data = self.desc.get(offset + 1..offset + 1 + size)?;
+        self.pos = offset + 1 + size;
+        let item_type = match (prefix >> 2) & 0x03 {
+            0 => ItemType::Main,
+            1 => ItemType::Global,
+            2 => ItemType::Local,
+            _ => ItemType::Reserved,
+        };
+        Some(Item { offset, item_type, tag: prefix >> 4, data, long: false })
+    }
+}
+
+/// Whether the descriptor declares any Report ID, i.e. every report carries an ID byte.
+pub fn uses_report_ids(desc: &[u8]) -> bool {
+    items(desc).any(|i| i.item_type == ItemType::Global && i.tag == TAG_REPORT_ID)
+}
+
+/// Report ID in effect at the first Output item, or `None` if the descriptor
+/// has no Output items or doesn't use report IDs.
+pub fn first_output_report_id(desc: &[u8]) -> Option<u8> {
+    let mut current = None;
+    for item in items(desc) {
+        match (item.item_type, item.tag) {
+            (ItemType::Global, TAG_REPORT_ID) => current = Some(item.unsigned() as u8),
+            (ItemType::Main, TAG_OUTPUT) => return current,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Length in bytes of every report the descriptor declares, keyed by report ID
+/// (0 when the device doesn't use report IDs). Lengths exclude the ID byte.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReportSizes {
+    pub input: BTreeMap<u8, usize>,
+    pub output: BTreeMap<u8, usize>,
+    pub feature: BTreeMap<u8, usize>,
+}
+
+impl ReportSizes {
+    /// Length of output report `report_id`.
+    pub fn output_size(&self, report_id: u8) -> Option<usize> {
+        self.output.get(&report_id).copied()
+    }
+
+    /// Length of the longest input report, the buffer size reads need.
+    pub fn max_input_size(&self) -> Option<usize> {
+        self.input.values().copied().max()
+    }
+
+    /// Length of the longest output report.
+    pub fn max_output_size(&self) -> Option<usize> {
+        self.output.values().copied().max()
+    }
+}
+
+/// Add up the fields of every Input, Output and Feature item per report ID.
+pub fn report_sizes(desc: &[u8]) -> ReportSizes {
+    #[derive(Clone, Copy, Default)]
+    struct Globals {
+        report_id: u8,
+        report_size: u32,
+        report_count: u32,
+    }
+
+    let mut globals = Globals::default();
+    let mut stack = Vec::new();
+    let mut bits: [BTreeMap<u8, u32>; 3] = Default::default();
+
+    for item in items(desc) {
+        match (item.item_type, item.tag) {
+            (ItemType::Global, TAG_REPORT_ID) => globals.report_id = item.unsigned() as u8,
+            (ItemType::Global, TAG_REPORT_SIZE) => globals.report_size = item.unsigned(),
+            (ItemType::Global, TAG_REPORT_COUNT) => globals.report_count = item.unsigned(),
+            (ItemType::Global, TAG_PUSH) => stack.push(globals),
+            (ItemType::Global, TAG_POP) => globals = stack.pop().unwrap_or_default(),
+            (ItemType::Main, tag @ (TAG_INPUT | TAG_OUTPUT | TAG_FEATURE)) => {
+                let kind = match tag {
+                    TAG_INPUT => 0,
+                    TAG_OUTPUT => 1,
+                    _ => 2,
+                };
+                // A hostile descriptor mustn't overflow the sums
+                let total = bits[kind].entry(globals.report_id).or_default();
+                *total = total.saturating_add(globals.report_size.saturating_mul(globals.report_count));
+            }
+            _ => {}
+        }
+    }
+
+    let [input, output, feature] = bits.map(|kind| {
+        kind.into_iter().map(|(id, bits)| (id, bits.div_ceil(8) as usize)).collect()
+    });
+    ReportSizes { input, output, feature }
+}
+
+/// Kind of report a Main item belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportKind {
+    Input,
+    Output,
+    Feature,
+}
+
+impl ReportKind {
+    fn tag(self) -> u8 {
+        match self {
+            ReportKind::Input => TAG_INPUT,
+            ReportKind::Output => TAG_OUTPUT,
+            ReportKind::Feature => TAG_FEATURE,
+        }
+    }
+}
+
+/// One Input, Output or Feature item of a report: `count` values of `bit_size`
+/// bits each, starting `bit_offset` bits into the report (after the ID byte).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub bit_offset: usize,
+    pub bit_size: usize,
+    pub count: usize,
+    pub usage_page: u16,
+    /// Usages of the item as `page << 16 | id`, one per value for variable
+    /// items; may be shorter than `count`, in which case the last usage repeats.
+    pub usages: Vec<u32>,
+    pub logical_min: i32,
+    pub logical_max: i32,
+    /// Constant items are padding.
+    pub constant: bool,
+    /// Variable items hold one value per usage; array items hold usage indices.
+    pub variable: bool,
+    /// Relative items hold changes, such as mouse movement, rather than positions.
+    pub relative: bool,
+}
+
+impl Field {
+    /// Value `index` of this field in `report` (without the ID byte), sign-extended
+    /// when the logical minimum is negative. `None` if the report is too short.
+    pub fn value(&self, report: &[u8], index: usize) -> Option<i64> {
+        let start = self.bit_offset + index * self.bit_size;
+        if self.bit_size == 0 || self.bit_size > 32 || (start + self.bit_size).div_ceil(8) > report.len() {
+            return None;
+        }
+        let mut raw = 0u64;
+        for bit in 0..self.bit_size {
+            let pos = start + bit;
+            if report[pos / 8] & (1 << (pos % 8)) != 0 {
+                raw |= 1 << bit;
+            }
+        }
+        let signed = self.logical_min < 0 && raw & (1 << (self.bit_size - 1)) != 0;
+        Some(if signed { raw as i64 - (1i64 << self.bit_size) } else { raw as i64 })
+    }
+
+    /// Usage of value `index`: usage page and usage ID.
+    pub fn usage(&self, index: usize) -> Option<(u16, u16)> {
+        let usage = *self.usages.get(index).or(self.usages.last())?;
+        Some(((usage >> 16) as u16, usage as u16))
+    }
+}
+
+/// The fields of report `report_id` of `kind`, in report order. Pass 0 for
+/// devices without report IDs.
+pub fn report_fields(desc: &[u8], kind: ReportKind, report_id: u8) -> Vec<Field> {
+    #[derive(Clone, Copy, Default)]
+    struct Globals {
+        usage_page: u16,
+        logical_min: i32,
+        logical_max: i32,
+        report_id: u8,
+        report_size: u32,
+        report_count: u32,
+    }
+
+    let mut globals = Globals::default();
+    let mut stack = Vec::new();
+    let mut usages: Vec<u32> = Vec::new();
+    let mut usage_min = None;
+    // Input, output and feature reports with the same ID are laid out separately
+    let mut offsets: BTreeMap<(u8, u8), usize> = BTreeMap::new();
+    let mut fields = Vec::new();
+
+    // A 1- or 2-byte usage is on the current page; keep it that way so that a
+    // later Usage Page item doesn't change it
+    let full_usage = |item: &Item, page: u16| {
+        if item.data.len() == 4 { item.unsigned() } else { (u32::from(page) << 16) | item.unsigned() }
+    };
+
+    for item in items(desc) {
+        match (item.item_type, item.tag) {
+            (ItemType::Global, TAG_USAGE_PAGE) => globals.usage_page = item.unsigned() as u16,
+            (ItemType::Global, 0x1) => globals.logical_min = item.signed(),
+            (ItemType::Global, 0x2) => globals.logical_max = item.signed(),
+            (ItemType::Global, TAG_REPORT_ID) => globals.report_id = item.unsigned() as u8,
+            (ItemType::Global, TAG_REPORT_SIZE) => globals.report_size = item.unsigned(),
+            (ItemType::Global, TAG_REPORT_COUNT) => globals.report_count = item.unsigned(),
+            (ItemType::Global, TAG_PUSH) => stack.push(globals),
+            (ItemType::Global, TAG_POP) => globals = stack.pop().unwrap_or_default(),
+            (ItemType::Local, TAG_USAGE) => usages.push(full_usage(&item, globals.usage_page)),
+            (ItemType::Local, 0x1) => usage_min = Some(full_usage(&item, globals.usage_page)),
+            (ItemType::Local, 0x2) => {
+                if let Some(min) = usage_min.take() {
+                    // Cap the expansion; a bogus range shouldn't allocate gigabytes
+                    let max = full_usage(&item, globals.usage_page).min(min.saturating_add(0xffff));
+                    usages.extend(min..=max);
+                }
+            }
+            (ItemType::Main, tag) => {
+                if matches!(tag, TAG_INPUT | TAG_OUTPUT | TAG_FEATURE) {
+                    let offset = offsets.entry((tag, globals.report_id)).or_default();
+                    let bits = (globals.report_size as usize).saturating_mul(globals.report_count as usize);
+                    if tag == kind.tag() && globals.report_id == report_id {
+                        let flags = item.unsigned();
+                        fields.push(Field {
+                            bit_offset: *offset,
+                            bit_size: globals.report_size as usize,
+                            count: globals.report_count as usize,
+                            usage_page: globals.usage_page,
+                            usages: std::mem::take(&mut usages),
+                            logical_min: globals.logical_min,
+                            logical_max: globals.logical_max,
+                            constant: flags & 1 != 0,
+                            variable: flags & 2 != 0,
+                            relative: flags & 4 != 0,
+                        });
+                    }
+                    *offset = offset.saturating_add(bits);
+                }
+                // Local items only apply to the next Main item
+                usages.clear();
+                usage_min = None;
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+/// One item with its nesting depth and a readable rendering, e.g.
+/// `Usage Page (Generic Desktop)` or `Input (Data, Variable, Absolute)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedItem<'a> {
+    pub item: Item<'a>,
+    /// Number of enclosing collections.
+    pub depth: usize,
+    pub text: String,
+}
+
+/// Decode every item of `desc`, tracking collections for indentation and the
+/// usage page for usage names.
+pub fn decode(desc: &[u8]) -> Vec<DecodedItem<'_>> {
+    let mut depth = 0usize;
+    let mut usage_page = 0u16;
+    let mut page_stack = Vec::new();
+    let mut decoded = Vec::new();
+
+    for item in items(desc) {
+        let mut item_depth = depth;
+        let text = match (item.item_type, item.tag) {
+            _ if item.long => format!("Long Item (tag 0x{:02x}, {} bytes)", item.tag, item.data.len()),
+            (ItemType::Main, TAG_INPUT) => format!("Input ({})", main_flags(item.unsigned(), false)),
+            (ItemType::Main, TAG_OUTPUT) => format!("Output ({})", main_flags(item.unsigned(), true)),
+            (ItemType::Main, TAG_FEATURE) => format!("Feature ({})", main_flags(item.unsigned(), true)),
+            (ItemType::Main, TAG_COLLECTION) => {
+                depth += 1;
+                format!("Collection ({})", collection_name(item.unsigned()))
+            }
+            (ItemType::Main, TAG_END_COLLECTION) => {
+                depth = depth.saturating_sub(1);
+                item_depth = depth;
+                "End Collection".to_string()
+            }
+            (ItemType::Global, TAG_USAGE_PAGE) => {
+                usage_page = item.unsigned() as u16;
+                format!("Usage Page ({})", usage_page_name(usage_page))
+            }
+            (ItemType::Global, 0x1) => format!("Logical Minimum ({})", item.signed()),
+            (ItemType::Global, 0x2) => format!("Logical Maximum ({})", item.signed()),
+            (ItemType::Global, 0x3) => format!("Physical Minimum ({})", item.signed()),
+            (ItemType::Global, 0x4) => format!("Physical Maximum ({})", item.signed()),
+            (ItemType::Global, 0x5) => format!("Unit Exponent ({})", item.signed()),
+            (ItemType::Global, 0x6) => format!("Unit (0x{:x})", item.unsigned()),
+            (ItemType::Global, TAG_REPORT_SIZE) => format!("Report Size ({})", item.unsigned()),
+            (ItemType::Global, TAG_REPORT_ID) => format!("Report ID ({})", item.unsigned()),
+            (ItemType::Global, TAG_REPORT_COUNT) => format!("Report Count ({})", item.unsigned()),
+            (ItemType::Global, TAG_PUSH) => {
+                page_stack.push(usage_page);
+                "Push".to_string()
+            }
+            (ItemType::Global, TAG_POP) => {
+                usage_page = page_stack.pop().unwrap_or(usage_page);
+                "Pop".to_string()
+            }
+            (ItemType::Local, TAG_USAGE) => format!("Usage ({})", local_usage(&item, usage_page)),
+            (ItemType::Local, 0x1) => format!("Usage Minimum ({})", local_usage(&item, usage_page)),
+            (ItemType::Local, 0x2) => format!("Usage Maximum ({})", local_usage(&item, usage_page)),
+            (ItemType::Local, 0x3) => format!("Designator Index ({})", item.unsigned()),
+            (ItemType::Local, 0x4) => format!("Designator Minimum ({})", item.unsigned()),
+            (ItemType::Local, 0x5) => format!("Designator Maximum ({})", item.unsigned()),
+            (ItemType::Local, 0x7) => format!("String Index ({})", item.unsigned()),
+            (ItemType::Local, 0x8) => format!("String Minimum ({})", item.unsigned()),
+            (ItemType::Local, 0x9) => format!("String Maximum ({})", item.unsigned()),
+            (ItemType::Local, 0xa) => format!("Delimiter ({})", item.unsigned()),
+            (item_type, tag) => format!("Unknown {:?} item (tag 0x{:x}, data 0x{:x})", item_type, tag, item.unsigned()),
+        };
+        decoded.push(DecodedItem { item, depth: item_depth, text });
+    }
+    decoded
+}
+
+/// A Usage, Usage Minimum or Usage Maximum. Four-byte data carries its own
+/// usage page in the high half.
+fn local_usage(item: &Item, usage_page: u16) -> String {
+    let value = item.unsigned();
+    if item.data.len() == 4 {
+        let page = (value >> 16) as u16;
+        format!("{}: {}", usage_page_name(page), usage_name(page, value as u16))
+    } else {
+        usage_name(usage_page, value as u16)
+    }
+}
+
+fn main_flags(flags: u32, has_volatile: bool) -> String {
+    let bit = |n: u32, set: &'static str, clear: &'static str| if flags & (1 << n) != 0 { set } else { clear };
+    let mut parts = vec![
+        bit(0, "Constant", "Data"),
+        bit(1, "Variable", "Array"),
+        bit(2, "Relative", "Absolute"),
+    ];
+    // The remaining flags only matter when set
+    for (n, name) in [(3, "Wrap"), (4, "Non Linear"), (5, "No Preferred State"), (6, "Null State"), (8, "Buffered Bytes")] {
+        if flags & (1 << n) != 0 {
+            parts.push(name);
+        }
+    }
+    if has_volatile && flags & (1 << 7) != 0 {
+        parts.push("Volatile");
+    }
+    parts.join(", ")
+}
+
+fn collection_name(kind: u32) -> String {
+    match kind {
+        0x00 => "Physical".to_string(),
+        0x01 => "Application".to_string(),
+        0x02 => "Logical".to_string(),
+        0x03 => "Report".to_string(),
+        0x04 => "Named Array".to_string(),
+        0x05 => "Usage Switch".to_string(),
+        0x06 => "Usage Modifier".to_string(),
+        0x80..=0xff => format!("Vendor Defined 0x{:02x}", kind),
+        _ => format!("Reserved 0x{:02x}", kind),
+    }
+}
+
+/// Name of a usage page from the HID Usage Tables.
+pub fn usage_page_name(page: u16) -> String {
+    let name = match page {
+        0x01 => "Generic Desktop",
+        0x02 => "Simulation Controls",
+        0x03 => "VR Controls",
+        0x04 => "Sport Controls",
+        0x05 => "Game Controls",
+        0x06 => "Generic Device Controls",
+        0x07 => "Keyboard/Keypad",
+        0x08 => "LED",
+        0x09 => "Button",
+        0x0a => "Ordinal",
+        0x0b => "Telephony Device",
+        0x0c => "Consumer",
+        0x0d => "Digitizers",
+        0x0e => "Haptics",
+        0x0f => "Physical Input Device",
+        0x10 => "Unicode",
+        0x12 => "Eye and Head Trackers",
+        0x14 => "Auxiliary Display",
+        0x20 => "Sensors",
+        0x40 => "Medical Instrument",
+        0x41 => "Braille Display",
+        0x59 => "Lighting And Illumination",
+        0x80 => "Monitor",
+        0x84 => "Power",
+        0x85 => "Battery System",
+        0x8c => "Barcode Scanner",
+        0x8d => "Scales",
+        0x8e => "Magnetic Stripe Reader",
+        0x90 => "Camera Control",
+        0x91 => "Arcade",
+        0xf1d0 => "FIDO Alliance",
+        0xff00..=0xffff => return format!("Vendor Defined 0x{:04x}", page),
+        _ => return format!("0x{:04x}", page),
+    };
+    name.to_string()
+}
+
+/// Name of `usage` on `page` for the common usages, otherwise its number.
+pub fn usage_name(page: u16, usage: u16) -> String {
+    let name = match (page, usage) {
+        (0x01, 0x01) => "Pointer",
+        (0x01, 0x02) => "Mouse",
+        (0x01, 0x04) => "Joystick",
+        (0x01, 0x05) => "Gamepad",
+        (0x01, 0x06) => "Keyboard",
+        (0x01, 0x07) => "Keypad",
+        (0x01, 0x08) => "Multi-axis Controller",
+        (0x01, 0x30) => "X",
+        (0x01, 0x31) => "Y",
+        (0x01, 0x32) => "Z",
+        (0x01, 0x33) => "Rx",
+        (0x01, 0x34) => "Ry",
+        (0x01, 0x35) => "Rz",
+        (0x01, 0x36) => "Slider",
+        (0x01, 0x37) => "Dial",
+        (0x01, 0x38) => "Wheel",
+        (0x01, 0x39) => "Hat Switch",
+        (0x01, 0x80) => "System Control",
+        (0x01, 0x81) => "System Power Down",
+        (0x01, 0x82) => "System Sleep",
+        (0x01, 0x83) => "System Wake Up",
+        (0x08, 0x01) => "Num Lock",
+        (0x08, 0x02) => "Caps Lock",
+        (0x08, 0x03) => "Scroll Lock",
+        (0x09, 0) => "No Button",
+        (0x09, n) => return format!("Button {}", n),
+        (0x0c, 0x01) => "Consumer Control",
+        (0x0c, 0xb5) => "Scan Next Track",
+        (0x0c, 0xb6) => "Scan Previous Track",
+        (0x0c, 0xcd) => "Play/Pause",
+        (0x0c, 0xe2) => "Mute",
+        (0x0c, 0xe9) => "Volume Increment",
+        (0x0c, 0xea) => "Volume Decrement",
+        (0x0d, 0x01) => "Digitizer",
+        (0x0d, 0x02) => "Pen",
+        (0x0d, 0x04) => "Touch Screen",
+        (0x0d, 0x05) => "Touch Pad",
+        (0x0d, 0x42) => "Tip Switch",
+        (0x0d, 0x51) => "Contact Identifier",
+        (0xf1d0, 0x01) => "U2F Authenticator Device",
+        (0xf1d0, 0x20) => "Input Report Data",
+        (0xf1d0, 0x21) => "Output Report Data",
+        _ => return format!("0x{:02x}", usage),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Report ID 2 with an 8-byte input report and a 2-byte output report.
+    const DESCRIPTOR: &[u8] = &[
+        0x06, 0x42, 0xff, // Usage Page (0xFF42)
+        0x09, 0x01, // Usage (1)
+        0xa1, 0x01, // Collection (Application)
+        0x85, 0x02, //   Report ID (2)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x08, //   Report Count (8)
+        0x81, 0x02, //   Input (Data, Variable)
+        0x95, 0x02, //   Report Count (2)
+        0x91, 0x02, //   Output (Data, Variable)
+        0xc0, // End Collection
+    ];
+
+    #[test]
+    fn sizes_and_ids() {
+        let sizes = report_sizes(DESCRIPTOR);
+        assert_eq!(sizes.max_input_size(), Some(8));
+        assert_eq!(sizes.output_size(2), Some(2));
+        assert!(uses_report_ids(DESCRIPTOR));
+        assert_eq!(first_output_report_id(DESCRIPTOR), Some(2));
+    }
+
+    #[test]
+    fn hostile_sizes_saturate() {
+        // Two inputs of 0xffffffff fields of 0xffffffff bits each
+        let desc = [0x77, 0xff, 0xff, 0xff, 0xff, 0x97, 0xff, 0xff, 0xff, 0xff, 0x81, 0x02, 0x81, 0x02, 0x81, 0x02];
+        assert_eq!(report_sizes(&desc).max_input_size(), Some(u32::MAX.div_ceil(8) as usize));
+        let fields = report_fields(&desc, ReportKind::Input, 0);
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[2].bit_offset, usize::MAX);
+    }
+}
@@ -0,0 +1,99 @@
+//! Reading and writing one device at the same time, for `monitor
+//! --interactive` and `record`.
+//!
+//! A hidapi handle can't be shared between threads, so one thread owns the
+//! device. It reads with a short timeout and, between reads, writes whatever
+//! has been queued. What happens arrives as [`Event`]s on a channel that can
+//! also carry lines from stdin ([`forward_stdin`]), so the caller waits for
+//! everything in one place.
+
+use crate::HidSession;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long the device thread waits for an input report before it looks at
+/// the write queue again, which bounds the delay of a queued write.
+pub const POLL: Duration = Duration::from_millis(5);
+
+pub enum Event {
+    Input { report: Vec<u8>, received: Instant },
+    /// A queued report was written, or failed to be.
+    Written { report: Vec<u8>, result: anyhow::Result<usize> },
+    /// A read failed, usually because the device went away. The device thread
+    /// has stopped; reports still queued are dropped.
+    ReadError(anyhow::Error),
+    /// A line from stdin, see [`forward_stdin`].
+    Line(std::io::Result<String>),
+    StdinEnded,
+}
+
+pub struct Duplex {
+    writes: Sender<Vec<u8>>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<HidSession>,
+}
+
+impl Duplex {
+    /// Hand `session` to a device thread that reports to `events`, reading
+    /// into a buffer of `input_buffer` bytes.
+    pub fn start(session: HidSession, input_buffer: usize, events: Sender<Event>) -> Self {
+        let (writes, queued) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || run(session, input_buffer, &queued, &events, &stop))
+        };
+        Duplex { writes, stop, thread }
+    }
+
+    /// Queue `report`, exactly as hidapi takes it (report ID first), for
+    /// writing. The outcome arrives as [`Event::Written`].
+    pub fn write(&self, report: Vec<u8>) {
+        // The thread only goes away after a read error, which has been sent
+        let _ = self.writes.send(report);
+    }
+
+    /// Stop the device thread and take the session back.
+    pub fn stop(self) -> HidSession {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+fn run(session: HidSession, input_buffer: usize, queued: &Receiver<Vec<u8>>, events: &Sender<Event>, stop: &AtomicBool) -> HidSession {
+    let mut buf = vec![0u8; input_buffer];
+    while !stop.load(Ordering::Relaxed) {
+        while let Ok(report) = queued.try_recv() {
+            let result = session.write_report(&report);
+            let _ = events.send(Event::Written { report, result });
+        }
+        match session.read_report_timeout(&mut buf, POLL) {
+            Ok(0) => {}
+            Ok(len) => {
+                let _ = events.send(Event::Input { report: buf[..len].to_vec(), received: Instant::now() });
+            }
+            Err(e) => {
+                let _ = events.send(Event::ReadError(e));
+                break;
+            }
+        }
+    }
+    session
+}
+
+/// Send each line of stdin to `events` from a thread of its own, followed by
+/// [`Event::StdinEnded`].
+pub fn forward_stdin(events: Sender<Event>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            if events.send(Event::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(Event::StdinEnded);
+    });
+}
@@ -0,0 +1,386 @@
+//! DWARF line tables (`.debug_line`, versions 2 to 5), to turn code
+//! addresses into source file and line for crash dumps.
+//!
+//! Only the line number program is read. Inlined calls show the line they
+//! were inlined at, the way `addr2line` without `-i` does.
+
+use std::collections::BTreeMap;
+
+/// Where the code at an address came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: u64,
+}
+
+/// The rows of every line number program, by start address.
+#[derive(Debug, Default)]
+pub struct LineTable {
+    /// Each row covers the addresses up to `end`.
+    rows: BTreeMap<u64, (u64, usize, u64)>,
+    files: Vec<String>,
+}
+
+/// The sections the line programs refer to: `.debug_line` itself and the
+/// string sections of DWARF 5.
+pub struct Sections<'a> {
+    pub line: &'a [u8],
+    pub line_str: &'a [u8],
+    pub str: &'a [u8],
+}
+
+impl LineTable {
+    pub fn parse(sections: &Sections) -> anyhow::Result<Self> {
+        let mut table = LineTable::default();
+        let mut reader = Reader { data: sections.line, at: 0 };
+        while reader.at < sections.line.len() {
+            let (len, offset_size) = match reader.u32()? {
+                0xffff_ffff => (reader.u64()?, 8),
+                len => (u64::from(len), 4),
+            };
+            let unit = usize::try_from(len).ok().and_then(|len| sections.line[reader.at..].get(..len))
+                .ok_or_else(|| anyhow::anyhow!("truncated .debug_line unit"))?;
+            table.unit(&mut Reader { data: unit, at: 0 }, offset_size, sections)?;
+            reader.at += unit.len();
+        }
+        Ok(table)
+    }
+
+    /// Source location of the instruction at `address`.
+    pub fn find(&self, address: u64) -> Option<Location> {
+        let (_, &(end, file, line)) = self.rows.range(..=address).next_back()?;
+        (address < end && line > 0).then(|| Location { file: self.files[file].clone(), line })
+    }
+
+    fn unit(&mut self, r: &mut Reader, offset_size: usize, sections: &Sections) -> anyhow::Result<()> {
+        let version = r.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(anyhow::anyhow!("unsupported .debug_line version {}", version));
+        }
+        if version >= 5 {
+            r.u8()?; // address size, which DW_LNE_set_address gives anyway
+            r.u8()?; // segment selector size
+        }
+        let header_len = r.uint(offset_size)? as usize;
+        let program = r.at.saturating_add(header_len);
+        let min_inst = u64::from(r.u8()?);
+        if version >= 4 {
+            r.u8()?; // maximum operations per instruction, for VLIW only
+        }
+        r.u8()?; // default_is_stmt
+        let line_base = r.u8()? as i8 as i64;
+        let line_range = u64::from(r.u8()?);
+        let opcode_base = r.u8()?;
+        let opcode_lengths: Vec<u8> = (1..opcode_base).map(|_| r.u8()).collect::<anyhow::Result<_>>()?;
+        if line_range == 0 {
+            return Err(anyhow::anyhow!("line_range of 0 in .debug_line"));
+        }
+
+        // Index into `self.files` of each file number the program uses
+        let mut files = Vec::new();
+        if version >= 5 {
+            let dirs = entries(r, offset_size, sections)?;
+            for (path, dir) in entries(r, offset_size, sections)? {
+                self.files.push(join(dirs.get(dir).map(|(d, _)| d.as_str()), &path));
+                files.push(Some(self.files.len() - 1));
+            }
+        } else {
+            let mut dirs = vec![String::new()];
+            loop {
+                let dir = r.str()?;
+                if dir.is_empty() {
+                    break;
+                }
+                dirs.push(dir);
+            }
+            // File numbers start at 1
+            files.push(None);
+            loop {
+                let path = r.str()?;
+                if path.is_empty() {
+                    break;
+                }
+                let dir = r.uleb()? as usize;
+                r.uleb()?;
+                r.uleb()?;
+                self.files.push(join(dirs.get(dir).map(String::as_str), &path));
+                files.push(Some(self.files.len() - 1));
+            }
+        }
+        r.at = program;
+
+        let mut state = Row::START;
+        let mut sequence: Vec<Row> = Vec::new();
+        while r.at < r.data.len() {
+            let opcode = r.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = u64::from(opcode - opcode_base);
+                state.address = state.address.wrapping_add(adjusted / line_range * min_inst);
+                state.line = state.line.wrapping_add_signed(line_base + (adjusted % line_range) as i64);
+                sequence.push(state);
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let len = r.uleb()? as usize;
+                    let end = r.at.saturating_add(len);
+                    match r.u8()? {
+                        1 => {
+                            sequence.push(state);
+                            self.add_sequence(&sequence, &files);
+                            sequence.clear();
+                            state = Row::START;
+                        }
+                        2 => state.address = r.uint(len.saturating_sub(1).min(8))?,
+                        3 => {
+                            // DW_LNE_define_file, before DWARF 5
+                            let path = r.str()?;
+                            self.files.push(path);
+                            files.push(Some(self.files.len() - 1));
+                        }
+                        _ => {}
+                    }
+                    r.at = end;
+                }
+                1 => sequence.push(state),
+                2 => state.address = state.address.wrapping_add(r.uleb()?.wrapping_mul(min_inst)),
+                3 => state.line = state.line.wrapping_add_signed(r.sleb()?),
+                4 => state.file = r.uleb()? as usize,
+                5 => {
+                    r.uleb()?;
+                }
+                6 | 7 | 10 | 11 => {}
+                8 => state.address = state.address.wrapping_add(u64::from(255 - opcode_base) / line_range * min_inst),
+                9 => state.address = state.address.wrapping_add(u64::from(r.u16()?)),
+                _ => {
+                    for _ in 0..opcode_lengths[usize::from(opcode) - 1] {
+                        r.uleb()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_sequence(&mut self, sequence: &[Row], files: &[Option<usize>]) {
+        for pair in sequence.windows(2) {
+            let (row, next) = (pair[0], pair[1]);
+            let Some(&Some(file)) = files.get(row.file) else {
+                continue;
+            };
+            if next.address > row.address {
+                self.rows.insert(row.address, (next.address, file, row.line));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Row {
+    address: u64,
+    file: usize,
+    line: u64,
+}
+
+impl Row {
+    /// The registers at the start of each sequence.
+    const START: Row = Row { address: 0, file: 1, line: 1 };
+}
+
+/// A DWARF 5 directory or file name table: each entry's path and directory
+/// index.
+fn entries(r: &mut Reader, offset_size: usize, sections: &Sections) -> anyhow::Result<Vec<(String, usize)>> {
+    let formats: Vec<(u64, u64)> = (0..r.u8()?).map(|_| Ok((r.uleb()?, r.uleb()?))).collect::<anyhow::Result<_>>()?;
+    let count = r.uleb()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let (mut path, mut dir) = (String::new(), 0);
+        for &(content, form) in &formats {
+            let value = r.form(form, offset_size, sections)?;
+            match (content, value) {
+                // DW_LNCT_path
+                (1, Value::Str(s)) => path = s,
+                // DW_LNCT_directory_index
+                (2, Value::Int(n)) => dir = n as usize,
+                _ => {}
+            }
+        }
+        entries.push((path, dir));
+    }
+    Ok(entries)
+}
+
+fn join(dir: Option<&str>, path: &str) -> String {
+    match dir {
+        Some(dir) if !dir.is_empty() && !path.starts_with('/') => format!("{}/{}", dir.trim_end_matches('/'), path),
+        _ => path.to_string(),
+    }
+}
+
+enum Value {
+    Int(u64),
+    Str(String),
+    Other,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&[u8]> {
+        let bytes = self.data.get(self.at..).and_then(|rest| rest.get(..len)).ok_or_else(|| anyhow::anyhow!("truncated .debug_line"))?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    /// A little-endian unsigned integer of `len` bytes.
+    fn uint(&mut self, len: usize) -> anyhow::Result<u64> {
+        Ok(self.take(len)?.iter().rev().fold(0u64, |n, &b| n << 8 | u64::from(b)))
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.uint(1)? as u8)
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        self.uint(8)
+    }
+
+    fn uleb(&mut self) -> anyhow::Result<u64> {
+        let (mut value, mut shift) = (0u64, 0);
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= u64::from(b & 0x7f) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> anyhow::Result<i64> {
+        let (mut value, mut shift) = (0i64, 0);
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= i64::from(b & 0x7f) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && b & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    /// A NUL-terminated string.
+    fn str(&mut self) -> anyhow::Result<String> {
+        let rest = self.data.get(self.at..).unwrap_or_default();
+        let len = rest.iter().position(|&b| b == 0).ok_or_else(|| anyhow::anyhow!("unterminated string in .debug_line"))?;
+        let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.at += len + 1;
+        Ok(s)
+    }
+
+    /// An attribute value of `form`, as used in DWARF 5 file tables.
+    fn form(&mut self, form: u64, offset_size: usize, sections: &Sections) -> anyhow::Result<Value> {
+        Ok(match form {
+            // DW_FORM_string
+            0x08 => Value::Str(self.str()?),
+            // DW_FORM_line_strp, DW_FORM_strp
+            0x1f | 0x0e => {
+                let offset = self.uint(offset_size)? as usize;
+                let section = if form == 0x1f { sections.line_str } else { sections.str };
+                let mut r = Reader { data: section, at: offset };
+                Value::Str(r.str().unwrap_or_default())
+            }
+            // DW_FORM_udata, data1, data2, data4, data8
+            0x0f => Value::Int(self.uleb()?),
+            0x0b => Value::Int(self.uint(1)?),
+            0x05 => Value::Int(self.uint(2)?),
+            0x06 => Value::Int(self.uint(4)?),
+            0x07 => Value::Int(self.uint(8)?),
+            // DW_FORM_data16, such as MD5 checksums
+            0x1e => {
+                self.take(16)?;
+                Value::Other
+            }
+            // DW_FORM_block
+            0x09 => {
+                let len = self.uleb()? as usize;
+                self.take(len)?;
+                Value::Other
+            }
+            _ => return Err(anyhow::anyhow!("unsupported form 0x{:x} in a .debug_line file table", form)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::Elf;
+
+    /// Built from `tests/fixtures/crash.c`, which says how.
+    const FIXTURES: [&[u8]; 3] = [
+        include_bytes!("../tests/fixtures/crash-dwarf3.elf"),
+        include_bytes!("../tests/fixtures/crash-dwarf4.elf"),
+        include_bytes!("../tests/fixtures/crash-dwarf5.elf"),
+    ];
+
+    fn line_table(elf: &[u8]) -> anyhow::Result<LineTable> {
+        let elf = Elf::parse(elf)?;
+        let section = |name| elf.section(name).map_or(Ok(&[][..]), |(_, s)| elf.section_data(s));
+        LineTable::parse(&Sections { line: section(".debug_line")?, line_str: section(".debug_line_str")?, str: section(".debug_str")? })
+    }
+
+    fn parse(line: &[u8]) -> anyhow::Result<LineTable> {
+        LineTable::parse(&Sections { line, line_str: &[], str: &[] })
+    }
+
+    #[test]
+    fn finds_lines_in_every_version() {
+        // As given by addr2line
+        let expected = [(0x4000b0, 12), (0x4000bc, 13), (0x4000df, 18), (0x4000e3, 19), (0x4000f7, 23), (0x4000f9, 24)];
+        for fixture in FIXTURES {
+            let table = line_table(fixture).unwrap();
+            for (address, line) in expected {
+                let location = table.find(address).unwrap_or_else(|| panic!("no line for {:#x}", address));
+                assert!(location.file.ends_with("crash.c"), "{}", location.file);
+                assert_eq!(location.line, line, "{:#x}", address);
+            }
+            // Before .text and after the end of the sequence
+            assert_eq!(table.find(0x4000af), None);
+            assert_eq!(table.find(0x4000fa), None);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_units() {
+        let elf = Elf::parse(FIXTURES[1]).unwrap();
+        let line = elf.section_data(elf.section(".debug_line").unwrap().1).unwrap();
+        for cut in [2, 8, line.len() / 2, line.len() - 1] {
+            assert!(parse(&line[..cut]).is_err(), "cut at {}", cut);
+        }
+        let oversized = [&[0xff; 4][..], &u64::MAX.to_le_bytes(), &[4, 0]].concat();
+        assert_eq!(parse(&oversized).unwrap_err().to_string(), "truncated .debug_line unit");
+        assert_eq!(parse(&[2, 0, 0, 0, 9, 0]).unwrap_err().to_string(), "unsupported .debug_line version 9");
+        // A header length past the end of the unit leaves no program to run
+        let header = [&[13, 0, 0, 0, 3, 0][..], &u32::MAX.to_le_bytes(), &[1, 1, 0xfb, 14, 1, 0, 0]].concat();
+        assert_eq!(parse(&header).unwrap().find(0), None);
+    }
+}
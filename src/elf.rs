@@ -0,0 +1,178 @@
+//! Just enough of the ELF format to read firmware images: sections and the
+//! symbol table, for defmt format strings and crash dump symbols.
+
+/// `SHT_SYMTAB`
+const SYMTAB: u32 = 2;
+/// `STT_FUNC`
+pub const FUNCTION: u8 = 2;
+
+/// A little-endian ELF file, 32 or 64-bit.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    wide: bool,
+    sections: Vec<Section<'a>>,
+}
+
+pub struct Section<'a> {
+    pub name: &'a str,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+    entsize: usize,
+}
+
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub value: u64,
+    pub size: u64,
+    /// `STT_*`, such as [`FUNCTION`].
+    pub kind: u8,
+    /// Index of the section the symbol is in.
+    pub section: usize,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> anyhow::Result<Self> {
+        if data.get(..4) != Some(b"\x7fELF") {
+            return Err(anyhow::anyhow!("not an ELF file"));
+        }
+        if data.get(5) != Some(&1) {
+            return Err(anyhow::anyhow!("only little-endian ELF files are supported"));
+        }
+        let wide = data.get(4) == Some(&2);
+        let mut elf = Elf { data, wide, sections: Vec::new() };
+        let (shoff, rest) = if wide { (elf.word(0x28, 8)?, 0x3a) } else { (elf.word(0x20, 4)?, 0x2e) };
+        let shentsize = elf.word(rest, 2)? as usize;
+        let shnum = elf.word(rest + 2, 2)? as usize;
+        let shstrndx = elf.word(rest + 4, 2)? as usize;
+
+        let mut names = Vec::with_capacity(shnum);
+        for i in 0..shnum {
+            let at = (shoff as usize).saturating_add(i * shentsize);
+            let (name, section) = if wide {
+                (elf.word(at, 4)?, Section {
+                    name: "",
+                    kind: elf.word(at + 4, 4)? as u32,
+                    offset: elf.word(at + 24, 8)? as usize,
+                    size: elf.word(at + 32, 8)? as usize,
+                    link: elf.word(at + 40, 4)? as usize,
+                    entsize: elf.word(at + 56, 8)? as usize,
+                })
+            } else {
+                (elf.word(at, 4)?, Section {
+                    name: "",
+                    kind: elf.word(at + 4, 4)? as u32,
+                    offset: elf.word(at + 16, 4)? as usize,
+                    size: elf.word(at + 20, 4)? as usize,
+                    link: elf.word(at + 24, 4)? as usize,
+                    entsize: elf.word(at + 36, 4)? as usize,
+                })
+            };
+            names.push(name as usize);
+            elf.sections.push(section);
+        }
+        let names_at = elf.sections.get(shstrndx).map(|s| s.offset)
+            .ok_or_else(|| anyhow::anyhow!("section name table missing"))?;
+        for (section, name) in elf.sections.iter_mut().zip(names) {
+            section.name = c_str(data, names_at.saturating_add(name)).unwrap_or("");
+        }
+        Ok(elf)
+    }
+
+    fn word(&self, at: usize, len: usize) -> anyhow::Result<u64> {
+        let bytes = self.data.get(at..).and_then(|rest| rest.get(..len)).ok_or_else(|| anyhow::anyhow!("truncated ELF file"))?;
+        Ok(bytes.iter().rev().fold(0u64, |n, &b| n << 8 | u64::from(b)))
+    }
+
+    /// The index of section `name`, as in [`Symbol::section`], and the section.
+    pub fn section(&self, name: &str) -> Option<(usize, &Section<'a>)> {
+        self.sections.iter().enumerate().find(|(_, s)| s.name == name)
+    }
+
+    pub fn section_data(&self, section: &Section) -> anyhow::Result<&'a [u8]> {
+        self.data.get(section.offset..).and_then(|rest| rest.get(..section.size))
+            .ok_or_else(|| anyhow::anyhow!("section {} runs past the end of the file", section.name))
+    }
+
+    /// The entries of the symbol table; an error if the file has none, as
+    /// stripped images don't.
+    pub fn symbols(&self) -> anyhow::Result<Vec<Symbol<'a>>> {
+        let symtab = self.sections.iter().find(|s| s.kind == SYMTAB)
+            .ok_or_else(|| anyhow::anyhow!("no symbol table; is the file stripped?"))?;
+        let names_at = self.sections.get(symtab.link).map(|s| s.offset)
+            .ok_or_else(|| anyhow::anyhow!("symbol string table missing"))?;
+        let entsize = match symtab.entsize {
+            0 if self.wide => 24,
+            0 => 16,
+            n => n,
+        };
+        (0..symtab.size / entsize)
+            .map(|i| {
+                let at = symtab.offset.saturating_add(i * entsize);
+                let (name, value, size, info, section) = if self.wide {
+                    (self.word(at, 4)?, self.word(at + 8, 8)?, self.word(at + 16, 8)?, self.word(at + 4, 1)?, self.word(at + 6, 2)?)
+                } else {
+                    (self.word(at, 4)?, self.word(at + 4, 4)?, self.word(at + 8, 4)?, self.word(at + 12, 1)?, self.word(at + 14, 2)?)
+                };
+                Ok(Symbol {
+                    name: c_str(self.data, names_at.saturating_add(name as usize)).unwrap_or(""),
+                    value,
+                    size,
+                    kind: info as u8 & 0x0f,
+                    section: section as usize,
+                })
+            })
+            .collect()
+    }
+}
+
+fn c_str(data: &[u8], at: usize) -> Option<&str> {
+    let bytes = data.get(at..)?;
+    let end = bytes.iter().position(|&b| b == 0)?;
+    std::str::from_utf8(&bytes[..end]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Built from `tests/fixtures/crash.c`, which says how.
+    const FIXTURE: &[u8] = include_bytes!("../tests/fixtures/crash-dwarf5.elf");
+
+    #[test]
+    fn sections_and_symbols() {
+        let elf = Elf::parse(FIXTURE).unwrap();
+        let (index, text) = elf.section(".text").unwrap();
+        assert_eq!(elf.section_data(text).unwrap().len(), 0x4a);
+        assert!(elf.section(".debug_info").is_none());
+
+        let symbols = elf.symbols().unwrap();
+        let middle = symbols.iter().find(|s| s.name == "middle").unwrap();
+        assert_eq!((middle.value, middle.size, middle.kind, middle.section), (0x4000cb, 0x1f, FUNCTION, index));
+        let mut functions: Vec<&str> = symbols.iter().filter(|s| s.kind == FUNCTION).map(|s| s.name).collect();
+        functions.sort();
+        assert_eq!(functions, ["_start", "crash_here", "middle"]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert_eq!(Elf::parse(b"MZ\x90\x00").err().unwrap().to_string(), "not an ELF file");
+        let mut big_endian = FIXTURE.to_vec();
+        big_endian[5] = 2;
+        assert_eq!(Elf::parse(&big_endian).err().unwrap().to_string(), "only little-endian ELF files are supported");
+        for cut in [4, 0x30, FIXTURE.len() - 1] {
+            assert!(Elf::parse(&FIXTURE[..cut]).is_err(), "cut at {}", cut);
+        }
+        let mut far = FIXTURE.to_vec();
+        far[0x28..0x30].copy_from_slice(&(u64::MAX - 2).to_le_bytes());
+        assert_eq!(Elf::parse(&far).err().unwrap().to_string(), "truncated ELF file");
+    }
+
+    #[test]
+    fn stripped() {
+        let mut elf = Elf::parse(FIXTURE).unwrap();
+        elf.sections.retain(|s| s.kind != SYMTAB);
+        assert_eq!(elf.symbols().err().unwrap().to_string(), "no symbol table; is the file stripped?");
+    }
+}
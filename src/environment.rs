@@ -0,0 +1,79 @@
+//! Environmental metadata such as a temperature chamber's setpoint, for
+//! `--env` and `--env-command`.
+//!
+//! Tags are `key=value` pairs. They are given by hand or read from a source
+//! command, which prints one `key=value` per line, e.g. a script asking the
+//! chamber controller for its current state:
+//!
+//! ```text
+//! temperature=85C
+//! humidity=40%
+//! ```
+//!
+//! A script's `env` step starts a new segment of the session, reading the
+//! source again and applying the step's own tags.
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Environment {
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Environment {
+    /// Set `tags`, replacing those with the same keys.
+    pub fn update(&mut self, tags: impl IntoIterator<Item = (String, String)>) {
+        self.tags.extend(tags);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// `humidity=40%, temperature=85C`, in key order.
+    pub fn label(&self) -> String {
+        self.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Parse one `key=value` tag. Keys are letters, digits, `_`, `-` and `.`.
+pub fn parse_tag(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("expected key=value, got '{}'", s))?;
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c)) {
+        return Err(format!("invalid tag name '{}'", key));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+/// Parse a source command's output: one tag per line; blank lines and `#`
+/// comments are skipped.
+pub fn parse_tags(text: &str) -> anyhow::Result<Vec<(String, String)>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| parse_tag(line).map_err(|e| anyhow::anyhow!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// A command that prints the current tags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    pub command: String,
+}
+
+impl Source {
+    /// Run the command through the shell and parse what it prints.
+    pub fn read(&self) -> anyhow::Result<Vec<(String, String)>> {
+        #[cfg(windows)]
+        let output = std::process::Command::new("cmd").args(["/C", &self.command]).output();
+        #[cfg(not(windows))]
+        let output = std::process::Command::new("sh").args(["-c", &self.command]).output();
+        let output = output.map_err(|e| anyhow::anyhow!("Cannot run the environment command: {}", e))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!("The environment command exited with {}", output.status));
+        }
+        parse_tags(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|e| anyhow::anyhow!("Invalid environment command output: {}", e))
+    }
+}
@@ -0,0 +1,365 @@
+//! Request/response exchanges: writing an output report and reading input
+//! reports until the answer arrives, for `send`, `read --expect` and
+//! `send --all-matching`.
+//!
+//! Nothing is shown here. An [`Observer`] is told about every report as it is
+//! written or read, so that the caller can show or audit it.
+
+use crate::framing::Framing;
+use crate::pattern::HexPattern;
+use crate::transport::HidTransport;
+use crate::{numbered_report, pad_report, strip_report_id};
+use std::time::{Duration, Instant};
+
+/// How long a response may take when no read timeout is given.
+pub const EXPECT_TIMEOUT_MS: u64 = 5000;
+
+/// How one command frames its reports.
+#[derive(Clone, Copy, Debug)]
+pub struct ReportFormat {
+    /// `None` keeps the legacy behaviour where the first data byte is the report ID.
+    pub report_id: Option<u8>,
+    /// Read buffer size: the longest input report plus a report ID byte.
+    pub input_buffer: usize,
+    /// How long to wait for an input report; `None` blocks.
+    pub read_timeout: Option<Duration>,
+    /// Encoding of payloads within reports.
+    pub framing: Framing,
+}
+
+impl ReportFormat {
+    /// How long to wait for a response: the read timeout, or
+    /// [`EXPECT_TIMEOUT_MS`] without one.
+    pub fn response_timeout(&self) -> Duration {
+        self.read_timeout.unwrap_or(Duration::from_millis(EXPECT_TIMEOUT_MS))
+    }
+
+    /// The complete output report for `data` in `report_size`-byte reports.
+    ///
+    /// Without a report ID the data is padded to a full report and its first
+    /// byte is the report ID; with one, the ID is prepended to the padded data.
+    /// Framed data is encoded first and must fit in the report.
+    pub fn output_report(&self, report_size: usize, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let data = self.framing.encode(data)?;
+        if self.framing != Framing::Raw && data.len() > report_size {
+            return Err(anyhow::anyhow!("{} frame of {} bytes does not fit in a {}-byte report",
+                self.framing.name(), data.len(), report_size));
+        }
+        Ok(match self.report_id {
+            Some(id) => numbered_report(id, &data, report_size),
+            None => pad_report(&data, report_size),
+        })
+    }
+
+    /// The payload of `data` as [`output_report`](Self::output_report) takes
+    /// it: after its first byte, the report ID, when the format has none of
+    /// its own. Deny-lists are matched against this, so that a report is
+    /// caught however its ID is given.
+    pub fn data_payload<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        match self.report_id {
+            Some(_) => data,
+            None => data.get(1..).unwrap_or_default(),
+        }
+    }
+
+    /// The payload of an input report: after its report ID, if any, and
+    /// decoded. `None` for reports with another ID, an error for invalid frames.
+    pub fn payload(&self, report: &[u8]) -> Option<anyhow::Result<Vec<u8>>> {
+        let payload = match self.report_id {
+            Some(id) => strip_report_id(report, id).ok()?,
+            None => report,
+        };
+        Some(self.framing.decode(payload))
+    }
+
+    /// Whether `report` has a valid payload starting with `pattern`.
+    pub fn matches(&self, report: &[u8], pattern: &HexPattern) -> bool {
+        self.payload(report).and_then(Result::ok).is_some_and(|p| pattern.matches_prefix(&p))
+    }
+}
+
+/// Told about every report an exchange writes or reads.
+pub trait Observer {
+    /// An output report was written, or failed to be.
+    fn sent(&mut self, report: &[u8], result: &anyhow::Result<usize>);
+    /// An input report was read.
+    fn received(&mut self, report: &[u8]);
+}
+
+/// Observes nothing.
+impl Observer for () {
+    fn sent(&mut self, _report: &[u8], _result: &anyhow::Result<usize>) {}
+    fn received(&mut self, _report: &[u8]) {}
+}
+
+/// An input report and when the read returned it.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub report: Vec<u8>,
+    pub received: Instant,
+}
+
+/// Write `report` as is; returns when the write completed.
+pub fn write(transport: &dyn HidTransport, report: &[u8], observer: &mut dyn Observer) -> anyhow::Result<Instant> {
+    let result = transport.write(report);
+    let sent = Instant::now();
+    observer.sent(report, &result);
+    result.map(|_| sent)
+}
+
+/// Read one input report, waiting at most the format's read timeout if it has
+/// one. `None` if the read timed out.
+pub fn read(transport: &dyn HidTransport, format: &ReportFormat, observer: &mut dyn Observer) -> anyhow::Result<Option<Response>> {
+    let mut input_report = vec![0u8; format.input_buffer];
+    let len = transport.read(&mut input_report, format.read_timeout)?;
+    if len == 0 && format.read_timeout.is_some() {
+        return Ok(None);
+    }
+    let received = Instant::now();
+    input_report.truncate(len);
+    observer.received(&input_report);
+    Ok(Some(Response { report: input_report, received }))
+}
+
+/// Read input reports of up to `buffer_len` bytes until `accept` takes one,
+/// giving up at `deadline`. `None` if none was accepted in time.
+pub fn read_until(
+    transport: &dyn HidTransport,
+    buffer_len: usize,
+    deadline: Instant,
+    observer: &mut dyn Observer,
+    mut accept: impl FnMut(&[u8]) -> bool,
+) -> anyhow::Result<Option<Response>> {
+    let mut input_report = vec![0u8; buffer_len];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let len = transport.read(&mut input_report, Some(remaining))?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let received = Instant::now();
+        let report = &input_report[..len];
+        observer.received(report);
+        if accept(report) {
+            return Ok(Some(Response { report: report.to_vec(), received }));
+        }
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+    }
+}
+
+/// Read input reports until one's payload matches `pattern`, giving up once
+/// the [response timeout](ReportFormat::response_timeout) has passed. Reports
+/// with another report ID are skipped.
+pub fn expect(transport: &dyn HidTransport, format: &ReportFormat, pattern: &HexPattern, observer: &mut dyn Observer) -> anyhow::Result<Option<Response>> {
+    let deadline = Instant::now() + format.response_timeout();
+    read_until(transport, format.input_buffer, deadline, observer, |report| format.matches(report, pattern))
+}
+
+/// What one device did with a report sent by [`fan_out`].
+#[derive(Debug)]
+pub struct FanOut {
+    pub written: anyhow::Result<usize>,
+    /// Every input report read, up to the response.
+    pub reports: Vec<Vec<u8>>,
+    /// Time from the write to the response, if one came.
+    pub latency: Option<Duration>,
+    pub read_error: Option<anyhow::Error>,
+}
+
+/// Keeps the reports it sees.
+struct Collect(Vec<Vec<u8>>);
+
+impl Observer for Collect {
+    fn sent(&mut self, _report: &[u8], _result: &anyhow::Result<usize>) {}
+
+    fn received(&mut self, report: &[u8]) {
+        self.0.push(report.to_vec());
+    }
+}
+
+/// Write `report` and read until a response, or one matching `expect`,
+/// arrives or the [response timeout](ReportFormat::response_timeout) passes.
+pub fn fan_out_exchange(transport: &dyn HidTransport, format: &ReportFormat, report: &[u8], expect: Option<&HexPattern>) -> FanOut {
+    let mut reports = Collect(Vec::new());
+    let sent = Instant::now();
+    let written = transport.write(report);
+    if written.is_err() {
+        return FanOut { written, reports: reports.0, latency: None, read_error: None };
+    }
+    let deadline = sent + format.response_timeout();
+    let response = read_until(transport, format.input_buffer, deadline, &mut reports, |input| {
+        expect.is_none_or(|pattern| format.matches(input, pattern))
+    });
+    let (latency, read_error) = match response {
+        Ok(response) => (response.map(|r| r.received - sent), None),
+        Err(e) => (None, Some(e)),
+    };
+    FanOut { written, reports: reports.0, latency, read_error }
+}
+
+/// Run `exchange` on every target at the same time, each on its own thread,
+/// and return the targets with what they did, in order.
+pub fn fan_out<T: Send>(targets: Vec<T>, exchange: impl Fn(&T) -> FanOut + Sync) -> Vec<(T, FanOut)> {
+    let exchange = &exchange;
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = targets.into_iter()
+            .map(|target| scope.spawn(move || {
+                let outcome = exchange(&target);
+                (target, outcome)
+            }))
+            .collect();
+        threads.into_iter().map(|t| t.join().expect("fan-out thread panicked")).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+
+    fn format(report_id: Option<u8>, framing: Framing) -> ReportFormat {
+        ReportFormat { report_id, input_buffer: 65, read_timeout: Some(Duration::from_millis(50)), framing }
+    }
+
+    fn pattern(s: &str) -> HexPattern {
+        HexPattern::parse(s).unwrap()
+    }
+
+    /// Keeps every report it sees, written ones with whether the write worked.
+    #[derive(Default)]
+    struct Log {
+        sent: Vec<(Vec<u8>, bool)>,
+        received: Vec<Vec<u8>>,
+    }
+
+    impl Observer for Log {
+        fn sent(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+            self.sent.push((report.to_vec(), result.is_ok()));
+        }
+
+        fn received(&mut self, report: &[u8]) {
+            self.received.push(report.to_vec());
+        }
+    }
+
+    #[test]
+    fn output_reports() {
+        let raw = format(None, Framing::Raw);
+        assert_eq!(raw.output_report(4, &[0x00, 0x01]).unwrap(), [0x00, 0x01, 0x00, 0x00]);
+        let numbered = format(Some(2), Framing::Raw);
+        // The ID comes on top of the report size
+        assert_eq!(numbered.output_report(4, &[0x01]).unwrap(), [0x02, 0x01, 0x00, 0x00, 0x00]);
+
+        let cobs = format(Some(2), Framing::Cobs);
+        assert_eq!(cobs.output_report(4, &[0x11, 0x00]).unwrap(), [0x02, 0x02, 0x11, 0x01, 0x00]);
+        let e = cobs.output_report(3, &[0x11, 0x22, 0x33]).unwrap_err();
+        assert_eq!(e.to_string(), "cobs frame of 5 bytes does not fit in a 3-byte report");
+    }
+
+    #[test]
+    fn deny_list_sees_the_payload_however_the_id_is_given() {
+        let profile = crate::profile::Profile::parse("[[deny]]\npattern = \"ee ff ??\"\nreason = \"erases flash\"\n").unwrap();
+        let legacy = format(None, Framing::Raw);
+        let numbered = format(Some(0), Framing::Raw);
+        // `send 00eeff00` and `send --report-id 0 eeff00` put the same report on the wire
+        assert_eq!(legacy.output_report(4, &[0x00, 0xee, 0xff, 0x00]).unwrap(), numbered.output_report(3, &[0xee, 0xff, 0x00]).unwrap());
+        assert!(profile.denied(legacy.data_payload(&[0x00, 0xee, 0xff, 0x00])).is_some());
+        assert!(profile.denied(numbered.data_payload(&[0xee, 0xff, 0x00])).is_some());
+        assert!(profile.denied(legacy.data_payload(&[0xee, 0xff, 0x00])).is_none());
+        assert_eq!(legacy.data_payload(&[]), [0u8; 0]);
+    }
+
+    #[test]
+    fn skips_other_reports_until_a_match() {
+        let mock = MockTransport::new();
+        mock.respond(pattern("01 10"), [vec![0x02, 0xaa], vec![0x01, 0x55], vec![0x01, 0x90, 0x01]]);
+        let format = format(Some(1), Framing::Raw);
+        let mut log = Log::default();
+
+        let request = format.output_report(4, &[0x10]).unwrap();
+        write(&mock, &request, &mut log).unwrap();
+        let response = expect(&mock, &format, &pattern("90"), &mut log).unwrap().unwrap();
+        assert_eq!(response.report, [0x01, 0x90, 0x01]);
+        assert_eq!(log.sent, [(vec![0x01, 0x10, 0x00, 0x00, 0x00], true)]);
+        // Another report ID, then a payload that doesn't match
+        assert_eq!(log.received, [vec![0x02, 0xaa], vec![0x01, 0x55], vec![0x01, 0x90, 0x01]]);
+        assert_eq!(mock.pending_inputs(), 0);
+    }
+
+    #[test]
+    fn times_out() {
+        let mock = MockTransport::new();
+        mock.push_input(&[0x01, 0x55]);
+        let format = format(Some(1), Framing::Raw);
+        let mut log = Log::default();
+
+        let started = Instant::now();
+        assert!(expect(&mock, &format, &pattern("90"), &mut log).unwrap().is_none());
+        assert!(started.elapsed() >= format.response_timeout());
+        assert_eq!(log.received, [vec![0x01, 0x55]]);
+
+        assert!(read(&mock, &format, &mut log).unwrap().is_none());
+        mock.push_input(&[0x01, 0x56]);
+        assert_eq!(read(&mock, &format, &mut log).unwrap().unwrap().report, [0x01, 0x56]);
+
+        mock.disconnect();
+        assert!(read(&mock, &format, &mut log).is_err());
+    }
+
+    #[test]
+    fn framed_round_trips() {
+        for framing in [Framing::Cobs, Framing::LenCrc16] {
+            let mock = MockTransport::new();
+            let format = format(Some(3), framing);
+            let request = format.output_report(16, &[0x00, 0x07, 0x00]).unwrap();
+            let answer = format.output_report(16, &[0x80, 0x00, 0x2a]).unwrap();
+            mock.respond(pattern(&hex::encode(&request)), [answer]);
+
+            write(&mock, &request, &mut ()).unwrap();
+            assert_eq!(format.payload(&mock.written()[0]).unwrap().unwrap(), [0x00, 0x07, 0x00]);
+            let response = expect(&mock, &format, &pattern("80 00"), &mut ()).unwrap().unwrap();
+            assert_eq!(format.payload(&response.report).unwrap().unwrap(), [0x80, 0x00, 0x2a], "{}", framing.name());
+
+            // A corrupt frame is no match: its COBS code or its length runs past the data
+            let mut corrupt = format.output_report(16, &[0x80, 0x00]).unwrap();
+            corrupt[1] += 4;
+            mock.push_input(&corrupt);
+            assert!(format.payload(&corrupt).unwrap().is_err());
+            assert!(expect(&mock, &format, &pattern("80"), &mut ()).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn failed_writes_are_observed() {
+        let mock = MockTransport::new();
+        mock.fail_writes(1);
+        let mut log = Log::default();
+        assert!(write(&mock, &[0x00, 0x01], &mut log).is_err());
+        assert!(write(&mock, &[0x00, 0x02], &mut log).is_ok());
+        assert_eq!(log.sent, [(vec![0x00, 0x01], false), (vec![0x00, 0x02], true)]);
+        assert_eq!(mock.written(), [vec![0x00, 0x02]]);
+    }
+
+    #[test]
+    fn fans_out() {
+        let answering = MockTransport::new();
+        answering.respond(pattern("00 01"), [vec![0x00, 0x55], vec![0x00, 0x90]]);
+        let silent = MockTransport::new();
+        let failing = MockTransport::new();
+        failing.fail_writes(1);
+        let format = format(None, Framing::Raw);
+
+        let results = fan_out(vec![answering, silent, failing], |mock| {
+            fan_out_exchange(mock, &format, &[0x00, 0x01], Some(&pattern("00 90")))
+        });
+        let [(_, answered), (_, timed_out), (_, failed)] = &results[..] else {
+            panic!("{} results", results.len());
+        };
+        assert!(answered.latency.is_some());
+        assert_eq!(answered.reports, [vec![0x00, 0x55], vec![0x00, 0x90]]);
+        assert!(timed_out.written.is_ok() && timed_out.latency.is_none() && timed_out.read_error.is_none());
+        assert!(failed.written.is_err() && failed.reports.is_empty());
+    }
+}
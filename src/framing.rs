@@ -0,0 +1,211 @@
+//! Frame encodings layered over report payloads, selected with `--framing`.
+//!
+//! - `raw`: the payload as is, zero-padded to the report size.
+//! - `cobs`: Consistent Overhead Byte Stuffing, followed by a `00` delimiter,
+//!   so the frame's end is found despite the zero padding.
+//! - `len-crc16`: a length byte, the payload, and a little-endian CRC-16 of the
+//!   length and payload (see [`crate::crc`]).
+//!
+//! Encoding applies to the payload after the report ID; the ID itself is
+//! never framed.
+
+use crate::crc::crc16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Framing {
+    #[default]
+    Raw,
+    Cobs,
+    #[value(name = "len-crc16")]
+    LenCrc16,
+}
+
+impl Framing {
+    pub fn name(self) -> &'static str {
+        match self {
+            Framing::Raw => "raw",
+            Framing::Cobs => "cobs",
+            Framing::LenCrc16 => "len-crc16",
+        }
+    }
+
+    /// Frame `payload`.
+    pub fn encode(self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Framing::Raw => Ok(payload.to_vec()),
+            Framing::Cobs => {
+                let mut frame = cobs_encode(payload);
+                frame.push(0);
+                Ok(frame)
+            }
+            Framing::LenCrc16 => {
+                let len = u8::try_from(payload.len())
+                    .map_err(|_| anyhow::anyhow!("len-crc16 frames hold at most 255 bytes, not {}", payload.len()))?;
+                let mut frame = Vec::with_capacity(payload.len() + 3);
+                frame.push(len);
+                frame.extend_from_slice(payload);
+                frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Recover the payload from a received report payload, which may carry
+    /// padding after the frame.
+    pub fn decode(self, report: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Framing::Raw => Ok(report.to_vec()),
+            Framing::Cobs => {
+                let end = report.iter().position(|&b| b == 0).unwrap_or(report.len());
+                cobs_decode(&report[..end])
+            }
+            Framing::LenCrc16 => {
+                let len = *report.first().ok_or_else(|| anyhow::anyhow!("Empty report has no length byte"))? as usize;
+                let Some(frame) = report.get(..1 + len + 2) else {
+                    return Err(anyhow::anyhow!("Frame claims {} bytes and a CRC, but only {} bytes follow its length byte",
+                        len, report.len() - 1));
+                };
+                let (body, crc) = frame.split_at(1 + len);
+                let crc = u16::from_le_bytes([crc[0], crc[1]]);
+                if crc16(body) != crc {
+                    return Err(anyhow::anyhow!("Frame CRC is {:04x}, expected {:04x}", crc, crc16(body)));
+                }
+                Ok(body[1..].to_vec())
+            }
+        }
+    }
+
+    /// The longest payload whose frame fits in `len` bytes.
+    pub fn max_payload(self, len: usize) -> usize {
+        match self {
+            Framing::Raw => len,
+            // A code byte per 254 data bytes, one more, and the delimiter
+            Framing::Cobs => {
+                let mut n = len.saturating_sub(2);
+                while n > 0 && n + n / 254 + 2 > len {
+                    n -= 1;
+                }
+                n
+            }
+            Framing::LenCrc16 => len.saturating_sub(3).min(u8::MAX as usize),
+        }
+    }
+}
+
+fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 1);
+    let mut code_pos = 0;
+    out.push(0);
+    let mut code = 1u8;
+    for &byte in data {
+        if byte != 0 {
+            out.push(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            out[code_pos] = code;
+            code_pos = out.len();
+            out.push(0);
+            code = 1;
+        }
+    }
+    out[code_pos] = code;
+    out
+}
+
+fn cobs_decode(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        let block = data.get(i + 1..i + code)
+            .ok_or_else(|| anyhow::anyhow!("COBS block at byte {} runs past the end of the frame", i))?;
+        out.extend_from_slice(block);
+        i += code;
+        if code < 0xff && i < data.len() {
+            out.push(0);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Payloads with zeros in them, including at both ends.
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| if i % 7 == 0 { 0 } else { i as u8 }).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        for framing in [Framing::Raw, Framing::Cobs, Framing::LenCrc16] {
+            for len in [0, 1, 253, 254, 255, 300] {
+                let data = payload(len);
+                let Ok(frame) = framing.encode(&data) else {
+                    assert!(framing == Framing::LenCrc16 && len > 255);
+                    continue;
+                };
+                // Padding after the frame is ignored
+                let mut report = frame.clone();
+                report.resize(frame.len() + 16, 0);
+                let expected = if framing == Framing::Raw { report.clone() } else { data };
+                assert_eq!(framing.decode(&report).unwrap(), expected, "{} with {} bytes", framing.name(), len);
+            }
+        }
+    }
+
+    #[test]
+    fn cobs_without_zeros() {
+        // A full block of 254 non-zero bytes needs no zero after it
+        for (len, frame_len) in [(253, 255), (254, 257), (255, 258), (508, 512)] {
+            let data = vec![0x11; len];
+            let frame = Framing::Cobs.encode(&data).unwrap();
+            assert_eq!(frame.iter().filter(|&&b| b == 0).count(), 1);
+            assert_eq!(frame.len(), frame_len);
+            assert_eq!(Framing::Cobs.decode(&frame).unwrap(), data);
+        }
+        assert_eq!(Framing::Cobs.encode(&[]).unwrap(), [0x01, 0x00]);
+        assert_eq!(Framing::Cobs.encode(&[0x00]).unwrap(), [0x01, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn max_payload_fits() {
+        for framing in [Framing::Raw, Framing::Cobs, Framing::LenCrc16] {
+            for len in [3, 64, 256, 512, 1024] {
+                let max = framing.max_payload(len);
+                let frame = framing.encode(&vec![0x11; max]).unwrap();
+                assert!(frame.len() <= len, "{} in {} bytes", framing.name(), len);
+            }
+        }
+    }
+
+    #[test]
+    fn cobs_corrupt() {
+        // The code byte says 5, but only two bytes follow
+        let error = Framing::Cobs.decode(&[0x05, 0x01, 0x02, 0x00]).unwrap_err();
+        assert!(error.to_string().contains("runs past the end"), "{}", error);
+    }
+
+    #[test]
+    fn len_crc16_corrupt() {
+        let mut frame = Framing::LenCrc16.encode(&[1, 2, 3]).unwrap();
+        frame[2] ^= 0xff;
+        let error = Framing::LenCrc16.decode(&frame).unwrap_err();
+        assert!(error.to_string().contains("CRC"), "{}", error);
+    }
+
+    #[test]
+    fn len_crc16_short() {
+        assert!(Framing::LenCrc16.decode(&[]).is_err());
+        let frame = Framing::LenCrc16.encode(&[1, 2, 3, 4]).unwrap();
+        let error = Framing::LenCrc16.decode(&frame[..4]).unwrap_err();
+        assert_eq!(error.to_string(), "Frame claims 4 bytes and a CRC, but only 3 bytes follow its length byte");
+    }
+
+    #[test]
+    fn len_crc16_too_long() {
+        assert!(Framing::LenCrc16.encode(&[0; 256]).is_err());
+    }
+}
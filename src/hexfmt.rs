@@ -0,0 +1,40 @@
+//! Hex formatting into reusable buffers.
+//!
+//! `hex::encode` allocates a fresh `String` per call, which shows up when
+//! printing thousands of reports per second. [`HexBuf`] keeps one buffer
+//! alive across reports and fills it from a byte-pair lookup table.
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Two ASCII hex digits for every byte value.
+const HEX_PAIRS: [[u8; 2]; 256] = {
+    let mut table = [[0u8; 2]; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = [HEX_DIGITS[i >> 4], HEX_DIGITS[i & 0x0f]];
+        i += 1;
+    }
+    table
+};
+
+/// Reusable lowercase hex encoder.
+#[derive(Default)]
+pub struct HexBuf {
+    buf: Vec<u8>,
+}
+
+impl HexBuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encode `bytes`, replacing the previous contents of the buffer.
+    pub fn encode(&mut self, bytes: &[u8]) -> &str {
+        self.buf.clear();
+        self.buf.reserve(bytes.len() * 2);
+        for &b in bytes {
+            self.buf.extend_from_slice(&HEX_PAIRS[b as usize]);
+        }
+        std::str::from_utf8(&self.buf).expect("hex digits are ASCII")
+    }
+}
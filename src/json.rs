@@ -0,0 +1,168 @@
+//! Just enough JSON to write machine-readable output: a value type whose
+//! `Display` is compact JSON, one document per line for JSON Lines.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in insertion order.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// An object from `(key, value)` pairs, keeping their order.
+    pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    /// Append a member to an object; no-op on other values.
+    pub fn push(&mut self, key: &str, value: impl Into<Json>) {
+        if let Json::Object(members) = self {
+            members.push((key.to_string(), value.into()));
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(i) => write!(f, "{}", i),
+            // JSON has no NaN or infinity
+            Json::Float(x) if !x.is_finite() => f.write_str("null"),
+            Json::Float(x) => write!(f, "{}", x),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+macro_rules! from_int {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Json {
+            fn from(i: $t) -> Self {
+                Json::Int(i as i64)
+            }
+        }
+    )*};
+}
+
+from_int!(u8, u16, u32, i32, i64, usize, u64);
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Self {
+        Json::Float(x)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(items: Vec<T>) -> Self {
+        Json::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(Json::from(r#"say "hi""#).to_string(), r#""say \"hi\"""#);
+        assert_eq!(Json::from(r"C:\hid").to_string(), r#""C:\\hid""#);
+        assert_eq!(Json::from("a\nb\r\tc").to_string(), r#""a\nb\r\tc""#);
+        assert_eq!(Json::from("\u{0}\u{1b}\u{1f}").to_string(), r#""\u0000\u001b\u001f""#);
+        // Only control characters are escaped
+        assert_eq!(Json::from("µs ✓ \u{7f}").to_string(), "\"µs ✓ \u{7f}\"");
+    }
+
+    #[test]
+    fn keys_are_escaped_too() {
+        assert_eq!(Json::object([("a\"b", Json::Null)]).to_string(), r#"{"a\"b":null}"#);
+    }
+
+    #[test]
+    fn non_finite_floats_are_null() {
+        assert_eq!(Json::from(f64::NAN).to_string(), "null");
+        assert_eq!(Json::from(f64::INFINITY).to_string(), "null");
+        assert_eq!(Json::from(f64::NEG_INFINITY).to_string(), "null");
+        assert_eq!(Json::from(1.5).to_string(), "1.5");
+    }
+
+    #[test]
+    fn nested_values_keep_their_order() {
+        let mut event = Json::object([("event", "read".into()), ("bytes", vec![1u8, 2].into())]);
+        event.push("error", None::<&str>);
+        event.push("ok", true);
+        assert_eq!(event.to_string(), r#"{"event":"read","bytes":[1,2],"error":null,"ok":true}"#);
+        let mut array = Json::from(Vec::<u8>::new());
+        array.push("ignored", 1);
+        assert_eq!(array.to_string(), "[]");
+    }
+}
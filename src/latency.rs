@@ -0,0 +1,43 @@
+//! Round-trip latency statistics: the time from writing an output report to
+//! receiving its input report, aggregated per command.
+
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Kept sorted so percentiles are a lookup.
+    samples: Vec<Duration>,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, latency: Duration) {
+        let pos = self.samples.partition_point(|&s| s <= latency);
+        self.samples.insert(pos, latency);
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.first().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.last().copied()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let total: Duration = self.samples.iter().sum();
+        (!self.samples.is_empty()).then(|| total / self.samples.len() as u32)
+    }
+
+    /// Nearest-rank percentile, `p` in 0..=100.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let rank = (p / 100.0 * self.samples.len() as f64).ceil() as usize;
+        Some(self.samples[rank.clamp(1, self.samples.len()) - 1])
+    }
+}
@@ -0,0 +1,534 @@
+//! Device discovery, retrying open and report I/O for HID devices.
+//!
+//! The `hid-cmd` binary is a thin CLI over this crate; other programs can use
+//! [`HidSession`] directly instead of shelling out to it.
+//!
+//! ```no_run
+//! use hid_cmd::{HidSession, RetryPolicy};
+//!
+//! let api = hidapi::HidApi::new()?;
+//! let session = HidSession::open(&api, 0x1234, 0x5678, &RetryPolicy::default())?;
+//! session.send_report(&[0x01, 0x02])?;
+//! let mut buf = [0u8; hid_cmd::HID_REPORT_SIZE];
+//! let len = session.read_report(&mut buf)?;
+//! println!("{}", hex::encode(&buf[..len]));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod audit;
+pub mod bench;
+pub mod compare;
+pub mod config;
+pub mod crashdump;
+pub mod crc;
+pub mod defmt;
+mod demangle;
+pub mod descriptor;
+mod dwarf;
+pub mod duplex;
+mod elf;
+pub mod environment;
+pub mod exchange;
+pub mod framing;
+pub mod hexfmt;
+pub mod json;
+pub mod latency;
+pub mod loopback;
+pub mod mock;
+pub mod passthrough;
+pub mod payload;
+pub mod pattern;
+pub mod pcapng;
+pub mod power;
+pub mod profile;
+pub mod rtt;
+pub mod script;
+mod sha256;
+pub mod state;
+pub mod target_test;
+pub mod timeline;
+pub mod timestamp;
+pub mod trace;
+pub mod transcript;
+pub mod transfer;
+pub mod transport;
+pub mod uhid;
+pub mod usages;
+pub mod usb;
+
+pub use hidapi;
+
+use hidapi::{DeviceInfo, HidApi, HidDevice, HidError};
+use transport::HidTransport;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default report length; shorter payloads are zero-padded to it.
+pub const HID_REPORT_SIZE: usize = 64;
+/// Usage page preferred when a device exposes several interfaces.
+pub const VENDOR_PAGE: u16 = 0xFF42;
+pub const MAX_RETRIES: u32 = 3;
+pub const RETRY_DELAY_MS: u64 = 100;
+/// How long a candidate interface gets to answer a probe handshake.
+pub const PROBE_TIMEOUT_MS: u64 = 250;
+
+/// How often and how patiently to retry opening a busy device.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: MAX_RETRIES,
+            delay: Duration::from_millis(RETRY_DELAY_MS),
+        }
+    }
+}
+
+/// How the device is opened with respect to other processes.
+///
+/// - macOS: `exclusive` seizes the device from the OS and other apps, `shared` does not.
+/// - Windows: hidapi always opens with read/write sharing, so only `shared` is possible.
+/// - Linux: hidraw nodes are always shared; `exclusive` would require unbinding the
+///   kernel driver, which this tool does not do.
+///
+/// `auto` keeps the platform default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OpenMode {
+    Shared,
+    Exclusive,
+    Auto,
+}
+
+/// Configure `api` so that subsequently opened devices use `mode`.
+#[cfg(target_os = "macos")]
+pub fn apply_open_mode(api: &HidApi, mode: OpenMode) -> anyhow::Result<()> {
+    match mode {
+        OpenMode::Shared => api.set_open_exclusive(false),
+        OpenMode::Exclusive => api.set_open_exclusive(true),
+        OpenMode::Auto => {}
+    }
+    Ok(())
+}
+
+/// Configure `api` so that subsequently opened devices use `mode`.
+#[cfg(not(target_os = "macos"))]
+pub fn apply_open_mode(_api: &HidApi, mode: OpenMode) -> anyhow::Result<()> {
+    if mode == OpenMode::Exclusive {
+        return Err(anyhow::anyhow!(
+            "Exclusive open mode is not supported on this platform; devices are always opened shared"));
+    }
+    Ok(())
+}
+
+/// Criteria for picking devices out of the enumeration; unset fields match anything.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceFilter {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub usage_page: Option<u16>,
+    pub usage: Option<u16>,
+    /// USB interface number, to pick one HID interface of a composite device.
+    pub interface: Option<i32>,
+    /// Exact serial number string, to tell identical devices apart.
+    pub serial: Option<String>,
+    /// Exact platform path as reported by hidapi, e.g. `/dev/hidraw3`.
+    pub path: Option<String>,
+    /// Case-insensitive substring of the manufacturer string.
+    pub manufacturer: Option<String>,
+}
+
+impl DeviceFilter {
+    /// Match every interface of `vid:pid`.
+    pub fn vid_pid(vid: u16, pid: u16) -> Self {
+        DeviceFilter { vid: Some(vid), pid: Some(pid), ..DeviceFilter::default() }
+    }
+
+    pub fn matches(&self, d: &DeviceInfo) -> bool {
+        self.vid.is_none_or(|vid| d.vendor_id() == vid) &&
+            self.pid.is_none_or(|pid| d.product_id() == pid) &&
+            self.usage_page.is_none_or(|page| d.usage_page() == page) &&
+            self.usage.is_none_or(|usage| d.usage() == usage) &&
+            self.interface.is_none_or(|interface| d.interface_number() == interface) &&
+            self.serial.as_ref().is_none_or(|serial| d.serial_number() == Some(serial.as_str())) &&
+            self.path.as_ref().is_none_or(|path| d.path().to_str() == Ok(path.as_str())) &&
+            self.manufacturer.as_ref().is_none_or(|wanted| {
+                d.manufacturer_string()
+                    .is_some_and(|m| m.to_lowercase().contains(&wanted.to_lowercase()))
+            })
+    }
+}
+
+/// All enumerated HID interfaces matching `filter`.
+pub fn list_devices<'a>(api: &'a HidApi, filter: &'a DeviceFilter) -> impl Iterator<Item = &'a DeviceInfo> {
+    api.device_list().filter(move |d| filter.matches(d))
+}
+
+/// Find the interface matching `filter`, preferring one on `vendor_page` unless the
+/// filter names a usage page itself.
+pub fn find_device<'a>(api: &'a HidApi, filter: &DeviceFilter, vendor_page: u16) -> Option<&'a DeviceInfo> {
+    api.device_list()
+        .find(|d| filter.matches(d) &&
+                  (filter.usage_page.is_some() || d.usage_page() == vendor_page))
+        .or_else(|| {
+            // Fallback to any interface if vendor page not found
+            api.device_list().find(|d| filter.matches(d))
+        })
+}
+
+/// Open the interface matching `filter` (see [`find_device`]), retrying according
+/// to `policy` while the device is busy; see [`open_with_retry`].
+pub fn open_device_with_retry(
+    api: &HidApi,
+    filter: &DeviceFilter,
+    vendor_page: u16,
+    policy: &RetryPolicy,
+    on_retry: impl FnMut(u32, &HidError),
+) -> anyhow::Result<HidDevice> {
+    let device_info = find_device(api, filter, vendor_page)
+        .ok_or_else(|| anyhow::anyhow!("Device not found"))?;
+    open_with_retry(policy, on_retry, || api.open_path(device_info.path()))
+}
+
+/// Call `open` until it succeeds, up to `policy.max_retries` more times after
+/// the first failure, waiting `policy.delay` in between. Opens anything: a
+/// hidapi device, or a [`HidTransport`] some other way.
+///
+/// `on_retry` is called with the failed attempt number (starting at 1) and its error
+/// before each wait.
+pub fn open_with_retry<T, E: std::fmt::Display>(
+    policy: &RetryPolicy,
+    mut on_retry: impl FnMut(u32, &E),
+    mut open: impl FnMut() -> Result<T, E>,
+) -> anyhow::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match open() {
+            Ok(device) => return Ok(device),
+            Err(e) if attempt < policy.max_retries => {
+                attempt += 1;
+                on_retry(attempt, &e);
+                thread::sleep(policy.delay);
+            }
+            Err(e) => return Err(anyhow::anyhow!("Failed to open device after {} retries: {}", policy.max_retries, e)),
+        }
+    }
+}
+
+/// A report to send to each candidate interface and the response prefix that
+/// identifies the right one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProbeHandshake {
+    pub probe: Vec<u8>,
+    pub expect: Vec<u8>,
+}
+
+impl ProbeHandshake {
+    /// Parse `<probe hex>:<expected response prefix hex>`.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (probe, expect) = s.split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Probe handshake must be <hex>:<expect>"))?;
+        let probe = hex::decode(probe)?;
+        let expect = hex::decode(expect)?;
+        if probe.is_empty() || expect.is_empty() {
+            return Err(anyhow::anyhow!("Probe and expected response must not be empty"));
+        }
+        Ok(ProbeHandshake { probe, expect })
+    }
+}
+
+/// Open the interface matching `filter` that answers `handshake`.
+///
+/// Each candidate is opened in turn, sent the probe and given up to `timeout` to
+/// return an input report starting with the expected bytes. Interfaces that fail
+/// to open or answer are skipped.
+///
+/// The probe is a whole output report, ID first, padded to `report_size`
+/// bytes. Without one, each candidate's report descriptor gives the length of
+/// the probe's report, falling back to [`HID_REPORT_SIZE`].
+///
+/// `on_probe` is called with each candidate the probe was written to, the
+/// report and the result of the write, so that every write can be audited.
+pub fn open_device_by_probe(
+    api: &HidApi,
+    filter: &DeviceFilter,
+    handshake: &ProbeHandshake,
+    report_size: Option<usize>,
+    timeout: Duration,
+    mut on_probe: impl FnMut(&DeviceInfo, &[u8], &anyhow::Result<usize>),
+) -> anyhow::Result<HidDevice> {
+    let candidates = api.device_list().filter(|d| filter.matches(d));
+    let mut tried = 0;
+
+    for info in candidates {
+        tried += 1;
+        let Ok(device) = api.open_path(info.path()) else {
+            continue;
+        };
+        let sizes = HidTransport::report_descriptor(&device).map(|desc| descriptor::report_sizes(&desc)).unwrap_or_default();
+        let probe_size = report_size.unwrap_or_else(|| {
+            // Descriptor sizes leave out the report ID byte
+            sizes.output_size(handshake.probe[0]).or(sizes.max_output_size()).map_or(HID_REPORT_SIZE, |n| n + 1)
+        });
+        let probe = pad_report(&handshake.probe, probe_size);
+        let written = HidTransport::write(&device, &probe);
+        on_probe(info, &probe, &written);
+        if written.is_err() {
+            continue;
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0u8; sizes.max_input_size().map_or(HID_REPORT_SIZE, |n| n + 1).max(probe_size)];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match HidTransport::read(&device, &mut buf, Some(remaining)) {
+                Ok(len) if len > 0 => {
+                    if buf[..len].starts_with(&handshake.expect) {
+                        return Ok(device);
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    if tried == 0 {
+        return Err(anyhow::anyhow!("Device not found"));
+    }
+    Err(anyhow::anyhow!("None of the {} interface(s) answered the probe handshake", tried))
+}
+
+/// Zero-pad (or truncate) `data` to a `len`-byte output report.
+pub fn pad_report(data: &[u8], len: usize) -> Vec<u8> {
+    let mut report = vec![0u8; len];
+    let n = data.len().min(len);
+    report[..n].copy_from_slice(&data[..n]);
+    report
+}
+
+/// Build an output report for `report_id`: the ID byte followed by `payload`
+/// zero-padded to `size` bytes.
+///
+/// Use report ID 0 for devices without numbered reports; hidapi drops it before
+/// sending, so the device still receives a full-size report.
+pub fn numbered_report(report_id: u8, payload: &[u8], size: usize) -> Vec<u8> {
+    let mut report = Vec::with_capacity(size + 1);
+    report.push(report_id);
+    report.extend_from_slice(&pad_report(payload, size));
+    report
+}
+
+/// Parse a duration such as `250ms`, `2s` or `500us`; a bare number is
+/// milliseconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 1e-3)
+    } else if let Some(us) = s.strip_suffix("us") {
+        (us, 1e-6)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1.0)
+    } else {
+        (s, 1e-3)
+    };
+    match number.trim().parse::<f64>() {
+        // try_from also turns away durations too long to represent
+        Ok(n) if n >= 0.0 => Duration::try_from_secs_f64(n * scale).map_err(|_| format!("invalid duration '{}'", s)),
+        _ => Err(format!("invalid duration '{}'", s)),
+    }
+}
+
+/// Check that an input report belongs to `report_id` and return its payload.
+///
+/// For report ID 0 (unnumbered reports) hidapi delivers no ID byte, so the report
+/// is returned as is. On mismatch the ID that was actually received is returned.
+pub fn strip_report_id(report: &[u8], report_id: u8) -> Result<&[u8], u8> {
+    if report_id == 0 {
+        return Ok(report);
+    }
+    match report.split_first() {
+        Some((&id, payload)) if id == report_id => Ok(payload),
+        Some((&id, _)) => Err(id),
+        None => Err(0),
+    }
+}
+
+/// An open HID device.
+pub struct HidSession {
+    transport: Box<dyn HidTransport>,
+    report_size: usize,
+}
+
+impl HidSession {
+    /// Open `vid:pid`, preferring its vendor-page interface.
+    pub fn open(api: &HidApi, vid: u16, pid: u16, policy: &RetryPolicy) -> anyhow::Result<Self> {
+        let filter = DeviceFilter::vid_pid(vid, pid);
+        let device = open_device_with_retry(api, &filter, VENDOR_PAGE, policy, |_, _| {})?;
+        Ok(HidSession::from_device(device))
+    }
+
+    /// Wrap a device that was opened some other way.
+    pub fn from_device(device: HidDevice) -> Self {
+        HidSession::from_transport(device)
+    }
+
+    /// Run the session over another transport, such as a
+    /// [`MockTransport`](mock::MockTransport).
+    pub fn from_transport(transport: impl HidTransport + 'static) -> Self {
+        HidSession { transport: Box::new(transport), report_size: HID_REPORT_SIZE }
+    }
+
+    /// Pad output reports to `report_size` bytes instead of [`HID_REPORT_SIZE`].
+    pub fn with_report_size(mut self, report_size: usize) -> Self {
+        self.report_size = report_size;
+        self
+    }
+
+    pub fn report_size(&self) -> usize {
+        self.report_size
+    }
+
+    /// The transport the session runs over, for code that works on any.
+    pub fn transport(&self) -> &dyn HidTransport {
+        &*self.transport
+    }
+
+    /// The underlying hidapi handle, for operations the session doesn't wrap;
+    /// `None` for other transports.
+    pub fn device(&self) -> Option<&HidDevice> {
+        self.transport.hid_device()
+    }
+
+    /// hidapi's information about the device.
+    pub fn device_info(&self) -> anyhow::Result<DeviceInfo> {
+        let device = self.device().ok_or_else(|| anyhow::anyhow!("The session has no hidapi device to describe"))?;
+        Ok(device.get_device_info()?)
+    }
+
+    /// Write `report` as is, without padding, report ID first.
+    pub fn write_report(&self, report: &[u8]) -> anyhow::Result<usize> {
+        self.transport.write(report)
+    }
+
+    /// Write `data` as one output report, padded to the session's report size.
+    ///
+    /// The first byte is the report ID (0 for devices without numbered reports).
+    pub fn send_report(&self, data: &[u8]) -> anyhow::Result<usize> {
+        self.transport.write(&pad_report(data, self.report_size))
+    }
+
+    /// Write `payload` as output report `report_id`, padding the payload to the
+    /// session's report size; see [`numbered_report`].
+    pub fn send_numbered_report(&self, report_id: u8, payload: &[u8]) -> anyhow::Result<usize> {
+        self.transport.write(&numbered_report(report_id, payload, self.report_size))
+    }
+
+    /// The device's raw report descriptor.
+    pub fn report_descriptor(&self) -> anyhow::Result<Vec<u8>> {
+        self.transport.report_descriptor()
+    }
+
+    /// Report lengths declared by the report descriptor.
+    pub fn report_sizes(&self) -> anyhow::Result<descriptor::ReportSizes> {
+        Ok(descriptor::report_sizes(&self.report_descriptor()?))
+    }
+
+    /// The report ID to send output reports with, from the report descriptor:
+    /// the ID of the first output report on devices with numbered reports, 0 otherwise.
+    pub fn detect_output_report_id(&self) -> anyhow::Result<u8> {
+        let desc = self.report_descriptor()?;
+        if !descriptor::uses_report_ids(&desc) {
+            return Ok(0);
+        }
+        descriptor::first_output_report_id(&desc)
+            .ok_or_else(|| anyhow::anyhow!("Device uses numbered reports but declares no output report"))
+    }
+
+    /// Block until an input report arrives and return its length.
+    pub fn read_report(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        self.transport.read(buf, None)
+    }
+
+    /// Wait at most `timeout` for an input report; returns 0 if none arrived.
+    pub fn read_report_timeout(&self, buf: &mut [u8], timeout: Duration) -> anyhow::Result<usize> {
+        self.transport.read(buf, Some(timeout))
+    }
+
+    /// Send a feature report. `data[0]` is the report ID and the rest is sent as-is,
+    /// without padding, since feature reports usually have exact lengths.
+    pub fn send_feature_report(&self, data: &[u8]) -> anyhow::Result<()> {
+        self.transport.send_feature_report(data)
+    }
+
+    /// Read feature report `report_id` into `buf`.
+    ///
+    /// On return `buf[0]` holds the report ID; the returned length includes it.
+    pub fn get_feature_report(&self, report_id: u8, buf: &mut [u8]) -> anyhow::Result<usize> {
+        if buf.is_empty() {
+            return Err(anyhow::anyhow!("Feature report buffer must hold at least the report ID"));
+        }
+        buf[0] = report_id;
+        self.transport.get_feature_report(buf)
+    }
+
+    /// Close the device. Dropping the session does the same.
+    pub fn close(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("500us"), Ok(Duration::from_micros(500)));
+        assert_eq!(parse_duration("40"), Ok(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_unrepresentable() {
+        assert!(parse_duration("1e30s").is_err());
+        assert!(parse_duration("inf").is_err());
+        assert!(parse_duration("NaN").is_err());
+        assert!(parse_duration("-1ms").is_err());
+    }
+
+    #[test]
+    fn open_retries_until_success() {
+        let policy = RetryPolicy { max_retries: 3, delay: Duration::ZERO };
+        let mock = mock::MockTransport::new();
+        let mut failures = 2;
+        let mut retries = Vec::new();
+        let session = open_with_retry(&policy, |attempt, e: &String| retries.push((attempt, e.clone())), || {
+            if failures > 0 {
+                failures -= 1;
+                return Err("busy".to_string());
+            }
+            Ok(HidSession::from_transport(mock.clone()))
+        }).unwrap();
+        assert_eq!(retries, [(1, "busy".to_string()), (2, "busy".to_string())]);
+
+        session.send_report(&[0x00, 0x01]).unwrap();
+        assert_eq!(mock.written().len(), 1);
+    }
+
+    #[test]
+    fn open_gives_up_after_max_retries() {
+        let policy = RetryPolicy { max_retries: 2, delay: Duration::ZERO };
+        let mut attempts = 0;
+        let mut retries = 0;
+        let result: anyhow::Result<()> = open_with_retry(&policy, |_, _| retries += 1, || {
+            attempts += 1;
+            Err(format!("busy {}", attempts))
+        });
+        assert_eq!(result.unwrap_err().to_string(), "Failed to open device after 2 retries: busy 3");
+        assert_eq!((attempts, retries), (3, 2));
+    }
+}
@@ -0,0 +1,159 @@
+//! Echo self-test for `hid-cmd loopback`: random payloads that the device is
+//! expected to send back, possibly moved or transformed.
+
+use crate::exchange::{self, ReportFormat};
+use crate::{numbered_report, strip_report_id, HidSession};
+use std::time::Instant;
+
+/// Where and how the device echoes a payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Echo {
+    /// Position of the echo in the response payload.
+    pub offset: usize,
+    /// Added to every byte, wrapping, by the device.
+    pub add: u8,
+}
+
+impl Echo {
+    /// The response bytes expected for `payload`, from [`offset`](Self::offset) on.
+    pub fn expected(&self, payload: &[u8]) -> Vec<u8> {
+        payload.iter().map(|b| b.wrapping_add(self.add)).collect()
+    }
+
+    /// Positions in `payload` whose echo in `response` is wrong or missing.
+    pub fn mismatches(&self, payload: &[u8], response: &[u8]) -> Vec<usize> {
+        let echoed = response.get(self.offset..).unwrap_or_default();
+        self.expected(payload).iter()
+            .enumerate()
+            .filter(|&(i, expected)| echoed.get(i) != Some(expected))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// xorshift64*, which is plenty for test data and reproducible from its seed.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The all-zero state would only ever produce zeros
+        Rng(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    /// A seed from the clock, for runs that don't give one.
+    pub fn seed_from_time() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Payloads drawn for one test before giving up when the deny-list keeps
+/// rejecting them.
+pub const MAX_DRAWS: usize = 100;
+
+/// A loopback run: `count` random payloads of `length` bytes from `seed`, each
+/// sent as output report `report_id` and expected back on the same ID.
+#[derive(Clone, Copy, Debug)]
+pub struct Loopback {
+    pub echo: Echo,
+    pub length: usize,
+    pub count: u32,
+    pub seed: u64,
+    pub report_id: u8,
+}
+
+/// What became of one payload.
+#[derive(Debug)]
+pub enum Outcome {
+    Echoed,
+    /// No response on the payload's report ID within the response timeout.
+    Lost,
+    /// The response isn't a valid frame.
+    BadFrame(anyhow::Error),
+    /// The echo is wrong at `positions` of the payload.
+    Mismatch { expected: Vec<u8>, echoed: Vec<u8>, positions: Vec<usize> },
+}
+
+/// Told about every report, like an [`exchange::Observer`], and how each
+/// payload fared.
+pub trait Observer: exchange::Observer {
+    /// Payloads are numbered from 1.
+    fn outcome(&mut self, payload: u32, outcome: &Outcome);
+}
+
+/// Payloads that didn't come back right.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub mismatched: u32,
+    pub lost: u32,
+}
+
+impl Loopback {
+    /// Send the payloads one at a time, waiting for each echo. Payloads that
+    /// `denied` rejects are drawn again, so that random data never happens to
+    /// be a destructive command. Fails if a write fails.
+    pub fn run(&self, session: &HidSession, format: &ReportFormat, denied: impl Fn(&[u8]) -> bool, observer: &mut impl Observer) -> anyhow::Result<Summary> {
+        let mut rng = Rng::new(self.seed);
+        let mut payload = vec![0u8; self.length];
+        let mut summary = Summary::default();
+        for i in 1..=self.count {
+            let mut draws = 0;
+            loop {
+                rng.fill(&mut payload);
+                if !denied(&payload) {
+                    break;
+                }
+                draws += 1;
+                if draws == MAX_DRAWS {
+                    return Err(anyhow::anyhow!("The profile's deny-list rejects almost every {}-byte payload", self.length));
+                }
+            }
+            let report = numbered_report(self.report_id, &format.framing.encode(&payload)?, session.report_size());
+            exchange::write(session.transport(), &report, observer).map_err(|_| anyhow::anyhow!("Sending payload {} failed", i))?;
+
+            // Reports on other IDs aren't answers to the test
+            let deadline = Instant::now() + format.response_timeout();
+            let response = exchange::read_until(session.transport(), format.input_buffer, deadline, observer, |report| {
+                strip_report_id(report, self.report_id).is_ok()
+            })?;
+            let outcome = match response {
+                None => Outcome::Lost,
+                Some(response) => match format.framing.decode(&response.report[1..]) {
+                    Err(e) => Outcome::BadFrame(e),
+                    Ok(response) => {
+                        let positions = self.echo.mismatches(&payload, &response);
+                        if positions.is_empty() {
+                            Outcome::Echoed
+                        } else {
+                            let echoed = response.get(self.echo.offset..).unwrap_or_default().to_vec();
+                            Outcome::Mismatch { expected: self.echo.expected(&payload), echoed, positions }
+                        }
+                    }
+                },
+            };
+            match outcome {
+                Outcome::Echoed => {}
+                Outcome::Lost => summary.lost += 1,
+                Outcome::BadFrame(_) | Outcome::Mismatch { .. } => summary.mismatched += 1,
+            }
+            observer.outcome(i, &outcome);
+        }
+        Ok(summary)
+    }
+}
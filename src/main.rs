@@ -1,158 +1,3937 @@
-use clap::Parser;
+mod reporter;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use hid_cmd::audit::{self, AuditLog};
+use hid_cmd::bench::Bench;
+use hid_cmd::compare::{self, Tolerances};
+use hid_cmd::crashdump;
+use hid_cmd::defmt;
+use hid_cmd::descriptor;
+use hid_cmd::duplex::{self, Duplex, Event};
+use hid_cmd::environment::{self, Environment};
+use hid_cmd::exchange::{self, ReportFormat, EXPECT_TIMEOUT_MS};
+use hid_cmd::framing::Framing;
+use hid_cmd::json::Json;
+use hid_cmd::latency::LatencyStats;
+use hid_cmd::loopback::{self, Echo, Loopback, Outcome, Rng};
+use hid_cmd::passthrough::{self, InputMap};
+use hid_cmd::payload::DataFormat;
+use hid_cmd::pattern::HexPattern;
+use hid_cmd::pcapng::UsbCapture;
+use hid_cmd::power;
+use hid_cmd::profile::{ErrorCode, Profile, ResponseStatus, Role};
+use hid_cmd::rtt;
+use hid_cmd::script::{Op, Script, Step};
+use hid_cmd::state::{self, DeviceState};
+use hid_cmd::target_test::{self, Case, Record, Status};
+use hid_cmd::timeline::{self, Action, Recorder, Timeline};
+use hid_cmd::trace::{ItmDemux, Pushed, Stream};
+use hid_cmd::transfer::{self, Reassembler};
+use hid_cmd::uhid;
+use hid_cmd::usages::{InputDecoder, Layout};
+use hid_cmd::transcript::Transcript;
+use hid_cmd::usb;
+use hid_cmd::{
+    DeviceFilter, HidSession, OpenMode, ProbeHandshake, RetryPolicy, HID_REPORT_SIZE, MAX_RETRIES,
+    PROBE_TIMEOUT_MS, RETRY_DELAY_MS, VENDOR_PAGE,
+};
 use hidapi::HidApi;
-use std::time::Duration;
-use std::thread;
+use reporter::{Identity, Reporter};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Write a Markdown transcript of the session (reports, timing, errors) to this file
+    #[arg(long, global = true, value_name = "FILE")]
+    transcript: Option<PathBuf>,
+
+    /// Device profile (TOML) with device defaults, named commands and error codes
+    #[arg(long, global = true, value_name = "FILE")]
+    profile: Option<PathBuf>,
+
+    /// Send data even if it matches the profile's deny-list
+    #[arg(long, global = true)]
+    i_know_what_im_doing: bool,
+
+    /// Operator badge ID to attribute the session to [default: $HID_CMD_OPERATOR]
+    #[arg(long, global = true, value_name = "ID")]
+    operator: Option<String>,
+
+    /// Record every report sent and received in a pcapng file for Wireshark
+    #[arg(long, global = true, value_name = "FILE")]
+    capture: Option<PathBuf>,
+
+    /// Sample a bench power meter during the session: tcp:<host>[:<port>] or serial:<path>
+    #[arg(long, global = true, value_name = "METER", requires = "power_log")]
+    power_meter: Option<String>,
+
+    /// SCPI query that returns one power meter reading
+    #[arg(long, global = true, value_name = "QUERY", default_value = "MEAS:CURR?")]
+    power_query: String,
+
+    /// Time between power meter readings
+    #[arg(long, global = true, value_parser = hid_cmd::parse_duration, default_value = "10ms")]
+    power_interval: Duration,
+
+    /// Write power meter readings and all reports to this CSV timeline
+    #[arg(long, global = true, value_name = "FILE", requires = "power_meter")]
+    power_log: Option<PathBuf>,
+
+    /// Tag the session with environmental metadata, e.g. temperature=85C; repeatable
+    #[arg(long = "env", global = true, value_parser = environment::parse_tag, value_name = "KEY=VALUE")]
+    env: Vec<(String, String)>,
+
+    /// Command printing key=value environment tags, run at the start and at every script `env` step
+    #[arg(long, global = true, value_name = "CMD")]
+    env_command: Option<String>,
+
+    /// Append every write to this tamper-evident audit log
+    #[arg(long, global = true, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Act as operator, engineer or factory, up to what the profile allows you
+    #[arg(long, global = true, value_parser = parse_role, value_name = "ROLE")]
+    role: Option<Role>,
+
+    /// Print machine-readable JSON Lines on stdout instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Language for profile error descriptions, e.g. "de" [default: from $LANG]
+    #[arg(long, global = true, value_name = "LANG")]
+    lang: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send an output report and read the response
+    Send(SendArgs),
+    /// Read a single input report
+    Read(ReadArgs),
+    /// Keep reading input reports, optionally after sending one
+    Monitor(MonitorArgs),
+    /// Like monitor --reconnect, but also wait for the device to appear first
+    Watch(MonitorArgs),
+    /// Read a feature report
+    FeatureGet(FeatureGetArgs),
+    /// Send a feature report
+    FeatureSet(FeatureSetArgs),
+    /// Poll a feature report and print its fields whenever they change
+    FeatureWatch(FeatureWatchArgs),
+    /// Show details of the device that would be opened
+    Info(InfoArgs),
+    /// List connected HID devices and their interfaces
+    List(ListArgs),
+    /// Show composite devices as a tree of interfaces, endpoints and HID collections
+    Topology(TopologyArgs),
+    /// Send a named command from the profile, or `cmd list` to show them
+    Cmd(CmdArgs),
+    /// Explain a device status code from the profile's error dictionary, or list them all
+    Errors(ErrorsArgs),
+    /// Run a script of send/expect/read/sleep/loop lines against the device
+    Run(RunArgs),
+    /// Send reports at fixed offsets from the start, read from a timeline file
+    Timeline(TimelineArgs),
+    /// Turn local keyboard and mouse input into output reports (Linux evdev)
+    Passthrough(PassthroughArgs),
+    /// Measure round-trip latency and throughput of a request and its response
+    Bench(BenchArgs),
+    /// Run a script against a golden and a suspect unit and diff their responses
+    Compare(CompareArgs),
+    /// Send random payloads and check that the device echoes them back
+    Loopback(LoopbackArgs),
+    /// Flash and test firmware images to find the first one that fails a script
+    BisectFirmware(BisectFirmwareArgs),
+    /// Like `monitor`, recording the traffic in a pcapng file
+    Capture(CaptureArgs),
+    /// Send reports typed on stdin and record them, with their timing, for `replay`
+    Record(RecordArgs),
+    /// Resend a recorded session, keeping the time between reports
+    Replay(ReplayArgs),
+    /// Run the firmware's on-target unit tests and collect their results, e.g. as JUnit
+    TargetTest(TargetTestArgs),
+    /// Stream trace or coverage data from the firmware into files, with `trace pull`
+    Trace(TraceArgs),
+    /// Print the firmware's RTT log output and send typed lines to it, over HID
+    Rtt(RttArgs),
+    /// Create a virtual HID device (Linux uhid) and script its input reports
+    Emulate(EmulateArgs),
+    /// Fetch the firmware's crash dump and symbolicate it (`crashdump pull`), or show a saved one
+    Crashdump(CrashdumpArgs),
+    /// Check that an audit log's hash chain is intact
+    AuditVerify(AuditVerifyArgs),
+    /// Send a file as a sequence of framed output reports
+    SendFile(SendFileArgs),
+    /// Receive a file sent as framed input reports
+    RecvFile(RecvFileArgs),
+}
+
+/// Options shared by every command that opens a device.
+#[derive(Clone, clap::Args)]
+struct DeviceArgs {
+    /// Vendor ID of the HID device (hexadecimal) [default: from --profile]
+    #[arg(short, long, value_parser = parse_hex)]
+    vid: Option<u16>,
+
+    /// Product ID of the HID device (hexadecimal) [default: from --profile]
+    #[arg(short, long, value_parser = parse_hex)]
+    pid: Option<u16>,
+
+    /// Serial number of the device, to choose between identical devices
+    #[arg(long)]
+    serial: Option<String>,
+
+    /// USB interface number to open on a composite device
+    #[arg(long)]
+    interface: Option<i32>,
+
+    /// Usage page of the interface to open (hexadecimal) [default: prefer 0xff42]
+    #[arg(long, value_parser = parse_hex)]
+    usage_page: Option<u16>,
+
+    /// Usage of the interface to open (hexadecimal)
+    #[arg(long, value_parser = parse_hex)]
+    usage: Option<u16>,
+
+    /// Platform path of the interface to open, as shown by `list`
+    #[arg(long)]
+    path: Option<String>,
+
+    /// Number of times to retry if device is busy
+    #[arg(short = 'r', long, default_value_t = MAX_RETRIES)]
+    retries: u32,
+
+    /// Delay between retries in milliseconds
+    #[arg(short = 'd', long, default_value_t = RETRY_DELAY_MS)]
+    retry_delay: u64,
+
+    /// Whether other processes may use the device while it is open
+    #[arg(long, value_enum, default_value_t = OpenMode::Auto)]
+    open_mode: OpenMode,
+
+    /// Pick the interface that answers this probe: <hex>:<expected response prefix>
+    #[arg(long, value_parser = parse_probe, value_name = "HEX:EXPECT")]
+    probe_handshake: Option<ProbeHandshake>,
+
+    /// Milliseconds each interface gets to answer the probe handshake
+    #[arg(long, default_value_t = PROBE_TIMEOUT_MS)]
+    probe_timeout: u64,
+
+    /// Compare the device with its last known good state cached in DIR
+    #[arg(long, value_name = "DIR")]
+    state_cache: Option<PathBuf>,
+
+    /// Save the current state as the last known good one, even if it changed
+    #[arg(long)]
+    accept_state: bool,
+
+    /// Feature reports that are part of the cached state, from the profile
+    #[arg(skip)]
+    state_features: Vec<u8>,
+}
+
+/// Report ID handling for commands that send or read interrupt reports.
+#[derive(clap::Args)]
+struct ReportArgs {
+    /// Report ID to prepend to output reports and expect on input reports
+    /// (hexadecimal, or "auto" to read it from the report descriptor)
+    #[arg(long, value_parser = parse_report_id, value_name = "ID|auto")]
+    report_id: Option<ReportIdArg>,
+
+    /// Report length in bytes, or "auto" to read it from the report descriptor.
+    /// With --report-id this is the payload after the ID, otherwise the whole
+    /// report including its leading ID byte [default: 64]
+    #[arg(long, value_parser = parse_report_size, value_name = "BYTES|auto")]
+    report_size: Option<ReportSizeArg>,
+
+    /// Give up waiting for an input report after this many milliseconds and
+    /// exit with status 124 (monitor: stop once the device is idle this long)
+    #[arg(long, value_name = "MS", conflicts_with = "non_blocking")]
+    timeout: Option<u64>,
+
+    /// Only take input reports that have already arrived; same as --timeout 0
+    #[arg(long)]
+    non_blocking: bool,
+
+    /// Frame payloads within reports; anything but raw implies --report-id 0
+    /// unless one is given [default: raw]
+    #[arg(long, value_enum)]
+    framing: Option<Framing>,
+}
+
+#[derive(Clone, Copy)]
+enum ReportIdArg {
+    Id(u8),
+    Auto,
+}
+
+#[derive(Clone, Copy)]
+enum ReportSizeArg {
+    Bytes(usize),
+    Auto,
+}
+
+/// Exit status when no input report arrives within `--timeout`, as timeout(1) uses.
+const EXIT_TIMEOUT: u8 = 124;
+
+/// How often `--reconnect` and `watch` look for the device while it is gone.
+const RECONNECT_POLL_MS: u64 = 250;
+
+/// How long `target-test` waits for the next test record when no `--timeout`
+/// is given.
+const TARGET_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long `rtt` waits for the device to answer a poll or a write when no
+/// `--timeout` is given.
+const RTT_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+/// Returned when a read gives up under `--timeout` or `--non-blocking`, so that
+/// `main` can exit with [`EXIT_TIMEOUT`]. The reporter has already told the user.
+#[derive(Debug)]
+struct NoResponse;
+
+impl std::fmt::Display for NoResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("no input report received")
+    }
+}
+
+impl std::error::Error for NoResponse {}
+
+#[derive(clap::Args)]
+struct SendArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Data to send (hex string, padded to --report-size; with --report-id,
+    /// the payload after the ID)
+    #[arg(required_unless_present_any = ["data_file", "stdin"])]
+    data: Option<String>,
+
+    #[command(flatten)]
+    source: DataSource,
+
+    /// Read until a response matches this pattern (?? or ? for any byte or
+    /// nibble, /mask for bits), failing after --timeout
+    #[arg(long, value_parser = parse_pattern, value_name = "PATTERN")]
+    expect: Option<HexPattern>,
+
+    /// Send the report this many times, reading a response after each, and
+    /// print how many exchanges succeeded
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "N")]
+    repeat: Option<u32>,
+
+    /// Time from the start of one send to the next with --repeat, e.g. 10ms
+    /// (a bare number is milliseconds) [default: back to back]
+    #[arg(long, value_parser = hid_cmd::parse_duration, requires = "repeat", conflicts_with = "rate")]
+    interval: Option<Duration>,
+
+    /// Sends per second with --repeat, instead of --interval
+    #[arg(long, value_parser = parse_rate, requires = "repeat", value_name = "HZ")]
+    rate: Option<Duration>,
+
+    /// Send to every device matching the filter at once, and print each one's result
+    #[arg(long, conflicts_with_all = ["repeat", "serial", "path", "probe_handshake", "state_cache"])]
+    all_matching: bool,
+}
+
+/// Where the data of `send`, `feature-set` and `bench` comes from, when it
+/// isn't a hex argument.
+#[derive(clap::Args)]
+struct DataSource {
+    /// Read the data from this file instead
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["data", "stdin"])]
+    data_file: Option<PathBuf>,
+
+    /// Read the data from stdin instead
+    #[arg(long, conflicts_with = "data")]
+    stdin: bool,
+
+    /// Encoding of the data [default: hex for the argument, bin for --data-file and --stdin]
+    #[arg(long, value_enum)]
+    format: Option<DataFormat>,
+}
+
+impl DataSource {
+    /// The data, from `arg` or wherever this says, decoded.
+    fn load(&self, arg: Option<&str>) -> anyhow::Result<Vec<u8>> {
+        let (input, default, source) = if let Some(path) = &self.data_file {
+            let input = std::fs::read(path).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+            (input, DataFormat::Bin, path.display().to_string())
+        } else if self.stdin {
+            let mut input = Vec::new();
+            io::Read::read_to_end(&mut io::stdin(), &mut input).context("Cannot read data from stdin")?;
+            (input, DataFormat::Bin, "stdin".to_string())
+        } else {
+            (arg.unwrap_or_default().as_bytes().to_vec(), DataFormat::Hex, "data".to_string())
+        };
+        let format = self.format.unwrap_or(default);
+        format.decode(&input).with_context(|| format!("Cannot decode {} as {}", source, format.name()))
+    }
+
+    /// Refuse data from a file or stdin that is longer than one
+    /// `report_size`-byte report, rather than cutting it off; a hex argument
+    /// is still truncated to the report.
+    fn check_fits(&self, data: &[u8], report_size: usize) -> anyhow::Result<()> {
+        let source = match (&self.data_file, self.stdin) {
+            (Some(path), _) => path.display().to_string(),
+            (None, true) => "stdin".to_string(),
+            (None, false) => return Ok(()),
+        };
+        if data.len() > report_size {
+            return Err(anyhow::anyhow!("{} holds {} bytes, more than one {}-byte report; use send-file to send it in chunks",
+                source, data.len(), report_size));
+        }
+        Ok(())
+    }
+}
+
+#[derive(clap::Args)]
+struct ReadArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Read until an input report matches this pattern, failing after --timeout
+    #[arg(long, value_parser = parse_pattern, value_name = "PATTERN")]
+    expect: Option<HexPattern>,
+}
+
+#[derive(clap::Args)]
+struct MonitorArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Output report to send before reading (hex string)
+    #[arg(short, long)]
+    send: Option<String>,
+
+    /// Print read statistics every N milliseconds
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    stats_interval: Option<u64>,
+
+    /// When the device goes away, wait for it to come back and keep reading
+    /// (re-sending --send)
+    #[arg(long)]
+    reconnect: bool,
+
+    /// Also send each hex line typed on stdin while reading, until stdin ends
+    #[arg(long, conflicts_with = "stats_interval")]
+    interactive: bool,
+
+    /// Also show each input report's fields in words (keys, buttons, axes),
+    /// from the report descriptor
+    #[arg(long)]
+    decode: bool,
+
+    /// Keyboard layout to name keys by with --decode
+    #[arg(long, value_enum, default_value_t = Layout::Us, requires = "decode")]
+    layout: Layout,
+
+    /// Decode the input report payloads as a defmt log stream, with the
+    /// format strings from this firmware ELF file
+    #[arg(long, value_name = "PATH", conflicts_with = "decode")]
+    defmt_elf: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct FeatureGetArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    /// Report ID to read (hexadecimal, 0 for devices without numbered reports)
+    #[arg(value_parser = parse_hex_u8)]
+    report_id: u8,
+
+    /// Expected report length in bytes, excluding the report ID
+    #[arg(short, long, default_value_t = HID_REPORT_SIZE)]
+    length: usize,
+}
+
+#[derive(clap::Args)]
+struct FeatureSetArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    /// Report to send (hex string, report ID first; sent without padding)
+    #[arg(required_unless_present_any = ["data_file", "stdin"])]
+    data: Option<String>,
+
+    #[command(flatten)]
+    source: DataSource,
+}
+
+#[derive(clap::Args)]
+struct FeatureWatchArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    /// Report ID to poll (hexadecimal, 0 for devices without numbered reports)
+    #[arg(value_parser = parse_hex_u8)]
+    report_id: u8,
+
+    /// Expected report length in bytes, excluding the report ID
+    #[arg(short, long, default_value_t = HID_REPORT_SIZE)]
+    length: usize,
+
+    /// Time between reads, e.g. 500ms or 2s (a bare number is milliseconds)
+    #[arg(long, value_parser = hid_cmd::parse_duration, default_value = "500ms")]
+    interval: Duration,
+}
+
+#[derive(clap::Args)]
+struct InfoArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    /// Also dump the report descriptor, decoded item by item
+    #[arg(long)]
+    descriptor: bool,
+}
+
+#[derive(clap::Args)]
+struct ListArgs {
+    /// Only show devices with this Vendor ID (hexadecimal)
+    #[arg(short, long, value_parser = parse_hex)]
+    vid: Option<u16>,
+
+    /// Only show devices with this Product ID (hexadecimal)
+    #[arg(short, long, value_parser = parse_hex)]
+    pid: Option<u16>,
+
+    /// Only show interfaces on this usage page (hexadecimal)
+    #[arg(short = 'u', long, value_parser = parse_hex)]
+    usage_page: Option<u16>,
+
+    /// Only show this USB interface number of composite devices
+    #[arg(short, long)]
+    interface: Option<i32>,
+
+    /// Only show devices whose manufacturer contains this text (case-insensitive)
+    #[arg(short, long)]
+    manufacturer: Option<String>,
+
+    /// Also show every USB interface of the device (CCID, CDC, ...), not just the HID ones
+    #[arg(long)]
+    composite: bool,
+}
+
+#[derive(clap::Args)]
+struct CmdArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Command name from the profile's [commands] table, or "list"
+    name: String,
+
+    /// Parameters as name=value (decimal, or hexadecimal with 0x)
+    #[arg(value_parser = parse_param, value_name = "NAME=VALUE")]
+    params: Vec<(String, String)>,
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Script file, one operation per line
+    script: PathBuf,
+
+    /// Write the result to this JUnit XML file, as one test case
+    #[arg(long, value_name = "FILE")]
+    junit: Option<PathBuf>,
+
+    /// Test suite name in the JUnit report [default: the script's file name]
+    #[arg(long)]
+    suite: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct TimelineArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Timeline file of `t=<offset> send <hex>` entries
+    file: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct PassthroughArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Mapping from input events to report payloads
+    #[arg(long, value_name = "FILE")]
+    map: PathBuf,
+
+    /// evdev node to capture, e.g. /dev/input/event3; repeat for a keyboard
+    /// and a mouse
+    #[arg(long = "input", required = true, value_name = "DEVICE")]
+    inputs: Vec<PathBuf>,
+
+    /// evdev code of the key that stops the passthrough [default: 119, Pause]
+    #[arg(long, default_value_t = passthrough::KEY_PAUSE, hide_default_value = true, value_name = "CODE")]
+    kill_key: u16,
+
+    /// Start capturing without asking for confirmation
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(clap::Args)]
+struct BenchArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Request to send (hex string, framed like `send`)
+    #[arg(required_unless_present_any = ["data_file", "stdin"])]
+    data: Option<String>,
+
+    #[command(flatten)]
+    source: DataSource,
+
+    /// Round trips to measure
+    #[arg(short = 'n', long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    count: u32,
+
+    /// Round trips to run before measuring
+    #[arg(long, default_value_t = 10)]
+    warmup: u32,
+
+    /// Wait for a response matching this pattern [default: any input report]
+    #[arg(long, value_parser = parse_pattern, value_name = "PATTERN")]
+    expect: Option<HexPattern>,
+}
+
+#[derive(clap::Args)]
+struct CompareArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Serial number of the known good unit, or its platform path as shown by `list`
+    #[arg(long, value_name = "SERIAL|PATH")]
+    golden: String,
+
+    /// Serial number or platform path of the unit to check against it
+    #[arg(long, value_name = "SERIAL|PATH")]
+    suspect: String,
+
+    /// Let a field differ by up to N, e.g. "byte 3=2"; "*" sets it for every field
+    #[arg(long = "tolerance", value_parser = parse_tolerance, value_name = "FIELD=N")]
+    tolerances: Vec<(String, i64)>,
+
+    /// Script of stimuli, as for `run`
+    script: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct LoopbackArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Payloads to send
+    #[arg(short = 'n', long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    count: u32,
+
+    /// Payload length in bytes [default: as much as fits in a report]
+    #[arg(long)]
+    length: Option<usize>,
+
+    /// Position of the echo in the response payload
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+
+    /// The device adds this to every echoed byte, wrapping at 256
+    #[arg(long, default_value_t = 0)]
+    add: u8,
+
+    /// Seed for the payloads, to repeat an earlier run [default: from the clock]
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write the results to this JUnit XML file, one test case per payload
+    #[arg(long, value_name = "FILE")]
+    junit: Option<PathBuf>,
+
+    /// Test suite name in the JUnit report
+    #[arg(long, default_value = "loopback")]
+    suite: String,
+}
+
+#[derive(clap::Args)]
+struct BisectFirmwareArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Shell command that flashes one image; {} is replaced by its path,
+    /// e.g. "dfu-util -a 0 -D {}"
+    #[arg(long, value_name = "COMMAND")]
+    flash: String,
+
+    /// Script that passes on good builds and fails on bad ones, as for `run`
+    #[arg(long)]
+    script: PathBuf,
+
+    /// Time to wait after flashing before looking for the device, so the old
+    /// firmware isn't tested by mistake (a bare number is milliseconds)
+    #[arg(long, value_parser = hid_cmd::parse_duration, default_value = "2s")]
+    settle: Duration,
+
+    /// How long the device may take to re-enumerate after flashing
+    #[arg(long, value_parser = hid_cmd::parse_duration, default_value = "30s")]
+    boot_timeout: Duration,
+
+    /// Trust that the first image is good and the last bad instead of testing them
+    #[arg(long)]
+    skip_endpoints: bool,
+
+    /// Firmware images from oldest to newest; the first must be good and the
+    /// last bad
+    #[arg(required = true, num_args = 2.., value_name = "IMAGE")]
+    images: Vec<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct CaptureArgs {
+    #[command(flatten)]
+    monitor: MonitorArgs,
+
+    /// pcapng file to write
+    file: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct RecordArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Record the input reports received as well
+    #[arg(long)]
+    responses: bool,
+
+    /// Timeline file to write
+    file: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct ReplayArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Replay this many times faster than recorded, e.g. 0.5 for half speed
+    #[arg(long, value_parser = parse_speed, default_value = "1")]
+    speed: f64,
+
+    /// Timeline file written by `record`
+    file: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct TargetTestArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Report that starts the tests (hex, framed like `send`)
+    #[arg(long, default_value = "5401")]
+    start: String,
+
+    /// Write the results to this JUnit XML file
+    #[arg(long, value_name = "FILE")]
+    junit: Option<PathBuf>,
+
+    /// Test suite name in the JUnit report [default: the device's product string]
+    #[arg(long)]
+    suite: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct TraceArgs {
+    #[command(subcommand)]
+    command: TraceCommand,
+}
+
+#[derive(Subcommand)]
+enum TraceCommand {
+    /// Reassemble the trace chunks the firmware sends into a file, until the last one
+    Pull(TracePullArgs),
+}
+
+#[derive(clap::Args)]
+struct TracePullArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Where to write the trace
+    file: PathBuf,
+
+    /// Output report that asks the device to start tracing (hex string)
+    #[arg(long, value_name = "HEX")]
+    start: Option<String>,
+
+    /// Output report acknowledging each --window of chunks (hex string)
+    #[arg(long, value_name = "HEX")]
+    ack: Option<String>,
+
+    /// Chunks the firmware sends per acknowledgement
+    #[arg(long, default_value_t = 1, requires = "ack", value_parser = clap::value_parser!(u32).range(1..))]
+    window: u32,
+
+    /// Also split the trace, as ITM packets, into <FILE>.port<N> per stimulus port
+    #[arg(long)]
+    itm: bool,
+}
+
+#[derive(clap::Args)]
+struct RttArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// RTT buffer index, for both the up- and the down-buffer
+    #[arg(long, default_value_t = 0)]
+    channel: u8,
+
+    /// How often to poll the up-buffer while it is empty, e.g. 10ms
+    #[arg(long, value_parser = hid_cmd::parse_duration, default_value = "10ms")]
+    interval: Duration,
+}
+
+#[derive(clap::Args)]
+struct CrashdumpArgs {
+    #[command(subcommand)]
+    command: CrashdumpCommand,
+}
+
+#[derive(Subcommand)]
+enum CrashdumpCommand {
+    /// Receive the crash dump the firmware sends as file transfer chunks and show it
+    Pull(Box<CrashdumpPullArgs>),
+    /// Show a crash dump saved with `pull --save`
+    Show(CrashdumpShowArgs),
+}
+
+#[derive(clap::Args)]
+struct CrashdumpPullArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Output report that asks the device to send its crash dump (hex string)
+    #[arg(short, long)]
+    send: Option<String>,
+
+    /// Firmware ELF file, to name the functions and source lines of code addresses
+    #[arg(long, value_name = "PATH")]
+    elf: Option<PathBuf>,
+
+    /// Also write the crash dump as received to this file
+    #[arg(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct CrashdumpShowArgs {
+    /// Crash dump file
+    file: PathBuf,
+
+    /// Firmware ELF file, to name the functions and source lines of code addresses
+    #[arg(long, value_name = "PATH")]
+    elf: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct EmulateArgs {
+    /// Report descriptor of the virtual device
+    #[arg(long, value_name = "FILE")]
+    descriptor: PathBuf,
+
+    /// Encoding of the --descriptor file
+    #[arg(long, value_enum, default_value_t = DataFormat::Bin)]
+    descriptor_format: DataFormat,
+
+    /// Vendor ID to present (hexadecimal)
+    #[arg(short, long, value_parser = parse_hex)]
+    vid: u16,
+
+    /// Product ID to present (hexadecimal)
+    #[arg(short, long, value_parser = parse_hex)]
+    pid: u16,
+
+    /// Product name to present
+    #[arg(long, default_value = "hid-cmd virtual device")]
+    name: String,
+
+    /// Serial number to present
+    #[arg(long, default_value = "")]
+    serial: String,
+
+    /// Contents of feature report ID (hex, without the ID) until the host sets
+    /// it, e.g. 05=0102; repeatable
+    #[arg(long = "feature", value_parser = parse_feature, value_name = "ID=HEX")]
+    features: Vec<(u8, Vec<u8>)>,
+
+    /// Wait for host software to open the device before running the script
+    #[arg(long)]
+    wait_open: bool,
+
+    /// Keep the device after the script, until interrupted
+    #[arg(long)]
+    hold: bool,
+
+    /// Milliseconds `read` and `expect` wait for an output report from the host
+    #[arg(long, default_value_t = EXPECT_TIMEOUT_MS)]
+    timeout: u64,
+
+    /// Script to run as the device: `send` sends an input report, `read` and
+    /// `expect` wait for an output report [default: just answer the host]
+    script: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct SendFileArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// File to send
+    file: PathBuf,
+
+    /// After each chunk, wait for an input report matching this pattern
+    #[arg(long, value_parser = parse_pattern, value_name = "PATTERN")]
+    ack: Option<HexPattern>,
+
+    /// Pause between chunks, e.g. 5ms
+    #[arg(long, value_parser = hid_cmd::parse_duration, default_value = "0")]
+    delay: Duration,
+}
+
+#[derive(clap::Args)]
+struct RecvFileArgs {
+    #[command(flatten)]
+    device: DeviceArgs,
+
+    #[command(flatten)]
+    report: ReportArgs,
+
+    /// Where to write the received file
+    file: PathBuf,
+
+    /// Output report that asks the device to start sending (hex string)
+    #[arg(short, long)]
+    send: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct AuditVerifyArgs {
+    /// Audit log written with --audit-log or a profile's audit_log
+    file: PathBuf,
+
+    /// Head hash printed by an earlier check; fail if the log no longer reaches that entry
+    #[arg(long, value_name = "HASH")]
+    head: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct ErrorsArgs {
+    /// Status code to explain (decimal, or hexadecimal with 0x)
+    code: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct TopologyArgs {
+    /// Only show devices with this Vendor ID (hexadecimal)
+    #[arg(short, long, value_parser = parse_hex)]
+    vid: Option<u16>,
+
+    /// Only show devices with this Product ID (hexadecimal)
+    #[arg(short, long, value_parser = parse_hex)]
+    pid: Option<u16>,
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let s = s.trim_start_matches("0x");
+    u16::from_str_radix(s, 16).map_err(|e| e.to_string())
+}
+
+fn parse_probe(s: &str) -> Result<ProbeHandshake, String> {
+    ProbeHandshake::parse(s).map_err(|e| e.to_string())
+}
+
+fn parse_report_id(s: &str) -> Result<ReportIdArg, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(ReportIdArg::Auto);
+    }
+    parse_hex_u8(s).map(ReportIdArg::Id)
+}
+
+fn parse_report_size(s: &str) -> Result<ReportSizeArg, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(ReportSizeArg::Auto);
+    }
+    match s.parse::<usize>() {
+        Ok(0) => Err("report size must be at least 1".to_string()),
+        Ok(n) => Ok(ReportSizeArg::Bytes(n)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_param(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s.split_once('=').ok_or_else(|| format!("expected NAME=VALUE, got '{}'", s))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_tolerance(s: &str) -> Result<(String, i64), String> {
+    let (field, n) = s.rsplit_once('=').ok_or_else(|| format!("expected FIELD=N, got '{}'", s))?;
+    match n.trim().parse::<i64>() {
+        Ok(n) if n >= 0 => Ok((field.trim().to_string(), n)),
+        _ => Err(format!("invalid tolerance '{}' (expected a non-negative number)", n)),
+    }
+}
+
+fn parse_pattern(s: &str) -> Result<HexPattern, String> {
+    HexPattern::parse(s).map_err(|e| e.to_string())
+}
+
+/// A rate in Hz, as the interval between events.
+fn parse_rate(s: &str) -> Result<Duration, String> {
+    let interval = match s.parse::<f64>() {
+        Ok(hz) if hz > 0.0 && hz.is_finite() => Duration::try_from_secs_f64(1.0 / hz).ok(),
+        _ => None,
+    };
+    interval.ok_or_else(|| format!("invalid rate '{}' (expected a positive number of Hz)", s))
+}
+
+/// Playback speeds outside these bounds make offsets overflow or vanish.
+const SPEED_RANGE: std::ops::RangeInclusive<f64> = 0.001..=1000.0;
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if SPEED_RANGE.contains(&speed) => Ok(speed),
+        _ => Err(format!("invalid speed '{}' (expected a factor from {} to {}, e.g. 2 or 0.5)",
+            s, SPEED_RANGE.start(), SPEED_RANGE.end())),
+    }
+}
+
+fn parse_feature(s: &str) -> Result<(u8, Vec<u8>), String> {
+    let (id, data) = s.split_once('=').ok_or_else(|| format!("expected ID=HEX, got '{}'", s))?;
+    let data = hex::decode(data.trim()).map_err(|e| format!("invalid hex data: {}", e))?;
+    Ok((parse_hex_u8(id.trim())?, data))
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    Role::parse(s).map_err(|e| e.to_string())
+}
+
+fn parse_hex_u8(s: &str) -> Result<u8, String> {
+    let s = s.trim_start_matches("0x");
+    u8::from_str_radix(s, 16).map_err(|e| e.to_string())
+}
+
+/// Counters printed periodically by `--stats-interval`.
+struct ReadStats {
+    interval: Duration,
+    window_start: Instant,
+    reports: u64,
+    bytes: u64,
+    format_time: Duration,
+    total_reports: u64,
+}
+
+impl ReadStats {
+    fn new(interval: Duration) -> Self {
+        ReadStats {
+            interval,
+            window_start: Instant::now(),
+            reports: 0,
+            bytes: 0,
+            format_time: Duration::ZERO,
+            total_reports: 0,
+        }
+    }
+
+    fn record(&mut self, len: usize, format_time: Duration) {
+        self.reports += 1;
+        self.bytes += len as u64;
+        self.format_time += format_time;
+        self.total_reports += 1;
+    }
+
+    /// Time left in the current window, used as the read timeout.
+    fn remaining(&self) -> Duration {
+        self.interval.saturating_sub(self.window_start.elapsed())
+    }
+
+    fn print_if_due(&mut self, out: &mut Reporter) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < self.interval {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        let avg_format_us = if self.reports > 0 {
+            self.format_time.as_secs_f64() * 1e6 / self.reports as f64
+        } else {
+            0.0
+        };
+        out.read_stats(self.reports as f64 / secs, self.bytes as f64 / secs, avg_format_us, self.total_reports);
+        self.window_start = Instant::now();
+        self.reports = 0;
+        self.bytes = 0;
+        self.format_time = Duration::ZERO;
+    }
+}
+
+/// Why [`read_input_reports`] or [`interact`] stopped.
+#[derive(PartialEq, Eq)]
+enum StreamEnd {
+    /// `--timeout` passed without a report.
+    Idle,
+    /// Stdin ended, for `monitor --interactive`.
+    StdinEnded,
+    /// A read failed, usually because the device was unplugged or reset.
+    ReadError,
+}
+
+/// How `monitor` shows the input reports it receives.
+enum InputView {
+    Hex,
+    /// `--decode`: as the fields the descriptor lays out.
+    Fields(InputDecoder),
+    /// `--defmt-elf`: the payloads carry a defmt log stream.
+    Defmt(defmt::Table, defmt::Frames),
+}
+
+/// Show an input report `monitor` received, with its payload when framed. With
+/// `--decode`, reports the descriptor describes are shown as their fields
+/// instead; with `--defmt-elf`, the log messages their payloads complete.
+/// Others, and those `--report-id` ignores, are shown as usual.
+fn show_input_report(report: &[u8], format: &ReportFormat, view: &mut InputView, out: &mut Reporter) {
+    let wanted = format.report_id.is_none_or(|id| report.first() == Some(&id));
+    match view {
+        InputView::Fields(decoder) if wanted => {
+            if let Some(readings) = decoder.decode(report) {
+                out.decoded_report("Received Input Report", report, &readings);
+                return;
+            }
+        }
+        InputView::Defmt(table, frames) => {
+            if let Some(payload) = format.payload(report) {
+                out.quiet_input(report);
+                match payload {
+                    Ok(payload) => {
+                        for frame in frames.push(&payload) {
+                            match defmt::rzcobs_decode(&frame).and_then(|frame| table.decode(&frame)) {
+                                Ok(line) => out.defmt_log(&line),
+                                Err(e) => out.defmt_frame_invalid(&frame, &e),
+                            }
+                        }
+                    }
+                    Err(e) => out.frame_error(&e),
+                }
+                return;
+            }
+        }
+        _ => {}
+    }
+    out.input_report("Received Input Report", report, format.report_id);
+    if format.framing != Framing::Raw {
+        input_payload(format, report, out);
+    }
+}
+
+fn read_input_reports(session: &HidSession, stats_interval: Option<Duration>, format: &ReportFormat, view: &mut InputView, out: &mut Reporter) -> anyhow::Result<StreamEnd> {
+    let mut input_report = vec![0u8; format.input_buffer];
+    let mut stats = stats_interval.map(ReadStats::new);
+    let mut received = false;
+    let mut last_report = Instant::now();
+    loop {
+        // With stats enabled, wake up at the end of each window even if the device is quiet
+        let idle_left = format.read_timeout.map(|t| t.saturating_sub(last_report.elapsed()));
+        let wait = match (stats.as_ref().map(ReadStats::remaining), idle_left) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let result = match wait {
+            Some(wait) => session.read_report_timeout(&mut input_report, wait),
+            None => session.read_report(&mut input_report),
+        };
+        match result {
+            Ok(0) if wait.is_some() => {
+                if let Some(timeout) = format.read_timeout.filter(|&t| last_report.elapsed() >= t) {
+                    out.idle(timeout, received);
+                    return if received { Ok(StreamEnd::Idle) } else { Err(NoResponse.into()) };
+                }
+            }
+            Ok(len) => {
+                let started = Instant::now();
+                show_input_report(&input_report[..len], format, view, out);
+                if let Some(stats) = &mut stats {
+                    stats.record(len, started.elapsed());
+                }
+                received = true;
+                last_report = Instant::now();
+            }
+            Err(e) => {
+                out.read_error("Error reading input report", &e);
+                return Ok(StreamEnd::ReadError);
+            }
+        }
+        if let Some(stats) = &mut stats {
+            stats.print_if_due(out);
+        }
+    }
+}
+
+/// Show input reports as they arrive, like [`read_input_reports`], while
+/// sending each hex line from `events` as an output report. The session is
+/// handed back when reading stops.
+fn interact(session: HidSession, format: &ReportFormat, view: &mut InputView, sender: &std::sync::mpsc::Sender<Event>, events: &std::sync::mpsc::Receiver<Event>, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<(HidSession, StreamEnd)> {
+    let report_size = session.report_size();
+    let duplex = Duplex::start(session, format.input_buffer, sender.clone());
+    out.info("Type an output report in hex and press Enter to send it; Ctrl-D ends");
+    let mut received = false;
+    let mut last_report = Instant::now();
+    let end = loop {
+        let event = match format.read_timeout {
+            Some(timeout) => match events.recv_timeout(timeout.saturating_sub(last_report.elapsed())) {
+                Ok(event) => event,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    out.idle(timeout, received);
+                    if !received {
+                        duplex.stop();
+                        return Err(NoResponse.into());
+                    }
+                    break StreamEnd::Idle;
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break StreamEnd::StdinEnded,
+            },
+            None => match events.recv() {
+                Ok(event) => event,
+                Err(_) => break StreamEnd::StdinEnded,
+            },
+        };
+        match event {
+            Event::Input { report, .. } => {
+                show_input_report(&report, format, view, out);
+                received = true;
+                last_report = Instant::now();
+            }
+            Event::Line(line) => {
+                let Some(data) = typed_line(&line?, format, guard, out) else {
+                    continue;
+                };
+                match format.output_report(report_size, &data) {
+                    Ok(report) => {
+                        out.output_report(format.report_id, &report);
+                        duplex.write(report);
+                    }
+                    Err(e) => out.frame_error(&e),
+                }
+            }
+            Event::Written { report, result } => out.output_result(&report, &result),
+            Event::StdinEnded => break StreamEnd::StdinEnded,
+            Event::ReadError(e) => {
+                out.read_error("Error reading input report", &e);
+                break StreamEnd::ReadError;
+            }
+        }
+    };
+    Ok((duplex.stop(), end))
+}
+
+fn interface_json(i: &usb::UsbInterface) -> Json {
+    let endpoints = i.endpoints.iter().map(|e| Json::object([
+        ("address", e.address.into()),
+        ("direction", if e.is_in() { "in" } else { "out" }.into()),
+        ("transfer", e.transfer_type().into()),
+        ("max_packet_size", e.max_packet_size.into()),
+        ("interval", e.interval.into()),
+    ])).collect();
+    Json::object([
+        ("number", i.number.into()),
+        ("class", i.class.into()),
+        ("class_name", i.class_name().into()),
+        ("subclass", i.subclass.into()),
+        ("protocol", i.protocol.into()),
+        ("endpoints", Json::Array(endpoints)),
+    ])
+}
+
+/// `composite` member for JSON output: every USB interface, or null if unknown.
+fn composite_json(d: &hidapi::DeviceInfo) -> Json {
+    usb::composite_interfaces(d.path())
+        .map_or(Json::Null, |interfaces| Json::Array(interfaces.iter().map(interface_json).collect()))
+}
+
+fn print_composite(d: &hidapi::DeviceInfo) {
+    match usb::composite_interfaces(d.path()) {
+        Some(interfaces) => {
+            println!("  Composite:");
+            for i in interfaces {
+                let marker = if i32::from(i.number) == d.interface_number() { "  <- this entry" } else { "" };
+                println!("    #{:<2} class 0x{:02x} {:<18} subclass 0x{:02x} protocol 0x{:02x}{}",
+                    i.number, i.class, i.class_name(), i.subclass, i.protocol, marker);
+            }
+        }
+        None => println!("  Composite:  (interface layout unavailable)"),
+    }
+}
+
+fn list_devices(api: &HidApi, args: &ListArgs, out: &Reporter) {
+    let filter = DeviceFilter {
+        vid: args.vid,
+        pid: args.pid,
+        usage_page: args.usage_page,
+        interface: args.interface,
+        manufacturer: args.manufacturer.clone(),
+        ..DeviceFilter::default()
+    };
+
+    if out.is_json() {
+        for d in hid_cmd::list_devices(api, &filter) {
+            let mut device = reporter::device_json(d);
+            if args.composite {
+                device.push("composite", composite_json(d));
+            }
+            out.emit(&device);
+        }
+        return;
+    }
+
+    let mut count = 0;
+    for d in hid_cmd::list_devices(api, &filter) {
+        count += 1;
+        println!("{:04x}:{:04x}  {} {}", d.vendor_id(), d.product_id(),
+            d.manufacturer_string().unwrap_or("(unknown manufacturer)"),
+            d.product_string().unwrap_or("(unknown product)"));
+        println!("  Serial:     {}", d.serial_number().unwrap_or("-"));
+        println!("  Usage:      page 0x{:04x}, usage 0x{:04x}", d.usage_page(), d.usage());
+        println!("  Interface:  {}", d.interface_number());
+        println!("  Path:       {}", d.path().to_string_lossy());
+        if args.composite {
+            print_composite(d);
+        }
+        println!();
+    }
+    println!("{} matching interface(s)", count);
+}
+
+/// Open the device; `report` sizes the probe handshake, which is
+/// `HID_REPORT_SIZE` bytes without it.
+fn open_session(api: &HidApi, args: &DeviceArgs, report: Option<&ReportArgs>, out: &mut Reporter) -> anyhow::Result<HidSession> {
+    let probe_size = report.map_or(Some(HID_REPORT_SIZE), ReportArgs::probe_size);
+    let session = open_device(api, args, probe_size, out)?;
+    if let Ok(info) = session.device_info() {
+        out.device_opened(&info);
+        if out.is_capturing() {
+            if let Ok(descriptor) = session.report_descriptor() {
+                out.descriptor_read(&descriptor);
+            }
+        }
+        if let Some(dir) = &args.state_cache {
+            check_state(&session, &info, dir, args, out)?;
+        }
+    }
+    Ok(session)
+}
+
+/// Compare the device with its cached last known good state, or cache it if
+/// this is the first time the serial number is seen.
+fn check_state(session: &HidSession, info: &hidapi::DeviceInfo, dir: &Path, args: &DeviceArgs, out: &mut Reporter) -> anyhow::Result<()> {
+    let Some(serial) = info.serial_number().filter(|s| !s.is_empty()) else {
+        out.info("Device has no serial number, so its state is not cached");
+        return Ok(());
+    };
+    let path = state::cache_path(dir, info.vendor_id(), info.product_id(), serial);
+    let current = DeviceState::capture(session, info, &args.state_features);
+    let Some(cached) = DeviceState::load(&path)? else {
+        current.save(&path)?;
+        out.info(&format!("Saved the state of {} as last known good in {}", serial, path.display()));
+        return Ok(());
+    };
+    let changes = cached.diff(&current);
+    if !changes.is_empty() {
+        out.state_changed(serial, &changes, args.accept_state);
+        if args.accept_state {
+            current.save(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Open the device and apply the report options to it.
+fn open_configured(api: &HidApi, device: &DeviceArgs, report: &ReportArgs, out: &mut Reporter) -> anyhow::Result<(HidSession, ReportFormat)> {
+    configure_reports(open_session(api, device, Some(report), out)?, report, out)
+}
+
+impl ReportArgs {
+    /// Length of the probe handshake report, ID included, as `--report-size`
+    /// gives it; `None` for `auto`, to read it from each candidate's descriptor.
+    fn probe_size(&self) -> Option<usize> {
+        match (self.report_size, self.report_id) {
+            (None, _) => Some(HID_REPORT_SIZE),
+            (Some(ReportSizeArg::Auto), _) => None,
+            // With a report ID the size is that of the payload after it
+            (Some(ReportSizeArg::Bytes(n)), Some(_)) => Some(n + 1),
+            (Some(ReportSizeArg::Bytes(n)), None) => Some(n),
+        }
+    }
+
+    /// Whether reports will have a report ID of their own, which is known
+    /// before the device is open even for `auto`.
+    fn has_report_id(&self) -> bool {
+        self.report_id.is_some() || self.framing.is_some_and(|framing| framing != Framing::Raw)
+    }
+
+    /// `data` in the form deny-lists are matched against (see
+    /// [`ReportFormat::data_payload`]), before the device is open.
+    fn payload<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        let format = ReportFormat { report_id: self.has_report_id().then_some(0), input_buffer: 0, read_timeout: None, framing: Framing::Raw };
+        format.data_payload(data)
+    }
+
+    fn apply_profile(&mut self, profile: &Profile) {
+        self.report_id = self.report_id.or(profile.device.report_id.map(ReportIdArg::Id));
+        self.report_size = self.report_size.or(profile.device.report_size.map(ReportSizeArg::Bytes));
+        self.framing = self.framing.or(profile.device.framing);
+    }
+}
+
+impl DeviceArgs {
+    /// Fill in options not given on the command line from the profile's `[device]`.
+    fn apply_profile(&mut self, profile: &Profile) {
+        let d = &profile.device;
+        self.vid = self.vid.or(d.vid);
+        self.pid = self.pid.or(d.pid);
+        self.usage_page = self.usage_page.or(d.usage_page);
+        self.interface = self.interface.or(d.interface);
+        if let Some(state) = &profile.state {
+            self.state_cache = self.state_cache.take().or_else(|| state.dir.clone()).or_else(state::default_cache_dir);
+            self.state_features = state.feature_reports.clone();
+        }
+    }
+
+    fn filter(&self) -> DeviceFilter {
+        DeviceFilter {
+            vid: self.vid,
+            pid: self.pid,
+            usage_page: self.usage_page,
+            usage: self.usage,
+            interface: self.interface,
+            serial: self.serial.clone(),
+            path: self.path.clone(),
+            manufacturer: None,
+        }
+    }
+
+    /// `--retries` and `--retry-delay`.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy { max_retries: self.retries, delay: Duration::from_millis(self.retry_delay) }
+    }
+}
+
+fn open_device(api: &HidApi, args: &DeviceArgs, probe_size: Option<usize>, out: &mut Reporter) -> anyhow::Result<HidSession> {
+    match (args.vid, args.pid, &args.path) {
+        (Some(vid), Some(pid), _) => out.info(&format!("Searching for devices with VID:PID = {:04x}:{:04x}\n", vid, pid)),
+        (_, _, Some(path)) => out.info(&format!("Searching for device at {}\n", path)),
+        _ => return Err(anyhow::anyhow!("Give --vid and --pid, --path, or a --profile with a [device] section")),
+    }
+
+    hid_cmd::apply_open_mode(api, args.open_mode)?;
+
+    let filter = args.filter();
+    let serials: Vec<Option<&str>> = {
+        let mut serials: Vec<_> = hid_cmd::list_devices(api, &filter).map(|d| d.serial_number()).collect();
+        serials.sort();
+        serials.dedup();
+        serials
+    };
+    if serials.len() > 1 && args.serial.is_none() && args.path.is_none() {
+        out.info(&format!("Note: {} matching devices, opening the first; use --serial or --path to choose\n", serials.len()));
+    }
+
+    if let Some(handshake) = &args.probe_handshake {
+        let timeout = Duration::from_millis(args.probe_timeout);
+        let device = hid_cmd::open_device_by_probe(api, &filter, handshake, probe_size, timeout, |info, probe, written| {
+            out.select_device(info);
+            out.quiet_output(probe, written);
+        })?;
+        out.info("Successfully opened device (answered probe handshake)");
+        return Ok(HidSession::from_device(device));
+    }
+
+    let device = hid_cmd::open_device_with_retry(api, &filter, VENDOR_PAGE, &args.retry_policy(), |attempt, e| {
+        out.info(&format!("Attempt {} failed: {}. Retrying in {}ms...", attempt, e, args.retry_delay));
+    })?;
+
+    out.info("Successfully opened device");
+    Ok(HidSession::from_device(device))
+}
+
+/// Apply `--report-id`, `--report-size` and `--framing` to `session`,
+/// reading the report descriptor for `auto`.
+fn configure_reports(session: HidSession, args: &ReportArgs, out: &mut Reporter) -> anyhow::Result<(HidSession, ReportFormat)> {
+    let framing = args.framing.unwrap_or_default();
+    let report_id = match args.report_id {
+        // A frame's first byte must not be taken for a report ID
+        None if args.has_report_id() => Some(0),
+        None => None,
+        Some(ReportIdArg::Id(id)) => Some(id),
+        Some(ReportIdArg::Auto) => {
+            let id = session.detect_output_report_id()?;
+            if id == 0 {
+                out.info("Device does not use numbered reports");
+            } else {
+                out.info(&format!("Detected report ID 0x{:02x}", id));
+            }
+            Some(id)
+        }
+    };
+
+    let (report_size, input_size) = match args.report_size {
+        None => (HID_REPORT_SIZE, HID_REPORT_SIZE),
+        Some(ReportSizeArg::Bytes(n)) => (n, n),
+        Some(ReportSizeArg::Auto) => {
+            let sizes = session.report_sizes()?;
+            let output = match report_id {
+                Some(id) => sizes.output_size(id),
+                // Legacy data carries the report ID as its first byte
+                None => sizes.max_output_size().map(|n| n + 1),
+            };
+            let output = output.unwrap_or_else(|| {
+                out.info(&format!("Device declares no matching output report; using {} bytes", HID_REPORT_SIZE));
+                HID_REPORT_SIZE
+            });
+            let input = sizes.max_input_size().unwrap_or(HID_REPORT_SIZE);
+            out.info(&format!("Detected report sizes: output {} bytes, input {} bytes", output, input));
+            (output, input)
+        }
+    };
+
+    let read_timeout = match (args.non_blocking, args.timeout) {
+        (true, _) => Some(Duration::ZERO),
+        (false, timeout) => timeout.map(Duration::from_millis),
+    };
+    let format = ReportFormat { report_id, input_buffer: input_size + 1, read_timeout, framing };
+    Ok((session.with_report_size(report_size), format))
+}
+
+/// Build the output report for `bytes` (see [`ReportFormat::output_report`]),
+/// print it and send it. Returns when the write completed, or `None` if it
+/// failed.
+fn send_output_report(session: &HidSession, bytes: &[u8], format: &ReportFormat, out: &mut Reporter) -> Option<Instant> {
+    let output_report = match format.output_report(session.report_size(), bytes) {
+        Ok(report) => report,
+        Err(e) => {
+            out.frame_error(&e);
+            return None;
+        }
+    };
+    exchange::write(session.transport(), &output_report, &mut Shown { out, format, label: "Received Input Report" }).ok()
+}
+
+/// Shows every report of an exchange as it is written or read, input reports
+/// under `label` and with their payload when framing is on.
+struct Shown<'a> {
+    out: &'a mut Reporter,
+    format: &'a ReportFormat,
+    label: &'a str,
+}
+
+impl exchange::Observer for Shown<'_> {
+    fn sent(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+        self.out.output_report(self.format.report_id, report);
+        self.out.output_result(report, result);
+    }
+
+    fn received(&mut self, report: &[u8]) {
+        self.out.input_report(self.label, report, self.format.report_id);
+        if self.format.framing != Framing::Raw {
+            input_payload(self.format, report, self.out);
+        }
+    }
+}
+
+/// Records writes in the audit log, capture and power log as they happen and
+/// leaves showing them for later.
+struct Recorded<'a>(&'a mut Reporter);
+
+impl exchange::Observer for Recorded<'_> {
+    fn sent(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+        self.0.output_recorded(report, result);
+    }
+
+    fn received(&mut self, _report: &[u8]) {}
+}
+
+/// Shows nothing, for commands where printing would skew the timing; reports
+/// are still audited.
+struct Quiet<'a>(&'a mut Reporter);
+
+impl exchange::Observer for Quiet<'_> {
+    fn sent(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+        self.0.quiet_output(report, result);
+    }
+
+    fn received(&mut self, report: &[u8]) {
+        self.0.quiet_input(report);
+    }
+}
+
+/// An input report and when the read returned it.
+struct Response {
+    /// See [`input_payload`].
+    payload: Option<Vec<u8>>,
+    received: Instant,
+}
+
+/// The payload of an input report: after its report ID, if any, and decoded
+/// (and shown) when framing is on. `None` for reports with another ID and for
+/// invalid frames, which are reported.
+fn input_payload(format: &ReportFormat, report: &[u8], out: &mut Reporter) -> Option<Vec<u8>> {
+    match format.payload(report)? {
+        Ok(payload) => {
+            if format.framing != Framing::Raw {
+                out.frame_in(format.framing, &payload);
+            }
+            Some(payload)
+        }
+        Err(e) => {
+            out.frame_error(&e);
+            None
+        }
+    }
+}
+
+/// Checks outgoing data against the profiles' deny-lists and roles before a
+/// device is even opened.
+struct WriteGuard<'a> {
+    /// The [system profile](SYSTEM_PROFILE) and `--profile`, as far as there
+    /// are any; both apply.
+    profiles: Vec<&'a Profile>,
+    overridden: bool,
+    role: Role,
+}
+
+impl WriteGuard<'_> {
+    /// Fails unless acting as at least `required`.
+    fn require(&self, required: Role, what: &str) -> anyhow::Result<()> {
+        if self.role >= required {
+            return Ok(());
+        }
+        Err(anyhow::anyhow!("{} needs the {} role, but you are acting as {}", what, required, self.role))
+    }
+
+    /// Fails unless the role may send data that isn't a named command.
+    fn require_raw(&self) -> anyhow::Result<()> {
+        match self.profiles.iter().map(|p| p.roles.raw).max() {
+            Some(raw) => self.require(raw, "Sending raw data"),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails if the deny-list rejects `data`, the payload after the report ID
+    /// (see [`ReportFormat::data_payload`]), unless overridden.
+    fn check(&self, data: &[u8], out: &mut Reporter) -> anyhow::Result<()> {
+        let Some(rule) = self.profiles.iter().find_map(|p| p.denied(data)) else {
+            return Ok(());
+        };
+        out.write_denied(data, rule, self.overridden);
+        if self.overridden {
+            return Ok(());
+        }
+        Err(anyhow::anyhow!("Refusing to send {}: it matches deny-list pattern '{}' ({}). \
+            Pass --i-know-what-im-doing to send it anyway", hex::encode(data), rule.pattern, rule.reason))
+    }
+
+    /// Whether `data` matches the deny-list, override or not.
+    fn denies(&self, data: &[u8]) -> bool {
+        self.profiles.iter().any(|p| p.denied(data).is_some())
+    }
+}
+
+fn send(api: &HidApi, args: &SendArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let bytes = args.source.load(args.data.as_deref())?;
+    guard.check(args.report.payload(&bytes), out)?;
+    if args.all_matching {
+        return send_all_matching(api, args, &bytes, out);
+    }
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    args.source.check_fits(&bytes, session.report_size())?;
+    let Some(repeat) = args.repeat else {
+        if !exchange(&session, &format, &bytes, args.expect.as_ref(), out)? {
+            return Err(anyhow::anyhow!("The exchange failed"));
+        }
+        return Ok(());
+    };
+
+    // Sends are scheduled from the start rather than after each other, so a
+    // slow response delays one exchange instead of everything after it
+    let interval = args.interval.or(args.rate).unwrap_or_default();
+    let mut failures = 0;
+    let start = Instant::now();
+    for i in 0..repeat {
+        timeline::wait_until(start + interval * i);
+        match exchange(&session, &format, &bytes, args.expect.as_ref(), out) {
+            Ok(true) => {}
+            Ok(false) => failures += 1,
+            Err(e) => {
+                // A timeout has been reported already
+                if !e.is::<NoResponse>() {
+                    out.exchange_failed(i + 1, &e);
+                }
+                failures += 1;
+            }
+        }
+    }
+    out.repeat_done(repeat, failures, start.elapsed());
+    if failures > 0 {
+        return Err(anyhow::anyhow!("{} of {} exchanges failed", failures, repeat));
+    }
+    Ok(())
+}
+
+/// One device of `send --all-matching`.
+struct Target {
+    session: HidSession,
+    format: ReportFormat,
+    info: Option<hidapi::DeviceInfo>,
+    /// Serial number, or path for devices without one.
+    label: String,
+    /// The complete output report, framed for the device.
+    report: Vec<u8>,
+}
+
+/// Send `bytes` to every interface matching the device options, all at the
+/// same time, then show what each one answered. Without --usage-page, only
+/// vendor-page interfaces are used if there are any, so a composite device
+/// gets the report once.
+fn send_all_matching(api: &HidApi, args: &SendArgs, bytes: &[u8], out: &mut Reporter) -> anyhow::Result<()> {
+    hid_cmd::apply_open_mode(api, args.device.open_mode)?;
+    let filter = args.device.filter();
+    if filter.vid.is_none() || filter.pid.is_none() {
+        return Err(anyhow::anyhow!("--all-matching needs --vid and --pid, or a --profile with both"));
+    }
+    let mut candidates: Vec<_> = hid_cmd::list_devices(api, &filter).collect();
+    if filter.usage_page.is_none() && candidates.iter().any(|d| d.usage_page() == VENDOR_PAGE) {
+        candidates.retain(|d| d.usage_page() == VENDOR_PAGE);
+    }
+    if candidates.is_empty() {
+        return Err(anyhow::anyhow!("Device not found"));
+    }
+    out.info(&format!("Sending to {} matching device(s)", candidates.len()));
+
+    let policy = args.device.retry_policy();
+    let mut targets = Vec::new();
+    let mut failed = 0;
+    for candidate in &candidates {
+        let label = candidate.serial_number().filter(|s| !s.is_empty()).map(str::to_string)
+            .unwrap_or_else(|| candidate.path().to_string_lossy().into_owned());
+        let opened = hid_cmd::open_with_retry(&policy, |attempt, e: &hidapi::HidError| {
+            out.info(&format!("{}: attempt {} failed: {}. Retrying in {}ms...", label, attempt, e, args.device.retry_delay));
+        }, || api.open_path(candidate.path()));
+        let session = match opened {
+            Ok(device) => HidSession::from_device(device),
+            Err(e) => {
+                out.fan_out_result(&label, &Err(e));
+                failed += 1;
+                continue;
+            }
+        };
+        out.device_opened(candidate);
+        let (session, format) = match configure_reports(session, &args.report, out) {
+            Ok(configured) => configured,
+            Err(e) => {
+                out.fan_out_result(&label, &Err(e));
+                failed += 1;
+                continue;
+            }
+        };
+        let report = args.source.check_fits(bytes, session.report_size())
+            .and_then(|()| format.output_report(session.report_size(), bytes));
+        let report = match report {
+            Ok(report) => report,
+            Err(e) => {
+                out.fan_out_result(&label, &Err(e));
+                failed += 1;
+                continue;
+            }
+        };
+        let info = session.device_info().ok();
+        targets.push(Target { session, format, info, label, report });
+    }
+
+    // Nothing is shown until every device is done, since they run side by side
+    let expect = args.expect.as_ref();
+    let results = exchange::fan_out(targets, |target| {
+        exchange::fan_out_exchange(target.session.transport(), &target.format, &target.report, expect)
+    });
+
+    let total = results.len() + failed;
+    for (target, outcome) in results {
+        out.fan_out_device(&target.label);
+        if let Some(info) = &target.info {
+            out.select_device(info);
+        }
+        let mut shown = Shown { out, format: &target.format, label: "Received Input Report" };
+        exchange::Observer::sent(&mut shown, &target.report, &outcome.written);
+        for input in &outcome.reports {
+            exchange::Observer::received(&mut shown, input);
+        }
+        let timeout = target.format.response_timeout();
+        let result = match (outcome.written, outcome.read_error, outcome.latency) {
+            (Err(e), _, _) => Err(e.context("Sending failed")),
+            (_, Some(e), _) => Err(e.context("Reading the response failed")),
+            (_, _, Some(latency)) => Ok(latency),
+            (_, _, None) => Err(match expect {
+                Some(pattern) => anyhow::anyhow!("No input report matching {} within {} ms", pattern, timeout.as_millis()),
+                None => anyhow::anyhow!("No input report within {} ms", timeout.as_millis()),
+            }),
+        };
+        if result.is_err() {
+            failed += 1;
+        }
+        out.fan_out_result(&target.label, &result);
+    }
+    out.fan_out_done(total, failed);
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} devices failed", failed, total));
+    }
+    Ok(())
+}
+
+/// Send `bytes` and read a response, or wait for one matching `expect`.
+/// Returns whether both the write and the read succeeded; failures are
+/// reported as they happen.
+fn exchange(session: &HidSession, format: &ReportFormat, bytes: &[u8], expect: Option<&HexPattern>, out: &mut Reporter) -> anyhow::Result<bool> {
+    let written = send_output_report(session, bytes, format, out).is_some();
+    let read = match expect {
+        Some(pattern) => {
+            expect_response(session, format, pattern, out)?;
+            true
+        }
+        None => read_response(session, format, "Received Input Report", "Error reading response", out)?.is_some(),
+    };
+    Ok(written && read)
+}
+
+/// Read one input report, showing it under `label`, and return it; read errors
+/// are reported after `error_message`. Fails with [`NoResponse`] if the read
+/// times out.
+fn read_response(session: &HidSession, format: &ReportFormat, label: &str, error_message: &str, out: &mut Reporter) -> anyhow::Result<Option<Response>> {
+    match exchange::read(session.transport(), format, &mut Shown { out, format, label }) {
+        Ok(Some(response)) => {
+            let payload = format.payload(&response.report).and_then(Result::ok);
+            Ok(Some(Response { payload, received: response.received }))
+        }
+        Ok(None) => {
+            out.no_response(format.read_timeout.unwrap_or_default());
+            Err(NoResponse.into())
+        }
+        Err(e) => {
+            out.read_error(error_message, &e);
+            Ok(None)
+        }
+    }
+}
+
+fn read(api: &HidApi, args: &ReadArgs, out: &mut Reporter) -> anyhow::Result<()> {
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    match &args.expect {
+        Some(pattern) => expect_response(&session, &format, pattern, out),
+        None => {
+            match read_response(&session, &format, "Current Input Report", "Could not read input report", out)? {
+                Some(_) => Ok(()),
+                None => Err(anyhow::anyhow!("No input report was read")),
+            }
+        }
+    }
+}
+
+/// Read input reports until one matches `pattern`, giving up once
+/// `--timeout` (or [`EXPECT_TIMEOUT_MS`]) has passed since the first read.
+/// Reports with another report ID are skipped.
+fn expect_response(session: &HidSession, format: &ReportFormat, pattern: &HexPattern, out: &mut Reporter) -> anyhow::Result<()> {
+    match exchange::expect(session.transport(), format, pattern, &mut Shown { out, format, label: "Received Input Report" }) {
+        Ok(Some(_)) => {
+            out.info(&format!("Matched {}", pattern));
+            Ok(())
+        }
+        Ok(None) => Err(anyhow::anyhow!("No input report matching {} within {} ms", pattern, format.response_timeout().as_millis())),
+        Err(e) => {
+            out.read_error("Error reading input report", &e);
+            Err(anyhow::anyhow!("No input report matching {} was read", pattern))
+        }
+    }
+}
+
+/// `monitor`, and `watch` when `wait_first` is set. With `--reconnect` (implied
+/// by `watch`) a failed read waits for the device to come back, reopens it and
+/// carries on.
+fn monitor(api: &mut HidApi, args: &MonitorArgs, wait_first: bool, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let bytes = args.send.as_deref().map(hex::decode).transpose()?;
+    if let Some(bytes) = &bytes {
+        guard.require_raw()?;
+        guard.check(args.report.payload(bytes), out)?;
+    }
+    if args.interactive {
+        guard.require_raw()?;
+    }
+    let lines = args.interactive.then(|| {
+        let (sender, events) = std::sync::mpsc::channel();
+        duplex::forward_stdin(sender.clone());
+        (sender, events)
+    });
+    let defmt_table = args.defmt_elf.as_deref().map(defmt::Table::load).transpose()?;
+    let reconnect = args.reconnect || wait_first;
+    let mut session = if wait_first {
+        wait_for_device(api, &args.device, Some(&args.report), None, out)?
+    } else {
+        open_session(api, &args.device, Some(&args.report), out)?
+    };
+    // The device is the same after a reconnect, so its descriptor is too
+    let mut view = match (defmt_table, args.decode) {
+        (Some(table), _) => InputView::Defmt(table, defmt::Frames::new()),
+        (None, true) => InputView::Fields(input_decoder(&session)?.with_layout(args.layout)),
+        (None, false) => InputView::Hex,
+    };
+    loop {
+        let format;
+        (session, format) = configure_reports(session, &args.report, out)?;
+        if matches!(view, InputView::Fields(_)) && format.framing != Framing::Raw {
+            return Err(anyhow::anyhow!("--decode reads reports as the descriptor lays them out, which --framing doesn't"));
+        }
+        if let Some(bytes) = &bytes {
+            send_output_report(&session, bytes, &format, out);
+        }
+        let end = match &lines {
+            Some((sender, events)) => {
+                let end;
+                (session, end) = interact(session, &format, &mut view, sender, events, guard, out)?;
+                end
+            }
+            None => read_input_reports(&session, args.stats_interval.map(Duration::from_millis), &format, &mut view, out)?,
+        };
+        if end != StreamEnd::ReadError || !reconnect {
+            return Ok(());
+        }
+        // Close the old handle before the device re-enumerates under it
+        drop(session);
+        out.disconnected();
+        let lost = Instant::now();
+        session = wait_for_device(api, &args.device, Some(&args.report), None, out)?;
+        out.reconnected(lost.elapsed());
+        // The frame in progress died with the firmware
+        if let InputView::Defmt(_, frames) = &mut view {
+            *frames = defmt::Frames::new();
+        }
+    }
+}
+
+/// The layouts of the device's input reports, for `monitor --decode`.
+fn input_decoder(session: &HidSession) -> anyhow::Result<InputDecoder> {
+    let desc = session.report_descriptor().map_err(|e| anyhow::anyhow!("Cannot read the report descriptor for --decode: {}", e))?;
+    InputDecoder::new(&desc).ok_or_else(|| anyhow::anyhow!("The report descriptor declares no input reports to --decode"))
+}
+
+/// Poll the device list until a device matching `args` is present and can be
+/// opened, failing if it isn't by `deadline`. Opening right after
+/// re-enumeration often fails, so failures just mean another round.
+fn wait_for_device(api: &mut HidApi, args: &DeviceArgs, report: Option<&ReportArgs>, deadline: Option<Instant>, out: &mut Reporter) -> anyhow::Result<HidSession> {
+    let filter = args.filter();
+    let mut announced = false;
+    loop {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(anyhow::anyhow!("The device did not appear in time"));
+        }
+        api.refresh_devices()?;
+        if hid_cmd::list_devices(api, &filter).next().is_some() {
+            match open_session(api, args, report, out) {
+                Ok(session) => return Ok(session),
+                Err(e) => out.info(&format!("Could not open device yet: {}", e)),
+            }
+        } else if !announced {
+            out.info("Waiting for the device to appear...");
+            announced = true;
+        }
+        std::thread::sleep(Duration::from_millis(RECONNECT_POLL_MS));
+    }
+}
+
+fn feature_get(api: &HidApi, args: &FeatureGetArgs, out: &mut Reporter) -> anyhow::Result<()> {
+    let session = open_session(api, &args.device, None, out)?;
+
+    let mut buf = vec![0u8; args.length + 1];
+    match session.get_feature_report(args.report_id, &mut buf) {
+        Ok(len) => {
+            out.feature_report_in(args.report_id, &buf[..len]);
+            Ok(())
+        }
+        Err(e) => {
+            out.read_error("Error reading feature report", &e);
+            Err(anyhow::anyhow!("Could not read feature report 0x{:02x}", args.report_id))
+        }
+    }
+}
+
+fn feature_watch(api: &HidApi, args: &FeatureWatchArgs, out: &mut Reporter) -> anyhow::Result<()> {
+    let session = open_session(api, &args.device, None, out)?;
+    let fields: Vec<descriptor::Field> = match session.report_descriptor() {
+        Ok(desc) => descriptor::report_fields(&desc, descriptor::ReportKind::Feature, args.report_id),
+        Err(_) => Vec::new(),
+    };
+    if fields.is_empty() {
+        out.info("No descriptor fields for this report; showing changes per byte");
+    }
+
+    let mut buf = vec![0u8; args.length + 1];
+    let mut previous: Option<Vec<(String, i64)>> = None;
+    loop {
+        let len = match session.get_feature_report(args.report_id, &mut buf) {
+            Ok(len) => len,
+            Err(e) => {
+                out.read_error("Error reading feature report", &e);
+                return Ok(());
+            }
+        };
+        let report = &buf[..len];
+        let values = field_values(&fields, report.get(1..).unwrap_or_default());
+
+        match &previous {
+            None => {
+                out.feature_report_in(args.report_id, report);
+                out.feature_values(&values);
+            }
+            Some(old) => {
+                let changes: Vec<(String, Option<i64>, i64)> = values.iter().enumerate()
+                    .filter(|(i, (_, new))| old.get(*i).map(|(_, v)| v) != Some(new))
+                    .map(|(i, (label, new))| (label.clone(), old.get(i).map(|(_, v)| *v), *new))
+                    .collect();
+                if !changes.is_empty() {
+                    out.feature_changes(args.report_id, report, &changes);
+                }
+            }
+        }
+        previous = Some(values);
+        std::thread::sleep(args.interval);
+    }
+}
+
+/// Label and value of every non-constant field of a report payload, or of
+/// every byte when the descriptor doesn't describe the report.
+fn field_values(fields: &[descriptor::Field], payload: &[u8]) -> Vec<(String, i64)> {
+    if fields.is_empty() {
+        return payload.iter().enumerate().map(|(i, b)| (format!("byte {}", i), i64::from(*b))).collect();
+    }
+
+    let mut values = Vec::new();
+    for field in fields.iter().filter(|f| !f.constant) {
+        for i in 0..field.count {
+            let Some(value) = field.value(payload, i) else {
+                break;
+            };
+            let name = match field.usage(i) {
+                Some((page, usage)) => descriptor::usage_name(page, usage),
+                None => "(no usage)".to_string(),
+            };
+            values.push((if field.variable { name } else { format!("{} (array)", name) }, value));
+        }
+    }
+    // Fields sharing a usage (typical for vendor data) get numbered
+    let labels: Vec<String> = values.iter().map(|(l, _)| l.clone()).collect();
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    for (label, _) in &mut values {
+        if labels.iter().filter(|l| *l == label).count() > 1 {
+            let n = seen.entry(label.clone()).or_default();
+            *label = format!("{}[{}]", label, n);
+            *n += 1;
+        }
+    }
+    values
+}
+
+fn feature_set(api: &HidApi, args: &FeatureSetArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let report = args.source.load(args.data.as_deref())?;
+    if report.is_empty() {
+        return Err(anyhow::anyhow!("Feature report needs at least a report ID byte"));
+    }
+    guard.require_raw()?;
+    // The deny-list sees the payload after the report ID, as for output reports
+    guard.check(&report[1..], out)?;
+    let session = open_session(api, &args.device, None, out)?;
+
+    out.feature_report_out(&report);
+    out.feature_result(&report, &session.send_feature_report(&report));
+    Ok(())
+}
+
+fn info(api: &HidApi, args: &InfoArgs, out: &mut Reporter) -> anyhow::Result<()> {
+    let session = open_session(api, &args.device, None, out)?;
+    let d = session.device_info()?;
+
+    if out.is_json() {
+        let mut info = reporter::device_json(&d);
+        info.push("bus", format!("{:?}", d.bus_type()));
+        info.push("composite", composite_json(&d));
+        match session.report_sizes() {
+            Ok(sizes) => {
+                let reports = |reports: &BTreeMap<u8, usize>| Json::Array(reports.iter()
+                    .map(|(id, len)| Json::object([("report_id", (*id).into()), ("length", (*len).into())]))
+                    .collect());
+                info.push("reports", Json::object([
+                    ("input", reports(&sizes.input)),
+                    ("output", reports(&sizes.output)),
+                    ("feature", reports(&sizes.feature)),
+                ]));
+            }
+            Err(_) => info.push("reports", Json::Null),
+        }
+        if args.descriptor {
+            let desc = session.report_descriptor()?;
+            let items = descriptor::decode(&desc).iter().map(|d| Json::object([
+                ("offset", d.item.offset.into()),
+                ("depth", d.depth.into()),
+                ("hex", hex::encode(&desc[d.item.offset..d.item.offset + d.item.encoded_len()]).into()),
+                ("item", d.text.as_str().into()),
+            ])).collect();
+            info.push("descriptor", hex::encode(&desc));
+            info.push("items", Json::Array(items));
+        }
+        out.emit(&info);
+        return Ok(());
+    }
+
+    println!();
+    println!("Manufacturer:  {}", d.manufacturer_string().unwrap_or("-"));
+    println!("Product:       {}", d.product_string().unwrap_or("-"));
+    println!("Serial:        {}", d.serial_number().unwrap_or("-"));
+    println!("VID:PID:       {:04x}:{:04x}", d.vendor_id(), d.product_id());
+    println!("Release:       {:x}.{:02x}", d.release_number() >> 8, d.release_number() & 0xff);
+    println!("Bus:           {:?}", d.bus_type());
+    println!("Usage:         page 0x{:04x}, usage 0x{:04x}", d.usage_page(), d.usage());
+    println!("Interface:     {}", d.interface_number());
+    println!("Path:          {}", d.path().to_string_lossy());
+    print_composite(&d);
+
+    match session.report_sizes() {
+        Ok(sizes) => {
+            println!("  Reports:");
+            for (kind, reports) in [("Input", &sizes.input), ("Output", &sizes.output), ("Feature", &sizes.feature)] {
+                for (id, len) in reports {
+                    println!("    {:<8} ID 0x{:02x}  {} bytes", kind, id, len);
+                }
+            }
+        }
+        Err(e) => println!("  Reports:    (report descriptor unavailable: {})", e),
+    }
+
+    if args.descriptor {
+        print_descriptor(&session.report_descriptor()?);
+    }
+    Ok(())
+}
+
+fn print_descriptor(desc: &[u8]) {
+    println!("\nReport descriptor ({} bytes):", desc.len());
+    let decoded = descriptor::decode(desc);
+    for d in &decoded {
+        let raw = &desc[d.item.offset..d.item.offset + d.item.encoded_len()];
+        let bytes: Vec<String> = raw.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("  0x{:04x}  {:<15} {:indent$}{}", d.item.offset, bytes.join(" "), "", d.text, indent = d.depth * 2);
+    }
+    let decoded_len = decoded.last().map_or(0, |d| d.item.offset + d.item.encoded_len());
+    if decoded_len < desc.len() {
+        println!("  ({} trailing bytes could not be decoded: {})", desc.len() - decoded_len, hex::encode(&desc[decoded_len..]));
+    }
+}
+
+fn cmd(api: &HidApi, args: &CmdArgs, profile: Option<&Profile>, lang: Option<&str>, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let profile = profile.ok_or_else(|| anyhow::anyhow!("cmd needs --profile <file> defining the commands"))?;
+
+    if args.name == "list" {
+        list_commands(profile, out);
+        return Ok(());
+    }
+
+    let command = profile.command(&args.name)?;
+    guard.require(command.role, &format!("Command {}", args.name))?;
+    let bytes = command.template.render(&args.params)?;
+    guard.check(args.report.payload(&bytes), out)?;
+
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    out.info(&format!("\nCommand {}: {}", args.name, hex::encode(&bytes)));
+    let sent = send_output_report(&session, &bytes, &format, out);
+    let response = read_response(&session, &format, "Received Input Report", "Error reading response", out)?;
+    let (Some(sent), Some(response)) = (sent, response) else {
+        return Err(anyhow::anyhow!("Command {} failed", args.name));
+    };
+    out.command_latency(&args.name, response.received.duration_since(sent));
+
+    let (Some(schema), Some(payload)) = (&command.response, response.payload) else {
+        return Ok(());
+    };
+    let status = schema.check(&payload);
+    let entry = match status {
+        ResponseStatus::Error { code, .. } => profile.error(code),
+        _ => None,
+    };
+    out.response_status(&args.name, &status, entry, lang);
+    match status {
+        ResponseStatus::Success(_) => Ok(()),
+        _ => Err(anyhow::anyhow!("Command {} failed", args.name)),
+    }
+}
+
+fn run_script(api: &HidApi, args: &RunArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let script = Script::load(&args.script)?;
+    let location = |line: usize| format!("{} line {}", args.script.display(), line);
+    let payloads = script.payloads();
+    if !payloads.is_empty() {
+        guard.require_raw()?;
+    }
+    for (line, data) in payloads {
+        guard.check(args.report.payload(data), out).with_context(|| location(line))?;
+    }
+
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let Some(path) = &args.junit else {
+        return run_steps(&session, &format, &script.steps, &location, out);
+    };
+    let timestamp = hid_cmd::timestamp::now_utc();
+    let started = Instant::now();
+    let result = run_steps(&session, &format, &script.steps, &location, out);
+    let name = args.script.display().to_string();
+    let (status, message) = match &result {
+        Ok(()) => (Status::Pass, String::new()),
+        Err(e) => (Status::Fail, format!("{:#}", e)),
+    };
+    let case = Case { name, status, message, output: String::new(), time: started.elapsed() };
+    let suite = args.suite.clone().unwrap_or_else(|| {
+        args.script.file_name().map_or_else(|| "script".to_string(), |name| name.to_string_lossy().into_owned())
+    });
+    write_junit(path, &suite, &timestamp, &[case], out)?;
+    result
+}
+
+fn run_steps(session: &HidSession, format: &ReportFormat, steps: &[Step], location: &dyn Fn(usize) -> String, out: &mut Reporter) -> anyhow::Result<()> {
+    for step in steps {
+        match &step.op {
+            Op::Send(data) => {
+                if send_output_report(session, data, format, out).is_none() {
+                    return Err(anyhow::anyhow!("{}: sending {} failed", location(step.line), hex::encode(data)));
+                }
+            }
+            Op::Read => {
+                read_response(session, format, "Received Input Report", "Error reading input report", out)
+                    .with_context(|| location(step.line))?
+                    .ok_or_else(|| anyhow::anyhow!("{}: the read failed", location(step.line)))?;
+            }
+            Op::Expect(pattern) => expect_response(session, format, pattern, out).with_context(|| location(step.line))?,
+            Op::Sleep(duration) => std::thread::sleep(*duration),
+            Op::Env(tags) => out.new_segment(tags).with_context(|| location(step.line))?,
+            Op::Loop { count, body } => {
+                for _ in 0..*count {
+                    run_steps(session, format, body, location, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send the `send` entries of a timeline at their offsets, divided by
+/// `speed`, for `timeline` and `replay`.
+///
+/// Reports are built before the clock starts. Each write is audited as it
+/// happens, but only printed once the timeline is over, so that printing
+/// doesn't run on schedule.
+fn play_timeline(api: &HidApi, device: &DeviceArgs, report: &ReportArgs, file: &Path, speed: f64, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let timeline = Timeline::load(file)?;
+    let location = |line: usize| format!("{} line {}", file.display(), line);
+    let entries: Vec<_> = timeline.entries.iter().filter(|entry| entry.action == Action::Send).collect();
+    if !entries.is_empty() {
+        guard.require_raw()?;
+    }
+    for entry in &entries {
+        guard.check(report.payload(&entry.data), out).with_context(|| location(entry.line))?;
+    }
+
+    let offsets = entries.iter()
+        .map(|entry| Duration::try_from_secs_f64(entry.offset.as_secs_f64() / speed)
+            .map_err(|_| anyhow::anyhow!("{}: offset is too large at speed {}", location(entry.line), speed)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let (session, format) = open_configured(api, device, report, out)?;
+    let reports = entries.iter()
+        .map(|entry| format.output_report(session.report_size(), &entry.data).with_context(|| location(entry.line)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let played = timeline::play(&session, &reports, &offsets, &mut Recorded(out));
+    let mut lateness = LatencyStats::default();
+    for ((entry, report), sent) in entries.iter().zip(&reports).zip(&played.sent) {
+        out.output_report(format.report_id, report);
+        out.output_shown(&sent.result);
+        if sent.result.is_err() {
+            return Err(anyhow::anyhow!("{}: sending {} failed", location(entry.line), hex::encode(&entry.data)));
+        }
+        out.scheduled(sent.offset, sent.late);
+        lateness.record(sent.late);
+    }
+    if let Some(i) = played.unreachable {
+        return Err(anyhow::anyhow!("{}: offset is too far in the future", location(entries[i].line)));
+    }
+    out.timeline_done(&lateness);
+    Ok(())
+}
+
+/// Send one output report per hex line on stdin until it ends, recording each
+/// with its offset, and with `--responses` the input reports in between, as a
+/// timeline. Sends keep the data as typed, so `replay` frames them the same
+/// way; received reports are recorded whole.
+fn record(api: &HidApi, args: &RecordArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let file = std::fs::File::create(&args.file)
+        .map_err(|e| anyhow::anyhow!("Cannot create recording {}: {}", args.file.display(), e))?;
+
+    let header = format!("Recorded by hid-cmd {} at {}", env!("CARGO_PKG_VERSION"), hid_cmd::timestamp::now_utc());
+    let mut recorder = Recorder::new(io::BufWriter::new(file), &header)?;
+    let report_size = session.report_size();
+    let (sender, events) = std::sync::mpsc::channel();
+    let duplex = Duplex::start(session, format.input_buffer, sender.clone());
+    duplex::forward_stdin(sender);
+    out.info("Recording; enter one output report per line in hex, and end with Ctrl-D");
+
+    // Typed data of the reports queued but not written yet, in order
+    let mut queued = std::collections::VecDeque::new();
+    let (mut sent, mut received) = (0, 0);
+    let mut stdin_ended = false;
+    while !(stdin_ended && queued.is_empty()) {
+        let Ok(event) = events.recv() else {
+            break;
+        };
+        match event {
+            Event::Line(line) => {
+                let Some(data) = typed_line(&line?, &format, guard, out) else {
+                    continue;
+                };
+                let report = format.output_report(report_size, &data)?;
+                out.output_report(format.report_id, &report);
+                duplex.write(report);
+                queued.push_back(data);
+            }
+            Event::StdinEnded => stdin_ended = true,
+            Event::Written { report, result } => {
+                out.output_result(&report, &result);
+                let data = queued.pop_front().unwrap_or_default();
+                if result.is_err() {
+                    duplex.stop();
+                    return Err(anyhow::anyhow!("Sending {} failed", hex::encode(&data)));
+                }
+                recorder.record(Action::Send, &data)?;
+                sent += 1;
+            }
+            Event::Input { report, .. } => {
+                out.input_report("Received Input Report", &report, format.report_id);
+                if args.responses {
+                    recorder.record(Action::Recv, &report)?;
+                    received += 1;
+                }
+            }
+            Event::ReadError(e) => {
+                out.read_error("Error reading input report", &e);
+                break;
+            }
+        }
+    }
+    duplex.stop();
+    out.recording_done(&args.file, sent, received);
+    Ok(())
+}
+
+/// The data of a hex line typed into `record` or `monitor --interactive`, or
+/// `None` for blank lines and comments. Invalid and denied lines are reported
+/// and skipped rather than ending the session.
+fn typed_line(line: &str, format: &ReportFormat, guard: &WriteGuard, out: &mut Reporter) -> Option<Vec<u8>> {
+    let code = line.split_once('#').map_or(line, |(code, _)| code);
+    let digits: String = code.split_whitespace().collect();
+    if digits.is_empty() {
+        return None;
+    }
+    let data = match hex::decode(&digits) {
+        Ok(data) => data,
+        Err(e) => {
+            out.line_skipped(line.trim(), &anyhow::anyhow!("invalid hex data: {}", e));
+            return None;
+        }
+    };
+    if let Err(e) = guard.check(format.data_payload(&data), out) {
+        out.line_skipped(line.trim(), &e);
+        return None;
+    }
+    Some(data)
+}
+
+/// Test suite properties for a JUnit report: who ran it and the environment
+/// tags.
+fn junit_properties(out: &Reporter) -> Vec<(String, String)> {
+    let identity = out.identity();
+    let who = [("user", &identity.user), ("operator", &identity.operator)];
+    let mut properties: Vec<_> = who.into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.clone()?)))
+        .collect();
+    properties.extend(out.environment().tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+    properties
+}
+
+/// Write `cases` to the JUnit report at `path` as the test suite `suite`,
+/// which started at `timestamp`.
+fn write_junit(path: &Path, suite: &str, timestamp: &str, cases: &[Case], out: &Reporter) -> anyhow::Result<()> {
+    let properties = junit_properties(out);
+    let file = std::fs::File::create(path).map_err(|e| anyhow::anyhow!("Cannot create {}: {}", path.display(), e))?;
+    target_test::write_junit(&mut io::BufWriter::new(file), suite, timestamp, cases, &properties)
+        .map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+/// Start the on-target tests and collect their results until the device sends
+/// its end record. The JUnit report is written even when the run is cut short,
+/// with the results that arrived.
+fn target_test(api: &HidApi, args: &TargetTestArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let start = hex::decode(&args.start)?;
+    guard.check(args.report.payload(&start), out)?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let suite = args.suite.clone()
+        .or_else(|| session.device_info().ok()?.product_string().map(str::to_string))
+        .unwrap_or_else(|| "target".to_string());
+    let timeout = format.read_timeout.unwrap_or(TARGET_TEST_TIMEOUT);
+
+    // Records left over from an earlier run
+    let mut input_report = vec![0u8; format.input_buffer];
+    while session.read_report_timeout(&mut input_report, Duration::ZERO)? > 0 {}
+    let timestamp = hid_cmd::timestamp::now_utc();
+    let mut last = send_output_report(&session, &start, &format, out)
+        .ok_or_else(|| anyhow::anyhow!("Sending the start report failed"))?;
+
+    let mut cases = Vec::new();
+    let mut output = String::new();
+    let end = loop {
+        let len = session.read_report_timeout(&mut input_report, timeout)?;
+        if len == 0 {
+            out.no_response(timeout);
+            break None;
+        }
+        let report = &input_report[..len];
+        out.quiet_input(report);
+        let Some(payload) = input_payload(&format, report, out) else {
+            continue;
+        };
+        match Record::parse(&payload) {
+            Ok(None) => {}
+            Ok(Some(Record::Output(text))) => output.push_str(&text),
+            Ok(Some(Record::Result { status, name, message })) => {
+                let case = Case { name, status, message, output: std::mem::take(&mut output), time: last.elapsed() };
+                last = Instant::now();
+                out.test_result(&case);
+                cases.push(case);
+            }
+            Ok(Some(Record::End { total, failed })) => break Some((total, failed)),
+            Err(e) => out.test_record_invalid(report, &e),
+        }
+    };
+    out.target_test_done(&cases);
+
+    if let Some(path) = &args.junit {
+        write_junit(path, &suite, &timestamp, &cases, out)?;
+    }
+
+    let failed = cases.iter().filter(|c| c.status == Status::Fail).count();
+    let Some((total, device_failed)) = end else {
+        return Err(anyhow::anyhow!("No test record within {} ms; the run is incomplete", timeout.as_millis()));
+    };
+    if usize::from(total) != cases.len() || usize::from(device_failed) != failed {
+        return Err(anyhow::anyhow!("The device counted {} tests and {} failures, but {} results with {} failures arrived",
+            total, device_failed, cases.len(), failed));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} on-target tests failed", failed, cases.len()));
+    }
+    Ok(())
+}
+
+/// Forward local input events to the device as mapped output reports until
+/// the kill key is pressed.
+fn passthrough(api: &HidApi, args: &PassthroughArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let map = InputMap::load(&args.map)?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+
+    let inputs: Vec<String> = args.inputs.iter().map(|p| p.display().to_string()).collect();
+    if !args.yes {
+        eprint!("Everything typed or moved on {} will be sent to the device and logged, passwords included.\n\
+            Press the kill key (evdev code {}) to stop. Type 'yes' to start: ", inputs.join(", "), args.kill_key);
+        io::stderr().flush()?;
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        if line.trim() != "yes" {
+            return Err(anyhow::anyhow!("Passthrough not confirmed"));
+        }
+    }
+
+    // Inputs are opened only now, so the confirmation isn't captured
+    let (events, received) = std::sync::mpsc::channel();
+    for path in &args.inputs {
+        let mut file = std::fs::File::open(path)
+            .map_err(|e| anyhow::anyhow!("Cannot open input device {}: {}", path.display(), e))?;
+        let path = path.clone();
+        let events = events.clone();
+        std::thread::spawn(move || loop {
+            let event = passthrough::read_event(&mut file)
+                .map_err(|e| anyhow::anyhow!("Cannot read input device {}: {}", path.display(), e));
+            let failed = event.is_err();
+            if events.send(event).is_err() || failed {
+                return;
+            }
+        });
+    }
+    drop(events);
+    out.info(&format!("Capturing {}; press the kill key to stop", inputs.join(", ")));
+
+    // Releases and repeats of keys that were already down when capture
+    // started would reach the device without their press
+    let mut pressed = std::collections::BTreeSet::new();
+    while let Ok(event) = received.recv() {
+        let event = event?;
+        if event.is_key_press(args.kill_key) {
+            out.info("Kill key pressed, stopping");
+            return Ok(());
+        }
+        if event.kind == passthrough::EV_KEY {
+            match event.value {
+                1 => {
+                    pressed.insert(event.code);
+                }
+                0 if !pressed.remove(&event.code) => continue,
+                2 if !pressed.contains(&event.code) => continue,
+                _ => {}
+            }
+        }
+        let Some(payload) = map.payload(&event)? else {
+            continue;
+        };
+        guard.check(format.data_payload(&payload), out)?;
+        if send_output_report(&session, &payload, &format, out).is_none() {
+            return Err(anyhow::anyhow!("Sending the report for input event {}:{} failed", event.kind, event.code));
+        }
+    }
+    Ok(())
+}
+
+/// Time `--count` request/response round trips. Reports aren't shown, so that
+/// printing doesn't count towards the latency; each write is still audited.
+fn bench(api: &HidApi, args: &BenchArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let bytes = args.source.load(args.data.as_deref())?;
+    guard.check(args.report.payload(&bytes), out)?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    args.source.check_fits(&bytes, session.report_size())?;
+    let request = format.output_report(session.report_size(), &bytes)?;
+    out.info(&format!("Running {} round trips after {} to warm up", args.count, args.warmup));
+    let bench = Bench { request: &request, expect: args.expect.as_ref(), warmup: args.warmup, count: args.count };
+    let summary = bench.run(session.transport(), &format, &mut Quiet(out))?;
+    out.bench_done(&summary.latencies, summary.lost, summary.elapsed, summary.bytes);
+    if summary.latencies.count() == 0 {
+        return Err(NoResponse.into());
+    }
+    Ok(())
+}
+
+/// One of the two devices `compare` drives.
+struct Unit {
+    name: &'static str,
+    session: HidSession,
+    format: ReportFormat,
+    info: Option<hidapi::DeviceInfo>,
+    /// Input report fields from the descriptor; empty to compare bytes.
+    fields: Vec<descriptor::Field>,
+}
+
+/// Responses compared so far, and how many of them differed.
+#[derive(Default)]
+struct Tally {
+    responses: usize,
+    differing: usize,
+}
+
+/// Run a script against two units and diff each pair of responses.
+fn compare(api: &HidApi, args: &CompareArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let script = Script::load(&args.script)?;
+    let location = |line: usize| format!("{} line {}", args.script.display(), line);
+    let payloads = script.payloads();
+    if !payloads.is_empty() {
+        guard.require_raw()?;
+    }
+    for (line, data) in payloads {
+        guard.check(args.report.payload(data), out).with_context(|| location(line))?;
+    }
+    let mut tolerances = Tolerances::default();
+    for (field, n) in &args.tolerances {
+        tolerances.insert(field, *n);
+    }
+
+    let mut units = Vec::new();
+    for (name, unit) in [("golden", &args.golden), ("suspect", &args.suspect)] {
+        let mut device = args.device.clone();
+        if unit.contains(['/', '\\']) {
+            device.path = Some(unit.clone());
+        } else {
+            device.serial = Some(unit.clone());
+        }
+        out.info(&format!("Opening the {} unit", name));
+        let (session, format) = open_configured(api, &device, &args.report, out)?;
+        let fields = match (format.framing, session.report_descriptor()) {
+            (Framing::Raw, Ok(desc)) => descriptor::report_fields(&desc, descriptor::ReportKind::Input, format.report_id.unwrap_or(0)),
+            _ => Vec::new(),
+        };
+        let info = session.device_info().ok();
+        units.push(Unit { name, session, format, info, fields });
+    }
+
+    let mut tally = Tally::default();
+    compare_steps(&units, &script.steps, &location, &tolerances, &mut tally, out)?;
+    out.compare_done(tally.responses, tally.differing);
+    if tally.differing > 0 {
+        return Err(anyhow::anyhow!("The suspect unit differs from the golden one in {} of {} responses", tally.differing, tally.responses));
+    }
+    Ok(())
+}
+
+/// Send each `send` to both units in turn and compare what they answer to each
+/// `read` or `expect`. An `expect` checks the golden unit's response, so a
+/// golden unit in the wrong state stops the comparison.
+fn compare_steps(units: &[Unit], steps: &[Step], location: &dyn Fn(usize) -> String, tolerances: &Tolerances, tally: &mut Tally, out: &mut Reporter) -> anyhow::Result<()> {
+    for step in steps {
+        match &step.op {
+            Op::Send(data) => {
+                for unit in units {
+                    if let Some(info) = &unit.info {
+                        out.select_device(info);
+                    }
+                    if send_output_report(&unit.session, data, &unit.format, out).is_none() {
+                        return Err(anyhow::anyhow!("{}: sending {} to the {} unit failed", location(step.line), hex::encode(data), unit.name));
+                    }
+                }
+            }
+            Op::Read | Op::Expect(_) => {
+                let mut responses = Vec::new();
+                for (i, unit) in units.iter().enumerate() {
+                    let label = format!("Response of the {} unit", unit.name);
+                    let Op::Expect(pattern) = &step.op else {
+                        let payload = match read_response(&unit.session, &unit.format, &label, "Error reading response", out) {
+                            Ok(response) => response.and_then(|r| r.payload),
+                            // A unit that stays silent is a difference, not a failure
+                            Err(e) if e.is::<NoResponse>() => None,
+                            Err(e) => return Err(e.context(location(step.line))),
+                        };
+                        responses.push(payload);
+                        continue;
+                    };
+                    // The golden unit must answer as `expect` says, as with
+                    // `--expect`; whatever the suspect answers with the same
+                    // report ID is compared against it
+                    let (transport, format) = (unit.session.transport(), &unit.format);
+                    let shown = &mut Shown { out, format, label: &label };
+                    let response = if i == 0 {
+                        exchange::expect(transport, format, pattern, shown)
+                    } else {
+                        let deadline = Instant::now() + format.response_timeout();
+                        exchange::read_until(transport, format.input_buffer, deadline, shown, |r| format.payload(r).is_some())
+                    };
+                    let response = response.with_context(|| location(step.line))?;
+                    if i == 0 && response.is_none() {
+                        return Err(anyhow::anyhow!("{}: no response from the golden unit matching {} within {} ms",
+                            location(step.line), pattern, format.response_timeout().as_millis()));
+                    }
+                    responses.push(response.and_then(|r| format.payload(&r.report)).and_then(Result::ok));
+                }
+                let (golden, suspect) = (&responses[0], &responses[1]);
+                let (differences, silent) = match (golden, suspect) {
+                    (Some(g), Some(s)) => (compare::diff(&field_values(&units[0].fields, g), &field_values(&units[1].fields, s), tolerances), None),
+                    (None, None) => (Vec::new(), None),
+                    (Some(_), None) => (Vec::new(), Some(units[1].name)),
+                    (None, Some(_)) => (Vec::new(), Some(units[0].name)),
+                };
+                tally.responses += 1;
+                if !differences.is_empty() || silent.is_some() {
+                    tally.differing += 1;
+                }
+                out.responses_compared(&location(step.line), &differences, silent);
+            }
+            Op::Sleep(duration) => std::thread::sleep(*duration),
+            Op::Env(tags) => out.new_segment(tags).with_context(|| location(step.line))?,
+            Op::Loop { count, body } => {
+                for _ in 0..*count {
+                    compare_steps(units, body, location, tolerances, tally, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send `--count` random payloads and check each response for the echo.
+/// Reports are only shown when the echo is wrong.
+fn loopback(api: &HidApi, args: &LoopbackArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let report_id = transfer_report_id(&format);
+    let capacity = format.framing.max_payload(session.report_size());
+    let length = args.length.unwrap_or(capacity.saturating_sub(args.offset));
+    if length == 0 || length > capacity {
+        return Err(anyhow::anyhow!("Payloads of {} bytes don't fit in {}-byte reports", length, session.report_size()));
+    }
+    let echo = Echo { offset: args.offset, add: args.add };
+    let seed = args.seed.unwrap_or_else(Rng::seed_from_time);
+    out.info(&format!("Sending {} random payloads of {} bytes (seed {})", args.count, length, seed));
+
+    let test = Loopback { echo, length, count: args.count, seed, report_id };
+    let timeout = format.response_timeout();
+    let timestamp = hid_cmd::timestamp::now_utc();
+    let mut echoes = Echoes { out, timeout, cases: Vec::new(), last: Instant::now() };
+    let loopback::Summary { mismatched, lost } = test.run(&session, &format, |payload| guard.denies(payload), &mut echoes)?;
+    let cases = echoes.cases;
+    out.loopback_done(args.count, mismatched, lost, seed);
+    if let Some(path) = &args.junit {
+        write_junit(path, &args.suite, &timestamp, &cases, out)?;
+    }
+    if mismatched + lost > 0 {
+        return Err(anyhow::anyhow!("Loopback failed: {} of {} payloads were not echoed correctly", mismatched + lost, args.count));
+    }
+    Ok(())
+}
+
+/// Shows how each `loopback` payload fared, and nothing of the reports, and
+/// keeps each outcome as a test case for `--junit`.
+struct Echoes<'a> {
+    out: &'a mut Reporter,
+    timeout: Duration,
+    cases: Vec<Case>,
+    /// When the previous outcome was known.
+    last: Instant,
+}
+
+impl exchange::Observer for Echoes<'_> {
+    fn sent(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+        self.out.quiet_output(report, result);
+    }
+
+    fn received(&mut self, report: &[u8]) {
+        self.out.quiet_input(report);
+    }
+}
+
+impl loopback::Observer for Echoes<'_> {
+    fn outcome(&mut self, payload: u32, outcome: &Outcome) {
+        let message = match outcome {
+            Outcome::Echoed => None,
+            Outcome::Lost => {
+                self.out.loopback_lost(payload, self.timeout);
+                Some(format!("No response within {} ms", self.timeout.as_millis()))
+            }
+            Outcome::BadFrame(e) => {
+                self.out.frame_error(e);
+                Some(format!("Invalid frame: {}", e))
+            }
+            Outcome::Mismatch { expected, echoed, positions } => {
+                self.out.loopback_mismatch(payload, expected, echoed, positions);
+                Some(format!("Wrong echo of {} byte(s): expected {}, received {}", positions.len(), hex::encode(expected), hex::encode(echoed)))
+            }
+        };
+        let (status, message) = message.map_or((Status::Pass, String::new()), |message| (Status::Fail, message));
+        let time = self.last.elapsed();
+        self.last = Instant::now();
+        self.cases.push(Case { name: format!("payload {}", payload), status, message, output: String::new(), time });
+    }
+}
 
-const HID_REPORT_SIZE: usize = 64;
-const VENDOR_PAGE: u16 = 0xFF42;
-const MAX_RETRIES: u32 = 3;
-const RETRY_DELAY_MS: u64 = 100;
+/// Binary search over `--images` for the first one that fails `--script`,
+/// flashing each candidate with `--flash` and testing it once it is back.
+fn bisect_firmware(api: &mut HidApi, args: &BisectFirmwareArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    if !args.flash.contains("{}") {
+        return Err(anyhow::anyhow!("--flash must contain {{}} where the image path goes"));
+    }
+    for image in &args.images {
+        if !image.is_file() {
+            return Err(anyhow::anyhow!("Firmware image {} does not exist", image.display()));
+        }
+    }
+    let script = Script::load(&args.script)?;
+    let location = |line: usize| format!("{} line {}", args.script.display(), line);
+    let payloads = script.payloads();
+    if !payloads.is_empty() {
+        guard.require_raw()?;
+    }
+    for (line, data) in payloads {
+        guard.check(args.report.payload(data), out).with_context(|| location(line))?;
+    }
 
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Vendor ID of the HID device (hexadecimal)
-    #[arg(short, long, value_parser = parse_hex)]
-    vid: u16,
+    let mut test = |index: usize, out: &mut Reporter| -> anyhow::Result<bool> {
+        let image = &args.images[index];
+        out.bisect_testing(index, args.images.len(), image);
+        let flashed = flash_image(&args.flash, image);
+        out.firmware_flashed(image, &flashed);
+        flashed?;
+        std::thread::sleep(args.settle);
+        let result = wait_for_device(api, &args.device, Some(&args.report), Some(Instant::now() + args.boot_timeout), out)
+            .and_then(|session| configure_reports(session, &args.report, out))
+            .and_then(|(session, format)| run_steps(&session, &format, &script.steps, &location, out));
+        out.bisect_verdict(image, &result);
+        Ok(result.is_ok())
+    };
 
-    /// Product ID of the HID device (hexadecimal)
-    #[arg(short, long, value_parser = parse_hex)]
-    pid: u16,
+    let (mut good, mut bad) = (0, args.images.len() - 1);
+    if !args.skip_endpoints {
+        if !test(good, out)? {
+            return Err(anyhow::anyhow!("The first image, {}, already fails the script", args.images[good].display()));
+        }
+        if test(bad, out)? {
+            return Err(anyhow::anyhow!("The last image, {}, passes the script", args.images[bad].display()));
+        }
+    }
+    while bad - good > 1 {
+        let mid = good + (bad - good) / 2;
+        if test(mid, out)? {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+    out.bisect_done(&args.images[good], &args.images[bad]);
+    Ok(())
+}
 
-    /// Data to send (hex string, will be padded to 64 bytes)
-    #[arg(short = 's', long)]
-    data: Option<String>,
+/// Run the `--flash` command for `image` and wait for it to finish.
+fn flash_image(command: &str, image: &Path) -> anyhow::Result<()> {
+    let path = image.display().to_string();
+    #[cfg(windows)]
+    let status = std::process::Command::new("cmd")
+        .args(["/C", &command.replace("{}", &format!("\"{}\"", path))])
+        .status();
+    #[cfg(not(windows))]
+    let status = std::process::Command::new("sh")
+        .args(["-c", &command.replace("{}", &format!("'{}'", path.replace('\'', "'\\''")))])
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(anyhow::anyhow!("Flashing {} failed: the flash command exited with {}", path, status)),
+        Err(e) => Err(anyhow::anyhow!("Cannot run the flash command: {}", e)),
+    }
+}
 
-    /// Number of times to retry if device is busy
-    #[arg(short = 'r', long, default_value = "3")]
-    retries: u32,
+/// Transfers always use report IDs, 0 for devices without numbered reports, so
+/// that the first payload byte is never taken for one.
+fn transfer_report_id(format: &ReportFormat) -> u8 {
+    format.report_id.unwrap_or(0)
+}
 
-    /// Delay between retries in milliseconds
-    #[arg(short = 'd', long, default_value = "100")]
-    retry_delay: u64,
+fn send_file(api: &HidApi, args: &SendFileArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let data = std::fs::read(&args.file)
+        .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", args.file.display(), e))?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let report_id = transfer_report_id(&format);
+    let frames = transfer::frames(&data, transfer::chunk_capacity(format.framing.max_payload(session.report_size())))?;
+    for frame in &frames {
+        guard.check(frame, out)?;
+    }
 
-    /// Keep reading input reports after sending data
-    #[arg(short = 'c', long = "continuous", default_value = "false")]
-    continuous: bool,
+    out.info(&format!("\nSending {} ({} bytes) in {} chunks", args.file.display(), data.len(), frames.len()));
+    let started = Instant::now();
+    let mut sent = 0;
+    for (i, frame) in frames.iter().enumerate() {
+        let framed = format.framing.encode(frame)?;
+        let result = session.send_numbered_report(report_id, &framed);
+        out.quiet_output(&hid_cmd::numbered_report(report_id, &framed, session.report_size()), &result);
+        result.with_context(|| format!("Sending chunk {} of {}", i + 1, frames.len()))?;
+        if let Some(ack) = &args.ack {
+            wait_for_ack(&session, &format, report_id, ack, out).with_context(|| format!("Chunk {} of {}", i + 1, frames.len()))?;
+        }
+        sent += frame[2] as usize;
+        out.transfer_progress("Sending", sent, Some(data.len()));
+        if !args.delay.is_zero() {
+            std::thread::sleep(args.delay);
+        }
+    }
+    out.transfer_done("Sent", &args.file, data.len(), frames.len(), started.elapsed());
+    Ok(())
 }
 
-fn parse_hex(s: &str) -> Result<u16, String> {
-    let s = s.trim_start_matches("0x");
-    u16::from_str_radix(s, 16).map_err(|e| e.to_string())
+/// Read input reports, without showing them, until one matches `ack`. Gives up
+/// after `--timeout`, or [`EXPECT_TIMEOUT_MS`] without one.
+fn wait_for_ack(session: &HidSession, format: &ReportFormat, report_id: u8, ack: &HexPattern, out: &mut Reporter) -> anyhow::Result<()> {
+    let timeout = format.response_timeout();
+    let deadline = Instant::now() + timeout;
+    let mut input_report = vec![0u8; format.input_buffer];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let len = session.read_report_timeout(&mut input_report, remaining)?;
+        if len == 0 {
+            return Err(anyhow::anyhow!("No acknowledgement matching {} within {} ms", ack, timeout.as_millis()));
+        }
+        out.quiet_input(&input_report[..len]);
+        // Reports on other IDs aren't meant for the transfer
+        let Ok(payload) = hid_cmd::strip_report_id(&input_report[..len], report_id) else {
+            continue;
+        };
+        let payload = format.framing.decode(payload)?;
+        if !ack.matches_prefix(&payload) {
+            return Err(anyhow::anyhow!("Expected acknowledgement {}, got {}", ack, hex::encode(&payload)));
+        }
+        return Ok(());
+    }
 }
 
-fn open_device_with_retry(api: &HidApi, vid: u16, pid: u16, vendor_page: u16, max_retries: u32, retry_delay_ms: u64) -> anyhow::Result<hidapi::HidDevice> {
-    let mut last_error = None;
-    
-    for attempt in 0..=max_retries {
-        // Find the vendor-specific interface
-        let device_info = api.device_list()
-            .find(|d| d.vendor_id() == vid && 
-                      d.product_id() == pid && 
-                      d.usage_page() == vendor_page)
-            .or_else(|| {
-                // Fallback to any interface if vendor page not found
-                api.device_list()
-                    .find(|d| d.vendor_id() == vid && 
-                              d.product_id() == pid)
-            });
+fn recv_file(api: &HidApi, args: &RecvFileArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let request = args.send.as_deref().map(hex::decode).transpose()?;
+    if let Some(request) = &request {
+        guard.require_raw()?;
+        guard.check(request, out)?;
+    }
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let report_id = transfer_report_id(&format);
+    if let Some(request) = &request {
+        if send_output_report(&session, request, &ReportFormat { report_id: Some(report_id), ..format }, out).is_none() {
+            return Err(anyhow::anyhow!("Could not ask the device to start the transfer"));
+        }
+    }
+
+    out.info(&format!("\nReceiving into {}", args.file.display()));
+    let started = Instant::now();
+    let reassembler = receive_chunks(&session, &format, report_id, out)?;
+    let chunks = reassembler.chunks();
+    let data = reassembler.into_data();
+    std::fs::write(&args.file, &data).map_err(|e| anyhow::anyhow!("Cannot write {}: {}", args.file.display(), e))?;
+    out.transfer_done("Received", &args.file, data.len(), chunks, started.elapsed());
+    Ok(())
+}
+
+/// Reassemble the file transfer chunks on `report_id` until the last one.
+fn receive_chunks(session: &HidSession, format: &ReportFormat, report_id: u8, out: &mut Reporter) -> anyhow::Result<Reassembler> {
+    let mut reassembler = Reassembler::new();
+    let mut input_report = vec![0u8; format.input_buffer];
+    loop {
+        let len = match format.read_timeout {
+            Some(timeout) => session.read_report_timeout(&mut input_report, timeout),
+            None => session.read_report(&mut input_report),
+        }.with_context(|| format!("Reading chunk {}", reassembler.chunks() + 1))?;
+        if len == 0 {
+            let timeout = format.read_timeout.unwrap_or_default();
+            out.no_response(timeout);
+            return Err(anyhow::Error::new(NoResponse)
+                .context(format!("Transfer stopped after {} chunks ({} bytes)", reassembler.chunks(), reassembler.data().len())));
+        }
+        out.quiet_input(&input_report[..len]);
+        let Ok(payload) = hid_cmd::strip_report_id(&input_report[..len], report_id) else {
+            continue;
+        };
+        let payload = format.framing.decode(payload).with_context(|| format!("Chunk {}", reassembler.chunks() + 1))?;
+        if reassembler.push(&payload)? {
+            return Ok(reassembler);
+        }
+        out.transfer_progress("Receiving", reassembler.data().len(), None);
+    }
+}
+
+/// `crashdump pull`: ask for the crash dump like `recv-file`, then show it.
+fn crashdump_pull(api: &HidApi, args: &CrashdumpPullArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let request = args.send.as_deref().map(hex::decode).transpose()?;
+    if let Some(request) = &request {
+        guard.require_raw()?;
+        guard.check(request, out)?;
+    }
+    // Before the device, so that a wrong path doesn't cost a transfer
+    let symbols = args.elf.as_deref().map(crashdump::Symbols::load).transpose()?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let report_id = transfer_report_id(&format);
+    if let Some(request) = &request {
+        if send_output_report(&session, request, &ReportFormat { report_id: Some(report_id), ..format }, out).is_none() {
+            return Err(anyhow::anyhow!("Could not ask the device for its crash dump"));
+        }
+    }
+    let blob = receive_chunks(&session, &format, report_id, out)?.into_data();
+    if let Some(path) = &args.save {
+        std::fs::write(path, &blob).map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))?;
+    }
+    let dump = crashdump::Dump::parse(&blob).context("The device sent an invalid crash dump")?;
+    out.crash_dump(&dump, symbols.as_ref());
+    Ok(())
+}
 
-        if let Some(device_info) = device_info {
-            match api.open_path(device_info.path()) {
-                Ok(device) => return Ok(device),
+fn crashdump_show(args: &CrashdumpShowArgs, out: &mut Reporter) -> anyhow::Result<()> {
+    let blob = std::fs::read(&args.file).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", args.file.display(), e))?;
+    let dump = crashdump::Dump::parse(&blob).with_context(|| format!("{}", args.file.display()))?;
+    let symbols = args.elf.as_deref().map(crashdump::Symbols::load).transpose()?;
+    out.crash_dump(&dump, symbols.as_ref());
+    Ok(())
+}
+
+/// One `--itm` output file of `trace pull`.
+struct PortFile {
+    path: PathBuf,
+    file: io::BufWriter<std::fs::File>,
+    bytes: usize,
+}
+
+fn create_trace_file(path: &Path) -> anyhow::Result<io::BufWriter<std::fs::File>> {
+    std::fs::File::create(path)
+        .map(io::BufWriter::new)
+        .map_err(|e| anyhow::anyhow!("Cannot create {}: {}", path.display(), e))
+}
+
+/// Write what arrives on stimulus `port` to its own file next to `trace`.
+fn write_itm_port(ports: &mut BTreeMap<u8, PortFile>, trace: &Path, port: u8, data: &[u8]) -> anyhow::Result<()> {
+    let port = match ports.entry(port) {
+        std::collections::btree_map::Entry::Occupied(entry) => entry.into_mut(),
+        std::collections::btree_map::Entry::Vacant(entry) => {
+            let mut path = trace.as_os_str().to_owned();
+            path.push(format!(".port{}", port));
+            let path = PathBuf::from(path);
+            let file = create_trace_file(&path)?;
+            entry.insert(PortFile { path, file, bytes: 0 })
+        }
+    };
+    port.file.write_all(data).map_err(|e| anyhow::anyhow!("Cannot write {}: {}", port.path.display(), e))?;
+    port.bytes += data.len();
+    Ok(())
+}
+
+/// Stream the firmware's trace into a file until its last chunk, or until
+/// `--timeout` passes without one. Everything is written as it arrives, so a
+/// pull stopped early keeps what came before. Lost and corrupt chunks leave a
+/// gap and are counted rather than ending the pull.
+fn trace_pull(api: &HidApi, args: &TracePullArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    let start = args.start.as_deref().map(hex::decode).transpose()?;
+    let ack = args.ack.as_deref().map(hex::decode).transpose()?;
+    if start.is_some() || ack.is_some() {
+        guard.require_raw()?;
+    }
+    for request in start.iter().chain(&ack) {
+        guard.check(request, out)?;
+    }
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let report_id = transfer_report_id(&format);
+    let ack = ack.map(|ack| format.framing.encode(&ack)).transpose()?;
+    let mut trace = create_trace_file(&args.file)?;
+    let mut itm = args.itm.then(ItmDemux::new);
+    let mut ports = BTreeMap::new();
+    if let Some(start) = &start {
+        if send_output_report(&session, start, &ReportFormat { report_id: Some(report_id), ..format }, out).is_none() {
+            return Err(anyhow::anyhow!("Could not ask the device to start tracing"));
+        }
+    }
+
+    out.info(&format!("\nPulling trace into {}", args.file.display()));
+    let started = Instant::now();
+    let mut stream = Stream::new();
+    let mut unacknowledged = 0;
+    let mut input_report = vec![0u8; format.input_buffer];
+    while !stream.done() {
+        let len = match format.read_timeout {
+            Some(timeout) => session.read_report_timeout(&mut input_report, timeout),
+            None => session.read_report(&mut input_report),
+        }.with_context(|| format!("Reading chunk {}", stream.chunks() + 1))?;
+        if len == 0 {
+            out.idle(format.read_timeout.unwrap_or_default(), stream.chunks() > 0);
+            if stream.chunks() == 0 {
+                return Err(NoResponse.into());
+            }
+            break;
+        }
+        out.quiet_input(&input_report[..len]);
+        // Other reports can go on while the trace streams
+        let Ok(payload) = hid_cmd::strip_report_id(&input_report[..len], report_id) else {
+            continue;
+        };
+        let pushed = format.framing.decode(payload).and_then(|payload| match stream.push(&payload)? {
+            Pushed::Data { data, lost } => Ok((data.to_vec(), lost)),
+            Pushed::Duplicate { seq } => Err(anyhow::anyhow!("Chunk {} arrived again", seq)),
+        });
+        match pushed {
+            Ok((data, lost)) => {
+                if lost > 0 {
+                    out.trace_gap(lost);
+                }
+                trace.write_all(&data).map_err(|e| anyhow::anyhow!("Cannot write {}: {}", args.file.display(), e))?;
+                trace.flush()?;
+                if let Some(itm) = &mut itm {
+                    if lost > 0 {
+                        itm.reset();
+                    }
+                    let mut written = Ok(());
+                    itm.push(&data, |port, payload| {
+                        if written.is_ok() {
+                            written = write_itm_port(&mut ports, &args.file, port, payload);
+                        }
+                    });
+                    written?;
+                    for port in ports.values_mut() {
+                        port.file.flush()?;
+                    }
+                }
+            }
+            Err(e) => {
+                out.trace_chunk_dropped(&e);
+                if let Some(itm) = &mut itm {
+                    itm.reset();
+                }
+            }
+        }
+
+        // The firmware counts the chunks it sent, whatever became of them
+        unacknowledged += 1;
+        if let Some(ack) = &ack {
+            if unacknowledged == args.window && !stream.done() {
+                let result = session.send_numbered_report(report_id, ack);
+                out.quiet_output(&hid_cmd::numbered_report(report_id, ack, session.report_size()), &result);
+                result.context("Sending the acknowledgement")?;
+                unacknowledged = 0;
+            }
+        }
+        out.transfer_progress("Receiving", stream.bytes(), None);
+    }
+
+    let mut files = vec![(args.file.clone(), stream.bytes())];
+    files.extend(ports.into_values().map(|port| (port.path, port.bytes)));
+    out.trace_done(&stream, started.elapsed(), &files);
+    Ok(())
+}
+
+/// Poll the firmware's RTT up-buffer and print what it holds, while typed lines
+/// go to its down-buffer. Runs until the device goes away or stops answering;
+/// a poll answered with more to come is followed by the next one right away.
+fn rtt(api: &HidApi, args: &RttArgs, guard: &WriteGuard, out: &mut Reporter) -> anyhow::Result<()> {
+    guard.require_raw()?;
+    let poll = rtt::read_request(args.channel);
+    guard.check(args.report.payload(&poll), out)?;
+    let (session, format) = open_configured(api, &args.device, &args.report, out)?;
+    let report_size = session.report_size();
+    let poll = format.output_report(report_size, &poll)?;
+    let capacity = rtt::write_capacity(format.framing.max_payload(report_size));
+    let timeout = format.read_timeout.unwrap_or(RTT_TIMEOUT);
+    let (sender, events) = std::sync::mpsc::channel();
+    let duplex = Duplex::start(session, format.input_buffer, sender.clone());
+    duplex::forward_stdin(sender);
+    out.info(&format!("RTT channel {}; typed lines go to the down-buffer", args.channel));
+
+    // Typed bytes the down-buffer hasn't taken yet
+    let mut down = std::collections::VecDeque::new();
+    // When the poll or write awaiting an answer was queued, and the write's length
+    let mut polled: Option<Instant> = None;
+    let mut writing: Option<(Instant, usize)> = None;
+    let mut next_poll = Instant::now();
+    let result = loop {
+        if polled.is_none() && Instant::now() >= next_poll {
+            duplex.write(poll.clone());
+            polled = Some(Instant::now());
+        }
+        if writing.is_none() && !down.is_empty() {
+            let data: Vec<u8> = down.iter().take(capacity).copied().collect();
+            let request = rtt::write_request(args.channel, &data);
+            match guard.check(format.data_payload(&request), out).and_then(|()| format.output_report(report_size, &request)) {
+                Ok(report) => {
+                    duplex.write(report);
+                    writing = Some((Instant::now(), data.len()));
+                }
                 Err(e) => {
-                    last_error = Some(e);
-                    if attempt < max_retries {
-                        println!("Attempt {} failed: {}. Retrying in {}ms...", 
-                            attempt + 1, last_error.as_ref().unwrap(), retry_delay_ms);
-                        thread::sleep(Duration::from_millis(retry_delay_ms));
+                    out.line_skipped(String::from_utf8_lossy(&data).trim_end(), &e);
+                    down.drain(..data.len());
+                }
+            }
+        }
+
+        let due = [polled.map(|t| t + timeout).or(Some(next_poll)), writing.map(|(t, _)| t + timeout)];
+        let wait = due.into_iter().flatten().min().map_or(timeout, |t| t.saturating_duration_since(Instant::now()));
+        let event = match events.recv_timeout(wait) {
+            Ok(event) => event,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                let late = |since: Instant| since.elapsed() >= timeout;
+                if polled.is_some_and(late) || writing.is_some_and(|(t, _)| late(t)) {
+                    break Err(anyhow::anyhow!("The device didn't answer an RTT request within {} ms", timeout.as_millis()));
+                }
+                continue;
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break Ok(()),
+        };
+        match event {
+            Event::Input { report, .. } => {
+                out.quiet_input(&report);
+                let Some(Ok(payload)) = format.payload(&report) else {
+                    continue;
+                };
+                match rtt::Response::parse(&payload) {
+                    Ok(Some(rtt::Response::Up { channel, more, data })) if channel == args.channel => {
+                        out.rtt_output(channel, &data);
+                        polled = None;
+                        next_poll = if more { Instant::now() } else { Instant::now() + args.interval };
                     }
+                    Ok(Some(rtt::Response::Accepted { channel, count })) if channel == args.channel => {
+                        if let Some((_, sent)) = writing.take() {
+                            down.drain(..count.min(sent));
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => out.rtt_record_invalid(&report, &e),
                 }
             }
-        } else {
-            return Err(anyhow::anyhow!("Device not found"));
+            Event::Written { report, result } => {
+                out.quiet_output(&report, &result);
+                if let Err(e) = result {
+                    break Err(e.context("Writing an RTT request failed"));
+                }
+            }
+            Event::Line(line) => {
+                down.extend(line?.into_bytes());
+                down.push_back(b'\n');
+            }
+            // Keep logging; there is just nothing more to send
+            Event::StdinEnded => {}
+            Event::ReadError(e) => {
+                out.read_error("Error reading input report", &e);
+                break Ok(());
+            }
+        }
+    };
+    duplex.stop();
+    result
+}
+
+/// The virtual device of `emulate`, answering the host while a script runs.
+struct Emulator {
+    device: uhid::Device,
+    events: std::sync::mpsc::Receiver<io::Result<uhid::Event>>,
+    /// Feature report contents by ID, without the ID.
+    features: BTreeMap<u8, Vec<u8>>,
+    /// Output reports from the host that no `read` or `expect` has taken yet.
+    outputs: std::collections::VecDeque<Vec<u8>>,
+    uses_report_ids: bool,
+    input_sizes: BTreeMap<u8, usize>,
+    timeout: Duration,
+}
+
+impl Emulator {
+    /// Handle the next event from the host, or return `None` once `deadline`
+    /// passes without one.
+    fn next_event(&mut self, deadline: Option<Instant>, out: &mut Reporter) -> anyhow::Result<Option<uhid::Event>> {
+        use uhid::{Event, ReportType};
+        let event = match deadline {
+            Some(deadline) => match self.events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) => event,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => return Ok(None),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return Err(anyhow::anyhow!("{} closed", uhid::PATH)),
+            },
+            None => self.events.recv().map_err(|_| anyhow::anyhow!("{} closed", uhid::PATH))?,
+        };
+        let event = event.map_err(|e| anyhow::anyhow!("Reading from {} failed: {}", uhid::PATH, e))?;
+        match &event {
+            Event::Start => out.emulation_state("started", "The kernel started the virtual device"),
+            Event::Stop => out.emulation_state("stopped", "The kernel stopped the virtual device"),
+            Event::Open => out.emulation_state("opened", "The host opened the virtual device"),
+            Event::Close => out.emulation_state("closed", "The host closed the virtual device"),
+            Event::Output { data, .. } => {
+                out.host_report("Output Report from the host", data);
+                self.outputs.push_back(data.clone());
+            }
+            // Reports from the host start with their number, 0 without report IDs
+            Event::SetReport { id, number, kind, data } => {
+                match kind {
+                    ReportType::Feature => {
+                        out.host_report(&format!("Feature Report 0x{:02x} set by the host", number), data);
+                        self.features.insert(*number, data.get(1..).unwrap_or_default().to_vec());
+                    }
+                    _ => {
+                        out.host_report("Output Report from the host", data);
+                        self.outputs.push_back(data.clone());
+                    }
+                }
+                self.device.set_report_reply(*id, 0)?;
+            }
+            Event::GetReport { id, number, kind } => {
+                let value = self.features.get(number).filter(|_| *kind == ReportType::Feature);
+                match value {
+                    Some(value) => {
+                        let mut report = vec![*number];
+                        report.extend_from_slice(value);
+                        out.host_feature_read(*number, Some(&report));
+                        self.device.get_report_reply(*id, 0, &report)?;
+                    }
+                    None => {
+                        out.host_feature_read(*number, None);
+                        self.device.get_report_reply(*id, uhid::EIO, &[])?;
+                    }
+                }
+            }
+            Event::Other(_) => {}
+        }
+        Ok(Some(event))
+    }
+
+    /// Handle events until `wanted` arrives.
+    fn wait_for(&mut self, wanted: uhid::Event, out: &mut Reporter) -> anyhow::Result<()> {
+        while self.next_event(None, out)? != Some(wanted.clone()) {}
+        Ok(())
+    }
+
+    /// The next output report from the host, waiting up to `--timeout`.
+    fn read_output(&mut self, out: &mut Reporter) -> anyhow::Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + self.timeout;
+        while self.outputs.is_empty() {
+            if self.next_event(Some(deadline), out)?.is_none() {
+                return Ok(None);
+            }
+        }
+        Ok(self.outputs.pop_front())
+    }
+
+    /// Input report `data` padded to the length the descriptor gives it, so
+    /// that the kernel doesn't reject it as too short.
+    fn input_report(&self, data: &[u8]) -> Vec<u8> {
+        let id = if self.uses_report_ids { data.first().copied().unwrap_or(0) } else { 0 };
+        match self.input_sizes.get(&id) {
+            Some(&size) => {
+                let len = size + usize::from(self.uses_report_ids);
+                hid_cmd::pad_report(data, len.max(data.len()))
+            }
+            None => data.to_vec(),
         }
     }
-    
-    Err(anyhow::anyhow!("Failed to open device after {} retries: {}", 
-        max_retries, last_error.unwrap()))
 }
 
-fn read_input_reports(device: &hidapi::HidDevice) {
-    let mut input_report = [0u8; HID_REPORT_SIZE];
+/// Create a virtual device from a report descriptor and run a script as it,
+/// answering the host's feature report and output requests along the way.
+/// The device goes away when the script ends, unless `--hold` keeps it.
+fn emulate(args: &EmulateArgs, out: &mut Reporter) -> anyhow::Result<()> {
+    let raw = std::fs::read(&args.descriptor)
+        .map_err(|e| anyhow::anyhow!("Cannot read report descriptor {}: {}", args.descriptor.display(), e))?;
+    let descriptor = args.descriptor_format.decode(&raw)
+        .map_err(|e| anyhow::anyhow!("Invalid report descriptor {}: {}", args.descriptor.display(), e))?;
+    let script = args.script.as_deref().map(Script::load).transpose()?;
+    let config = uhid::Config {
+        name: args.name.clone(),
+        serial: args.serial.clone(),
+        bus: uhid::BUS_USB,
+        vendor_id: args.vid,
+        product_id: args.pid,
+        version: 0x0100,
+        descriptor,
+    };
+    let device = uhid::Device::create(&config)?;
+    let events = device.events()?;
+    out.emulation_state("created", &format!("Created virtual device {:04x}:{:04x} '{}'", args.vid, args.pid, args.name));
+    let mut emulator = Emulator {
+        device,
+        events,
+        features: args.features.iter().cloned().collect(),
+        outputs: Default::default(),
+        uses_report_ids: descriptor::uses_report_ids(&config.descriptor),
+        input_sizes: descriptor::report_sizes(&config.descriptor).input,
+        timeout: Duration::from_millis(args.timeout),
+    };
+
+    emulator.wait_for(uhid::Event::Start, out)?;
+    if args.wait_open {
+        out.info("Waiting for the host to open the device");
+        emulator.wait_for(uhid::Event::Open, out)?;
+    }
+    if let Some(script) = &script {
+        let path = args.script.as_deref().unwrap_or(Path::new(""));
+        let location = |line: usize| format!("{} line {}", path.display(), line);
+        emulate_steps(&mut emulator, &script.steps, &location, out)?;
+        if !args.hold {
+            return Ok(());
+        }
+    }
+    out.info("Answering the host until interrupted");
     loop {
-        match device.read(&mut input_report) {
-            Ok(len) => {
-                println!("\nReceived Input Report ({} bytes):", len);
-                println!("Hex: {}", hex::encode(&input_report[..len]));
+        emulator.next_event(None, out)?;
+    }
+}
+
+/// Run script steps as the virtual device: `send` is an input report, `read`
+/// and `expect` take the host's next output report. Sleeps keep answering the
+/// host.
+fn emulate_steps(emulator: &mut Emulator, steps: &[Step], location: &dyn Fn(usize) -> String, out: &mut Reporter) -> anyhow::Result<()> {
+    for step in steps {
+        match &step.op {
+            Op::Send(data) => {
+                let report = emulator.input_report(data);
+                out.emulated_input(&report);
+                emulator.device.input(&report)
+                    .map_err(|e| anyhow::anyhow!("{}: sending input report {} failed: {}", location(step.line), hex::encode(&report), e))?;
+            }
+            Op::Read | Op::Expect(_) => {
+                let Some(report) = emulator.read_output(out).with_context(|| location(step.line))? else {
+                    return Err(anyhow::anyhow!("{}: no output report from the host within {} ms",
+                        location(step.line), emulator.timeout.as_millis()));
+                };
+                if let Op::Expect(pattern) = &step.op {
+                    if !pattern.matches_prefix(&report) {
+                        return Err(anyhow::anyhow!("{}: expected {}, got {}", location(step.line), pattern, hex::encode(&report)));
+                    }
+                }
+            }
+            Op::Sleep(duration) => {
+                let deadline = Instant::now() + *duration;
+                while emulator.next_event(Some(deadline), out)?.is_some() {}
+            }
+            Op::Env(tags) => out.new_segment(tags).with_context(|| location(step.line))?,
+            Op::Loop { count, body } => {
+                for _ in 0..*count {
+                    emulate_steps(emulator, body, location, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn audit_verify(args: &AuditVerifyArgs, out: &Reporter) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(&args.file)
+        .map_err(|e| anyhow::anyhow!("Cannot read audit log {}: {}", args.file.display(), e))?;
+    let result = audit::verify(&text, args.head.as_deref());
+    if out.is_json() {
+        let mut record = Json::object([("file", args.file.display().to_string().into()), ("intact", result.is_ok().into())]);
+        match &result {
+            Ok(verified) => {
+                record.push("entries", verified.entries);
+                record.push("head", verified.head.as_str());
             }
             Err(e) => {
-                eprintln!("Error reading input report: {}", e);
-                break;
+                record.push("line", e.line);
+                record.push("message", e.message.as_str());
             }
         }
+        out.emit(&record);
+    }
+    let verified = result.map_err(|e| anyhow::anyhow!("Audit log {} is broken at {}", args.file.display(), e))?;
+    if !out.is_json() {
+        println!("{}: {} entries, hash chain intact, head {}", args.file.display(), verified.entries, verified.head);
+    }
+    Ok(())
+}
+
+fn list_commands(profile: &Profile, out: &Reporter) {
+    if out.is_json() {
+        for (name, command) in &profile.commands {
+            let params = command.template.params()
+                .map(|(name, ty)| Json::object([("name", name.into()), ("type", ty.name().into())]))
+                .collect();
+            out.emit(&Json::object([
+                ("command", name.as_str().into()),
+                ("payload", command.template.to_string().into()),
+                ("params", Json::Array(params)),
+                ("description", command.description.as_deref().into()),
+                ("role", command.role.name().into()),
+            ]));
+        }
+        return;
+    }
+
+    match &profile.name {
+        Some(name) => println!("Commands for {}:", name),
+        None => println!("Commands:"),
+    }
+    let width = profile.commands.keys().map(String::len).max().unwrap_or(0);
+    for (name, command) in &profile.commands {
+        match command.role {
+            Role::Operator => println!("  {:<width$}  {}", name, command.template, width = width),
+            role => println!("  {:<width$}  {}  (needs {} role)", name, command.template, role, width = width),
+        }
+        if let Some(description) = &command.description {
+            println!("  {:<width$}    {}", "", description, width = width);
+        }
     }
+    println!("{} command(s)", profile.commands.len());
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    
-    // Initialize the HID API
-    let api = HidApi::new()?;
-    
-    println!("Searching for devices with VID:PID = {:04x}:{:04x}\n", args.vid, args.pid);
-    
-    // Open device with retries
-    let device = open_device_with_retry(&api, args.vid, args.pid, VENDOR_PAGE, args.retries, args.retry_delay)?;
-    
-    println!("Successfully opened device");
-    
-    if let Some(data) = args.data {
-        // Prepare output report (64 bytes)
-        let mut output_report = [0u8; HID_REPORT_SIZE];
-        
-        // Convert hex string to bytes
-        let bytes = hex::decode(data)?;
-        let len = bytes.len().min(HID_REPORT_SIZE);
-        output_report[..len].copy_from_slice(&bytes[..len]);
-        
-        println!("\nSending Output Report ({} bytes):", HID_REPORT_SIZE);
-        println!("Hex: {}", hex::encode(&output_report));
-        
-        // Send output report
-        match device.write(&output_report) {
-            Ok(_) => println!("Successfully sent data"),
-            Err(e) => eprintln!("Error sending data: {}", e),
-        }
-        
-        if args.continuous {
-            // Keep reading input reports in a loop
-            read_input_reports(&device);
-        } else {
-            // Read a single input report
-            let mut input_report = [0u8; HID_REPORT_SIZE];
-            match device.read(&mut input_report) {
-                Ok(len) => {
-                    println!("\nReceived Input Report ({} bytes):", len);
-                    println!("Hex: {}", hex::encode(&input_report[..len]));
-                }
-                Err(e) => eprintln!("Error reading response: {}", e),
+fn errors(args: &ErrorsArgs, profile: Option<&Profile>, lang: Option<&str>, out: &Reporter) -> anyhow::Result<()> {
+    let profile = profile.ok_or_else(|| anyhow::anyhow!("errors needs --profile <file> with an [errors] table"))?;
+
+    let print = |code: i64, entry: &ErrorCode| {
+        if out.is_json() {
+            out.emit(&Json::object([
+                ("code", code.into()),
+                ("name", entry.name.as_str().into()),
+                ("description", entry.description(lang).into()),
+                ("action", entry.action(lang).into()),
+            ]));
+            return;
+        }
+        println!("0x{:04x}  {}", code, entry.summary(lang));
+        if let Some(action) = entry.action(lang) {
+            println!("        Suggested action: {}", action);
+        }
+    };
+
+    let Some(code) = &args.code else {
+        for (code, entry) in &profile.errors {
+            print(*code, entry);
+        }
+        if !out.is_json() {
+            println!("{} error code(s)", profile.errors.len());
+        }
+        return Ok(());
+    };
+    let number = match code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => code.parse(),
+    }
+    .map_err(|_| anyhow::anyhow!("'{}' is not a status code", code))?;
+    let entry = profile.error(number)
+        .ok_or_else(|| anyhow::anyhow!("Status 0x{:04x} is not in the profile's error dictionary", number))?;
+    print(number, entry);
+    Ok(())
+}
+
+/// Language for translated profile text: `--lang`, else the language part of
+/// `$LC_ALL`, `$LC_MESSAGES` or `$LANG` (`de_DE.UTF-8` -> `de`).
+fn message_language(arg: Option<&str>) -> Option<String> {
+    if let Some(lang) = arg {
+        return Some(lang.to_string());
+    }
+    ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .map(|value| value.split(['_', '.', '@']).next().unwrap_or_default().to_string())
+        .filter(|lang| !lang.is_empty() && lang != "C" && lang != "POSIX")
+}
+
+/// Entries of one physical device, in enumeration order.
+struct PhysicalDevice<'a> {
+    usb_id: Option<String>,
+    entries: Vec<&'a hidapi::DeviceInfo>,
+}
+
+fn topology(api: &HidApi, args: &TopologyArgs, out: &Reporter) {
+    let filter = DeviceFilter {
+        vid: args.vid,
+        pid: args.pid,
+        ..DeviceFilter::default()
+    };
+
+    // hidapi lists one entry per interface (or per top-level collection on Windows
+    // and macOS), so group them back into physical devices
+    let mut devices: Vec<PhysicalDevice> = Vec::new();
+    for d in hid_cmd::list_devices(api, &filter) {
+        let usb_id = usb::usb_device_id(d.path());
+        let same_device = |p: &PhysicalDevice| match (&p.usb_id, &usb_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => {
+                let first = p.entries[0];
+                first.vendor_id() == d.vendor_id() &&
+                    first.product_id() == d.product_id() &&
+                    first.serial_number() == d.serial_number()
             }
+        };
+        match devices.iter_mut().find(|p| same_device(p)) {
+            Some(p) => p.entries.push(d),
+            None => devices.push(PhysicalDevice { usb_id, entries: vec![d] }),
         }
-    } else if args.continuous {
-        // Just read input reports continuously
-        read_input_reports(&device);
-    } else {
-        // Read a single input report
-        let mut input_report = [0u8; HID_REPORT_SIZE];
-        match device.read(&mut input_report) {
-            Ok(len) => {
-                println!("\nCurrent Input Report ({} bytes):", len);
-                println!("Hex: {}", hex::encode(&input_report[..len]));
+    }
+
+    if out.is_json() {
+        for device in &devices {
+            out.emit(&device_tree_json(device));
+        }
+        return;
+    }
+
+    for device in &devices {
+        print_device_tree(device);
+        println!();
+    }
+    println!("{} device(s)", devices.len());
+}
+
+fn device_tree_json(device: &PhysicalDevice) -> Json {
+    let first = device.entries[0];
+    let collections = |number: i32| Json::Array(device.entries.iter()
+        .filter(|d| d.interface_number() == number)
+        .map(|d| Json::object([
+            ("usage_page", d.usage_page().into()),
+            ("usage", d.usage().into()),
+            ("path", d.path().to_string_lossy().into_owned().into()),
+        ]))
+        .collect());
+
+    let interfaces = match usb::composite_interfaces(first.path()) {
+        Some(interfaces) => interfaces.iter().map(|i| {
+            let mut json = interface_json(i);
+            json.push("collections", collections(i32::from(i.number)));
+            json
+        }).collect(),
+        None => {
+            let mut numbers: Vec<i32> = device.entries.iter().map(|d| d.interface_number()).collect();
+            numbers.sort();
+            numbers.dedup();
+            numbers.into_iter()
+                .map(|n| Json::object([("number", n.into()), ("class_name", "HID".into()), ("collections", collections(n))]))
+                .collect()
+        }
+    };
+    Json::object([
+        ("usb_id", device.usb_id.as_deref().into()),
+        ("vid", first.vendor_id().into()),
+        ("pid", first.product_id().into()),
+        ("manufacturer", first.manufacturer_string().into()),
+        ("product", first.product_string().into()),
+        ("serial", first.serial_number().into()),
+        ("interfaces", Json::Array(interfaces)),
+    ])
+}
+
+fn print_device_tree(device: &PhysicalDevice) {
+    let first = device.entries[0];
+    println!("{:04x}:{:04x}  {} {}{}", first.vendor_id(), first.product_id(),
+        first.manufacturer_string().unwrap_or("(unknown manufacturer)"),
+        first.product_string().unwrap_or("(unknown product)"),
+        device.usb_id.as_ref().map(|id| format!("  [usb {}]", id)).unwrap_or_default());
+
+    let collection = |d: &hidapi::DeviceInfo| {
+        format!("HID collection: usage page 0x{:04x}, usage 0x{:04x} ({})",
+            d.usage_page(), d.usage(), d.path().to_string_lossy())
+    };
+
+    let Some(interfaces) = usb::composite_interfaces(first.path()) else {
+        // No USB layout available: show what hidapi knows, grouped by interface number
+        let mut numbers: Vec<i32> = device.entries.iter().map(|d| d.interface_number()).collect();
+        numbers.sort();
+        numbers.dedup();
+        for (i, number) in numbers.iter().enumerate() {
+            let last = i + 1 == numbers.len();
+            println!("{} Interface {}: HID", if last { "└──" } else { "├──" }, number);
+            let children: Vec<_> = device.entries.iter().filter(|d| d.interface_number() == *number).collect();
+            for (j, d) in children.iter().enumerate() {
+                println!("{}   {} {}", if last { " " } else { "│" },
+                    if j + 1 == children.len() { "└──" } else { "├──" }, collection(d));
             }
-            Err(e) => println!("Could not read input report: {}", e),
+        }
+        return;
+    };
+
+    for (i, interface) in interfaces.iter().enumerate() {
+        let last = i + 1 == interfaces.len();
+        let indent = if last { " " } else { "│" };
+        println!("{} Interface {}: {} (class 0x{:02x}, subclass 0x{:02x}, protocol 0x{:02x})",
+            if last { "└──" } else { "├──" }, interface.number, interface.class_name(),
+            interface.class, interface.subclass, interface.protocol);
+
+        let mut children: Vec<String> = interface.endpoints.iter()
+            .map(|e| format!("Endpoint 0x{:02x} {} {}, {} bytes, interval {}",
+                e.address, if e.is_in() { "IN" } else { "OUT" }, e.transfer_type(),
+                e.max_packet_size, e.interval))
+            .collect();
+        children.extend(device.entries.iter()
+            .filter(|d| d.interface_number() == i32::from(interface.number))
+            .map(|d| collection(d)));
+        for (j, child) in children.iter().enumerate() {
+            println!("{}   {} {}", indent, if j + 1 == children.len() { "└──" } else { "├──" }, child);
         }
     }
-    
-    Ok(())
+}
+
+impl Command {
+    fn device_and_report_args(&mut self) -> (Option<&mut DeviceArgs>, Option<&mut ReportArgs>) {
+        match self {
+            Command::Send(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Read(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Monitor(a) | Command::Watch(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Cmd(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Run(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Timeline(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Passthrough(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Bench(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Compare(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Loopback(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::BisectFirmware(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Record(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::TargetTest(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Replay(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Capture(a) => (Some(&mut a.monitor.device), Some(&mut a.monitor.report)),
+            Command::SendFile(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::RecvFile(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Trace(TraceArgs { command: TraceCommand::Pull(a) }) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Rtt(a) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::Crashdump(CrashdumpArgs { command: CrashdumpCommand::Pull(a) }) => (Some(&mut a.device), Some(&mut a.report)),
+            Command::FeatureGet(a) => (Some(&mut a.device), None),
+            Command::FeatureSet(a) => (Some(&mut a.device), None),
+            Command::FeatureWatch(a) => (Some(&mut a.device), None),
+            Command::Info(a) => (Some(&mut a.device), None),
+            Command::List(_) | Command::Topology(_) | Command::Errors(_) | Command::AuditVerify(_) | Command::Emulate(_)
+                | Command::Crashdump(CrashdumpArgs { command: CrashdumpCommand::Show(_) }) => (None, None),
+        }
+    }
+}
+
+/// Login name of whoever runs the tool, from the real user ID rather than
+/// `$USER`, which anyone can set.
+#[cfg(unix)]
+fn current_user() -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, and `buf` outlives the
+    // strings `passwd` points into
+    let status = unsafe { libc::getpwuid_r(libc::getuid(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if status != 0 || found.is_null() {
+        return None;
+    }
+    // SAFETY: getpwuid_r succeeded, so `pw_name` is a NUL-terminated string in `buf`
+    let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Without a trustworthy login name, users get the profile's default role.
+#[cfg(not(unix))]
+fn current_user() -> Option<String> {
+    None
+}
+
+/// A profile every session is held to if the machine has one, with or without
+/// `--profile`, so that leaving the option out doesn't escape its roles,
+/// deny-list or audit log. Only an administrator should be able to write it.
+const SYSTEM_PROFILE: &str = "/etc/hid-cmd/profile.toml";
+
+/// The [`SYSTEM_PROFILE`], if installed. One that exists but can't be read is
+/// an error rather than no policy.
+fn system_profile() -> anyhow::Result<Option<Profile>> {
+    let path = Path::new(SYSTEM_PROFILE);
+    match std::fs::metadata(path) {
+        Ok(_) => Profile::load(path).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("Cannot read profile {}: {}", path.display(), e)),
+    }
+}
+
+/// The operator ID from `--operator` or `$HID_CMD_OPERATOR`. If there is none
+/// and a profile requires one, a session that opens a device asks for it on
+/// stdin, which is where badge scanners type.
+fn operator_id(arg: Option<&str>, profiles: &[&Profile], opens_device: bool) -> anyhow::Result<Option<String>> {
+    let given = arg.map(str::to_string).or_else(|| std::env::var("HID_CMD_OPERATOR").ok());
+    if let Some(id) = given.map(|id| id.trim().to_string()).filter(|id| !id.is_empty()) {
+        return Ok(Some(id));
+    }
+    if !opens_device || !profiles.iter().any(|p| p.require_operator) {
+        return Ok(None);
+    }
+    eprint!("Operator ID: ");
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    match line.trim() {
+        "" => Err(anyhow::anyhow!("The profile requires an operator ID; scan a badge or pass --operator")),
+        id => Ok(Some(id.to_string())),
+    }
+}
+
+/// The role given by `--role`, or else the highest one every profile grants
+/// the current user. Without any profile nothing is restricted.
+fn acting_role(requested: Option<Role>, profiles: &[&Profile]) -> anyhow::Result<Role> {
+    let user = current_user();
+    let Some(allowed) = profiles.iter().map(|p| p.role_of(user.as_deref())).min() else {
+        return Ok(requested.unwrap_or(Role::Factory));
+    };
+    match requested {
+        Some(role) if role > allowed => Err(anyhow::anyhow!("{} may act as {} at most, not {}",
+            user.as_deref().unwrap_or("This user"), allowed, role)),
+        Some(role) => Ok(role),
+        None => Ok(allowed),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is::<NoResponse>() => {
+            // The timeout itself was reported; a script adds the line it happened on
+            if e.chain().count() > 1 {
+                eprintln!("Error: {:#}", e);
+            }
+            ExitCode::from(EXIT_TIMEOUT)
+        }
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let mut args = Args::parse();
+
+    let system = system_profile()?;
+    let profile = args.profile.as_deref().map(Profile::load).transpose()?;
+    let profiles: Vec<&Profile> = system.iter().chain(&profile).collect();
+    let lang = message_language(args.lang.as_deref());
+    let opens_device = args.command.device_and_report_args().0.is_some();
+    if let Some(profile) = &profile {
+        let (device, report) = args.command.device_and_report_args();
+        if let Some(device) = device {
+            device.apply_profile(profile);
+        }
+        if let Some(report) = report {
+            report.apply_profile(profile);
+        }
+    }
+
+    // Initialize the HID API
+    let mut api = HidApi::new()?;
+
+    let transcript = match &args.transcript {
+        Some(path) => {
+            let mut transcript = Transcript::create(path)?;
+            transcript.header(&std::env::args().collect::<Vec<_>>().join(" "))?;
+            Some(transcript)
+        }
+        None => None,
+    };
+    let operator = operator_id(args.operator.as_deref(), &profiles, opens_device)?;
+    let mut out = Reporter::new(transcript, args.json).with_identity(Identity { user: current_user(), operator });
+    // All of them get every write, so a profile's log can't be bypassed from the command line
+    let mut logs: Vec<&Path> = Vec::new();
+    for path in profiles.iter().filter_map(|p| p.audit_log.as_deref()).chain(args.audit_log.as_deref()) {
+        if !logs.contains(&path) {
+            logs.push(path);
+        }
+    }
+    for path in logs {
+        out = out.with_audit(AuditLog::open(path)?);
+    }
+    let capture = match &args.command {
+        Command::Capture(capture) => Some(&capture.file),
+        _ => args.capture.as_ref(),
+    };
+    if let Some(path) = capture {
+        let file = std::fs::File::create(path).map_err(|e| anyhow::anyhow!("Cannot create capture {}: {}", path.display(), e))?;
+        out = out.with_capture(UsbCapture::new(io::BufWriter::new(file), "hid-cmd")?);
+    }
+    if let (Some(spec), Some(path)) = (&args.power_meter, &args.power_log) {
+        let meter = power::connect(spec, &args.power_query)?;
+        out = out.with_power_meter(meter, args.power_interval, path.clone());
+    }
+    let env_source = args.env_command.clone().map(|command| environment::Source { command });
+    let mut env = Environment::default();
+    if let Some(source) = &env_source {
+        env.update(source.read()?);
+    }
+    env.update(args.env.iter().cloned());
+    out = out.with_environment(env, env_source);
+    let role = acting_role(args.role, &profiles)?;
+    let guard = WriteGuard { profiles, overridden: args.i_know_what_im_doing, role };
+    // Every command can probe, and the probe is written to every candidate
+    if let Some(handshake) = args.command.device_and_report_args().0.and_then(|device| device.probe_handshake.as_ref()) {
+        guard.require_raw()?;
+        guard.check(&handshake.probe[1..], &mut out)?;
+    }
+
+    let result = match &args.command {
+        Command::Send(args) => send(&api, args, &guard, &mut out),
+        Command::Read(args) => read(&api, args, &mut out),
+        Command::Monitor(args) => monitor(&mut api, args, false, &guard, &mut out),
+        Command::Watch(args) => monitor(&mut api, args, true, &guard, &mut out),
+        Command::Capture(args) => monitor(&mut api, &args.monitor, false, &guard, &mut out),
+        Command::FeatureGet(args) => feature_get(&api, args, &mut out),
+        Command::FeatureSet(args) => feature_set(&api, args, &guard, &mut out),
+        Command::FeatureWatch(args) => feature_watch(&api, args, &mut out),
+        Command::Info(args) => info(&api, args, &mut out),
+        Command::Cmd(args) => cmd(&api, args, profile.as_ref(), lang.as_deref(), &guard, &mut out),
+        Command::Errors(args) => errors(args, profile.as_ref(), lang.as_deref(), &out),
+        Command::Run(args) => run_script(&api, args, &guard, &mut out),
+        Command::Timeline(args) => play_timeline(&api, &args.device, &args.report, &args.file, 1.0, &guard, &mut out),
+        Command::Passthrough(args) => passthrough(&api, args, &guard, &mut out),
+        Command::Bench(args) => bench(&api, args, &guard, &mut out),
+        Command::Compare(args) => compare(&api, args, &guard, &mut out),
+        Command::Loopback(args) => loopback(&api, args, &guard, &mut out),
+        Command::BisectFirmware(args) => bisect_firmware(&mut api, args, &guard, &mut out),
+        Command::Record(args) => record(&api, args, &guard, &mut out),
+        Command::Replay(args) => play_timeline(&api, &args.device, &args.report, &args.file, args.speed, &guard, &mut out),
+        Command::TargetTest(args) => target_test(&api, args, &guard, &mut out),
+        Command::AuditVerify(args) => audit_verify(args, &out),
+        Command::SendFile(args) => send_file(&api, args, &guard, &mut out),
+        Command::RecvFile(args) => recv_file(&api, args, &guard, &mut out),
+        Command::Trace(TraceArgs { command: TraceCommand::Pull(args) }) => trace_pull(&api, args, &guard, &mut out),
+        Command::Rtt(args) => rtt(&api, args, &guard, &mut out),
+        Command::Emulate(args) => emulate(args, &mut out),
+        Command::Crashdump(CrashdumpArgs { command: CrashdumpCommand::Pull(args) }) => crashdump_pull(&api, args, &guard, &mut out),
+        Command::Crashdump(CrashdumpArgs { command: CrashdumpCommand::Show(args) }) => crashdump_show(args, &mut out),
+        Command::List(args) => {
+            list_devices(&api, args, &out);
+            Ok(())
+        }
+        Command::Topology(args) => {
+            topology(&api, args, &out);
+            Ok(())
+        }
+    };
+    out.finish();
+    result
 }
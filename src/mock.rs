@@ -0,0 +1,204 @@
+//! An in-memory [`HidTransport`] with scripted responses, for testing code
+//! built on [`HidSession`](crate::HidSession) without hardware.
+//!
+//! Written reports are recorded, and those matching a [`respond`] rule queue
+//! that rule's input reports, which reads then return in order. Clones share
+//! the one device, so a test can keep a clone to script and inspect while the
+//! session owns the other:
+//!
+//! ```
+//! use hid_cmd::mock::MockTransport;
+//! use hid_cmd::pattern::HexPattern;
+//! use hid_cmd::HidSession;
+//! use std::time::Duration;
+//!
+//! let mock = MockTransport::new();
+//! mock.respond(HexPattern::parse("00 01 ??")?, [vec![0x81, 0x00]]);
+//! let session = HidSession::from_transport(mock.clone());
+//!
+//! session.send_report(&[0x00, 0x01, 0x07])?;
+//! let mut buf = [0u8; 64];
+//! let len = session.read_report_timeout(&mut buf, Duration::from_millis(100))?;
+//! assert_eq!(&buf[..len], [0x81, 0x00]);
+//! // Padded to the session's report size
+//! assert_eq!(mock.written()[0].len(), 64);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`respond`]: MockTransport::respond
+
+use crate::pattern::HexPattern;
+use crate::transport::HidTransport;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// A scripted device; see the [module docs](self).
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when an input report is queued or the device disconnects.
+    input: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    descriptor: Vec<u8>,
+    rules: Vec<(HexPattern, Vec<Vec<u8>>)>,
+    inputs: VecDeque<Vec<u8>>,
+    written: Vec<Vec<u8>>,
+    /// By report ID, ID first.
+    features: BTreeMap<u8, Vec<u8>>,
+    feature_writes: Vec<Vec<u8>>,
+    failing_writes: usize,
+    disconnected: bool,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // No update leaves the state half-done, so a panic while holding the lock is harmless
+        self.shared.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Return `descriptor` as the report descriptor; empty by default.
+    pub fn set_descriptor(&self, descriptor: &[u8]) {
+        self.state().descriptor = descriptor.to_vec();
+    }
+
+    /// Answer every written report that matches `pattern`, report ID first,
+    /// with `reports` as input reports. Rules are tried in the order they were
+    /// added and only the first match answers; reports matching none get no
+    /// response.
+    pub fn respond(&self, pattern: HexPattern, reports: impl IntoIterator<Item = Vec<u8>>) {
+        self.state().rules.push((pattern, reports.into_iter().collect()));
+    }
+
+    /// Queue an input report, as if the device sent it unprompted.
+    pub fn push_input(&self, report: &[u8]) {
+        self.state().inputs.push_back(report.to_vec());
+        self.shared.input.notify_all();
+    }
+
+    /// Set the feature report `report[0]`, which reads then return.
+    pub fn set_feature_report(&self, report: &[u8]) {
+        if let Some(&id) = report.first() {
+            self.state().features.insert(id, report.to_vec());
+        }
+    }
+
+    /// Fail the next `count` writes, output and feature reports alike, as a
+    /// busy or stalling device would.
+    pub fn fail_writes(&self, count: usize) {
+        self.state().failing_writes = count;
+    }
+
+    /// Fail all I/O from now on, like an unplugged device. Reads waiting for
+    /// a report fail at once.
+    pub fn disconnect(&self) {
+        self.state().disconnected = true;
+        self.shared.input.notify_all();
+    }
+
+    /// The output reports written so far, as written.
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.state().written.clone()
+    }
+
+    /// The feature reports sent so far.
+    pub fn feature_writes(&self) -> Vec<Vec<u8>> {
+        self.state().feature_writes.clone()
+    }
+
+    /// Input reports queued and not read yet.
+    pub fn pending_inputs(&self) -> usize {
+        self.state().inputs.len()
+    }
+
+    /// Count a write against [`fail_writes`](Self::fail_writes) and a
+    /// disconnect.
+    fn check_write(state: &mut State) -> anyhow::Result<()> {
+        if state.disconnected {
+            return Err(anyhow::anyhow!("mock device disconnected"));
+        }
+        if state.failing_writes > 0 {
+            state.failing_writes -= 1;
+            return Err(anyhow::anyhow!("mock write failure"));
+        }
+        Ok(())
+    }
+}
+
+impl HidTransport for MockTransport {
+    fn write(&self, report: &[u8]) -> anyhow::Result<usize> {
+        let mut state = self.state();
+        Self::check_write(&mut state)?;
+        state.written.push(report.to_vec());
+        let responses = state.rules.iter()
+            .find(|(pattern, _)| pattern.matches_prefix(report))
+            .map(|(_, responses)| responses.clone());
+        if let Some(responses) = responses {
+            state.inputs.extend(responses);
+            self.shared.input.notify_all();
+        }
+        Ok(report.len())
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> anyhow::Result<usize> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.state();
+        loop {
+            if state.disconnected {
+                return Err(anyhow::anyhow!("mock device disconnected"));
+            }
+            if let Some(report) = state.inputs.pop_front() {
+                let len = report.len().min(buf.len());
+                buf[..len].copy_from_slice(&report[..len]);
+                return Ok(len);
+            }
+            state = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Ok(0);
+                    }
+                    self.shared.input.wait_timeout(state, left).unwrap_or_else(|poisoned| poisoned.into_inner()).0
+                }
+                None => self.shared.input.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+    }
+
+    fn send_feature_report(&self, report: &[u8]) -> anyhow::Result<()> {
+        let mut state = self.state();
+        Self::check_write(&mut state)?;
+        let &id = report.first().ok_or_else(|| anyhow::anyhow!("feature report without a report ID"))?;
+        state.feature_writes.push(report.to_vec());
+        state.features.insert(id, report.to_vec());
+        Ok(())
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let state = self.state();
+        if state.disconnected {
+            return Err(anyhow::anyhow!("mock device disconnected"));
+        }
+        let &id = buf.first().ok_or_else(|| anyhow::anyhow!("feature report buffer without room for the report ID"))?;
+        let report = state.features.get(&id).ok_or_else(|| anyhow::anyhow!("mock device has no feature report {}", id))?;
+        let len = report.len().min(buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+
+    fn report_descriptor(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(self.state().descriptor.clone())
+    }
+}
@@ -0,0 +1,136 @@
+//! Local keyboard and mouse input turned into output reports, for
+//! `hid-cmd passthrough`.
+//!
+//! Input comes from Linux evdev nodes (`/dev/input/event*`). Each event is
+//! mapped to a report payload by a [`Template`] from a mapping file, which is
+//! given the event's `code` and `value` (for keys 1 on press, 0 on release
+//! and 2 on autorepeat; for relative axes the movement):
+//!
+//! ```toml
+//! # Every key, with its evdev code
+//! key = "10 {code:u16} {value:u8}"
+//! # Mouse motion (code 0 is X, 1 is Y, 8 the wheel)
+//! rel = "20 {code:u8} {value:i16}"
+//! # Absolute axes, e.g. from a touchpad
+//! abs = "30 {code:u8} {value:i32}"
+//!
+//! # Keys with their own report
+//! [keys]
+//! 30 = "40 {value:u8}"     # KEY_A
+//! 57 = "41"                # KEY_SPACE, on press and release alike
+//! ```
+//!
+//! Events without a template are dropped, as are synchronisation and other
+//! event types.
+
+use crate::config::{self, Value};
+use crate::profile::Template;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::Path;
+
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+
+/// `KEY_PAUSE`, the default kill switch.
+pub const KEY_PAUSE: u16 = 119;
+
+/// One evdev event, without its timestamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl InputEvent {
+    pub fn is_key_press(&self, code: u16) -> bool {
+        self.kind == EV_KEY && self.code == code && self.value == 1
+    }
+}
+
+/// Read the next event from an evdev node. `struct input_event` starts with a
+/// `struct timeval` of two native longs.
+pub fn read_event(reader: &mut impl Read) -> io::Result<InputEvent> {
+    const TIME_LEN: usize = 2 * std::mem::size_of::<usize>();
+    let mut buf = [0u8; TIME_LEN + 8];
+    reader.read_exact(&mut buf)?;
+    let fields = &buf[TIME_LEN..];
+    Ok(InputEvent {
+        kind: u16::from_ne_bytes([fields[0], fields[1]]),
+        code: u16::from_ne_bytes([fields[2], fields[3]]),
+        value: i32::from_ne_bytes([fields[4], fields[5], fields[6], fields[7]]),
+    })
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputMap {
+    pub key: Option<Template>,
+    pub rel: Option<Template>,
+    pub abs: Option<Template>,
+    /// Per-key templates, by evdev key code; these win over `key`.
+    pub keys: BTreeMap<u16, Template>,
+}
+
+impl InputMap {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read input mapping {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("Invalid input mapping {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let template = |key: &str, value: &Value| -> anyhow::Result<Template> {
+            let s = value.as_str().ok_or_else(|| anyhow::anyhow!("'{}' must be a string, not {}", key, value.type_name()))?;
+            let template = Template::parse(s).map_err(|e| anyhow::anyhow!("'{}': {}", key, e))?;
+            if let Some((name, _)) = template.params().find(|(name, _)| *name != "code" && *name != "value") {
+                return Err(anyhow::anyhow!("'{}': unknown placeholder '{}' (expected code or value)", key, name));
+            }
+            Ok(template)
+        };
+
+        let mut map = InputMap::default();
+        for (key, value) in config::parse(text)? {
+            match key.as_str() {
+                "key" => map.key = Some(template(&key, &value)?),
+                "rel" => map.rel = Some(template(&key, &value)?),
+                "abs" => map.abs = Some(template(&key, &value)?),
+                "keys" => {
+                    let Value::Table(keys) = value else {
+                        return Err(anyhow::anyhow!("'keys' must be a table, not {}", value.type_name()));
+                    };
+                    for (code, value) in keys {
+                        let n = code.parse::<u16>()
+                            .map_err(|_| anyhow::anyhow!("'keys.{}': expected an evdev key code", code))?;
+                        map.keys.insert(n, template(&format!("keys.{}", code), &value)?);
+                    }
+                }
+                _ => return Err(anyhow::anyhow!("unknown key '{}'", key)),
+            }
+        }
+        if map.key.is_none() && map.rel.is_none() && map.abs.is_none() && map.keys.is_empty() {
+            return Err(anyhow::anyhow!("no events are mapped"));
+        }
+        Ok(map)
+    }
+
+    /// The payload for `event`, or `None` if it isn't mapped.
+    pub fn payload(&self, event: &InputEvent) -> anyhow::Result<Option<Vec<u8>>> {
+        let template = match event.kind {
+            EV_KEY => self.keys.get(&event.code).or(self.key.as_ref()),
+            EV_REL => self.rel.as_ref(),
+            EV_ABS => self.abs.as_ref(),
+            _ => None,
+        };
+        let Some(template) = template else {
+            return Ok(None);
+        };
+        let args: Vec<(String, String)> = [("code", event.code.to_string()), ("value", event.value.to_string())]
+            .into_iter()
+            .filter(|(name, _)| template.params().any(|(p, _)| p == *name))
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        template.render(&args).map(Some)
+    }
+}
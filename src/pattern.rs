@@ -0,0 +1,129 @@
+//! Hex byte patterns with wildcards and masks, e.g. `ff 00 ?? 8? 40/c0`.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HexPattern {
+    source: String,
+    bytes: Vec<MaskedByte>,
+}
+
+/// A byte matches if `byte & mask == value`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MaskedByte {
+    value: u8,
+    mask: u8,
+}
+
+impl HexPattern {
+    /// Parse a pattern; spaces are optional. Each byte is two hex digits, where
+    /// either digit may be `?` to match any nibble, optionally followed by
+    /// `/` and a mask selecting the bits to compare (`40/c0`).
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut chars = s.chars().filter(|c| !c.is_whitespace()).peekable();
+        let mut bytes = Vec::new();
+        while let Some(hi) = chars.next() {
+            let lo = chars.next()
+                .ok_or_else(|| anyhow::anyhow!("Pattern '{}' has an odd number of hex digits", s))?;
+            let invalid = || anyhow::anyhow!("Invalid byte '{}{}' in pattern '{}'", hi, lo, s);
+            let (hi_value, hi_mask) = nibble(hi).ok_or_else(invalid)?;
+            let (lo_value, lo_mask) = nibble(lo).ok_or_else(invalid)?;
+            let mut byte = MaskedByte { value: hi_value << 4 | lo_value, mask: hi_mask << 4 | lo_mask };
+            if chars.next_if_eq(&'/').is_some() {
+                let mask: String = chars.by_ref().take(2).collect();
+                let mask = u8::from_str_radix(&mask, 16)
+                    .ok()
+                    .filter(|_| mask.len() == 2)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid mask '{}' after '{}{}' in pattern '{}'", mask, hi, lo, s))?;
+                if byte.value & !mask != 0 {
+                    return Err(anyhow::anyhow!("Byte '{}{}' in pattern '{}' has bits outside its mask {:02x}", hi, lo, s, mask));
+                }
+                byte.mask &= mask;
+            }
+            bytes.push(byte);
+        }
+        if bytes.is_empty() {
+            return Err(anyhow::anyhow!("Empty pattern"));
+        }
+        Ok(HexPattern { source: s.to_string(), bytes })
+    }
+
+    /// Whether `data` starts with bytes matching the pattern.
+    pub fn matches_prefix(&self, data: &[u8]) -> bool {
+        data.len() >= self.bytes.len() &&
+            self.bytes.iter().zip(data).all(|(p, b)| b & p.mask == p.value)
+    }
+}
+
+/// A hex digit's value and mask; `?` matches anything.
+fn nibble(c: char) -> Option<(u8, u8)> {
+    match c {
+        '?' => Some((0, 0)),
+        c => c.to_digit(16).map(|d| (d as u8, 0xf)),
+    }
+}
+
+impl fmt::Display for HexPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, data: &[u8]) -> bool {
+        HexPattern::parse(pattern).unwrap().matches_prefix(data)
+    }
+
+    #[test]
+    fn exact_bytes_with_optional_spaces() {
+        assert_eq!(HexPattern::parse("ff00").unwrap().bytes, HexPattern::parse(" ff 0 0 ").unwrap().bytes);
+        assert!(matches("ff 00", &[0xff, 0x00]));
+        assert!(!matches("ff 00", &[0xff, 0x01]));
+        assert!(matches("AB", &[0xab]));
+    }
+
+    #[test]
+    fn wildcard_bytes_and_nibbles() {
+        assert!((0..=255).all(|b| matches("??", &[b])));
+        assert!(matches("01 ?? 03", &[0x01, 0x7e, 0x03]));
+        assert!(matches("a?", &[0xa0]) && matches("a?", &[0xaf]));
+        assert!(!matches("a?", &[0xb0]));
+        assert!(matches("?5", &[0x35]) && !matches("?5", &[0x36]));
+    }
+
+    #[test]
+    fn masks_select_the_bits_compared() {
+        assert!(matches("40/c0", &[0x40]) && matches("40/c0", &[0x7f]));
+        assert!(!matches("40/c0", &[0x80]) && !matches("40/c0", &[0x00]));
+        // Wildcard nibbles stay wildcards under a mask
+        assert!(matches("0?/0f", &[0xf3]));
+        assert!(matches("01/01 02", &[0xff, 0x02]));
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        for pattern in ["", "   ", "123", "0g", "0x", "40/c", "40/zz", "40/", "41/c0"] {
+            assert!(HexPattern::parse(pattern).is_err(), "{:?} parsed", pattern);
+        }
+        let error = HexPattern::parse("123").unwrap_err().to_string();
+        assert!(error.contains("odd number"), "{}", error);
+        let error = HexPattern::parse("41/c0").unwrap_err().to_string();
+        assert!(error.contains("outside its mask"), "{}", error);
+    }
+
+    #[test]
+    fn data_shorter_than_the_pattern_never_matches() {
+        assert!(!matches("01 02", &[]));
+        assert!(!matches("01 02", &[0x01]));
+        assert!(!matches("?? ??", &[0x01]));
+        assert!(matches("01 02", &[0x01, 0x02, 0x03]));
+    }
+
+    #[test]
+    fn displays_as_written() {
+        assert_eq!(HexPattern::parse("a0 ?? 40/c0").unwrap().to_string(), "a0 ?? 40/c0");
+    }
+}
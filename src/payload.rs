@@ -0,0 +1,73 @@
+//! Encodings of payloads given on the command line, in a file or on stdin,
+//! selected with `--format`.
+//!
+//! - `hex`: hex digits; whitespace between them is ignored.
+//! - `bin`: the bytes as they are.
+//! - `ascii`: ASCII text, sent as its bytes.
+//! - `base64`: standard base64, with or without `=` padding; whitespace is
+//!   ignored.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum DataFormat {
+    Hex,
+    Bin,
+    Ascii,
+    Base64,
+}
+
+impl DataFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            DataFormat::Hex => "hex",
+            DataFormat::Bin => "bin",
+            DataFormat::Ascii => "ascii",
+            DataFormat::Base64 => "base64",
+        }
+    }
+
+    /// The payload encoded in `input`.
+    pub fn decode(self, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            DataFormat::Hex => {
+                let digits: Vec<u8> = input.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+                hex::decode(&digits).map_err(|e| anyhow::anyhow!("invalid hex data: {}", e))
+            }
+            DataFormat::Bin => Ok(input.to_vec()),
+            DataFormat::Ascii => match input.iter().position(|b| !b.is_ascii()) {
+                Some(i) => Err(anyhow::anyhow!("byte {} (0x{:02x}) is not ASCII", i, input[i])),
+                None => Ok(input.to_vec()),
+            },
+            DataFormat::Base64 => base64_decode(input),
+        }
+    }
+}
+
+fn base64_decode(input: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let sextet = |b: u8| -> Option<u32> {
+        match b {
+            b'A'..=b'Z' => Some(u32::from(b - b'A')),
+            b'a'..=b'z' => Some(u32::from(b - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(b - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    };
+    let text: Vec<u8> = input.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+    let unpadded = text.strip_suffix(b"==").or_else(|| text.strip_suffix(b"=")).unwrap_or(&text);
+    if unpadded.len() % 4 == 1 || (unpadded.len() != text.len() && !text.len().is_multiple_of(4)) {
+        return Err(anyhow::anyhow!("invalid base64 data: wrong length"));
+    }
+
+    let mut out = Vec::with_capacity(unpadded.len() * 3 / 4);
+    for chunk in unpadded.chunks(4) {
+        let mut bits = 0u32;
+        for (i, &b) in chunk.iter().enumerate() {
+            let value = sextet(b).ok_or_else(|| anyhow::anyhow!("invalid base64 data: unexpected '{}'", b.escape_ascii()))?;
+            bits |= value << (18 - 6 * i);
+        }
+        let bytes = bits.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Ok(out)
+}
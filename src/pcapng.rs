@@ -0,0 +1,220 @@
+//! Traffic captures in pcapng, for `--capture` and `hid-cmd capture`.
+//!
+//! Reports are written as USBPcap packets (link type 249), which Wireshark
+//! dissects as USB HID: interrupt transfers for input and output reports,
+//! GET_REPORT and SET_REPORT control transfers for feature reports. A
+//! GET_DESCRIPTOR transfer carrying the report descriptor is recorded when a
+//! device is opened, so Wireshark has it to decode the reports that follow.
+//!
+//! hidapi doesn't see the bus, so the captured transfers are reconstructed:
+//! each report is one packet, stamped when the tool handled it, and transfers
+//! that failed are left out.
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const LINKTYPE_USBPCAP: u16 = 249;
+
+const BLOCK_SHB: u32 = 0x0a0d_0d0a;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const OPTION_END: u16 = 0;
+const OPTION_COMMENT: u16 = 1;
+const OPTION_IF_NAME: u16 = 2;
+
+const URB_FUNCTION_CONTROL_TRANSFER: u16 = 0x0008;
+const URB_FUNCTION_BULK_OR_INTERRUPT_TRANSFER: u16 = 0x0009;
+/// Set on packets travelling from the device to the host driver.
+const INFO_PDO_TO_FDO: u8 = 0x01;
+const TRANSFER_INTERRUPT: u8 = 1;
+const TRANSFER_CONTROL: u8 = 2;
+const STAGE_SETUP: u8 = 0;
+const STAGE_DATA: u8 = 1;
+const STAGE_COMPLETE: u8 = 3;
+
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const HID_GET_REPORT: u8 = 0x01;
+const HID_SET_REPORT: u8 = 0x09;
+const DESCRIPTOR_REPORT: u8 = 0x22;
+const REPORT_TYPE_FEATURE: u8 = 0x03;
+
+/// Where on the bus the captured interface sits. Unknown values keep their
+/// defaults: bus 1, device 1, interface 0, endpoints 0x81 and 0x01.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsbAddress {
+    pub bus: u16,
+    pub device: u16,
+    pub interface: u8,
+    pub endpoint_in: u8,
+    pub endpoint_out: u8,
+}
+
+impl Default for UsbAddress {
+    fn default() -> Self {
+        UsbAddress { bus: 1, device: 1, interface: 0, endpoint_in: 0x81, endpoint_out: 0x01 }
+    }
+}
+
+pub struct UsbCapture<W: Write> {
+    out: W,
+    /// Pairs the packets of one transfer.
+    irp: u64,
+    pub address: UsbAddress,
+    /// Attached to the next packet.
+    comment: Option<String>,
+}
+
+impl<W: Write> UsbCapture<W> {
+    /// Start a capture with one USBPcap interface called `name`.
+    pub fn new(mut out: W, name: &str) -> io::Result<Self> {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length unknown
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut out, BLOCK_SHB, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_USBPCAP.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        // No snapshot length limit
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut idb, OPTION_IF_NAME, name.as_bytes());
+        push_option(&mut idb, OPTION_END, &[]);
+        write_block(&mut out, BLOCK_IDB, &idb)?;
+
+        Ok(UsbCapture { out, irp: 0, address: UsbAddress::default(), comment: None })
+    }
+
+    /// An input report as hidapi returned it, which is what crossed the bus.
+    pub fn input_report(&mut self, report: &[u8]) -> io::Result<()> {
+        self.irp += 1;
+        let endpoint = self.address.endpoint_in;
+        self.packet(URB_FUNCTION_BULK_OR_INTERRUPT_TRANSFER, INFO_PDO_TO_FDO, endpoint, TRANSFER_INTERRUPT, None, report)
+    }
+
+    /// An output report as given to hidapi: report ID first, which is dropped
+    /// from the transfer when it is 0.
+    pub fn output_report(&mut self, report: &[u8]) -> io::Result<()> {
+        self.irp += 1;
+        let endpoint = self.address.endpoint_out;
+        self.packet(URB_FUNCTION_BULK_OR_INTERRUPT_TRANSFER, 0, endpoint, TRANSFER_INTERRUPT, None, on_wire(report))
+    }
+
+    /// A feature report read with GET_REPORT; `report` starts with its ID.
+    pub fn feature_in(&mut self, report_id: u8, report: &[u8]) -> io::Result<()> {
+        let data = on_wire(report);
+        let setup = self.setup(0xa1, HID_GET_REPORT, u16::from_be_bytes([REPORT_TYPE_FEATURE, report_id]), data.len());
+        self.control_in(setup, data)
+    }
+
+    /// A feature report written with SET_REPORT; `report` starts with its ID.
+    pub fn feature_out(&mut self, report: &[u8]) -> io::Result<()> {
+        let report_id = report.first().copied().unwrap_or(0);
+        let data = on_wire(report);
+        let setup = self.setup(0x21, HID_SET_REPORT, u16::from_be_bytes([REPORT_TYPE_FEATURE, report_id]), data.len());
+        self.irp += 1;
+        self.packet(URB_FUNCTION_CONTROL_TRANSFER, 0, 0x00, TRANSFER_CONTROL, Some(STAGE_SETUP), &setup)?;
+        self.packet(URB_FUNCTION_CONTROL_TRANSFER, 0, 0x00, TRANSFER_CONTROL, Some(STAGE_DATA), data)?;
+        self.packet(URB_FUNCTION_CONTROL_TRANSFER, INFO_PDO_TO_FDO, 0x00, TRANSFER_CONTROL, Some(STAGE_COMPLETE), &[])
+    }
+
+    /// The interface's report descriptor, as read with GET_DESCRIPTOR.
+    pub fn report_descriptor(&mut self, descriptor: &[u8]) -> io::Result<()> {
+        let setup = self.setup(0x81, REQUEST_GET_DESCRIPTOR, u16::from_be_bytes([DESCRIPTOR_REPORT, 0]), descriptor.len());
+        self.control_in(setup, descriptor)
+    }
+
+    /// A comment for the next packet, which Wireshark shows with it.
+    pub fn comment(&mut self, text: &str) {
+        self.comment = Some(text.to_string());
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn setup(&self, request_type: u8, request: u8, value: u16, length: usize) -> [u8; 8] {
+        let mut setup = [0u8; 8];
+        setup[0] = request_type;
+        setup[1] = request;
+        setup[2..4].copy_from_slice(&value.to_le_bytes());
+        setup[4..6].copy_from_slice(&u16::from(self.address.interface).to_le_bytes());
+        setup[6..8].copy_from_slice(&(length as u16).to_le_bytes());
+        setup
+    }
+
+    fn control_in(&mut self, setup: [u8; 8], data: &[u8]) -> io::Result<()> {
+        self.irp += 1;
+        self.packet(URB_FUNCTION_CONTROL_TRANSFER, 0, 0x80, TRANSFER_CONTROL, Some(STAGE_SETUP), &setup)?;
+        self.packet(URB_FUNCTION_CONTROL_TRANSFER, INFO_PDO_TO_FDO, 0x80, TRANSFER_CONTROL, Some(STAGE_COMPLETE), data)
+    }
+
+    /// One USBPcap packet in an enhanced packet block. Control transfers carry
+    /// a stage byte at the end of the header.
+    fn packet(&mut self, function: u16, info: u8, endpoint: u8, transfer: u8, stage: Option<u8>, data: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(28 + data.len());
+        let header_len: u16 = if stage.is_some() { 28 } else { 27 };
+        packet.extend_from_slice(&header_len.to_le_bytes());
+        packet.extend_from_slice(&self.irp.to_le_bytes());
+        // USBD_STATUS_SUCCESS
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&function.to_le_bytes());
+        packet.push(info);
+        packet.extend_from_slice(&self.address.bus.to_le_bytes());
+        packet.extend_from_slice(&self.address.device.to_le_bytes());
+        packet.push(endpoint);
+        packet.push(transfer);
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend(stage);
+        packet.extend_from_slice(data);
+
+        let micros = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64);
+        let mut epb = Vec::with_capacity(20 + packet.len() + 3);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&packet);
+        pad(&mut epb);
+        if let Some(comment) = self.comment.take() {
+            push_option(&mut epb, OPTION_COMMENT, comment.as_bytes());
+            push_option(&mut epb, OPTION_END, &[]);
+        }
+        write_block(&mut self.out, BLOCK_EPB, &epb)
+    }
+}
+
+/// hidapi takes report ID 0 as "no report ID", which isn't sent.
+fn on_wire(report: &[u8]) -> &[u8] {
+    match report.split_first() {
+        Some((0, rest)) => rest,
+        _ => report,
+    }
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+/// A block with its type and both copies of its total length around `body`,
+/// which must already be padded.
+fn write_block(out: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total = (body.len() + 12) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total.to_le_bytes())
+}
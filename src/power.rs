@@ -0,0 +1,181 @@
+//! Bench power meter readings taken alongside HID traffic, for `--power-meter`.
+//!
+//! A [`Meter`] is anything that returns one reading on demand. [`Scpi`] queries
+//! an instrument over a TCP socket (usually port 5025) or a serial port, which
+//! covers most bench supplies and source meters. A [`Sampler`] polls a meter
+//! on its own thread, stamping each reading against the same clock as the
+//! session's reports, so that both can be merged into one timeline
+//! ([`write_timeline`]).
+
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Port of the raw SCPI socket on most LAN instruments.
+pub const SCPI_PORT: u16 = 5025;
+/// How long an instrument gets to answer a query.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A source of readings, such as a power meter. Implement this to sample an
+/// instrument that doesn't speak SCPI.
+pub trait Meter: Send {
+    fn measure(&mut self) -> anyhow::Result<f64>;
+}
+
+/// An SCPI instrument answering `query` with one number per line.
+pub struct Scpi<S: Read + Write> {
+    stream: BufReader<S>,
+    query: String,
+}
+
+impl<S: Read + Write> Scpi<S> {
+    pub fn new(stream: S, query: &str) -> Self {
+        Scpi { stream: BufReader::new(stream), query: query.to_string() }
+    }
+}
+
+impl<S: Read + Write + Send> Meter for Scpi<S> {
+    fn measure(&mut self) -> anyhow::Result<f64> {
+        let stream = self.stream.get_mut();
+        stream.write_all(self.query.as_bytes())?;
+        stream.write_all(b"\n")?;
+        stream.flush()?;
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(anyhow::anyhow!("The power meter closed the connection"));
+        }
+        // Some instruments append units or further values: "1.234E-3,A"
+        let value = line.trim().split([',', ' ']).next().unwrap_or_default();
+        value.parse().map_err(|_| anyhow::anyhow!("Unexpected power meter reading '{}'", line.trim()))
+    }
+}
+
+/// Connect to the meter at `spec`: `tcp:<host>[:<port>]`, or `serial:<path>`
+/// for a serial port already set to the instrument's baud rate (e.g. with
+/// `stty`).
+pub fn connect(spec: &str, query: &str) -> anyhow::Result<Box<dyn Meter>> {
+    if let Some(address) = spec.strip_prefix("tcp:") {
+        let address = if address.contains(':') { address.to_string() } else { format!("{}:{}", address, SCPI_PORT) };
+        let stream = TcpStream::connect(&address)
+            .map_err(|e| anyhow::anyhow!("Cannot connect to power meter at {}: {}", address, e))?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        return Ok(Box::new(Scpi::new(stream, query)));
+    }
+    if let Some(path) = spec.strip_prefix("serial:") {
+        let port = OpenOptions::new().read(true).write(true).open(path)
+            .map_err(|e| anyhow::anyhow!("Cannot open power meter port {}: {}", path, e))?;
+        return Ok(Box::new(Scpi::new(port, query)));
+    }
+    Err(anyhow::anyhow!("Unknown power meter '{}' (expected tcp:<host>[:<port>] or serial:<path>)", spec))
+}
+
+/// One reading, taken `elapsed` after the session started.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub elapsed: Duration,
+    pub value: f64,
+}
+
+/// Polls a meter every `interval` until finished.
+pub struct Sampler {
+    samples: Arc<Mutex<Vec<Sample>>>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<anyhow::Result<()>>,
+}
+
+impl Sampler {
+    /// Start sampling; readings are stamped relative to `started`.
+    pub fn start(mut meter: Box<dyn Meter>, interval: Duration, started: Instant) -> Self {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (samples, stop) = (samples.clone(), stop.clone());
+            std::thread::spawn(move || {
+                let mut next = Instant::now();
+                // At least one reading, however short the session
+                loop {
+                    let asked = Instant::now();
+                    let value = meter.measure()?;
+                    // The reading was taken somewhere during the query; halfway
+                    // is the best guess
+                    let elapsed = (asked + asked.elapsed() / 2).saturating_duration_since(started);
+                    samples.lock().unwrap_or_else(|e| e.into_inner()).push(Sample { elapsed, value });
+                    if stop.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    next += interval;
+                    std::thread::sleep(next.saturating_duration_since(Instant::now()));
+                }
+            })
+        };
+        Sampler { samples, stop, thread }
+    }
+
+    /// Stop sampling and return the readings, and the error that stopped the
+    /// sampler early, if any.
+    pub fn finish(self) -> (Vec<Sample>, Option<anyhow::Error>) {
+        self.stop.store(true, Ordering::Relaxed);
+        let error = match self.thread.join() {
+            Ok(result) => result.err(),
+            Err(_) => Some(anyhow::anyhow!("The power meter thread panicked")),
+        };
+        let samples = std::mem::take(&mut *self.samples.lock().unwrap_or_else(|e| e.into_inner()));
+        (samples, error)
+    }
+}
+
+/// A report sent or received `elapsed` after the session started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Traffic {
+    pub elapsed: Duration,
+    /// `out`, `in`, `feature_out` or `feature_in`.
+    pub kind: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Something else that happened `elapsed` after the session started, such as
+/// a change of environment tags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Marker {
+    pub elapsed: Duration,
+    pub event: &'static str,
+    pub label: String,
+}
+
+/// Write readings, reports and markers to one CSV timeline, in time order:
+///
+/// ```text
+/// elapsed_ms,event,value
+/// 0.020,env,temperature=85C
+/// 12.502,reading,0.0412
+/// 12.871,out,0101000000
+/// ```
+pub fn write_timeline(path: &Path, samples: &[Sample], traffic: &[Traffic], markers: &[Marker]) -> io::Result<()> {
+    let mut rows: Vec<(Duration, String, String)> = samples.iter()
+        .map(|s| (s.elapsed, "reading".to_string(), s.value.to_string()))
+        .chain(traffic.iter().map(|t| (t.elapsed, t.kind.to_string(), hex::encode(&t.data))))
+        .chain(markers.iter().map(|m| (m.elapsed, m.event.to_string(), csv_field(&m.label))))
+        .collect();
+    rows.sort_by_key(|(elapsed, _, _)| *elapsed);
+    let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "elapsed_ms,event,value")?;
+    for (elapsed, event, value) in rows {
+        writeln!(out, "{:.3},{},{}", elapsed.as_secs_f64() * 1e3, event, value)?;
+    }
+    out.flush()
+}
+
+/// Quote `s` if it holds a comma, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
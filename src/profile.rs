@@ -0,0 +1,920 @@
+//! Device profiles: per-device defaults and named commands, loaded from a TOML file.
+//!
+//! ```toml
+//! name = "Acme Widget"
+//!
+//! [device]
+//! vid = 0x1234
+//! pid = 0x5678
+//!
+//! [commands]
+//! get_status = "01 00"
+//! set_mode = "02 {mode:u8}"
+//!
+//! [commands.set_led]
+//! payload = "03 {index:u8} {rgb:u32be}"
+//! description = "Set LED <index> to a 0xRRGGBB colour"
+//!
+//! [commands.set_led.response]
+//! expect = "83"
+//! status_offset = 1
+//! errors = { 0x01 = "LED index out of range" }
+//! ```
+//!
+//! A command's payload is a [`Template`]: hex bytes with `{name:type}`
+//! placeholders filled in from `name=value` arguments. An optional
+//! [`ResponseSchema`] says how to tell whether the device accepted it.
+//!
+//! The `[errors]` table is a dictionary of device status codes, consulted
+//! whenever a device reports one:
+//!
+//! ```toml
+//! [errors.0x8003]
+//! name = "FLASH_LOCKED"
+//! description = "Flash is write-protected"
+//! action = "Unlock required before write"
+//! lang.de = { description = "Flash ist schreibgeschützt", action = "Vor dem Schreiben entsperren" }
+//! ```
+//!
+//! `[[deny]]` entries list payloads the tool refuses to send without an
+//! explicit override, protecting shared devices from destructive commands:
+//!
+//! ```toml
+//! [[deny]]
+//! pattern = "ee ff ??"
+//! reason = "erase-all"
+//! ```
+//!
+//! `audit_log = "writes.log"` records every write in an [audit log](crate::audit),
+//! whether or not `--audit-log` is given. A relative path is relative to
+//! the profile. `require_operator = true` makes every session that opens a
+//! device ask for an operator ID unless one is given.
+//!
+//! `framing = "cobs"` in `[device]` sets the default [`Framing`] of report
+//! payloads.
+//!
+//! `[state]` keeps each device's [last known good state](crate::state) and
+//! warns when it changes: `feature_reports = [0x05]` adds feature reports to
+//! the firmware version and descriptor that are always compared.
+//!
+//! Commands can require a [`Role`]. Users get theirs from `[roles]`; anyone not
+//! listed is an operator. `raw` is the role needed to send arbitrary data
+//! rather than named commands:
+//!
+//! ```toml
+//! [roles]
+//! raw = "engineer"
+//! users = { alice = "engineer", fab-line = "factory" }
+//!
+//! [commands.unlock_debug]
+//! payload = "7f 01"
+//! role = "engineer"
+//! ```
+
+use crate::config::{self, Table, Value};
+use crate::framing::Framing;
+use crate::pattern::HexPattern;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub name: Option<String>,
+    pub device: DeviceDefaults,
+    pub commands: BTreeMap<String, NamedCommand>,
+    pub errors: BTreeMap<i64, ErrorCode>,
+    pub deny: Vec<DenyRule>,
+    pub roles: Roles,
+    pub audit_log: Option<PathBuf>,
+    /// Whether every session needs an operator ID.
+    pub require_operator: bool,
+    /// Set by `[state]`: cache each device's state and compare on connect.
+    pub state: Option<StateCache>,
+}
+
+/// What `[state]` caches, and where.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateCache {
+    /// Cache directory [default: the user's cache directory].
+    pub dir: Option<PathBuf>,
+    /// Feature reports to include in the state, by report ID.
+    pub feature_reports: Vec<u8>,
+}
+
+/// Who may do what, from `[roles]`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Roles {
+    /// Role of users not listed in `users`.
+    pub default: Role,
+    /// Role needed to send data that isn't a named command.
+    pub raw: Role,
+    /// Highest role of each user, by login name.
+    pub users: BTreeMap<String, Role>,
+}
+
+/// Access levels, each allowed everything the ones before it are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    #[default]
+    Operator,
+    Engineer,
+    Factory,
+}
+
+impl Role {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "operator" => Ok(Role::Operator),
+            "engineer" => Ok(Role::Engineer),
+            "factory" => Ok(Role::Factory),
+            _ => Err(anyhow::anyhow!("unknown role '{}' (expected operator, engineer or factory)", s)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Operator => "operator",
+            Role::Engineer => "engineer",
+            Role::Factory => "factory",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A payload the tool won't send unless told to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DenyRule {
+    /// Matched against the start of the data being sent.
+    pub pattern: HexPattern,
+    pub reason: String,
+}
+
+/// Values from `[device]`, used where the command line doesn't give them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceDefaults {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub usage_page: Option<u16>,
+    pub interface: Option<i32>,
+    pub report_id: Option<u8>,
+    pub report_size: Option<usize>,
+    pub framing: Option<Framing>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NamedCommand {
+    pub template: Template,
+    pub description: Option<String>,
+    pub response: Option<ResponseSchema>,
+    /// Least role allowed to send it.
+    pub role: Role,
+}
+
+/// One entry of the profile's error-code dictionary.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorCode {
+    pub name: String,
+    pub description: Option<String>,
+    /// What the user should do about it.
+    pub action: Option<String>,
+    /// Translated description and action, keyed by language code (`de`, `fr`, ...).
+    pub translations: BTreeMap<String, Translation>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Translation {
+    pub description: Option<String>,
+    pub action: Option<String>,
+}
+
+impl ErrorCode {
+    /// Description in `lang` if translated, otherwise the default one.
+    pub fn description(&self, lang: Option<&str>) -> Option<&str> {
+        self.translation(lang).and_then(|t| t.description.as_deref()).or(self.description.as_deref())
+    }
+
+    /// Suggested action in `lang` if translated, otherwise the default one.
+    pub fn action(&self, lang: Option<&str>) -> Option<&str> {
+        self.translation(lang).and_then(|t| t.action.as_deref()).or(self.action.as_deref())
+    }
+
+    fn translation(&self, lang: Option<&str>) -> Option<&Translation> {
+        self.translations.get(lang?)
+    }
+
+    /// `NAME: description`, or just the name.
+    pub fn summary(&self, lang: Option<&str>) -> String {
+        match self.description(lang) {
+            Some(description) => format!("{}: {}", self.name, description),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Expected shape of a command's response. Offsets count from the start of the
+/// input report payload, i.e. after the report ID when one is stripped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResponseSchema {
+    /// Leading bytes every response must start with, often an echoed opcode.
+    pub expect: Vec<u8>,
+    /// Where the status code is, if the response has one.
+    pub status_offset: Option<usize>,
+    pub status_type: ParamType,
+    /// Status values meaning success.
+    pub success: Vec<i64>,
+    /// Messages for known failing status values.
+    pub errors: BTreeMap<i64, String>,
+}
+
+/// Outcome of checking a response against its [`ResponseSchema`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResponseStatus {
+    /// The response matched; carries the status value if the schema has one.
+    Success(Option<i64>),
+    /// The status value is not a success value.
+    Error { code: i64, message: Option<String> },
+    /// The response is too short or doesn't start with the expected bytes.
+    Malformed(String),
+}
+
+impl ResponseSchema {
+    pub fn check(&self, response: &[u8]) -> ResponseStatus {
+        if !response.starts_with(&self.expect) {
+            return ResponseStatus::Malformed(format!("expected response starting with {}, got {}",
+                hex::encode(&self.expect), hex::encode(&response[..response.len().min(self.expect.len())])));
+        }
+        let Some(offset) = self.status_offset else {
+            return ResponseStatus::Success(None);
+        };
+        let Some(code) = response.get(offset..offset + self.status_type.size()).map(|b| self.status_type.decode(b)) else {
+            return ResponseStatus::Malformed(format!("response too short for a {} status at byte {}",
+                self.status_type.name(), offset));
+        };
+        if self.success.contains(&code) {
+            ResponseStatus::Success(Some(code))
+        } else {
+            ResponseStatus::Error { code, message: self.errors.get(&code).cloned() }
+        }
+    }
+}
+
+impl Profile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read profile {}: {}", path.display(), e))?;
+        let mut profile = Self::parse(&text).map_err(|e| anyhow::anyhow!("Invalid profile {}: {}", path.display(), e))?;
+        if let Some(dir) = path.parent() {
+            let paths = [profile.audit_log.as_mut(), profile.state.as_mut().and_then(|s| s.dir.as_mut())];
+            for p in paths.into_iter().flatten() {
+                *p = dir.join(&*p);
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let root = config::parse(text)?;
+        let mut profile = Profile::default();
+
+        for (key, value) in &root {
+            match key.as_str() {
+                "name" => profile.name = Some(string(key, value)?.to_string()),
+                "audit_log" => profile.audit_log = Some(PathBuf::from(string(key, value)?)),
+                "require_operator" => profile.require_operator = boolean(key, value)?,
+                "state" => profile.state = Some(state_cache(table(key, value)?)?),
+                "device" => profile.device = device_defaults(table(key, value)?)?,
+                "errors" => {
+                    for (code, entry) in table(key, value)? {
+                        let number = parse_code(code)
+                            .ok_or_else(|| anyhow::anyhow!("error code '{}' is not a number", code))?;
+                        let entry = error_code(entry).map_err(|e| anyhow::anyhow!("error {}: {}", code, e))?;
+                        profile.errors.insert(number, entry);
+                    }
+                }
+                "deny" => {
+                    let rules = value.as_array()
+                        .ok_or_else(|| anyhow::anyhow!("'deny' must be an array of tables ([[deny]])"))?;
+                    for (i, rule) in rules.iter().enumerate() {
+                        profile.deny.push(deny_rule(rule).map_err(|e| anyhow::anyhow!("deny entry {}: {}", i + 1, e))?);
+                    }
+                }
+                "roles" => profile.roles = roles(table(key, value)?)?,
+                "commands" => {
+                    for (name, command) in table(key, value)? {
+                        let command = named_command(name, command)
+                            .map_err(|e| anyhow::anyhow!("command '{}': {}", name, e))?;
+                        profile.commands.insert(name.clone(), command);
+                    }
+                }
+                _ => return Err(anyhow::anyhow!("unknown key '{}'", key)),
+            }
+        }
+        Ok(profile)
+    }
+
+    /// The first deny-list entry matching `data`, if any: the payload after
+    /// the report ID, as [`ReportFormat::data_payload`](crate::exchange::ReportFormat::data_payload) gives it.
+    pub fn denied(&self, data: &[u8]) -> Option<&DenyRule> {
+        self.deny.iter().find(|rule| rule.pattern.matches_prefix(data))
+    }
+
+    /// The highest role `user` may act as.
+    pub fn role_of(&self, user: Option<&str>) -> Role {
+        user.and_then(|u| self.roles.users.get(u)).copied().unwrap_or(self.roles.default)
+    }
+
+    /// Dictionary entry for a device status code.
+    pub fn error(&self, code: i64) -> Option<&ErrorCode> {
+        self.errors.get(&code)
+    }
+
+    pub fn command(&self, name: &str) -> anyhow::Result<&NamedCommand> {
+        self.commands.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.commands.keys().map(String::as_str).collect();
+            anyhow::anyhow!("Profile has no command '{}' (known: {})", name, known.join(", "))
+        })
+    }
+}
+
+fn device_defaults(t: &Table) -> anyhow::Result<DeviceDefaults> {
+    let mut d = DeviceDefaults::default();
+    for (key, value) in t {
+        match key.as_str() {
+            "vid" => d.vid = Some(integer(key, value)?),
+            "pid" => d.pid = Some(integer(key, value)?),
+            "usage_page" => d.usage_page = Some(integer(key, value)?),
+            "interface" => d.interface = Some(integer(key, value)?),
+            "report_id" => d.report_id = Some(integer(key, value)?),
+            "report_size" => d.report_size = Some(integer(key, value)?),
+            "framing" => {
+                let name = string(key, value)?;
+                let framing = Framing::from_str(name, false)
+                    .map_err(|_| anyhow::anyhow!("unknown framing '{}' (expected raw, cobs or len-crc16)", name))?;
+                d.framing = Some(framing);
+            }
+            _ => return Err(anyhow::anyhow!("unknown key 'device.{}'", key)),
+        }
+    }
+    Ok(d)
+}
+
+fn state_cache(t: &Table) -> anyhow::Result<StateCache> {
+    let mut state = StateCache::default();
+    for (key, value) in t {
+        match key.as_str() {
+            "dir" => state.dir = Some(PathBuf::from(string(key, value)?)),
+            "feature_reports" => {
+                let ids = value.as_array()
+                    .ok_or_else(|| anyhow::anyhow!("'state.feature_reports' must be an array of report IDs"))?;
+                state.feature_reports = ids.iter().map(|id| integer("state.feature_reports", id)).collect::<anyhow::Result<_>>()?;
+            }
+            _ => return Err(anyhow::anyhow!("unknown key 'state.{}'", key)),
+        }
+    }
+    Ok(state)
+}
+
+fn roles(t: &Table) -> anyhow::Result<Roles> {
+    let mut roles = Roles::default();
+    for (key, value) in t {
+        match key.as_str() {
+            "default" => roles.default = Role::parse(string(key, value)?)?,
+            "raw" => roles.raw = Role::parse(string(key, value)?)?,
+            "users" => {
+                for (user, role) in table(key, value)? {
+                    let role = Role::parse(string(user, role)?).map_err(|e| anyhow::anyhow!("user '{}': {}", user, e))?;
+                    roles.users.insert(user.clone(), role);
+                }
+            }
+            _ => return Err(anyhow::anyhow!("unknown key 'roles.{}'", key)),
+        }
+    }
+    Ok(roles)
+}
+
+fn deny_rule(value: &Value) -> anyhow::Result<DenyRule> {
+    let mut pattern = None;
+    let mut reason = None;
+    for (key, value) in table("deny", value)? {
+        match key.as_str() {
+            "pattern" => pattern = Some(HexPattern::parse(string(key, value)?)?),
+            "reason" => reason = Some(string(key, value)?.to_string()),
+            _ => return Err(anyhow::anyhow!("unknown key '{}'", key)),
+        }
+    }
+    Ok(DenyRule {
+        pattern: pattern.ok_or_else(|| anyhow::anyhow!("missing 'pattern'"))?,
+        reason: reason.ok_or_else(|| anyhow::anyhow!("missing 'reason'"))?,
+    })
+}
+
+fn error_code(value: &Value) -> anyhow::Result<ErrorCode> {
+    if let Value::String(name) = value {
+        return Ok(ErrorCode { name: name.clone(), ..ErrorCode::default() });
+    }
+    let mut entry = ErrorCode::default();
+    let mut name = None;
+    for (key, value) in table("error", value)? {
+        match key.as_str() {
+            "name" => name = Some(string(key, value)?.to_string()),
+            "description" => entry.description = Some(string(key, value)?.to_string()),
+            "action" => entry.action = Some(string(key, value)?.to_string()),
+            "lang" => {
+                for (lang, t) in table(key, value)? {
+                    let mut translation = Translation::default();
+                    for (key, value) in table(lang, t)? {
+                        match key.as_str() {
+                            "description" => translation.description = Some(string(key, value)?.to_string()),
+                            "action" => translation.action = Some(string(key, value)?.to_string()),
+                            _ => return Err(anyhow::anyhow!("unknown key 'lang.{}.{}'", lang, key)),
+                        }
+                    }
+                    entry.translations.insert(lang.clone(), translation);
+                }
+            }
+            _ => return Err(anyhow::anyhow!("unknown key '{}'", key)),
+        }
+    }
+    entry.name = name.ok_or_else(|| anyhow::anyhow!("missing 'name'"))?;
+    Ok(entry)
+}
+
+fn named_command(name: &str, value: &Value) -> anyhow::Result<NamedCommand> {
+    if let Value::String(payload) = value {
+        return Ok(NamedCommand { template: Template::parse(payload)?, description: None, response: None, role: Role::default() });
+    }
+    let t = table(name, value)?;
+    let mut payload = None;
+    let mut description = None;
+    let mut response = None;
+    let mut role = Role::default();
+    for (key, value) in t {
+        match key.as_str() {
+            "payload" => payload = Some(string(key, value)?),
+            "description" => description = Some(string(key, value)?.to_string()),
+            "response" => response = Some(response_schema(table(key, value)?)?),
+            "role" => role = Role::parse(string(key, value)?)?,
+            _ => return Err(anyhow::anyhow!("unknown key '{}'", key)),
+        }
+    }
+    let payload = payload.ok_or_else(|| anyhow::anyhow!("missing 'payload'"))?;
+    Ok(NamedCommand { template: Template::parse(payload)?, description, response, role })
+}
+
+fn response_schema(t: &Table) -> anyhow::Result<ResponseSchema> {
+    let mut schema = ResponseSchema {
+        expect: Vec::new(),
+        status_offset: None,
+        status_type: ParamType::U8,
+        success: vec![0],
+        errors: BTreeMap::new(),
+    };
+    for (key, value) in t {
+        match key.as_str() {
+            "expect" => {
+                let digits: String = string(key, value)?.chars().filter(|c| !c.is_whitespace()).collect();
+                schema.expect = hex::decode(&digits)
+                    .map_err(|e| anyhow::anyhow!("'expect' is not hex: {}", e))?;
+            }
+            "status_offset" => schema.status_offset = Some(integer(key, value)?),
+            "status_type" => {
+                let name = string(key, value)?;
+                schema.status_type = ParamType::parse(name)
+                    .ok_or_else(|| anyhow::anyhow!("unknown status_type '{}'", name))?;
+            }
+            "success" => {
+                schema.success = match value {
+                    Value::Array(items) => items.iter().map(|v| integer(key, v)).collect::<anyhow::Result<_>>()?,
+                    _ => vec![integer(key, value)?],
+                };
+            }
+            "errors" => {
+                for (code, message) in table(key, value)? {
+                    let code = parse_code(code)
+                        .ok_or_else(|| anyhow::anyhow!("error code '{}' is not a number", code))?;
+                    schema.errors.insert(code, string(key, message)?.to_string());
+                }
+            }
+            _ => return Err(anyhow::anyhow!("unknown key 'response.{}'", key)),
+        }
+    }
+    Ok(schema)
+}
+
+/// A status code written as a table key: decimal or `0x` hexadecimal.
+fn parse_code(s: &str) -> Option<i64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> anyhow::Result<&'a str> {
+    value.as_str().ok_or_else(|| anyhow::anyhow!("'{}' must be a string, not {}", key, value.type_name()))
+}
+
+fn boolean(key: &str, value: &Value) -> anyhow::Result<bool> {
+    value.as_bool().ok_or_else(|| anyhow::anyhow!("'{}' must be a boolean, not {}", key, value.type_name()))
+}
+
+fn table<'a>(key: &str, value: &'a Value) -> anyhow::Result<&'a Table> {
+    value.as_table().ok_or_else(|| anyhow::anyhow!("'{}' must be a table, not {}", key, value.type_name()))
+}
+
+fn integer<T: TryFrom<i64>>(key: &str, value: &Value) -> anyhow::Result<T> {
+    let i = value.as_integer()
+        .ok_or_else(|| anyhow::anyhow!("'{}' must be an integer, not {}", key, value.type_name()))?;
+    T::try_from(i).map_err(|_| anyhow::anyhow!("'{}' is out of range: {}", key, i))
+}
+
+/// Type of a template placeholder. Multi-byte values are little-endian unless
+/// the type ends in `be`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamType {
+    U8,
+    U16,
+    U16Be,
+    U32,
+    U32Be,
+    I8,
+    I16,
+    I16Be,
+    I32,
+    I32Be,
+}
+
+impl ParamType {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "u8" => ParamType::U8,
+            "u16" => ParamType::U16,
+            "u16be" => ParamType::U16Be,
+            "u32" => ParamType::U32,
+            "u32be" => ParamType::U32Be,
+            "i8" => ParamType::I8,
+            "i16" => ParamType::I16,
+            "i16be" => ParamType::I16Be,
+            "i32" => ParamType::I32,
+            "i32be" => ParamType::I32Be,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ParamType::U8 => "u8",
+            ParamType::U16 => "u16",
+            ParamType::U16Be => "u16be",
+            ParamType::U32 => "u32",
+            ParamType::U32Be => "u32be",
+            ParamType::I8 => "i8",
+            ParamType::I16 => "i16",
+            ParamType::I16Be => "i16be",
+            ParamType::I32 => "i32",
+            ParamType::I32Be => "i32be",
+        }
+    }
+
+    /// Encoded length in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            ParamType::U8 | ParamType::I8 => 1,
+            ParamType::U16 | ParamType::U16Be | ParamType::I16 | ParamType::I16Be => 2,
+            _ => 4,
+        }
+    }
+
+    fn signed(self) -> bool {
+        matches!(self, ParamType::I8 | ParamType::I16 | ParamType::I16Be | ParamType::I32 | ParamType::I32Be)
+    }
+
+    fn big_endian(self) -> bool {
+        matches!(self, ParamType::U16Be | ParamType::U32Be | ParamType::I16Be | ParamType::I32Be)
+    }
+
+    /// Decode a value of this type from exactly [`size`](Self::size) bytes.
+    pub fn decode(self, bytes: &[u8]) -> i64 {
+        let mut le = bytes.to_vec();
+        if self.big_endian() {
+            le.reverse();
+        }
+        let unsigned = le.iter().rev().fold(0i64, |acc, &b| (acc << 8) | i64::from(b));
+        let bits = self.size() as u32 * 8;
+        if self.signed() && unsigned & (1 << (bits - 1)) != 0 {
+            unsigned - (1 << bits)
+        } else {
+            unsigned
+        }
+    }
+
+    /// Encode `value` (decimal, or hexadecimal with `0x`), checking its range.
+    pub fn encode(self, value: &str) -> anyhow::Result<Vec<u8>> {
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let magnitude = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => digits.parse::<i64>(),
+        }
+        .map_err(|_| anyhow::anyhow!("'{}' is not a number", value))?;
+        let n = if negative { -magnitude } else { magnitude };
+
+        let bits = self.size() as u32 * 8;
+        let (min, max) = if self.signed() {
+            (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1)
+        } else {
+            (0, (1i64 << bits) - 1)
+        };
+        if n < min || n > max {
+            return Err(anyhow::anyhow!("{} does not fit in {} ({}..={})", value, self.name(), min, max));
+        }
+
+        let le = n.to_le_bytes();
+        let mut bytes = le[..self.size()].to_vec();
+        if self.big_endian() {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Bytes(Vec<u8>),
+    Param { name: String, ty: ParamType },
+}
+
+/// A report payload: hex bytes (spaces optional) with `{name:type}` placeholders.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('{') {
+                let end = after.find('}').ok_or_else(|| anyhow::anyhow!("unclosed '{{' in \"{}\"", s))?;
+                let (name, ty) = after[..end].split_once(':')
+                    .ok_or_else(|| anyhow::anyhow!("placeholder {{{}}} needs a type, e.g. {{{}:u8}}", &after[..end], &after[..end]))?;
+                let ty = ParamType::parse(ty.trim())
+                    .ok_or_else(|| anyhow::anyhow!("unknown type '{}' in {{{}}}", ty, &after[..end]))?;
+                parts.push(Part::Param { name: name.trim().to_string(), ty });
+                rest = &after[end + 1..];
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                let digits: String = rest[..end].chars().filter(|c| !c.is_whitespace()).collect();
+                if !digits.is_empty() {
+                    let bytes = hex::decode(&digits)
+                        .map_err(|e| anyhow::anyhow!("invalid hex '{}': {}", digits, e))?;
+                    parts.push(Part::Bytes(bytes));
+                }
+                rest = &rest[end..];
+            }
+        }
+        Ok(Template { source: s.to_string(), parts })
+    }
+
+    /// Placeholders in order of appearance.
+    pub fn params(&self) -> impl Iterator<Item = (&str, ParamType)> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Param { name, ty } => Some((name.as_str(), *ty)),
+            Part::Bytes(_) => None,
+        })
+    }
+
+    /// Fill in the placeholders from `name=value` pairs. Every placeholder needs a
+    /// value and every value a placeholder.
+    pub fn render(&self, args: &[(String, String)]) -> anyhow::Result<Vec<u8>> {
+        if let Some((name, _)) = args.iter().find(|(name, _)| !self.params().any(|(p, _)| p == name)) {
+            return Err(anyhow::anyhow!("unknown parameter '{}' (expected: {})", name, self.signature()));
+        }
+        let mut out = Vec::new();
+        for part in &self.parts {
+            match part {
+                Part::Bytes(bytes) => out.extend_from_slice(bytes),
+                Part::Param { name, ty } => {
+                    let (_, value) = args.iter().find(|(n, _)| n == name)
+                        .ok_or_else(|| anyhow::anyhow!("missing parameter '{}' (expected: {})", name, self.signature()))?;
+                    let bytes = ty.encode(value).map_err(|e| anyhow::anyhow!("parameter '{}': {}", name, e))?;
+                    out.extend_from_slice(&bytes);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// `name:type` of each placeholder, e.g. `mode:u8 level:u16`.
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self.params().map(|(n, t)| format!("{}:{}", n, t.name())).collect();
+        if params.is_empty() {
+            "no parameters".to_string()
+        } else {
+            params.join(" ")
+        }
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = r#"
+name = "Acme Widget"
+audit_log = "writes.log"
+require_operator = true
+
+[device]
+vid = 0x1234
+pid = 0x5678
+report_id = 2
+framing = "len-crc16"
+
+[commands]
+get_status = "01 00"
+set_mode = "02 {mode:u8}"
+
+[commands.set_led]
+payload = "03 {index:u8} {rgb:u32be}"
+description = "Set LED <index> to a 0xRRGGBB colour"
+
+[commands.set_led.response]
+expect = "83"
+status_offset = 1
+errors = { 0x01 = "LED index out of range" }
+
+[commands.unlock_debug]
+payload = "7f 01"
+role = "engineer"
+
+[errors]
+0x02 = "BUSY"
+
+[errors.0x8003]
+name = "FLASH_LOCKED"
+description = "Flash is write-protected"
+action = "Unlock required before write"
+lang.de = { description = "Flash ist schreibgeschützt" }
+
+[[deny]]
+pattern = "ee ff ??"
+reason = "erase-all"
+
+[roles]
+raw = "engineer"
+users = { alice = "engineer", fab-line = "factory" }
+
+[state]
+feature_reports = [0x05, 6]
+"#;
+
+    fn args(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn parse_error(text: &str) -> String {
+        Profile::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn loads_every_section() {
+        let p = Profile::parse(PROFILE).unwrap();
+        assert_eq!(p.name.as_deref(), Some("Acme Widget"));
+        assert_eq!(p.audit_log, Some(PathBuf::from("writes.log")));
+        assert!(p.require_operator);
+        assert_eq!(p.device.vid, Some(0x1234));
+        assert_eq!(p.device.pid, Some(0x5678));
+        assert_eq!(p.device.report_id, Some(2));
+        assert_eq!(p.device.framing, Some(Framing::LenCrc16));
+        assert_eq!(p.commands.len(), 4);
+        assert_eq!(p.state.unwrap().feature_reports, [5, 6]);
+    }
+
+    #[test]
+    fn templates_render() {
+        let p = Profile::parse(PROFILE).unwrap();
+        assert_eq!(p.command("get_status").unwrap().template.render(&[]).unwrap(), [0x01, 0x00]);
+        let set_led = &p.command("set_led").unwrap().template;
+        assert_eq!(set_led.signature(), "index:u8 rgb:u32be");
+        assert_eq!(set_led.render(&args(&[("rgb", "0xff8000"), ("index", "3")])).unwrap(), [0x03, 0x03, 0x00, 0xff, 0x80, 0x00]);
+        assert!(set_led.render(&args(&[("index", "3")])).unwrap_err().to_string().starts_with("missing parameter 'rgb'"));
+        assert!(set_led.render(&args(&[("index", "1"), ("rgb", "0"), ("x", "1")])).unwrap_err().to_string().starts_with("unknown parameter 'x'"));
+        assert!(set_led.render(&args(&[("index", "256"), ("rgb", "0")])).unwrap_err().to_string().contains("does not fit in u8"));
+        assert!(p.command("reboot").unwrap_err().to_string().contains("known: get_status, set_led, set_mode, unlock_debug"));
+    }
+
+    #[test]
+    fn template_errors() {
+        assert!(Template::parse("01 {mode}").unwrap_err().to_string().contains("needs a type"));
+        assert!(Template::parse("01 {mode:u24}").unwrap_err().to_string().contains("unknown type 'u24'"));
+        assert!(Template::parse("01 {mode:u8").unwrap_err().to_string().contains("unclosed"));
+        assert!(Template::parse("0 1 2").unwrap_err().to_string().contains("invalid hex"));
+        assert_eq!(Template::parse("").unwrap().signature(), "no parameters");
+    }
+
+    #[test]
+    fn param_types() {
+        let cases: &[(ParamType, &str, &[u8], i64)] = &[
+            (ParamType::U8, "255", &[0xff], 255),
+            (ParamType::U16, "0x1234", &[0x34, 0x12], 0x1234),
+            (ParamType::U16Be, "0x1234", &[0x12, 0x34], 0x1234),
+            (ParamType::U32, "1", &[1, 0, 0, 0], 1),
+            (ParamType::U32Be, "0xdeadbeef", &[0xde, 0xad, 0xbe, 0xef], 0xdead_beef),
+            (ParamType::I8, "-1", &[0xff], -1),
+            (ParamType::I16, "-2", &[0xfe, 0xff], -2),
+            (ParamType::I16Be, "-32768", &[0x80, 0x00], -32768),
+            (ParamType::I32, "-0x10", &[0xf0, 0xff, 0xff, 0xff], -16),
+            (ParamType::I32Be, "2147483647", &[0x7f, 0xff, 0xff, 0xff], i32::MAX.into()),
+        ];
+        for &(ty, text, bytes, value) in cases {
+            assert_eq!(ty.encode(text).unwrap(), bytes, "{}", ty.name());
+            assert_eq!(ty.decode(bytes), value, "{}", ty.name());
+        }
+        assert!(ParamType::U8.encode("-1").is_err());
+        assert!(ParamType::I8.encode("128").is_err());
+        assert!(ParamType::U16.encode("abc").is_err());
+    }
+
+    #[test]
+    fn response_schema() {
+        let p = Profile::parse(PROFILE).unwrap();
+        let schema = p.command("set_led").unwrap().response.as_ref().unwrap();
+        assert_eq!(schema.check(&[0x83, 0x00, 0xaa]), ResponseStatus::Success(Some(0)));
+        assert_eq!(schema.check(&[0x83, 0x01]),
+            ResponseStatus::Error { code: 1, message: Some("LED index out of range".to_string()) });
+        assert_eq!(schema.check(&[0x83, 0x07]), ResponseStatus::Error { code: 7, message: None });
+        assert!(matches!(schema.check(&[0x84, 0x00]), ResponseStatus::Malformed(_)));
+        assert!(matches!(schema.check(&[0x83]), ResponseStatus::Malformed(_)));
+    }
+
+    #[test]
+    fn error_dictionary() {
+        let p = Profile::parse(PROFILE).unwrap();
+        let busy = p.error(2).unwrap();
+        assert_eq!(busy.summary(None), "BUSY");
+        let locked = p.error(0x8003).unwrap();
+        assert_eq!(locked.summary(None), "FLASH_LOCKED: Flash is write-protected");
+        assert_eq!(locked.summary(Some("de")), "FLASH_LOCKED: Flash ist schreibgeschützt");
+        // Untranslated fields and unknown languages fall back to the default.
+        assert_eq!(locked.action(Some("de")), Some("Unlock required before write"));
+        assert_eq!(locked.description(Some("fr")), Some("Flash is write-protected"));
+        assert!(p.error(0x8004).is_none());
+    }
+
+    #[test]
+    fn deny_and_roles() {
+        let p = Profile::parse(PROFILE).unwrap();
+        assert_eq!(p.denied(&[0xee, 0xff, 0x00, 0x01]).map(|r| r.reason.as_str()), Some("erase-all"));
+        assert!(p.denied(&[0xee, 0xfe, 0x00]).is_none());
+        assert_eq!(p.roles.raw, Role::Engineer);
+        assert_eq!(p.role_of(Some("fab-line")), Role::Factory);
+        assert_eq!(p.role_of(Some("bob")), Role::Operator);
+        assert_eq!(p.role_of(None), Role::Operator);
+        assert_eq!(p.command("unlock_debug").unwrap().role, Role::Engineer);
+    }
+
+    #[test]
+    fn load_resolves_paths_against_the_profile() {
+        let dir = std::env::temp_dir().join(format!("hid-cmd-profile-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("widget.toml");
+        std::fs::write(&path, "audit_log = \"writes.log\"\n[state]\ndir = \"state\"\n").unwrap();
+        let p = Profile::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(p.audit_log, Some(dir.join("writes.log")));
+        assert_eq!(p.state.unwrap().dir, Some(dir.join("state")));
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        assert_eq!(parse_error("colour = \"red\""), "unknown key 'colour'");
+        assert_eq!(parse_error("[device]\nvendor = 1"), "unknown key 'device.vendor'");
+        assert_eq!(parse_error("[device]\nframing = \"slip\""), "unknown framing 'slip' (expected raw, cobs or len-crc16)");
+        assert!(parse_error("[device]\nvid = 0x10000").starts_with("'vid' is out of range"));
+        assert!(parse_error("[device]\nvid = \"1234\"").starts_with("'vid' must be an integer"));
+        assert_eq!(parse_error("[errors]\nbusy = \"BUSY\""), "error code 'busy' is not a number");
+        assert_eq!(parse_error("[errors.3]\ndescription = \"x\""), "error 3: missing 'name'");
+        assert_eq!(parse_error("[[deny]]\npattern = \"ee\""), "deny entry 1: missing 'reason'");
+        assert_eq!(parse_error("[roles]\nraw = \"admin\""), "unknown role 'admin' (expected operator, engineer or factory)");
+        assert_eq!(parse_error("[commands.x]\ndescription = \"y\""), "command 'x': missing 'payload'");
+        assert_eq!(parse_error("[commands.x]\npayload = \"01\"\nresponse = { wait = 1 }"), "command 'x': unknown key 'response.wait'");
+        assert_eq!(parse_error("name = \"a\"\nname = \"b\""), "line 2: duplicate key 'name'");
+    }
+}
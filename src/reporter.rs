@@ -0,0 +1,1513 @@
+//! Console output of report traffic for the CLI, mirrored into a transcript
+//! when `--transcript` is given.
+//!
+//! Writes are also appended to the audit log when one is configured.
+//!
+//! With `--json`, stdout carries one JSON object per line instead of the
+//! human-readable text, and progress messages move to stderr.
+
+use hid_cmd::audit::{AuditEntry, AuditLog};
+use hid_cmd::compare::Difference;
+use hid_cmd::crashdump;
+use hid_cmd::defmt;
+use hid_cmd::environment::{self, Environment};
+use hid_cmd::framing::Framing;
+use hid_cmd::hexfmt::HexBuf;
+use hid_cmd::json::Json;
+use hid_cmd::latency::LatencyStats;
+use hid_cmd::pcapng::{UsbAddress, UsbCapture};
+use hid_cmd::power::{self, Marker, Meter, Sampler, Traffic};
+use hid_cmd::profile::{DenyRule, ErrorCode, ResponseStatus};
+use hid_cmd::state::Change;
+use hid_cmd::target_test::{Case, Status};
+use hid_cmd::timestamp;
+use hid_cmd::trace;
+use hid_cmd::transcript::Transcript;
+use hid_cmd::usb;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub struct Reporter {
+    hex_buf: HexBuf,
+    transcript: Option<Transcript<BufWriter<File>>>,
+    /// Round-trip latency of each named command sent this session.
+    latencies: BTreeMap<String, LatencyStats>,
+    json: bool,
+    started: Instant,
+    identity: Identity,
+    audit: Option<Audit>,
+    capture: Option<UsbCapture<BufWriter<File>>>,
+    power: Option<PowerLog>,
+    environment: Environment,
+    /// Read again at every new segment.
+    env_source: Option<environment::Source>,
+}
+
+/// Who runs the session: the OS user and, at factory stations, the operator
+/// badge ID.
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    pub user: Option<String>,
+    pub operator: Option<String>,
+}
+
+impl Identity {
+    /// `user/operator` as recorded in the audit log, `-` for unknown parts.
+    fn label(&self) -> String {
+        let user = self.user.as_deref().unwrap_or("-");
+        match &self.operator {
+            Some(operator) => format!("{}/{}", user, operator),
+            None => user.to_string(),
+        }
+    }
+}
+
+/// Power meter readings and the reports to merge them with, written to `path`
+/// at the end of the session.
+struct PowerLog {
+    path: PathBuf,
+    sampler: Sampler,
+    traffic: Vec<Traffic>,
+    markers: Vec<Marker>,
+}
+
+/// The audit logs and the device each entry is about.
+struct Audit {
+    logs: Vec<AuditLog>,
+    /// `vid:pid` and serial number of the opened device.
+    device: Option<(String, String)>,
+}
+
+impl Reporter {
+    pub fn new(transcript: Option<Transcript<BufWriter<File>>>, json: bool) -> Self {
+        Reporter {
+            hex_buf: HexBuf::new(),
+            transcript,
+            latencies: BTreeMap::new(),
+            json,
+            started: Instant::now(),
+            identity: Identity::default(),
+            audit: None,
+            capture: None,
+            power: None,
+            environment: Environment::default(),
+            env_source: None,
+        }
+    }
+
+    /// Attribute the session to `identity` in the transcript, audit log and,
+    /// when there is an operator ID, every JSON event.
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        if let Some(user) = &identity.user {
+            self.log(|t| t.detail("User", user));
+        }
+        if let Some(operator) = &identity.operator {
+            self.log(|t| t.detail("Operator", operator));
+        }
+        self.identity = identity;
+        self
+    }
+
+    /// Record every write in `log`, as well as any log added before.
+    pub fn with_audit(mut self, log: AuditLog) -> Self {
+        self.audit.get_or_insert_with(|| Audit { logs: Vec::new(), device: None }).logs.push(log);
+        self
+    }
+
+    /// Record all traffic in `capture`.
+    pub fn with_capture(mut self, capture: UsbCapture<BufWriter<File>>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Sample `meter` every `interval` from now on, and write its readings
+    /// together with all traffic to a CSV timeline at `path` when finished.
+    pub fn with_power_meter(mut self, meter: Box<dyn Meter>, interval: Duration, path: PathBuf) -> Self {
+        let sampler = Sampler::start(meter, interval, self.started);
+        self.power = Some(PowerLog { path, sampler, traffic: Vec::new(), markers: Vec::new() });
+        self
+    }
+
+    /// Tag the session with `environment`, in the transcript, every JSON event,
+    /// the capture and the power timeline. `source`, if any, is read again
+    /// at every new segment.
+    pub fn with_environment(mut self, environment: Environment, source: Option<environment::Source>) -> Self {
+        if !environment.is_empty() {
+            let label = environment.label();
+            self.log(|t| t.detail("Environment", &label));
+        }
+        self.environment = environment;
+        self.env_source = source;
+        self.environment_marked();
+        self
+    }
+
+    /// Start a new segment, such as the next chamber setpoint: read the
+    /// environment source again, if any, then apply `tags`.
+    pub fn new_segment(&mut self, tags: &[(String, String)]) -> anyhow::Result<()> {
+        if let Some(source) = &self.env_source {
+            self.environment.update(source.read()?);
+        }
+        self.environment.update(tags.iter().cloned());
+        let message = format!("Environment: {}", self.environment.label());
+        if !self.json {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+        self.environment_marked();
+        Ok(())
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    /// Mark where the current tags start in the exports that have no
+    /// per-entry place for them.
+    fn environment_marked(&mut self) {
+        if self.environment.is_empty() {
+            return;
+        }
+        let label = self.environment.label();
+        if self.json {
+            let event = self.event("environment");
+            self.emit(&event);
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.comment(&format!("Environment: {}", label));
+        }
+        if let Some(power) = &mut self.power {
+            power.markers.push(Marker { elapsed: self.started.elapsed(), event: "env", label });
+        }
+    }
+
+    /// Add a report to the power timeline, if any, stamped now.
+    fn traffic(&mut self, kind: &'static str, data: &[u8]) {
+        if let Some(power) = &mut self.power {
+            power.traffic.push(Traffic { elapsed: self.started.elapsed(), kind, data: data.to_vec() });
+        }
+    }
+
+    /// Add to the capture, if any. It is given up after the first error,
+    /// rather than reporting a broken file on every report.
+    fn capture(&mut self, f: impl FnOnce(&mut UsbCapture<BufWriter<File>>) -> io::Result<()>) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        if let Err(e) = f(capture) {
+            self.capture = None;
+            self.error("Error writing capture, capturing stopped", &e.into());
+        }
+    }
+
+    /// Append a write to the audit logs, if any. A failed append ends the
+    /// session at once, so that no further write goes unrecorded.
+    fn audit(&mut self, operation: &str, data: &[u8], result: String) {
+        let Some(audit) = &mut self.audit else {
+            return;
+        };
+        let (device, serial) = audit.device.as_ref().map_or(("-", "-"), |(d, s)| (d.as_str(), s.as_str()));
+        let user = self.identity.label();
+        let entry = AuditEntry { user: &user, device, serial, operation, payload: data, result: &result };
+        let errors: Vec<_> = audit.logs.iter_mut().filter_map(|log| log.record(&entry).err()).collect();
+        if errors.is_empty() {
+            return;
+        }
+        for e in errors {
+            self.error("Error writing audit log", &e.into());
+        }
+        self.finish();
+        eprintln!("Error: Stopping, since writes can no longer be audited");
+        std::process::exit(1);
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// Run `f` against the transcript, if any. A failed write is reported once
+    /// and ends the transcript rather than the session.
+    fn log(&mut self, f: impl FnOnce(&mut Transcript<BufWriter<File>>) -> io::Result<()>) {
+        if let Some(transcript) = &mut self.transcript {
+            if let Err(e) = f(transcript) {
+                eprintln!("Error writing transcript, no longer recording: {}", e);
+                self.transcript = None;
+            }
+        }
+    }
+
+    /// Write one JSON Lines record.
+    pub fn emit(&self, value: &Json) {
+        println!("{}", value);
+    }
+
+    /// A JSON event record with its wall-clock time and offset from the start.
+    fn event(&self, kind: &str) -> Json {
+        let mut event = Json::object([
+            ("event", kind.into()),
+            ("time", timestamp::now_utc().into()),
+            ("elapsed_ms", millis(self.started.elapsed()).into()),
+        ]);
+        event.push("user", self.identity.user.as_deref());
+        if let Some(operator) = &self.identity.operator {
+            event.push("operator", operator.as_str());
+        }
+        if !self.environment.is_empty() {
+            let tags = self.environment.tags.iter().map(|(key, value)| (key.as_str(), value.as_str().into()));
+            event.push("environment", Json::object(tags));
+        }
+        event
+    }
+
+    fn report_event(&mut self, kind: &str, direction: &str, report_id: Option<u8>, data: &[u8]) -> Json {
+        let mut event = self.event(kind);
+        event.push("direction", direction);
+        event.push("report_id", report_id);
+        event.push("length", data.len());
+        event.push("hex", self.hex_buf.encode(data).to_string());
+        event
+    }
+
+    /// A progress message such as "Successfully opened device"; kept off stdout
+    /// in JSON mode.
+    pub fn info(&self, message: &str) {
+        if self.json {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    pub fn device_opened(&mut self, info: &hidapi::DeviceInfo) {
+        if self.json {
+            let mut event = self.event("device");
+            if let Json::Object(members) = device_json(info) {
+                for (key, value) in members {
+                    event.push(&key, value);
+                }
+            }
+            self.emit(&event);
+        }
+        let device = format!("{:04x}:{:04x} {} {} (serial {}, interface {}, {})",
+            info.vendor_id(), info.product_id(),
+            info.manufacturer_string().unwrap_or("(unknown manufacturer)"),
+            info.product_string().unwrap_or("(unknown product)"),
+            info.serial_number().unwrap_or("-"), info.interface_number(),
+            info.path().to_string_lossy());
+        self.log(|t| t.detail("Device", &device));
+        self.select_device(info);
+        if let Some(capture) = &mut self.capture {
+            capture.address = usb_address(info);
+        }
+    }
+
+    /// The report descriptor of the opened device, so the capture can be
+    /// decoded.
+    pub fn descriptor_read(&mut self, descriptor: &[u8]) {
+        self.capture(|c| c.report_descriptor(descriptor));
+    }
+
+    /// An input report that was read without being shown; only captured.
+    pub fn quiet_input(&mut self, report: &[u8]) {
+        self.capture(|c| c.input_report(report));
+        self.traffic("in", report);
+    }
+
+    /// Attribute the following writes to `info` in the audit log, for
+    /// commands that have more than one device open.
+    pub fn select_device(&mut self, info: &hidapi::DeviceInfo) {
+        if let Some(audit) = &mut self.audit {
+            let serial = info.serial_number().filter(|s| !s.is_empty()).unwrap_or("-");
+            audit.device = Some((format!("{:04x}:{:04x}", info.vendor_id(), info.product_id()), serial.to_string()));
+        }
+    }
+
+    /// Show an output report about to be written. `report_id` is set when the ID
+    /// was given separately rather than as the first data byte.
+    pub fn output_report(&mut self, report_id: Option<u8>, report: &[u8]) {
+        if self.json {
+            let event = self.report_event("report", "out", report.first().copied(), report);
+            self.emit(&event);
+        } else {
+            match report_id {
+                Some(id) => println!("\nSending Output Report 0x{:02x} ({} bytes):", id, report.len()),
+                None => println!("\nSending Output Report ({} bytes):", report.len()),
+            }
+            println!("Hex: {}", self.hex_buf.encode(report));
+        }
+
+        let annotations: Vec<String> = report.first()
+            .map(|id| format!("byte 0: report ID 0x{:02x}", id))
+            .into_iter()
+            .collect();
+        self.log(|t| t.report("Sent output report", &annotations, report));
+    }
+
+    /// The payload decoded from a framed input report.
+    pub fn frame_in(&mut self, framing: Framing, payload: &[u8]) {
+        if self.json {
+            let mut event = self.event("frame");
+            event.push("framing", framing.name());
+            event.push("length", payload.len());
+            event.push("hex", self.hex_buf.encode(payload).to_string());
+            self.emit(&event);
+        } else {
+            println!("Frame ({}, {} bytes): {}", framing.name(), payload.len(), self.hex_buf.encode(payload));
+        }
+        self.log(|t| t.report(&format!("Decoded {} frame", framing.name()), &[], payload));
+    }
+
+    /// A payload couldn't be framed or a received frame is invalid.
+    pub fn frame_error(&mut self, e: &anyhow::Error) {
+        self.error("Invalid frame", e);
+    }
+
+    pub fn output_result(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+        self.output_shown(result);
+        self.output_recorded(report, result);
+    }
+
+    /// Show how a write went, once it has been recorded with
+    /// [`output_recorded`](Self::output_recorded).
+    pub fn output_shown(&mut self, result: &anyhow::Result<usize>) {
+        match result {
+            Ok(_) => self.info("Successfully sent data"),
+            Err(e) => self.error("Error sending data", e),
+        }
+    }
+
+    /// Put a write in the capture, power log and audit log without showing
+    /// it, as it happens, for commands that show their writes later.
+    pub fn output_recorded(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+        if result.is_ok() {
+            self.capture(|c| c.output_report(report));
+            self.traffic("out", report);
+        }
+        self.audit("output", report, outcome(result));
+    }
+
+    /// Show an input report under `label`. With a report ID, the ID byte is checked
+    /// and stripped; reports with other IDs are reported and skipped.
+    pub fn input_report(&mut self, label: &str, report: &[u8], report_id: Option<u8>) {
+        self.capture(|c| c.input_report(report));
+        self.traffic("in", report);
+        let Some(id) = report_id else {
+            if self.json {
+                let event = self.report_event("report", "in", report.first().copied(), report);
+                self.emit(&event);
+            } else {
+                println!("\n{} ({} bytes):", label, report.len());
+                println!("Hex: {}", self.hex_buf.encode(report));
+            }
+            self.log(|t| t.report(label, &[], report));
+            return;
+        };
+        match hid_cmd::strip_report_id(report, id) {
+            Ok(payload) => {
+                if self.json {
+                    let event = self.report_event("report", "in", Some(id), payload);
+                    self.emit(&event);
+                } else {
+                    println!("\n{} 0x{:02x} ({} bytes):", label, id, payload.len());
+                    println!("Hex: {}", self.hex_buf.encode(payload));
+                }
+                let annotations = [format!("report ID 0x{:02x} (stripped)", id)];
+                self.log(|t| t.report(label, &annotations, payload));
+            }
+            Err(other) => {
+                if self.json {
+                    let mut event = self.report_event("ignored", "in", Some(other), report);
+                    event.push("expected_report_id", id);
+                    self.emit(&event);
+                } else {
+                    println!("\nIgnoring input report with ID 0x{:02x} (expected 0x{:02x})", other, id);
+                }
+                let annotations = [format!("ignored: report ID 0x{:02x}, expected 0x{:02x}", other, id)];
+                self.log(|t| t.report(label, &annotations, report));
+            }
+        }
+    }
+
+    pub fn feature_report_in(&mut self, report_id: u8, report: &[u8]) {
+        self.capture(|c| c.feature_in(report_id, report));
+        self.traffic("feature_in", report);
+        if self.json {
+            let event = self.report_event("feature", "in", Some(report_id), report);
+            self.emit(&event);
+        } else {
+            println!("\nFeature Report 0x{:02x} ({} bytes):", report_id, report.len());
+            println!("Hex: {}", self.hex_buf.encode(report));
+        }
+        let annotations = [format!("byte 0: report ID 0x{:02x}", report_id)];
+        self.log(|t| t.report("Received feature report", &annotations, report));
+    }
+
+    /// Decoded fields of the first report `feature-watch` reads.
+    pub fn feature_values(&mut self, values: &[(String, i64)]) {
+        if self.json {
+            let fields = values.iter()
+                .map(|(field, value)| Json::object([("field", field.as_str().into()), ("value", (*value).into())]))
+                .collect();
+            let mut event = self.event("feature_values");
+            event.push("fields", Json::Array(fields));
+            self.emit(&event);
+        } else {
+            for (field, value) in values {
+                println!("  {} = {}", field, value);
+            }
+        }
+        let lines: Vec<String> = values.iter().map(|(field, value)| format!("- {} = {}", field, value)).collect();
+        self.log(|t| t.note(&lines.join("\n")));
+    }
+
+    /// An input report shown as its fields rather than in hex, for `monitor
+    /// --decode`.
+    pub fn decoded_report(&mut self, label: &str, report: &[u8], readings: &[(String, String)]) {
+        self.capture(|c| c.input_report(report));
+        self.traffic("in", report);
+        if self.json {
+            let fields = readings.iter()
+                .map(|(field, value)| Json::object([("field", field.as_str().into()), ("value", value.as_str().into())]))
+                .collect();
+            let mut event = self.report_event("report", "in", report.first().copied(), report);
+            event.push("fields", Json::Array(fields));
+            self.emit(&event);
+        } else {
+            println!("\n{} ({} bytes):", label, report.len());
+            for (field, value) in readings {
+                println!("  {} = {}", field, value);
+            }
+        }
+        let annotations: Vec<String> = readings.iter().map(|(field, value)| format!("{} = {}", field, value)).collect();
+        self.log(|t| t.report(label, &annotations, report));
+    }
+
+    /// Fields of feature report `report_id` that changed since the last read,
+    /// as `(field, old value, new value)`.
+    pub fn feature_changes(&mut self, report_id: u8, report: &[u8], changes: &[(String, Option<i64>, i64)]) {
+        if self.json {
+            let fields = changes.iter()
+                .map(|(field, old, new)| Json::object([
+                    ("field", field.as_str().into()),
+                    ("old", (*old).into()),
+                    ("new", (*new).into()),
+                ]))
+                .collect();
+            let mut event = self.report_event("feature_change", "in", Some(report_id), report);
+            event.push("changes", Json::Array(fields));
+            self.emit(&event);
+        } else {
+            println!("\nFeature Report 0x{:02x} changed (+{:.3} s):", report_id, self.started.elapsed().as_secs_f64());
+            for (field, old, new) in changes {
+                match old {
+                    Some(old) => println!("  {}: {} -> {}", field, old, new),
+                    None => println!("  {}: {} (new)", field, new),
+                }
+            }
+        }
+        let annotations: Vec<String> = changes.iter()
+            .map(|(field, old, new)| match old {
+                Some(old) => format!("{}: {} -> {}", field, old, new),
+                None => format!("{}: {} (new)", field, new),
+            })
+            .collect();
+        self.log(|t| t.report("Feature report changed", &annotations, report));
+    }
+
+    pub fn feature_report_out(&mut self, report: &[u8]) {
+        if self.json {
+            let event = self.report_event("feature", "out", Some(report[0]), report);
+            self.emit(&event);
+        } else {
+            println!("\nSending Feature Report 0x{:02x} ({} bytes):", report[0], report.len());
+            println!("Hex: {}", self.hex_buf.encode(report));
+        }
+        let annotations = [format!("byte 0: report ID 0x{:02x}", report[0])];
+        self.log(|t| t.report("Sent feature report", &annotations, report));
+    }
+
+    pub fn feature_result(&mut self, report: &[u8], result: &anyhow::Result<()>) {
+        match result {
+            Ok(()) => {
+                self.info("Successfully sent feature report");
+                self.capture(|c| c.feature_out(report));
+                self.traffic("feature_out", report);
+            }
+            Err(e) => self.error("Error sending feature report", e),
+        }
+        self.audit("feature", report, outcome(result));
+    }
+
+    /// Result of checking a named command's response against its schema. Error
+    /// statuses are explained from the profile's dictionary entry, if any.
+    pub fn response_status(&mut self, command: &str, status: &ResponseStatus, entry: Option<&ErrorCode>, lang: Option<&str>) {
+        let message = match (status, entry) {
+            (ResponseStatus::Success(None), _) => "OK".to_string(),
+            (ResponseStatus::Success(Some(code)), _) => format!("OK (status 0x{:02x})", code),
+            (ResponseStatus::Error { code, .. }, Some(entry)) => format!("error 0x{:02x} {}", code, entry.summary(lang)),
+            (ResponseStatus::Error { code, message: Some(message) }, None) => format!("error 0x{:02x}: {}", code, message),
+            (ResponseStatus::Error { code, message: None }, None) => format!("error 0x{:02x} (unknown status)", code),
+            (ResponseStatus::Malformed(reason), _) => format!("unexpected response: {}", reason),
+        };
+        let action = entry.and_then(|e| e.action(lang)).filter(|_| matches!(status, ResponseStatus::Error { .. }));
+
+        if self.json {
+            let mut event = self.event("status");
+            event.push("command", command);
+            event.push("ok", matches!(status, ResponseStatus::Success(_)));
+            match status {
+                ResponseStatus::Success(code) => event.push("code", *code),
+                ResponseStatus::Error { code, .. } => event.push("code", *code),
+                ResponseStatus::Malformed(_) => event.push("code", Json::Null),
+            }
+            event.push("name", entry.map(|e| e.name.as_str()));
+            event.push("message", message.as_str());
+            event.push("action", action);
+            self.emit(&event);
+        } else {
+            println!("\nStatus: {}", message);
+            if let Some(action) = action {
+                println!("Suggested action: {}", action);
+            }
+        }
+        self.log(|t| {
+            t.note(&format!("**{} status:** {}", command, message))?;
+            match action {
+                Some(action) => t.note(&format!("Suggested action: {}", action)),
+                None => Ok(()),
+            }
+        });
+    }
+
+    /// Time from writing named command `command` to receiving its response.
+    pub fn command_latency(&mut self, command: &str, latency: Duration) {
+        if self.json {
+            let mut event = self.event("latency");
+            event.push("command", command);
+            event.push("ms", millis(latency));
+            self.emit(&event);
+        } else {
+            println!("Latency: {:.3} ms", millis(latency));
+        }
+        self.latencies.entry(command.to_string()).or_default().record(latency);
+        self.log(|t| t.note(&format!("{} round trip: {:.3} ms", command, millis(latency))));
+    }
+
+    /// Periodic `monitor --stats-interval` figures.
+    pub fn read_stats(&mut self, reports_per_sec: f64, bytes_per_sec: f64, format_us: f64, total_reports: u64) {
+        if self.json {
+            let mut event = self.event("stats");
+            event.push("reports_per_sec", reports_per_sec);
+            event.push("bytes_per_sec", bytes_per_sec);
+            event.push("format_us", format_us);
+            event.push("total_reports", total_reports);
+            self.emit(&event);
+        } else {
+            println!("\n[stats] {:.1} reports/s, {:.1} bytes/s, {:.1} us/report formatting, {} reports total",
+                reports_per_sec, bytes_per_sec, format_us, total_reports);
+        }
+    }
+
+    /// Print the end-of-session summary: per-command latency, if any were sent.
+    /// The power timeline, if any, is written now.
+    pub fn finish(&mut self) {
+        self.capture(|c| c.flush());
+        if let Some(power) = self.power.take() {
+            self.write_power_log(power);
+        }
+        if self.latencies.is_empty() {
+            return;
+        }
+        let stats = |s: &LatencyStats| [s.min(), s.mean(), s.percentile(95.0), s.max()].map(|d| millis(d.unwrap_or_default()));
+
+        if self.json {
+            let commands = self.latencies.iter().map(|(name, s)| {
+                let [min, mean, p95, max] = stats(s);
+                Json::object([
+                    ("command", name.as_str().into()),
+                    ("count", s.count().into()),
+                    ("min_ms", min.into()),
+                    ("mean_ms", mean.into()),
+                    ("p95_ms", p95.into()),
+                    ("max_ms", max.into()),
+                ])
+            }).collect();
+            let mut event = self.event("summary");
+            event.push("latency", Json::Array(commands));
+            self.emit(&event);
+        } else {
+            let width = self.latencies.keys().map(String::len).max().unwrap_or(0).max("Command".len());
+            println!("\nCommand latency (ms):");
+            println!("  {:<width$}  {:>5}  {:>8}  {:>8}  {:>8}  {:>8}", "Command", "Count", "Min", "Mean", "P95", "Max", width = width);
+            for (name, s) in &self.latencies {
+                let [min, mean, p95, max] = stats(s);
+                println!("  {:<width$}  {:>5}  {:>8.3}  {:>8.3}  {:>8.3}  {:>8.3}", name, s.count(), min, mean, p95, max, width = width);
+            }
+        }
+
+        let mut table = String::from("**Command latency (ms)**\n\n| Command | Count | Min | Mean | P95 | Max |\n|---|---:|---:|---:|---:|---:|");
+        for (name, s) in &self.latencies {
+            let [min, mean, p95, max] = stats(s);
+            table.push_str(&format!("\n| {} | {} | {:.3} | {:.3} | {:.3} | {:.3} |", name, s.count(), min, mean, p95, max));
+        }
+        self.log(|t| t.note(&table));
+    }
+
+    /// The device's state differs from its last known good one in the cache.
+    pub fn state_changed(&mut self, serial: &str, changes: &[Change], accepted: bool) {
+        if self.json {
+            let mut event = self.event("state_changed");
+            event.push("serial", serial);
+            event.push("accepted", accepted);
+            let changes = changes.iter()
+                .map(|c| Json::object([
+                    ("key", c.key.as_str().into()),
+                    ("old", c.old.as_deref().into()),
+                    ("new", c.new.as_deref().into()),
+                ]))
+                .collect();
+            event.push("changes", Json::Array(changes));
+            self.emit(&event);
+        }
+        let mut message = if accepted {
+            format!("Accepted new last known good state for {}:", serial)
+        } else {
+            format!("Warning: {} differs from its last known good state:", serial)
+        };
+        for change in changes {
+            message.push_str(&format!("\n  {}: {} -> {}", change.key,
+                change.old.as_deref().map_or("(none)".into(), state_value),
+                change.new.as_deref().map_or("(none)".into(), state_value)));
+        }
+        eprintln!("{}", message);
+        self.log(|t| t.note(&format!("**{}**", message.replace('\n', "\n\n"))));
+    }
+
+    /// `data` matches deny-list entry `rule`; it is sent only if `overridden`.
+    pub fn write_denied(&mut self, data: &[u8], rule: &DenyRule, overridden: bool) {
+        if self.json {
+            let mut event = self.event("denied");
+            event.push("hex", hex::encode(data));
+            event.push("pattern", rule.pattern.to_string());
+            event.push("reason", rule.reason.as_str());
+            event.push("overridden", overridden);
+            self.emit(&event);
+        }
+        let message = if overridden {
+            format!("Warning: sending {} despite deny-list pattern '{}' ({})", hex::encode(data), rule.pattern, rule.reason)
+        } else {
+            format!("Blocked {}: deny-list pattern '{}' ({})", hex::encode(data), rule.pattern, rule.reason)
+        };
+        if overridden {
+            eprintln!("{}", message);
+        }
+        self.log(|t| t.note(&format!("**{}**", message)));
+    }
+
+    /// No input report arrived within `timeout` (`--timeout` or `--non-blocking`).
+    pub fn no_response(&mut self, timeout: Duration) {
+        let message = if timeout.is_zero() {
+            "No input report pending".to_string()
+        } else {
+            format!("No input report within {} ms", timeout.as_millis())
+        };
+        if self.json {
+            let mut event = self.event("timeout");
+            event.push("timeout_ms", timeout.as_millis() as u64);
+            self.emit(&event);
+        }
+        eprintln!("{}", message);
+        self.log(|t| t.note(&format!("**{}**", message)));
+    }
+
+    /// An output report written without being shown, such as a file chunk or
+    /// a benchmark request; audited like any other write.
+    pub fn quiet_output(&mut self, report: &[u8], result: &anyhow::Result<usize>) {
+        if let Err(e) = result {
+            self.error("Error sending data", e);
+        }
+        self.output_recorded(report, result);
+    }
+
+    fn write_power_log(&mut self, power: PowerLog) {
+        let (samples, error) = power.sampler.finish();
+        if let Some(e) = error {
+            self.error("Error reading power meter, sampling stopped", &e);
+        }
+        match power::write_timeline(&power.path, &samples, &power.traffic, &power.markers) {
+            Ok(()) => {
+                let message = format!("Power timeline: {} readings and {} reports written to {}",
+                    samples.len(), power.traffic.len(), power.path.display());
+                self.info(&message);
+                self.log(|t| t.note(&message));
+            }
+            Err(e) => self.error(&format!("Error writing power timeline {}", power.path.display()), &e.into()),
+        }
+    }
+
+    /// Redraw the progress bar of a transfer on stderr, when it is a terminal.
+    /// Without a known `total` only the byte count is shown.
+    pub fn transfer_progress(&mut self, verb: &str, bytes: usize, total: Option<usize>) {
+        if self.json || !io::stderr().is_terminal() {
+            return;
+        }
+        const WIDTH: usize = 30;
+        match total {
+            Some(total) => {
+                let fraction = if total == 0 { 1.0 } else { bytes as f64 / total as f64 };
+                let filled = (fraction * WIDTH as f64) as usize;
+                eprint!("\r{} [{}{}] {:3.0}% {}/{} bytes", verb, "#".repeat(filled), " ".repeat(WIDTH - filled),
+                    fraction * 100.0, bytes, total);
+            }
+            None => eprint!("\r{} {} bytes", verb, bytes),
+        }
+    }
+
+    /// A transfer of `bytes` in `chunks` reports finished.
+    pub fn transfer_done(&mut self, verb: &str, path: &Path, bytes: usize, chunks: usize, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let rate = if secs > 0.0 { bytes as f64 / secs } else { 0.0 };
+        let message = format!("{} {} bytes in {} chunks ({}) in {:.3} s, {:.0} bytes/s",
+            verb, bytes, chunks, path.display(), secs, rate);
+        if self.json {
+            let mut event = self.event("transfer");
+            event.push("file", path.display().to_string());
+            event.push("bytes", bytes);
+            event.push("chunks", chunks);
+            event.push("duration_ms", millis(elapsed));
+            self.emit(&event);
+        } else {
+            if io::stderr().is_terminal() {
+                eprintln!();
+            }
+            println!("{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// A timeline entry due at `offset` was written `late` after that.
+    pub fn scheduled(&mut self, offset: Duration, late: Duration) {
+        if self.json {
+            let mut event = self.event("scheduled");
+            event.push("offset_ms", millis(offset));
+            event.push("late_ms", millis(late));
+            self.emit(&event);
+        } else {
+            println!("At {:.3} ms, {:.3} ms late", millis(offset), millis(late));
+        }
+        self.log(|t| t.note(&format!("Due at {:.3} ms, sent {:.3} ms late", millis(offset), millis(late))));
+    }
+
+    /// How closely a whole timeline kept to its schedule.
+    pub fn timeline_done(&mut self, lateness: &LatencyStats) {
+        let [mean, p95, max] = [lateness.mean(), lateness.percentile(95.0), lateness.max()].map(|d| millis(d.unwrap_or_default()));
+        let message = format!("Sent {} reports; lateness mean {:.3} ms, p95 {:.3} ms, max {:.3} ms", lateness.count(), mean, p95, max);
+        if self.json {
+            let mut event = self.event("timeline");
+            event.push("count", lateness.count());
+            event.push("mean_late_ms", mean);
+            event.push("p95_late_ms", p95);
+            event.push("max_late_ms", max);
+            self.emit(&event);
+        } else {
+            println!("{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// A line typed into `record` that wasn't sent.
+    pub fn line_skipped(&mut self, line: &str, e: &anyhow::Error) {
+        self.error(&format!("Skipping '{}'", line), e);
+    }
+
+    /// The end of `record`: `sent` reports and `received` responses written
+    /// to `path`.
+    pub fn recording_done(&mut self, path: &Path, sent: usize, received: usize) {
+        let message = format!("Recorded {} reports sent and {} received to {}", sent, received, path.display());
+        if self.json {
+            let mut event = self.event("recording");
+            event.push("sent", sent);
+            event.push("received", received);
+            event.push("file", path.display().to_string());
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// One on-target test finished.
+    pub fn test_result(&mut self, case: &Case) {
+        if self.json {
+            let mut event = self.event("test");
+            event.push("name", case.name.as_str());
+            event.push("status", case.status.name());
+            event.push("message", case.message.as_str());
+            event.push("output", case.output.as_str());
+            event.push("time_ms", millis(case.time));
+            self.emit(&event);
+        } else {
+            let status = case.status.name().to_uppercase();
+            match case.message.as_str() {
+                "" => println!("{} {}", status, case.name),
+                message => println!("{} {}: {}", status, case.name, message),
+            }
+        }
+        let note = match case.message.as_str() {
+            "" => format!("Test {}: {}", case.name, case.status.name()),
+            message => format!("Test {}: {} ({})", case.name, case.status.name(), message),
+        };
+        self.log(|t| t.note(&note));
+    }
+
+    /// A report that starts like a test record but isn't a valid one.
+    pub fn test_record_invalid(&mut self, report: &[u8], e: &anyhow::Error) {
+        self.error(&format!("Ignoring invalid test record {}", hex::encode(report)), e);
+    }
+
+    /// The totals of an on-target test run.
+    pub fn target_test_done(&mut self, cases: &[Case]) {
+        let count = |status: Status| cases.iter().filter(|c| c.status == status).count();
+        let (passed, failed, skipped) = (count(Status::Pass), count(Status::Fail), count(Status::Skip));
+        let message = format!("{} tests: {} passed, {} failed, {} skipped", cases.len(), passed, failed, skipped);
+        if self.json {
+            let mut event = self.event("target_test");
+            event.push("tests", cases.len());
+            event.push("passed", passed);
+            event.push("failed", failed);
+            event.push("skipped", skipped);
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// `lost` chunks of a trace never arrived, leaving a gap before the next.
+    pub fn trace_gap(&mut self, lost: u16) {
+        let message = format!("{} trace chunks lost; the trace has a gap", lost);
+        if self.json {
+            let mut event = self.event("trace_gap");
+            event.push("lost", u64::from(lost));
+            self.emit(&event);
+        }
+        if io::stderr().is_terminal() {
+            eprintln!();
+        }
+        eprintln!("{}", message);
+        self.log(|t| t.note(&message));
+    }
+
+    pub fn trace_chunk_dropped(&mut self, e: &anyhow::Error) {
+        self.error("Dropping trace chunk", e);
+    }
+
+    /// The end of `trace pull`, with the files written and their sizes.
+    pub fn trace_done(&mut self, stream: &trace::Stream, elapsed: Duration, files: &[(PathBuf, usize)]) {
+        let secs = elapsed.as_secs_f64();
+        let rate = if secs > 0.0 { stream.bytes() as f64 / secs } else { 0.0 };
+        let message = format!("Pulled {} bytes in {} chunks in {:.3} s, {:.0} bytes/s; {} chunks lost, {} corrupt",
+            stream.bytes(), stream.chunks(), secs, rate, stream.lost(), stream.corrupt());
+        if self.json {
+            let mut event = self.event("trace");
+            event.push("bytes", stream.bytes());
+            event.push("chunks", stream.chunks());
+            event.push("lost", stream.lost());
+            event.push("corrupt", stream.corrupt());
+            event.push("complete", stream.done());
+            event.push("duration_ms", millis(elapsed));
+            let files = files.iter()
+                .map(|(path, bytes)| Json::object([("file", path.display().to_string().into()), ("bytes", (*bytes).into())]))
+                .collect();
+            event.push("files", Json::Array(files));
+            self.emit(&event);
+        } else {
+            if io::stderr().is_terminal() {
+                eprintln!();
+            }
+            println!("{}", message);
+            for (path, bytes) in files {
+                println!("  {}: {} bytes", path.display(), bytes);
+            }
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// Output from RTT up-buffer `channel`, printed as it comes.
+    pub fn rtt_output(&mut self, channel: u8, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let text = String::from_utf8_lossy(data);
+        if self.json {
+            let mut event = self.event("rtt");
+            event.push("channel", channel);
+            event.push("text", text.as_ref());
+            self.emit(&event);
+        } else {
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(data).and_then(|()| stdout.flush());
+        }
+        self.log(|t| t.note(&format!("RTT: `{}`", text.escape_debug())));
+    }
+
+    pub fn rtt_record_invalid(&mut self, report: &[u8], e: &anyhow::Error) {
+        self.error(&format!("Ignoring invalid RTT record {}", hex::encode(report)), e);
+    }
+
+    /// A log message decoded from the device's defmt stream, for `monitor
+    /// --defmt-elf`.
+    pub fn defmt_log(&mut self, line: &defmt::Line) {
+        let mut text = String::new();
+        if let Some(timestamp) = &line.timestamp {
+            text.push_str(timestamp);
+            text.push(' ');
+        }
+        if let Some(level) = line.level {
+            text.push_str(&format!("{:<5} ", level));
+        }
+        text.push_str(&line.message);
+        if self.json {
+            let mut event = self.event("log");
+            if let Some(level) = line.level {
+                event.push("level", level);
+            }
+            if let Some(timestamp) = &line.timestamp {
+                event.push("timestamp", timestamp.as_str());
+            }
+            event.push("message", line.message.as_str());
+            self.emit(&event);
+        } else {
+            println!("{}", text);
+        }
+        self.log(|t| t.note(&format!("defmt: {}", text)));
+    }
+
+    pub fn defmt_frame_invalid(&mut self, frame: &[u8], e: &anyhow::Error) {
+        self.error(&format!("Ignoring undecodable defmt frame {}", hex::encode(frame)), e);
+    }
+
+    /// A crash dump and its backtrace, symbolicated if there are `symbols`.
+    pub fn crash_dump(&mut self, dump: &crashdump::Dump, symbols: Option<&crashdump::Symbols>) {
+        let (fault, fault_address) = dump.fault();
+        let frames = dump.backtrace(symbols);
+        let source = |frame: &crashdump::Frame| match frame.source {
+            crashdump::FrameSource::Pc => "pc".to_string(),
+            crashdump::FrameSource::Lr => "lr".to_string(),
+            crashdump::FrameSource::Stack(at) => format!("stack 0x{:08x}", at),
+        };
+        if self.json {
+            let registers = crashdump::REGISTER_NAMES.iter().zip(dump.registers)
+                .map(|(&name, value)| (name, value.into()));
+            let frames_json = frames.iter().map(|frame| Json::object([
+                ("source", source(frame).into()),
+                ("address", frame.address.into()),
+                ("function", frame.function.as_ref().map(|(name, _)| name.as_str()).into()),
+                ("offset", frame.function.as_ref().map(|&(_, offset)| offset).into()),
+                ("file", frame.location.as_ref().map(|l| l.file.as_str()).into()),
+                ("line", frame.location.as_ref().map(|l| l.line).into()),
+            ])).collect();
+            let mut event = self.event("crash_dump");
+            event.push("message", dump.message.as_str());
+            event.push("registers", Json::object(registers));
+            event.push("fault", fault.clone());
+            event.push("fault_address", fault_address);
+            event.push("frames", Json::Array(frames_json));
+            self.emit(&event);
+        } else {
+            println!("\nCrash dump: {}", if dump.message.is_empty() { "no message" } else { &dump.message });
+            match (fault.is_empty(), fault_address) {
+                (true, _) => println!("Fault: none recorded"),
+                (false, Some(address)) => println!("Fault: {} at 0x{:08x}", fault.join(", "), address),
+                (false, None) => println!("Fault: {}", fault.join(", ")),
+            }
+            println!("Registers:");
+            for row in crashdump::REGISTER_NAMES.iter().zip(dump.registers).collect::<Vec<_>>().chunks(4) {
+                let cells: Vec<String> = row.iter().map(|(name, value)| format!("{:<4} 0x{:08x}", name, value)).collect();
+                println!("  {}", cells.join("  "));
+            }
+            println!("Backtrace:");
+            for (i, frame) in frames.iter().enumerate() {
+                let mut line = format!("  #{:<2} 0x{:08x}", i, frame.address);
+                if let Some((name, offset)) = &frame.function {
+                    line.push_str(&format!(" in {} + 0x{:x}", name, offset));
+                }
+                if let Some(location) = &frame.location {
+                    line.push_str(&format!(" at {}:{}", location.file, location.line));
+                }
+                println!("{} ({})", line, source(frame));
+            }
+            match symbols {
+                None => println!("(give --elf to name the functions and scan the stack for return addresses)"),
+                Some(symbols) if !symbols.has_lines() => {
+                    println!("(the ELF file has no line tables; build with debug info, e.g. debug = \"line-tables-only\")");
+                }
+                Some(_) => {}
+            }
+        }
+        let top = frames.first().and_then(|f| f.function.as_ref()).map_or_else(
+            || format!("pc 0x{:08x}", dump.registers[15]),
+            |(name, offset)| format!("{} + 0x{:x}", name, offset));
+        self.log(|t| t.note(&format!("Crash dump: `{}` in {}; {} frames", dump.message.escape_debug(), top, frames.len())));
+    }
+
+    /// The virtual device of `emulate` was created, started, opened and so on.
+    pub fn emulation_state(&mut self, state: &str, message: &str) {
+        if self.json {
+            let mut event = self.event("emulation");
+            event.push("state", state);
+            self.emit(&event);
+        } else {
+            println!("{}", message);
+        }
+        self.log(|t| t.note(message));
+    }
+
+    /// An input report the virtual device sends to the host.
+    pub fn emulated_input(&mut self, report: &[u8]) {
+        self.capture(|c| c.input_report(report));
+        self.traffic("in", report);
+        if self.json {
+            let event = self.report_event("report", "in", report.first().copied(), report);
+            self.emit(&event);
+        } else {
+            println!("\nSending Input Report ({} bytes):", report.len());
+            println!("Hex: {}", self.hex_buf.encode(report));
+        }
+        self.log(|t| t.report("Sent input report", &[], report));
+    }
+
+    /// An output or feature report the host wrote to the virtual device,
+    /// report number first.
+    pub fn host_report(&mut self, label: &str, report: &[u8]) {
+        self.capture(|c| c.output_report(report));
+        self.traffic("out", report);
+        if self.json {
+            let event = self.report_event("report", "out", report.first().copied(), report);
+            self.emit(&event);
+        } else {
+            println!("\n{} ({} bytes):", label, report.len());
+            println!("Hex: {}", self.hex_buf.encode(report));
+        }
+        self.log(|t| t.report(label, &[], report));
+    }
+
+    /// The host asked the virtual device for feature report `number`; `None`
+    /// if it has no such report.
+    pub fn host_feature_read(&mut self, number: u8, report: Option<&[u8]>) {
+        let message = match report {
+            Some(_) => format!("The host read feature report 0x{:02x}", number),
+            None => format!("The host asked for feature report 0x{:02x}, which the device doesn't have", number),
+        };
+        if self.json {
+            let event = match report {
+                Some(report) => self.report_event("feature", "in", Some(number), report),
+                None => {
+                    let mut event = self.event("feature_missing");
+                    event.push("report_id", number);
+                    event
+                }
+            };
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// The reports of one device of `send --all-matching` follow.
+    pub fn fan_out_device(&mut self, label: &str) {
+        if !self.json {
+            println!("\n=== {} ===", label);
+        }
+        self.log(|t| t.note(&format!("**Device {}**", label)));
+    }
+
+    /// How one device of `send --all-matching` did: its response time, or why
+    /// it failed.
+    pub fn fan_out_result(&mut self, label: &str, result: &anyhow::Result<Duration>) {
+        let message = match result {
+            Ok(latency) => format!("{}: ok, response after {:.3} ms", label, millis(*latency)),
+            Err(e) => format!("{}: FAILED: {:#}", label, e),
+        };
+        if self.json {
+            let mut event = self.event("fan_out");
+            event.push("device", label);
+            event.push("ok", result.is_ok());
+            match result {
+                Ok(latency) => event.push("latency_ms", millis(*latency)),
+                Err(e) => event.push("error", format!("{:#}", e)),
+            }
+            self.emit(&event);
+        } else if result.is_ok() {
+            println!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// The outcome of `send --all-matching`.
+    pub fn fan_out_done(&mut self, devices: usize, failed: usize) {
+        let message = format!("{} of {} devices ok", devices - failed, devices);
+        if self.json {
+            let mut event = self.event("fan_out_done");
+            event.push("devices", devices);
+            event.push("failed", failed);
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// Exchange `n` of a `send --repeat` failed with `e`.
+    pub fn exchange_failed(&mut self, n: u32, e: &anyhow::Error) {
+        self.error(&format!("Exchange {} failed", n), e);
+    }
+
+    /// The outcome of `send --repeat`.
+    pub fn repeat_done(&mut self, count: u32, failures: u32, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let rate = if secs > 0.0 { count as f64 / secs } else { 0.0 };
+        let message = format!("{} exchanges: {} succeeded, {} failed, in {:.3} s ({:.1} per second)",
+            count, count - failures, failures, secs, rate);
+        if self.json {
+            let mut event = self.event("repeat");
+            event.push("count", count);
+            event.push("succeeded", count - failures);
+            event.push("failed", failures);
+            event.push("duration_ms", millis(elapsed));
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// Results of `bench`: round-trip latencies, requests without a response
+    /// and the bytes moved in both directions over `elapsed`.
+    pub fn bench_done(&mut self, latencies: &LatencyStats, lost: u32, elapsed: Duration, bytes: usize) {
+        let [min, mean, p95, max] = [latencies.min(), latencies.mean(), latencies.percentile(95.0), latencies.max()]
+            .map(|d| millis(d.unwrap_or_default()));
+        let secs = elapsed.as_secs_f64();
+        let per_sec = |n: f64| if secs > 0.0 { n / secs } else { 0.0 };
+        let round_trips = per_sec(latencies.count() as f64);
+        let bytes_per_sec = per_sec(bytes as f64);
+        let summary = format!("{} round trips ({} lost) in {:.3} s: {:.1} per second, {:.0} bytes/s",
+            latencies.count(), lost, secs, round_trips, bytes_per_sec);
+        let latency = format!("min {:.3}, mean {:.3}, p95 {:.3}, max {:.3}", min, mean, p95, max);
+        if self.json {
+            let mut event = self.event("bench");
+            event.push("count", latencies.count());
+            event.push("lost", lost);
+            event.push("duration_ms", millis(elapsed));
+            event.push("round_trips_per_sec", round_trips);
+            event.push("bytes_per_sec", bytes_per_sec);
+            event.push("min_ms", min);
+            event.push("mean_ms", mean);
+            event.push("p95_ms", p95);
+            event.push("max_ms", max);
+            self.emit(&event);
+        } else {
+            println!("\n{}", summary);
+            println!("Latency (ms): {}", latency);
+        }
+        self.log(|t| t.note(&format!("{}; latency (ms) {}", summary, latency)));
+    }
+
+    /// The responses of the golden and suspect units to the read at `location`:
+    /// the fields that differ, or which unit alone didn't respond.
+    pub fn responses_compared(&mut self, location: &str, differences: &[Difference], silent: Option<&str>) {
+        let value = |v: Option<i64>| v.map_or("(none)".to_string(), |v| v.to_string());
+        if self.json {
+            let mut event = self.event("compare");
+            event.push("location", location);
+            if let Some(unit) = silent {
+                event.push("no_response", unit);
+            }
+            let fields = differences.iter().map(|d| Json::object([
+                ("field", d.field.as_str().into()),
+                ("golden", d.golden.into()),
+                ("suspect", d.suspect.into()),
+                ("tolerance", d.tolerance.into()),
+            ])).collect();
+            event.push("differences", Json::Array(fields));
+            self.emit(&event);
+            return;
+        }
+        let mut message = match (silent, differences.len()) {
+            (Some(unit), _) => format!("{}: the {} unit did not respond", location, unit),
+            (None, 0) => format!("{}: responses match", location),
+            (None, 1) => format!("{}: 1 field differs", location),
+            (None, n) => format!("{}: {} fields differ", location, n),
+        };
+        for d in differences {
+            message.push_str(&format!("\n  {}: golden {}, suspect {} (tolerance {})", d.field, value(d.golden), value(d.suspect), d.tolerance));
+        }
+        println!("\n{}", message);
+        self.log(|t| t.note(&message.replace('\n', "\n\n")));
+    }
+
+    /// How many of the compared responses differed.
+    pub fn compare_done(&mut self, responses: usize, differing: usize) {
+        let message = format!("{} of {} responses differ", differing, responses);
+        if self.json {
+            let mut event = self.event("compare_summary");
+            event.push("responses", responses);
+            event.push("differing", differing);
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&format!("**{}**", message)));
+    }
+
+    /// Loopback payload `n` got no response within `timeout`.
+    pub fn loopback_lost(&mut self, n: u32, timeout: Duration) {
+        let message = format!("Payload {}: no response within {} ms", n, timeout.as_millis());
+        if self.json {
+            let mut event = self.event("loopback");
+            event.push("payload", n);
+            event.push("lost", true);
+            self.emit(&event);
+        } else {
+            println!("{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// Loopback payload `n` came back wrong at `positions`; `received` starts
+    /// where the echo should.
+    pub fn loopback_mismatch(&mut self, n: u32, expected: &[u8], received: &[u8], positions: &[usize]) {
+        const SHOWN: usize = 16;
+        let mut shown: Vec<String> = positions.iter().take(SHOWN).map(usize::to_string).collect();
+        if positions.len() > SHOWN {
+            shown.push(format!("and {} more", positions.len() - SHOWN));
+        }
+        if self.json {
+            let mut event = self.event("loopback");
+            event.push("payload", n);
+            event.push("expected", hex::encode(expected));
+            event.push("received", hex::encode(received));
+            event.push("mismatches", positions.to_vec());
+            self.emit(&event);
+        } else {
+            println!("\nPayload {}: {} of {} bytes differ at {}", n, positions.len(), expected.len(), shown.join(", "));
+            println!("Expected: {}", self.hex_buf.encode(expected));
+            println!("Received: {}", self.hex_buf.encode(received));
+        }
+        self.log(|t| t.note(&format!("Payload {} was not echoed: {} of {} bytes differ at {}\n\nExpected `{}`, received `{}`",
+            n, positions.len(), expected.len(), shown.join(", "), hex::encode(expected), hex::encode(received))));
+    }
+
+    /// The outcome of `loopback`; `seed` repeats the run.
+    pub fn loopback_done(&mut self, count: u32, mismatched: u32, lost: u32, seed: u64) {
+        let message = format!("{} payloads: {} echoed, {} mismatched, {} lost (seed {})",
+            count, count - mismatched - lost, mismatched, lost, seed);
+        if self.json {
+            let mut event = self.event("loopback_summary");
+            event.push("count", count);
+            event.push("echoed", count - mismatched - lost);
+            event.push("mismatched", mismatched);
+            event.push("lost", lost);
+            event.push("seed", seed);
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// `bisect-firmware` is about to flash and test image `index` of `count`.
+    pub fn bisect_testing(&mut self, index: usize, count: usize, image: &Path) {
+        let message = format!("Testing image {} of {}: {}", index + 1, count, image.display());
+        if self.json {
+            let mut event = self.event("bisect_test");
+            event.push("index", index);
+            event.push("image", image.display().to_string());
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&format!("**{}**", message)));
+    }
+
+    /// The flash command ran; audited, since it rewrites the device. A
+    /// failure ends the bisection, so only success is shown.
+    pub fn firmware_flashed(&mut self, image: &Path, result: &anyhow::Result<()>) {
+        if result.is_ok() {
+            self.info(&format!("Flashed {}", image.display()));
+        }
+        self.audit(&format!("flash {}", image.display()), &[], outcome(result));
+    }
+
+    /// Whether a flashed image passed the bisection script.
+    pub fn bisect_verdict(&mut self, image: &Path, result: &anyhow::Result<()>) {
+        let message = match result {
+            Ok(()) => format!("{} is good", image.display()),
+            Err(e) => format!("{} is bad: {:#}", image.display(), e),
+        };
+        if self.json {
+            let mut event = self.event("bisect_verdict");
+            event.push("image", image.display().to_string());
+            event.push("good", result.is_ok());
+            if let Err(e) = result {
+                event.push("error", format!("{:#}", e));
+            }
+            self.emit(&event);
+        } else {
+            println!("{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// The bisection found the first bad image, right after the last good one.
+    pub fn bisect_done(&mut self, last_good: &Path, first_bad: &Path) {
+        let message = format!("First bad image: {} (last good: {})", first_bad.display(), last_good.display());
+        if self.json {
+            let mut event = self.event("bisect");
+            event.push("first_bad", first_bad.display().to_string());
+            event.push("last_good", last_good.display().to_string());
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&format!("**{}**", message)));
+    }
+
+    /// The device stopped answering reads and will be waited for.
+    pub fn disconnected(&mut self) {
+        let message = "Device disconnected, waiting for it to come back";
+        if self.json {
+            let event = self.event("disconnected");
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&format!("**{}**", message)));
+    }
+
+    /// The device is back after being gone for `downtime`.
+    pub fn reconnected(&mut self, downtime: Duration) {
+        let message = format!("Device reconnected after {:.3} s", downtime.as_secs_f64());
+        if self.json {
+            let mut event = self.event("reconnected");
+            event.push("downtime_ms", millis(downtime));
+            self.emit(&event);
+        } else {
+            println!("{}", message);
+        }
+        self.log(|t| t.note(&format!("**{}**", message)));
+    }
+
+    /// Monitoring stopped after `timeout` without input reports; `received` is
+    /// whether any arrived before that.
+    pub fn idle(&mut self, timeout: Duration, received: bool) {
+        if !received {
+            self.no_response(timeout);
+            return;
+        }
+        let message = if timeout.is_zero() {
+            "No further input reports pending, stopping".to_string()
+        } else {
+            format!("No further input reports within {} ms, stopping", timeout.as_millis())
+        };
+        if self.json {
+            let mut event = self.event("idle");
+            event.push("timeout_ms", timeout.as_millis() as u64);
+            self.emit(&event);
+        } else {
+            println!("\n{}", message);
+        }
+        self.log(|t| t.note(&message));
+    }
+
+    /// Report a failed read; `message` is printed before the error.
+    pub fn read_error(&mut self, message: &str, e: &anyhow::Error) {
+        self.error(message, e);
+    }
+
+    fn error(&mut self, message: &str, e: &anyhow::Error) {
+        if self.json {
+            let mut event = self.event("error");
+            event.push("message", format!("{}: {}", message, e));
+            self.emit(&event);
+        }
+        eprintln!("{}: {}", message, e);
+        self.log(|t| t.note(&format!("**{}:** {}", message, e)));
+    }
+}
+
+/// The identifying details of a hidapi entry, as used by `list --json` and
+/// the `device` event.
+pub fn device_json(d: &hidapi::DeviceInfo) -> Json {
+    Json::object([
+        ("vid", d.vendor_id().into()),
+        ("pid", d.product_id().into()),
+        ("manufacturer", d.manufacturer_string().into()),
+        ("product", d.product_string().into()),
+        ("serial", d.serial_number().into()),
+        ("release", d.release_number().into()),
+        ("usage_page", d.usage_page().into()),
+        ("usage", d.usage().into()),
+        ("interface", d.interface_number().into()),
+        ("path", d.path().to_string_lossy().into_owned().into()),
+    ])
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e3
+}
+
+/// Bus position of an opened interface, for the capture: its interrupt
+/// endpoints where sysfs lists them, defaults otherwise.
+fn usb_address(info: &hidapi::DeviceInfo) -> UsbAddress {
+    let mut address = UsbAddress::default();
+    if let Ok(interface) = u8::try_from(info.interface_number()) {
+        address.interface = interface;
+    }
+    if let Some((bus, device)) = usb::usb_address(info.path()) {
+        address.bus = bus;
+        address.device = device;
+    }
+    let interfaces = usb::composite_interfaces(info.path()).unwrap_or_default();
+    let endpoints = interfaces.iter().find(|i| i.number == address.interface).map(|i| &i.endpoints[..]).unwrap_or_default();
+    for endpoint in endpoints.iter().filter(|e| e.attributes & 0x03 == 0x03) {
+        if endpoint.is_in() {
+            address.endpoint_in = endpoint.address;
+        } else {
+            address.endpoint_out = endpoint.address;
+        }
+    }
+    address
+}
+
+/// `ok`, or the error, as recorded in the audit log.
+fn outcome<T>(result: &anyhow::Result<T>) -> String {
+    match result {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+/// A cached state value for a warning; long ones such as descriptors are
+/// shortened.
+fn state_value(value: &str) -> String {
+    const MAX: usize = 40;
+    match value.char_indices().nth(MAX) {
+        Some((end, _)) => format!("{}... ({} chars)", &value[..end], value.chars().count()),
+        None => value.to_string(),
+    }
+}
@@ -0,0 +1,71 @@
+//! RTT-style logging over a vendor HID report set, for `hid-cmd rtt`.
+//!
+//! Firmware that keeps SEGGER RTT buffers can hand them over HID, for units in
+//! the field without a J-Link attached. The host polls an up-buffer (target to
+//! host) for log output and writes what is typed into a down-buffer. Every
+//! report starts with the magic byte `52` (`R`), so other reports can be told
+//! apart and ignored:
+//!
+//! ```text
+//! 52 01 <ch>                     host: read up-buffer <ch>
+//! 52 81 <ch> <more> <n> <data>   device: n bytes of it; more is 1 if it holds more
+//! 52 02 <ch> <n> <data>          host: write n bytes to down-buffer <ch>
+//! 52 82 <ch> <n>                 device: n of them fit in the buffer
+//! ```
+//!
+//! An empty up-buffer is answered with `n` = 0. Bytes that didn't fit in the
+//! down-buffer are sent again with the next write.
+
+pub const MAGIC: u8 = 0x52;
+pub const RECORD_READ: u8 = 0x01;
+pub const RECORD_WRITE: u8 = 0x02;
+pub const RECORD_UP: u8 = 0x81;
+pub const RECORD_ACCEPTED: u8 = 0x82;
+
+/// Magic, record type, channel and length of a write.
+const WRITE_HEADER_LEN: usize = 4;
+
+/// The poll for up-buffer `channel`.
+pub fn read_request(channel: u8) -> Vec<u8> {
+    vec![MAGIC, RECORD_READ, channel]
+}
+
+/// A write of `data` to down-buffer `channel`; the caller keeps it within
+/// [`write_capacity`].
+pub fn write_request(channel: u8, data: &[u8]) -> Vec<u8> {
+    let mut request = vec![MAGIC, RECORD_WRITE, channel, data.len() as u8];
+    request.extend_from_slice(data);
+    request
+}
+
+/// Bytes of down-buffer data that fit in a `payload_len`-byte report.
+pub fn write_capacity(payload_len: usize) -> usize {
+    payload_len.saturating_sub(WRITE_HEADER_LEN).min(u8::MAX as usize)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// Output from up-buffer `channel`; `more` if the device holds more of it.
+    Up { channel: u8, more: bool, data: Vec<u8> },
+    /// How many bytes of the last write to down-buffer `channel` it took.
+    Accepted { channel: u8, count: usize },
+}
+
+impl Response {
+    /// Parse a report payload. `Ok(None)` if it isn't an RTT report at all.
+    pub fn parse(payload: &[u8]) -> anyhow::Result<Option<Self>> {
+        let [MAGIC, kind, rest @ ..] = payload else {
+            return Ok(None);
+        };
+        match (*kind, rest) {
+            (RECORD_UP, [channel, more, len, data @ ..]) => {
+                let data = data.get(..usize::from(*len)).ok_or_else(|| anyhow::anyhow!(
+                    "up-buffer record claims {} bytes, but has only {}", len, data.len()))?;
+                Ok(Some(Response::Up { channel: *channel, more: *more != 0, data: data.to_vec() }))
+            }
+            (RECORD_ACCEPTED, [channel, count, ..]) => Ok(Some(Response::Accepted { channel: *channel, count: usize::from(*count) })),
+            (RECORD_UP | RECORD_ACCEPTED, _) => Err(anyhow::anyhow!("truncated RTT record 0x{:02x}", kind)),
+            _ => Err(anyhow::anyhow!("unknown RTT record type 0x{:02x}", kind)),
+        }
+    }
+}
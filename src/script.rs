@@ -0,0 +1,189 @@
+//! Batch scripts for `hid-cmd run`: one operation per line, run in order
+//! against a single open device.
+//!
+//! ```text
+//! # Unlock, then poll the status five times
+//! send 10 aa 55
+//! expect 90 00          # wait for an input report starting with these bytes
+//! loop 5
+//!   send 01
+//!   read
+//!   sleep 200ms
+//! end
+//! ```
+//!
+//! `send` takes hex bytes, spaces optional. `expect` reads input reports until
+//! one starts with the given [`HexPattern`], like `--expect`, and fails if
+//! none does within the response timeout. `read` prints one input report
+//! whatever it is. `sleep` takes a duration such as `250ms` or `2s`.
+//! `loop <n>` repeats the lines up to its matching `end`. `env key=value...`
+//! starts a new segment with updated [environment tags](crate::environment).
+//! `#` starts a comment.
+
+use crate::environment;
+use crate::pattern::HexPattern;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script {
+    pub steps: Vec<Step>,
+}
+
+/// One operation and where it came from, for error messages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// 1-based line number in the script.
+    pub line: usize,
+    pub op: Op,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    Send(Vec<u8>),
+    Expect(HexPattern),
+    Read,
+    Sleep(Duration),
+    Loop { count: u32, body: Vec<Step> },
+    Env(Vec<(String, String)>),
+}
+
+impl Script {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read script {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("Invalid script {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let (steps, end) = block(&mut lines)?;
+        if let Some(line) = end {
+            return Err(anyhow::anyhow!("line {}: 'end' without a matching 'loop'", line));
+        }
+        Ok(Script { steps })
+    }
+
+    /// Every payload the script may send, for checking before it runs.
+    pub fn payloads(&self) -> Vec<(usize, &[u8])> {
+        fn collect<'a>(steps: &'a [Step], out: &mut Vec<(usize, &'a [u8])>) {
+            for step in steps {
+                match &step.op {
+                    Op::Send(data) => out.push((step.line, data)),
+                    Op::Loop { body, .. } => collect(body, out),
+                    _ => {}
+                }
+            }
+        }
+        let mut payloads = Vec::new();
+        collect(&self.steps, &mut payloads);
+        payloads
+    }
+}
+
+/// Parse lines up to an `end` or the end of the text. Returns the steps and
+/// the line number of the `end`, if there was one.
+fn block<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>) -> anyhow::Result<(Vec<Step>, Option<usize>)> {
+    let mut steps = Vec::new();
+    while let Some((line, text)) = lines.next() {
+        let text = text.split_once('#').map_or(text, |(code, _)| code).trim();
+        let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let rest = rest.trim();
+        let error = |message: String| anyhow::anyhow!("line {}: {}", line, message);
+        let op = match word {
+            "" => continue,
+            "end" if rest.is_empty() => return Ok((steps, Some(line))),
+            "send" => {
+                let digits: String = rest.split_whitespace().collect();
+                let data = hex::decode(&digits).map_err(|e| error(format!("invalid hex data '{}': {}", rest, e)))?;
+                if data.is_empty() {
+                    return Err(error("send needs data".to_string()));
+                }
+                Op::Send(data)
+            }
+            "expect" => Op::Expect(HexPattern::parse(rest).map_err(|e| error(e.to_string()))?),
+            "read" if rest.is_empty() => Op::Read,
+            "sleep" => Op::Sleep(crate::parse_duration(rest).map_err(error)?),
+            "env" => Op::Env(rest.split_whitespace().map(environment::parse_tag).collect::<Result<_, _>>().map_err(error)?),
+            "loop" => {
+                let count = rest.parse().map_err(|_| error(format!("invalid loop count '{}'", rest)))?;
+                match block(lines)? {
+                    (body, Some(_)) => Op::Loop { count, body },
+                    (_, None) => return Err(error("'loop' without a matching 'end'".to_string())),
+                }
+            }
+            "end" | "read" => return Err(error(format!("'{}' takes no arguments", word))),
+            _ => return Err(error(format!("unknown operation '{}'", word))),
+        };
+        steps.push(Step { line, op });
+    }
+    Ok((steps, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        Script::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn operations_and_comments() {
+        let script = Script::parse("# setup\n\nsend 10 aa55  # unlock\nexpect 90 ??\n  read\nsleep 250ms\n").unwrap();
+        let ops: Vec<_> = script.steps.iter().map(|s| (s.line, &s.op)).collect();
+        assert_eq!(ops, [
+            (3, &Op::Send(vec![0x10, 0xaa, 0x55])),
+            (4, &Op::Expect(HexPattern::parse("90 ??").unwrap())),
+            (5, &Op::Read),
+            (6, &Op::Sleep(Duration::from_millis(250))),
+        ]);
+    }
+
+    #[test]
+    fn nested_loops() {
+        let script = Script::parse("loop 2\n  send 01\n  loop 3\n    read\n  end\nend\nsend 02\n").unwrap();
+        let [outer, last] = &script.steps[..] else { panic!("{:?}", script.steps) };
+        assert_eq!(last.line, 7);
+        let Op::Loop { count: 2, body } = &outer.op else { panic!("{:?}", outer.op) };
+        assert_eq!(body[0].op, Op::Send(vec![0x01]));
+        assert_eq!(body[1].op, Op::Loop { count: 3, body: vec![Step { line: 4, op: Op::Read }] });
+        assert_eq!(script.payloads(), [(2, &[0x01][..]), (7, &[0x02][..])]);
+    }
+
+    #[test]
+    fn stray_end() {
+        assert_eq!(error("send 01\nend\n"), "line 2: 'end' without a matching 'loop'");
+        assert_eq!(error("loop 1\nend\nend\n"), "line 3: 'end' without a matching 'loop'");
+    }
+
+    #[test]
+    fn unterminated_loop() {
+        assert_eq!(error("send 01\nloop 2\n  read\n"), "line 2: 'loop' without a matching 'end'");
+        assert_eq!(error("loop 2\n  loop 3\n  end\n"), "line 1: 'loop' without a matching 'end'");
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(error("send 01\n\n# comment\nsend 0g\n"), "line 4: invalid hex data '0g': Invalid character 'g' at position 1");
+        assert_eq!(error("send\n"), "line 1: send needs data");
+        assert_eq!(error("read\nread 1\n"), "line 2: 'read' takes no arguments");
+        assert_eq!(error("loop 1\n  end now\nend\n"), "line 2: 'end' takes no arguments");
+        assert_eq!(error("loop many\nend\n"), "line 1: invalid loop count 'many'");
+        assert_eq!(error("read\n  wait 1s\n"), "line 2: unknown operation 'wait'");
+        assert!(error("sleep soon\n").starts_with("line 1: "));
+        assert!(error("expect 9\n").starts_with("line 1: "));
+    }
+
+    #[test]
+    fn env_segments() {
+        let script = Script::parse("env station=3 fixture=A-7\nenv\n").unwrap();
+        assert_eq!(script.steps[0].op, Op::Env(vec![
+            ("station".to_string(), "3".to_string()),
+            ("fixture".to_string(), "A-7".to_string()),
+        ]));
+        assert_eq!(script.steps[1].op, Op::Env(Vec::new()));
+        assert_eq!(error("send 01\nenv station\n"), "line 2: expected key=value, got 'station'");
+        assert_eq!(error("env bad!=1\n"), "line 1: invalid tag name 'bad!'");
+    }
+}
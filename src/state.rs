@@ -0,0 +1,125 @@
+//! Last known good device state, cached per serial number.
+//!
+//! A [`DeviceState`] is a flat set of named values: the firmware release, the
+//! product strings, the report descriptor and any feature reports a profile
+//! asks for. The first state seen for a serial number is saved; later
+//! connections are compared against it, so a unit whose firmware or
+//! configuration changed between test stages stands out.
+//!
+//! Cache files are TOML, one string per value:
+//!
+//! ```toml
+//! release = "0x0102"
+//! product = "Acme Widget"
+//! descriptor = "0642ff0901a101..."
+//! feature_05 = "0501003c"
+//! ```
+
+use crate::{config, HidSession};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Feature reports are read into a buffer this large.
+const FEATURE_BUFFER: usize = 256;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceState {
+    pub values: BTreeMap<String, String>,
+}
+
+/// One value that differs from the cached state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub key: String,
+    /// `None` if the value is new.
+    pub old: Option<String>,
+    /// `None` if the value can no longer be read.
+    pub new: Option<String>,
+}
+
+impl DeviceState {
+    /// Read the state of an open device. Values that can't be read are left
+    /// out, which shows up as a change if they could be before.
+    pub fn capture(session: &HidSession, info: &hidapi::DeviceInfo, feature_reports: &[u8]) -> Self {
+        let mut values = BTreeMap::new();
+        values.insert("release".to_string(), format!("0x{:04x}", info.release_number()));
+        if let Some(manufacturer) = info.manufacturer_string() {
+            values.insert("manufacturer".to_string(), manufacturer.to_string());
+        }
+        if let Some(product) = info.product_string() {
+            values.insert("product".to_string(), product.to_string());
+        }
+        if let Ok(descriptor) = session.report_descriptor() {
+            values.insert("descriptor".to_string(), hex::encode(&descriptor));
+        }
+        for &id in feature_reports {
+            let mut buf = vec![0u8; FEATURE_BUFFER];
+            if let Ok(len) = session.get_feature_report(id, &mut buf) {
+                values.insert(format!("feature_{:02x}", id), hex::encode(&buf[..len]));
+            }
+        }
+        DeviceState { values }
+    }
+
+    /// The cached state, or `None` if nothing was cached yet.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(anyhow::anyhow!("Cannot read state cache {}: {}", path.display(), e)),
+        };
+        let table = config::parse(&text).map_err(|e| anyhow::anyhow!("Invalid state cache {}: {}", path.display(), e))?;
+        let mut values = BTreeMap::new();
+        for (key, value) in table {
+            let value = value.as_str()
+                .ok_or_else(|| anyhow::anyhow!("Invalid state cache {}: '{}' is not a string", path.display(), key))?;
+            values.insert(key, value.to_string());
+        }
+        Ok(Some(DeviceState { values }))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut text = String::new();
+        for (key, value) in &self.values {
+            let _ = writeln!(text, "{} = \"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\""));
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| anyhow::anyhow!("Cannot create state cache directory {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(path, text).map_err(|e| anyhow::anyhow!("Cannot write state cache {}: {}", path.display(), e))
+    }
+
+    /// What changed from `self`, the cached state, to `current`.
+    pub fn diff(&self, current: &DeviceState) -> Vec<Change> {
+        let mut keys: Vec<&String> = self.values.keys().chain(current.values.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .filter_map(|key| {
+                let old = self.values.get(key);
+                let new = current.values.get(key);
+                (old != new).then(|| Change { key: key.clone(), old: old.cloned(), new: new.cloned() })
+            })
+            .collect()
+    }
+}
+
+/// Cache file for one device in `dir`. Characters that aren't safe in file
+/// names are replaced in the serial number.
+pub fn cache_path(dir: &Path, vid: u16, pid: u16, serial: &str) -> PathBuf {
+    let serial: String = serial.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    dir.join(format!("{:04x}-{:04x}-{}.toml", vid, pid, serial))
+}
+
+/// The default cache directory: `$XDG_CACHE_HOME/hid-cmd/state`, falling back
+/// to `~/.cache`.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("hid-cmd").join("state"))
+}
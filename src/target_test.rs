@@ -0,0 +1,158 @@
+//! Results of unit tests running on the device, for `hid-cmd target-test`,
+//! and their JUnit XML report.
+//!
+//! The host starts the run with an output report; the firmware then sends
+//! one input report per record. Every record starts with the magic byte `54`
+//! (`T`), so other input reports can be told apart and ignored:
+//!
+//! ```text
+//! 54 01                                  host: run the tests
+//! 54 10 <status> <n> <name> <m> <message>  a result: status 0 pass, 1 fail, 2 skip
+//! 54 11 <total:u16> <failed:u16>         the run is over, with the device's counts
+//! 54 12 <text>                           output for the next result
+//! ```
+//!
+//! Names, messages and output are UTF-8, each preceded by its length where
+//! shown; counts are little-endian. Output records may be repeated to send
+//! more than fits in one report.
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+pub const MAGIC: u8 = 0x54;
+pub const RECORD_RUN: u8 = 0x01;
+pub const RECORD_RESULT: u8 = 0x10;
+pub const RECORD_END: u8 = 0x11;
+pub const RECORD_OUTPUT: u8 = 0x12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Skip => "skip",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Record {
+    Result { status: Status, name: String, message: String },
+    End { total: u16, failed: u16 },
+    Output(String),
+}
+
+impl Record {
+    /// Parse a report payload. `Ok(None)` if it isn't a test record at all.
+    pub fn parse(payload: &[u8]) -> anyhow::Result<Option<Self>> {
+        let [MAGIC, kind, rest @ ..] = payload else {
+            return Ok(None);
+        };
+        match *kind {
+            RECORD_RESULT => {
+                let (&status, rest) = rest.split_first().ok_or_else(|| anyhow::anyhow!("result record without a status"))?;
+                let status = match status {
+                    0 => Status::Pass,
+                    1 => Status::Fail,
+                    2 => Status::Skip,
+                    _ => return Err(anyhow::anyhow!("unknown test status {}", status)),
+                };
+                let (name, rest) = counted_string(rest).ok_or_else(|| anyhow::anyhow!("result record with a truncated name"))?;
+                let (message, _) = counted_string(rest).unwrap_or_default();
+                Ok(Some(Record::Result { status, name, message }))
+            }
+            RECORD_END => match rest {
+                [t0, t1, f0, f1, ..] => Ok(Some(Record::End {
+                    total: u16::from_le_bytes([*t0, *t1]),
+                    failed: u16::from_le_bytes([*f0, *f1]),
+                })),
+                _ => Err(anyhow::anyhow!("truncated end record")),
+            },
+            // The rest of the report is zero padding
+            RECORD_OUTPUT => {
+                let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+                Ok(Some(Record::Output(String::from_utf8_lossy(&rest[..end]).into_owned())))
+            }
+            _ => Err(anyhow::anyhow!("unknown test record type 0x{:02x}", kind)),
+        }
+    }
+}
+
+/// A string of `n` bytes after its length byte `n`, and what follows it.
+fn counted_string(data: &[u8]) -> Option<(String, &[u8])> {
+    let (&len, rest) = data.split_first()?;
+    let text = rest.get(..usize::from(len))?;
+    Some((String::from_utf8_lossy(text).into_owned(), &rest[usize::from(len)..]))
+}
+
+/// One finished test.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+    /// From the output records before the result.
+    pub output: String,
+    /// Since the previous record, or the start of the run.
+    pub time: Duration,
+}
+
+/// Write `cases` as a JUnit report with one test suite. `run` and `loopback`
+/// report their results this way too, as cases of their own.
+pub fn write_junit(out: &mut impl Write, suite: &str, timestamp: &str, cases: &[Case], properties: &[(String, String)]) -> io::Result<()> {
+    let count = |status: Status| cases.iter().filter(|c| c.status == status).count();
+    let time: Duration = cases.iter().map(|c| c.time).sum();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<testsuites tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        cases.len(), count(Status::Fail), count(Status::Skip), time.as_secs_f64())?;
+    writeln!(out, r#"  <testsuite name="{}" tests="{}" failures="{}" errors="0" skipped="{}" time="{:.3}" timestamp="{}">"#,
+        escape(suite), cases.len(), count(Status::Fail), count(Status::Skip), time.as_secs_f64(), escape(timestamp))?;
+    if !properties.is_empty() {
+        writeln!(out, "    <properties>")?;
+        for (name, value) in properties {
+            writeln!(out, r#"      <property name="{}" value="{}"/>"#, escape(name), escape(value))?;
+        }
+        writeln!(out, "    </properties>")?;
+    }
+    for case in cases {
+        write!(out, r#"    <testcase name="{}" classname="{}" time="{:.3}">"#, escape(&case.name), escape(suite), case.time.as_secs_f64())?;
+        match case.status {
+            Status::Pass => {}
+            Status::Fail => write!(out, r#"<failure message="{}"/>"#, escape(&case.message))?,
+            Status::Skip => write!(out, r#"<skipped message="{}"/>"#, escape(&case.message))?,
+        }
+        if !case.output.is_empty() {
+            write!(out, "<system-out>{}</system-out>", escape(&case.output))?;
+        }
+        writeln!(out, "</testcase>")?;
+    }
+    writeln!(out, "  </testsuite>")?;
+    writeln!(out, "</testsuites>")?;
+    out.flush()
+}
+
+/// Escape text for XML attributes and content. Control characters other than
+/// tab and line breaks aren't allowed in XML at all and are dropped.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,181 @@
+//! Timelines for `hid-cmd timeline`: reports sent at fixed offsets from the
+//! start, for reproducing stimulus patterns with precise timing.
+//!
+//! ```text
+//! # Two writes 12 ms apart, then a burst
+//! t=0ms send 01 02
+//! t=12ms send 03
+//! t=12.5ms send 04; t=12.75ms send 05
+//! ```
+//!
+//! Entries are separated by line breaks or `;`. The offset takes the same
+//! durations as `sleep` in scripts, plus `us` for microseconds. Entries run in
+//! order of their offsets; entries with the same offset keep their order in
+//! the file. `#` starts a comment.
+//!
+//! `hid-cmd record` writes timelines, with `recv` entries for the input
+//! reports it received; these are kept for reference and never sent.
+
+use crate::exchange::Observer;
+use crate::HidSession;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Sleeping is only trusted up to this close to a deadline; the rest is spent
+/// spinning, which costs a core but is accurate to a few microseconds.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timeline {
+    /// Sorted by offset.
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// 1-based line number in the file.
+    pub line: usize,
+    pub offset: Duration,
+    pub action: Action,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Send,
+    /// An input report received at this offset when the timeline was
+    /// recorded.
+    Recv,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Send => "send",
+            Action::Recv => "recv",
+        }
+    }
+}
+
+impl Timeline {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read timeline {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("Invalid timeline {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let error = |message: String| anyhow::anyhow!("line {}: {}", line_no, message);
+            let code = line.split_once('#').map_or(line, |(code, _)| code);
+            for item in code.split(';').map(str::trim).filter(|item| !item.is_empty()) {
+                let (time, rest) = item.split_once(char::is_whitespace)
+                    .ok_or_else(|| error(format!("expected 't=<offset> send <hex>', got '{}'", item)))?;
+                let offset = time.strip_prefix("t=")
+                    .ok_or_else(|| error(format!("expected an offset such as t=12ms, got '{}'", time)))?;
+                let offset = crate::parse_duration(offset).map_err(error)?;
+                let rest = rest.trim();
+                let (word, hex) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let action = match word {
+                    "send" => Action::Send,
+                    "recv" => Action::Recv,
+                    _ => return Err(error(format!("unknown operation '{}'", word))),
+                };
+                let digits: String = hex.split_whitespace().collect();
+                let data = hex::decode(&digits).map_err(|e| error(format!("invalid hex data '{}': {}", hex.trim(), e)))?;
+                if data.is_empty() {
+                    return Err(error(format!("{} needs data", word)));
+                }
+                entries.push(Entry { line: line_no, offset, action, data });
+            }
+        }
+        entries.sort_by_key(|entry| entry.offset);
+        Ok(Timeline { entries })
+    }
+}
+
+/// Writes a timeline as traffic happens, stamping each entry with the time
+/// since the recorder was created.
+pub struct Recorder<W: Write> {
+    out: W,
+    started: Instant,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Start a timeline, with `header` as its first comment line.
+    pub fn new(mut out: W, header: &str) -> io::Result<Self> {
+        writeln!(out, "# {}", header)?;
+        out.flush()?;
+        Ok(Recorder { out, started: Instant::now() })
+    }
+
+    /// Add an entry at the current offset. Every entry is flushed, so a
+    /// recording that is cut short keeps what happened up to then.
+    pub fn record(&mut self, action: Action, data: &[u8]) -> io::Result<()> {
+        let offset = self.started.elapsed();
+        writeln!(self.out, "t={:.3}ms {} {}", offset.as_secs_f64() * 1e3, action.name(), hex::encode(data))?;
+        self.out.flush()
+    }
+}
+
+/// Block until `deadline`, sleeping for most of the wait and spinning for the
+/// last [`SPIN_MARGIN`].
+pub fn wait_until(deadline: Instant) {
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        let left = deadline - now;
+        if left > SPIN_MARGIN {
+            std::thread::sleep(left - SPIN_MARGIN);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// One report [`play`] wrote, or tried to.
+#[derive(Debug)]
+pub struct Sent {
+    pub offset: Duration,
+    /// How long after its offset the write started.
+    pub late: Duration,
+    pub result: anyhow::Result<usize>,
+}
+
+/// What [`play`] did.
+#[derive(Debug, Default)]
+pub struct Played {
+    /// In order, up to and including the first failed write.
+    pub sent: Vec<Sent>,
+    /// The report whose offset is too far in the future to wait for, if any.
+    pub unreachable: Option<usize>,
+}
+
+/// Write each of `reports` once its offset in `offsets` has passed since the
+/// start, stopping after the first failed write. `observer` is told about
+/// each write as soon as it is done, and should be quick about it.
+pub fn play(session: &HidSession, reports: &[Vec<u8>], offsets: &[Duration], observer: &mut dyn Observer) -> Played {
+    let mut played = Played::default();
+    let start = Instant::now();
+    for (i, (report, &offset)) in reports.iter().zip(offsets).enumerate() {
+        let Some(due) = start.checked_add(offset) else {
+            played.unreachable = Some(i);
+            break;
+        };
+        wait_until(due);
+        let late = due.elapsed();
+        let result = session.write_report(report);
+        observer.sent(report, &result);
+        let failed = result.is_err();
+        played.sent.push(Sent { offset, late, result });
+        if failed {
+            break;
+        }
+    }
+    played
+}
@@ -0,0 +1,35 @@
+//! Wall-clock timestamps without a date/time dependency.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format `time` as an RFC 3339 UTC timestamp with milliseconds,
+/// e.g. `2024-05-01T12:34:56.789Z`.
+pub fn utc_string(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, since_epoch.subsec_millis())
+}
+
+/// The current time as [`utc_string`].
+pub fn now_utc() -> String {
+    utc_string(SystemTime::now())
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date.
+///
+/// Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,153 @@
+//! Trace and coverage data streamed by the firmware, for `hid-cmd trace pull`.
+//!
+//! The firmware sends its trace output, such as ITM packets or gcov records,
+//! as [file transfer](crate::transfer) chunks on one report ID. A trace is a
+//! stream rather than a file: the first chunk may have any sequence number,
+//! and a corrupt or missing chunk leaves a gap instead of ending it. A chunk
+//! with [`FLAG_LAST`](crate::transfer::FLAG_LAST) set ends the stream.
+//!
+//! For flow control, the host can acknowledge every `n` chunks with an output
+//! report; the firmware then sends no more than `n` chunks ahead.
+
+use crate::transfer;
+
+/// What one report payload added to a [`Stream`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pushed<'a> {
+    /// The data of the next chunk, after `lost` chunks that never arrived.
+    Data { data: &'a [u8], lost: u16 },
+    /// A chunk that arrived again, or so late that its place is taken; dropped.
+    Duplicate { seq: u16 },
+}
+
+/// Follows the sequence numbers of a trace stream.
+#[derive(Debug, Default)]
+pub struct Stream {
+    next_seq: Option<u16>,
+    chunks: usize,
+    bytes: usize,
+    lost: usize,
+    corrupt: usize,
+    done: bool,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Stream::default()
+    }
+
+    /// Add the chunk in one report payload. A corrupt chunk is counted and
+    /// returned as an error; the stream carries on with the next one.
+    pub fn push<'a>(&mut self, payload: &'a [u8]) -> anyhow::Result<Pushed<'a>> {
+        let chunk = transfer::parse_chunk(payload).inspect_err(|_| self.corrupt += 1)?;
+        let expected = self.next_seq.unwrap_or(chunk.seq);
+        let lost = chunk.seq.wrapping_sub(expected);
+        // More than half the sequence space ahead is taken to be behind
+        if lost > u16::MAX / 2 {
+            return Ok(Pushed::Duplicate { seq: chunk.seq });
+        }
+        self.next_seq = Some(chunk.seq.wrapping_add(1));
+        self.chunks += 1;
+        self.bytes += chunk.data.len();
+        self.lost += usize::from(lost);
+        self.done = chunk.last;
+        Ok(Pushed::Data { data: chunk.data, lost })
+    }
+
+    /// Whether the last chunk has arrived.
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Chunks skipped in the sequence numbers.
+    pub fn lost(&self) -> usize {
+        self.lost
+    }
+
+    /// Chunks dropped for a bad header or CRC.
+    pub fn corrupt(&self) -> usize {
+        self.corrupt
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+enum ItmState {
+    #[default]
+    Header,
+    /// In a synchronization packet, up to its closing `0x80`.
+    Sync,
+    /// In the continuation bytes of a timestamp or extension packet.
+    Continuation,
+    /// In the payload of a source packet; `port` for instrumentation packets.
+    Payload { port: Option<u8>, left: usize },
+}
+
+/// Splits an ARM ITM byte stream into the data written to each stimulus port.
+///
+/// Packets may span chunks, so the state is kept between calls to
+/// [`push`](Self::push). Hardware source, timestamp, overflow and extension
+/// packets are skipped.
+#[derive(Debug, Default)]
+pub struct ItmDemux {
+    state: ItmState,
+    packet: Vec<u8>,
+}
+
+impl ItmDemux {
+    pub fn new() -> Self {
+        ItmDemux::default()
+    }
+
+    /// Decode `bytes`, passing each instrumentation packet's port and payload
+    /// to `write` once it is complete.
+    pub fn push(&mut self, bytes: &[u8], mut write: impl FnMut(u8, &[u8])) {
+        for &b in bytes {
+            self.state = match std::mem::take(&mut self.state) {
+                ItmState::Header => match b {
+                    0x00 => ItmState::Sync,
+                    0x70 => ItmState::Header,
+                    _ if b & 0x03 != 0 => {
+                        let left = [0, 1, 2, 4][usize::from(b & 0x03)];
+                        let port = (b & 0x04 == 0).then_some(b >> 3);
+                        ItmState::Payload { port, left }
+                    }
+                    _ if b & 0x80 != 0 => ItmState::Continuation,
+                    _ => ItmState::Header,
+                },
+                // The zeros are closed by 0x80
+                ItmState::Sync if b == 0x00 => ItmState::Sync,
+                ItmState::Sync => ItmState::Header,
+                ItmState::Continuation if b & 0x80 != 0 => ItmState::Continuation,
+                ItmState::Continuation => ItmState::Header,
+                ItmState::Payload { port, left } => {
+                    if port.is_some() {
+                        self.packet.push(b);
+                    }
+                    if left > 1 {
+                        ItmState::Payload { port, left: left - 1 }
+                    } else {
+                        if let Some(port) = port {
+                            write(port, &self.packet);
+                        }
+                        self.packet.clear();
+                        ItmState::Header
+                    }
+                }
+            };
+        }
+    }
+
+    /// Drop any packet in progress, after a gap in the stream.
+    pub fn reset(&mut self) {
+        self.state = ItmState::Header;
+        self.packet.clear();
+    }
+}
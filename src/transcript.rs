@@ -0,0 +1,80 @@
+//! Markdown session transcripts.
+//!
+//! A transcript is a readable account of a session (the command line, the
+//! device, and every report with its timing) meant to be pasted into a bug
+//! ticket as is.
+
+use crate::timestamp;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Bytes per line of a transcript hex dump.
+const DUMP_WIDTH: usize = 16;
+
+pub struct Transcript<W: Write> {
+    out: W,
+    started: Instant,
+    last_event: Option<Instant>,
+}
+
+impl Transcript<BufWriter<File>> {
+    /// Create (or truncate) a transcript file.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Transcript::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> Transcript<W> {
+    pub fn new(out: W) -> Self {
+        Transcript { out, started: Instant::now(), last_event: None }
+    }
+
+    /// Write the title and the command that started the session.
+    pub fn header(&mut self, command_line: &str) -> io::Result<()> {
+        writeln!(self.out, "# HID session transcript\n")?;
+        writeln!(self.out, "- Command: `{}`", command_line)?;
+        writeln!(self.out, "- Started: {}", timestamp::now_utc())?;
+        self.out.flush()
+    }
+
+    /// Add a bullet to the session details, e.g. the opened device.
+    pub fn detail(&mut self, name: &str, value: &str) -> io::Result<()> {
+        writeln!(self.out, "- {}: {}", name, value)?;
+        self.out.flush()
+    }
+
+    /// Add a free-form paragraph, e.g. an error.
+    pub fn note(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.out, "\n{}", text)?;
+        self.out.flush()
+    }
+
+    /// Add one report: a heading with its offset from the start of the session,
+    /// the time since the previous report, `annotations` as bullets, and a hex dump.
+    pub fn report(&mut self, title: &str, annotations: &[String], data: &[u8]) -> io::Result<()> {
+        let now = Instant::now();
+        let offset = now.duration_since(self.started);
+        writeln!(self.out, "\n### +{:.3} ms: {} ({} bytes)\n", offset.as_secs_f64() * 1e3, title, data.len())?;
+        if let Some(last) = self.last_event {
+            writeln!(self.out, "_{:.3} ms after the previous report_\n", now.duration_since(last).as_secs_f64() * 1e3)?;
+        }
+        self.last_event = Some(now);
+
+        for annotation in annotations {
+            writeln!(self.out, "- {}", annotation)?;
+        }
+        if !annotations.is_empty() {
+            writeln!(self.out)?;
+        }
+
+        writeln!(self.out, "```")?;
+        for (i, line) in data.chunks(DUMP_WIDTH).enumerate() {
+            let bytes: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(self.out, "{:04x}  {}", i * DUMP_WIDTH, bytes.join(" "))?;
+        }
+        writeln!(self.out, "```")?;
+        self.out.flush()
+    }
+}
@@ -0,0 +1,216 @@
+//! Framing for moving files through fixed-size reports, used by `send-file`
+//! and `recv-file`.
+//!
+//! Each report payload carries one chunk:
+//!
+//! | bytes     | field                                            |
+//! |-----------|--------------------------------------------------|
+//! | 0..2      | sequence number, little-endian, from 0 (wraps)   |
+//! | 2         | number of data bytes `n`                         |
+//! | 3         | flags: [`FLAG_LAST`] on the final chunk          |
+//! | 4..4+n    | data                                             |
+//! | 4+n..6+n  | [CRC-16](crate::crc::crc16) of bytes 0..4+n, LE  |
+//!
+//! The rest of the report is padding. An empty file is a single empty chunk
+//! with [`FLAG_LAST`] set.
+
+use crate::crc::crc16;
+
+/// Sequence number, length and flags.
+pub const HEADER_LEN: usize = 4;
+pub const CRC_LEN: usize = 2;
+/// Set on the final chunk of a file.
+pub const FLAG_LAST: u8 = 0x01;
+
+/// Data bytes that fit in a chunk sent in `payload_len`-byte reports.
+pub fn chunk_capacity(payload_len: usize) -> usize {
+    payload_len.saturating_sub(HEADER_LEN + CRC_LEN).min(u8::MAX as usize)
+}
+
+/// Split `data` into framed chunks of at most `capacity` data bytes each.
+pub fn frames(data: &[u8], capacity: usize) -> anyhow::Result<Vec<Vec<u8>>> {
+    if capacity == 0 {
+        return Err(anyhow::anyhow!("Reports are too small to carry file chunks ({} bytes of framing per report)",
+            HEADER_LEN + CRC_LEN));
+    }
+    let chunks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(capacity).collect() };
+    let last = chunks.len() - 1;
+    Ok(chunks.iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut frame = Vec::with_capacity(HEADER_LEN + chunk.len() + CRC_LEN);
+            frame.extend_from_slice(&(i as u16).to_le_bytes());
+            frame.push(chunk.len() as u8);
+            frame.push(if i == last { FLAG_LAST } else { 0 });
+            frame.extend_from_slice(chunk);
+            frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+            frame
+        })
+        .collect())
+}
+
+/// One chunk taken out of a report payload.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    pub seq: u16,
+    pub data: &'a [u8],
+    pub last: bool,
+}
+
+/// Check the framing and CRC of the chunk at the start of `payload`.
+pub fn parse_chunk(payload: &[u8]) -> anyhow::Result<Chunk<'_>> {
+    let Some(header) = payload.get(..HEADER_LEN) else {
+        return Err(anyhow::anyhow!("Report of {} bytes is too short for a chunk header", payload.len()));
+    };
+    let seq = u16::from_le_bytes([header[0], header[1]]);
+    let len = header[2] as usize;
+    let flags = header[3];
+    let Some(framed) = payload.get(..HEADER_LEN + len + CRC_LEN) else {
+        return Err(anyhow::anyhow!("Chunk {} claims {} data bytes, but the report has only {}",
+            seq, len, payload.len().saturating_sub(HEADER_LEN + CRC_LEN)));
+    };
+    let (body, crc) = framed.split_at(HEADER_LEN + len);
+    let crc = u16::from_le_bytes([crc[0], crc[1]]);
+    if crc16(body) != crc {
+        return Err(anyhow::anyhow!("Chunk {} is corrupt: CRC {:04x}, expected {:04x}", seq, crc, crc16(body)));
+    }
+    Ok(Chunk { seq, data: &body[HEADER_LEN..], last: flags & FLAG_LAST != 0 })
+}
+
+/// Puts received chunks back together, checking their order and CRCs.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    data: Vec<u8>,
+    next_seq: u16,
+    chunks: usize,
+    done: bool,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler::default()
+    }
+
+    /// Add the chunk in one report payload. Returns `true` once the last chunk
+    /// has arrived.
+    pub fn push(&mut self, payload: &[u8]) -> anyhow::Result<bool> {
+        if self.done {
+            return Err(anyhow::anyhow!("Chunk received after the last one"));
+        }
+        let Chunk { seq, data, last } = parse_chunk(payload)?;
+        if seq != self.next_seq {
+            return Err(anyhow::anyhow!("Expected chunk {}, got chunk {}; chunks were lost", self.next_seq, seq));
+        }
+        self.data.extend_from_slice(data);
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.chunks += 1;
+        self.done = last;
+        Ok(self.done)
+    }
+
+    /// Data received so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send `data` through frames in `payload_len`-byte report payloads, padded
+    /// as they would be on the wire, and put it back together.
+    fn round_trip(data: &[u8], payload_len: usize) -> Reassembler {
+        let frames = frames(data, chunk_capacity(payload_len)).unwrap();
+        let mut reassembler = Reassembler::new();
+        for (i, frame) in frames.iter().enumerate() {
+            let mut payload = frame.clone();
+            payload.resize(payload_len, 0);
+            assert_eq!(reassembler.push(&payload).unwrap(), i == frames.len() - 1);
+        }
+        reassembler
+    }
+
+    #[test]
+    fn frames_reassemble_into_the_file() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let reassembler = round_trip(&data, 64);
+        assert_eq!(reassembler.chunks(), data.len().div_ceil(58));
+        assert_eq!(reassembler.into_data(), data);
+    }
+
+    #[test]
+    fn chunk_capacity_leaves_room_for_the_framing() {
+        assert_eq!(chunk_capacity(64), 58);
+        assert_eq!(chunk_capacity(6), 0);
+        assert_eq!(chunk_capacity(1024), 255);
+        assert!(frames(b"data", chunk_capacity(6)).is_err());
+    }
+
+    #[test]
+    fn empty_file_is_one_last_chunk() {
+        let frames = frames(&[], 58).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(parse_chunk(&frames[0]).unwrap(), Chunk { seq: 0, data: &[], last: true });
+        let reassembler = round_trip(&[], 64);
+        assert_eq!(reassembler.chunks(), 1);
+        assert!(reassembler.data().is_empty());
+    }
+
+    #[test]
+    fn lost_chunk_is_reported() {
+        let frames = frames(&[0x55; 20], 8).unwrap();
+        let mut reassembler = Reassembler::new();
+        reassembler.push(&frames[0]).unwrap();
+        let error = reassembler.push(&frames[2]).unwrap_err().to_string();
+        assert!(error.contains("Expected chunk 1, got chunk 2"), "{}", error);
+    }
+
+    #[test]
+    fn duplicated_chunk_is_reported() {
+        let frames = frames(&[0x55; 20], 8).unwrap();
+        let mut reassembler = Reassembler::new();
+        reassembler.push(&frames[0]).unwrap();
+        assert!(reassembler.push(&frames[0]).is_err());
+        assert_eq!(reassembler.data(), &[0x55; 8]);
+    }
+
+    #[test]
+    fn corrupt_chunk_is_reported() {
+        let mut frame = frames(b"hello", 8).unwrap().remove(0);
+        frame[HEADER_LEN] ^= 0x01;
+        let error = Reassembler::new().push(&frame).unwrap_err().to_string();
+        assert!(error.contains("corrupt"), "{}", error);
+        // A length beyond the report is caught before the CRC
+        let mut frame = frames(b"hello", 8).unwrap().remove(0);
+        frame[2] = 200;
+        assert!(parse_chunk(&frame).is_err());
+        assert!(parse_chunk(&[0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn nothing_after_the_last_chunk() {
+        let frames = frames(b"hi", 8).unwrap();
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(&frames[0]).unwrap());
+        assert!(reassembler.push(&frames[0]).is_err());
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        // One byte per chunk, so the sequence number passes 0xffff
+        let data: Vec<u8> = (0..0x1_0010u32).map(|i| i as u8).collect();
+        let frames = frames(&data, 1).unwrap();
+        assert_eq!(parse_chunk(&frames[0xffff]).unwrap().seq, 0xffff);
+        assert_eq!(parse_chunk(&frames[0x1_0000]).unwrap().seq, 0);
+        assert_eq!(round_trip(&data, HEADER_LEN + 1 + CRC_LEN).into_data(), data);
+    }
+}
@@ -0,0 +1,66 @@
+//! The device I/O a [`HidSession`](crate::HidSession) is built on.
+//!
+//! Sessions normally wrap a hidapi [`HidDevice`], but anything implementing
+//! [`HidTransport`] will do, such as the in-memory
+//! [`MockTransport`](crate::mock::MockTransport) for testing without hardware.
+
+use hidapi::HidDevice;
+use std::time::Duration;
+
+/// Report I/O on one open HID interface, with hidapi's conventions: the first
+/// byte of every written report is its report ID, 0 for devices without
+/// numbered reports.
+pub trait HidTransport: Send {
+    /// Write one output report as is; returns the bytes written.
+    fn write(&self, report: &[u8]) -> anyhow::Result<usize>;
+
+    /// Wait for an input report, at most `timeout` if given; returns its
+    /// length, 0 if none arrived in time.
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> anyhow::Result<usize>;
+
+    /// Send a feature report, report ID first.
+    fn send_feature_report(&self, report: &[u8]) -> anyhow::Result<()>;
+
+    /// Read the feature report whose ID is in `buf[0]` into `buf`; returns its
+    /// length, ID included.
+    fn get_feature_report(&self, buf: &mut [u8]) -> anyhow::Result<usize>;
+
+    fn report_descriptor(&self) -> anyhow::Result<Vec<u8>>;
+
+    /// The hidapi handle behind the transport, if there is one.
+    fn hid_device(&self) -> Option<&HidDevice> {
+        None
+    }
+}
+
+impl HidTransport for HidDevice {
+    fn write(&self, report: &[u8]) -> anyhow::Result<usize> {
+        Ok(HidDevice::write(self, report)?)
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: Option<Duration>) -> anyhow::Result<usize> {
+        Ok(match timeout {
+            Some(timeout) => self.read_timeout(buf, timeout.as_millis().min(i32::MAX as u128) as i32)?,
+            None => HidDevice::read(self, buf)?,
+        })
+    }
+
+    fn send_feature_report(&self, report: &[u8]) -> anyhow::Result<()> {
+        Ok(HidDevice::send_feature_report(self, report)?)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        Ok(HidDevice::get_feature_report(self, buf)?)
+    }
+
+    fn report_descriptor(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0u8; hidapi::MAX_REPORT_DESCRIPTOR_SIZE];
+        let len = self.get_report_descriptor(&mut buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    fn hid_device(&self) -> Option<&HidDevice> {
+        Some(self)
+    }
+}
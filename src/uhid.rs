@@ -0,0 +1,225 @@
+//! Virtual HID devices through Linux's `/dev/uhid`, for `hid-cmd emulate`.
+//!
+//! Writing a create event to `/dev/uhid` makes the kernel add a HID device
+//! with the given report descriptor, which host software then finds like any
+//! other (hidraw, evdev, hidapi). Input reports are written to the same file,
+//! and what the host does with the device arrives as [`Event`]s. The device
+//! goes away when the handle is closed.
+//!
+//! Events are the kernel's packed `struct uhid_event` from `linux/uhid.h`: a
+//! 32-bit type followed by the request.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+
+pub const PATH: &str = "/dev/uhid";
+
+const UHID_DESTROY: u32 = 1;
+const UHID_START: u32 = 2;
+const UHID_STOP: u32 = 3;
+const UHID_OPEN: u32 = 4;
+const UHID_CLOSE: u32 = 5;
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+/// `sizeof(struct uhid_event)`: the type and the largest request, `create2`.
+const EVENT_LEN: usize = 4 + 128 + 64 + 64 + 2 + 2 + 4 * 4 + DATA_MAX;
+/// `UHID_DATA_MAX` and `HID_MAX_DESCRIPTOR_SIZE`.
+pub const DATA_MAX: usize = 4096;
+pub const BUS_USB: u16 = 0x03;
+
+/// `EIO`, the answer to requests for reports the device doesn't have.
+pub const EIO: u16 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportType {
+    Feature,
+    Output,
+    Input,
+}
+
+impl ReportType {
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            0 => ReportType::Feature,
+            1 => ReportType::Output,
+            _ => ReportType::Input,
+        }
+    }
+}
+
+/// What the kernel tells the device about the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The HID driver bound to the device; input reports may be sent.
+    Start,
+    Stop,
+    /// Host software opened the device, e.g. its hidraw node.
+    Open,
+    /// The last host software closed it.
+    Close,
+    /// An output report written by the host, report ID first if the device
+    /// uses them.
+    Output { kind: ReportType, data: Vec<u8> },
+    /// The host asks for report `number`; answer with [`Device::get_report_reply`].
+    GetReport { id: u32, number: u8, kind: ReportType },
+    /// The host sets report `number`; answer with [`Device::set_report_reply`].
+    SetReport { id: u32, number: u8, kind: ReportType, data: Vec<u8> },
+    /// An event type this module doesn't know.
+    Other(u32),
+}
+
+impl Event {
+    fn parse(buf: &[u8]) -> io::Result<Self> {
+        let u16_at = |at: usize| buf.get(at..at + 2).map_or(0, |b| u16::from_ne_bytes([b[0], b[1]]));
+        let u32_at = |at: usize| buf.get(at..at + 4).map_or(0, |b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]));
+        let byte_at = |at: usize| buf.get(at).copied().unwrap_or(0);
+        let data = |at: usize, len: u16| buf.get(at..at + usize::from(len).min(DATA_MAX)).unwrap_or_default().to_vec();
+        if buf.len() < 4 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short uhid event"));
+        }
+        Ok(match u32_at(0) {
+            UHID_START => Event::Start,
+            UHID_STOP => Event::Stop,
+            UHID_OPEN => Event::Open,
+            UHID_CLOSE => Event::Close,
+            // struct uhid_output_req { data[4096]; u16 size; u8 rtype; }
+            UHID_OUTPUT => Event::Output { kind: ReportType::from_raw(byte_at(4 + DATA_MAX + 2)), data: data(4, u16_at(4 + DATA_MAX)) },
+            // struct uhid_get_report_req { u32 id; u8 rnum; u8 rtype; }
+            UHID_GET_REPORT => Event::GetReport { id: u32_at(4), number: byte_at(8), kind: ReportType::from_raw(byte_at(9)) },
+            // struct uhid_set_report_req { u32 id; u8 rnum; u8 rtype; u16 size; data[4096]; }
+            UHID_SET_REPORT => Event::SetReport {
+                id: u32_at(4),
+                number: byte_at(8),
+                kind: ReportType::from_raw(byte_at(9)),
+                data: data(12, u16_at(10)),
+            },
+            other => Event::Other(other),
+        })
+    }
+}
+
+/// How the device presents itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub name: String,
+    pub serial: String,
+    pub bus: u16,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub version: u32,
+    pub descriptor: Vec<u8>,
+}
+
+/// A virtual device, destroyed when dropped.
+pub struct Device {
+    file: File,
+}
+
+impl Device {
+    pub fn create(config: &Config) -> anyhow::Result<Self> {
+        if config.descriptor.len() > DATA_MAX {
+            return Err(anyhow::anyhow!("The report descriptor is {} bytes; uhid takes at most {}", config.descriptor.len(), DATA_MAX));
+        }
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(PATH)
+            .map_err(|e| anyhow::anyhow!("Cannot open {}: {} (emulation needs Linux with the uhid module, and write access to it)", PATH, e))?;
+        let mut device = Device { file };
+
+        // struct uhid_create2_req { name[128]; phys[64]; uniq[64]; u16 rd_size; u16 bus;
+        //     u32 vendor; u32 product; u32 version; u32 country; rd_data[4096]; }
+        let mut event = new_event(UHID_CREATE2);
+        put_str(&mut event[4..132], &config.name);
+        put_str(&mut event[132..196], "hid-cmd");
+        put_str(&mut event[196..260], &config.serial);
+        event[260..262].copy_from_slice(&(config.descriptor.len() as u16).to_ne_bytes());
+        event[262..264].copy_from_slice(&config.bus.to_ne_bytes());
+        event[264..268].copy_from_slice(&u32::from(config.vendor_id).to_ne_bytes());
+        event[268..272].copy_from_slice(&u32::from(config.product_id).to_ne_bytes());
+        event[272..276].copy_from_slice(&config.version.to_ne_bytes());
+        event[280..280 + config.descriptor.len()].copy_from_slice(&config.descriptor);
+        device.send(&event).map_err(|e| anyhow::anyhow!("Cannot create the virtual device: {}", e))?;
+        Ok(device)
+    }
+
+    /// Send an input report, report ID first if the device uses them.
+    pub fn input(&mut self, report: &[u8]) -> io::Result<()> {
+        // struct uhid_input2_req { u16 size; data[4096]; }
+        let report = report.get(..DATA_MAX).unwrap_or(report);
+        let mut event = new_event(UHID_INPUT2);
+        event[4..6].copy_from_slice(&(report.len() as u16).to_ne_bytes());
+        event[6..6 + report.len()].copy_from_slice(report);
+        self.send(&event)
+    }
+
+    /// Answer [`Event::GetReport`] `id` with `data`, or fail it with `err`
+    /// (an errno such as [`EIO`]).
+    pub fn get_report_reply(&mut self, id: u32, err: u16, data: &[u8]) -> io::Result<()> {
+        // struct uhid_get_report_reply_req { u32 id; u16 err; u16 size; data[4096]; }
+        let data = data.get(..DATA_MAX).unwrap_or(data);
+        let mut event = new_event(UHID_GET_REPORT_REPLY);
+        event[4..8].copy_from_slice(&id.to_ne_bytes());
+        event[8..10].copy_from_slice(&err.to_ne_bytes());
+        event[10..12].copy_from_slice(&(data.len() as u16).to_ne_bytes());
+        event[12..12 + data.len()].copy_from_slice(data);
+        self.send(&event)
+    }
+
+    /// Answer [`Event::SetReport`] `id`, successfully with `err` 0.
+    pub fn set_report_reply(&mut self, id: u32, err: u16) -> io::Result<()> {
+        // struct uhid_set_report_reply_req { u32 id; u16 err; }
+        let mut event = new_event(UHID_SET_REPORT_REPLY);
+        event[4..8].copy_from_slice(&id.to_ne_bytes());
+        event[8..10].copy_from_slice(&err.to_ne_bytes());
+        self.send(&event)
+    }
+
+    /// Read events on a thread of their own, so that the caller can wait for
+    /// them with a timeout. The channel closes when reading fails.
+    pub fn events(&self) -> io::Result<Receiver<io::Result<Event>>> {
+        let mut file = self.file.try_clone()?;
+        let (sender, events) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = vec![0u8; EVENT_LEN];
+            loop {
+                let event = file.read(&mut buf).and_then(|len| Event::parse(&buf[..len]));
+                let failed = event.is_err();
+                if sender.send(event).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(events)
+    }
+
+    fn send(&mut self, event: &[u8]) -> io::Result<()> {
+        let written = self.file.write(event)?;
+        if written != event.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, format!("uhid took {} of {} bytes", written, event.len())));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let _ = self.send(&new_event(UHID_DESTROY));
+    }
+}
+
+fn new_event(kind: u32) -> Vec<u8> {
+    let mut event = vec![0u8; EVENT_LEN];
+    event[..4].copy_from_slice(&kind.to_ne_bytes());
+    event
+}
+
+/// Copy `s` into a fixed-size C string field, truncated to leave its NUL.
+fn put_str(field: &mut [u8], s: &str) {
+    let len = s.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
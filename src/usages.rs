@@ -0,0 +1,479 @@
+//! Input reports of standard keyboards, mice and gamepads in words, for
+//! `monitor --decode`.
+//!
+//! The report descriptor says where each field is and its usage what it
+//! means: keys held, buttons pressed, how far a mouse moved, where a stick or
+//! hat switch points. Keys are named as printed on a US keyboard, or on a
+//! German or French one with [`Layout`].
+
+use crate::descriptor::{self, Field, ReportKind};
+use std::collections::BTreeMap;
+
+const PAGE_GENERIC_DESKTOP: u16 = 0x01;
+const PAGE_KEYBOARD: u16 = 0x07;
+const PAGE_BUTTON: u16 = 0x09;
+const USAGE_HAT_SWITCH: u16 = 0x39;
+
+const DIRECTIONS: [&str; 8] = ["up", "up-right", "right", "down-right", "down", "down-left", "left", "up-left"];
+
+/// Keyboard layout whose keycaps name the keys. Usages give a key's
+/// position, so the same usage is `Z` on a US keyboard and `Y` on a German one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    #[default]
+    Us,
+    /// German QWERTZ
+    De,
+    /// French AZERTY
+    Fr,
+}
+
+impl Layout {
+    /// The keycap of `usage` where it differs from a US keyboard. Digits
+    /// keep their names on an AZERTY keyboard, whose keycaps show them too.
+    fn key(self, usage: u16) -> Option<&'static str> {
+        Some(match (self, usage) {
+            (Layout::Us, _) => return None,
+            (Layout::De, 0x1c) => "Z",
+            (Layout::De, 0x1d) => "Y",
+            (Layout::De, 0x2d) => "ß",
+            (Layout::De, 0x2e) => "´",
+            (Layout::De, 0x2f) => "Ü",
+            (Layout::De, 0x30) => "+",
+            (Layout::De, 0x32) => "#",
+            (Layout::De, 0x33) => "Ö",
+            (Layout::De, 0x34) => "Ä",
+            (Layout::De, 0x35) => "^",
+            (Layout::De, 0x38) => "-",
+            (Layout::Fr, 0x04) => "Q",
+            (Layout::Fr, 0x10) => ",",
+            (Layout::Fr, 0x14) => "A",
+            (Layout::Fr, 0x1a) => "Z",
+            (Layout::Fr, 0x1d) => "W",
+            (Layout::Fr, 0x2d) => ")",
+            (Layout::Fr, 0x2e) => "=",
+            (Layout::Fr, 0x2f) => "^",
+            (Layout::Fr, 0x30) => "$",
+            (Layout::Fr, 0x32) => "*",
+            (Layout::Fr, 0x33) => "M",
+            (Layout::Fr, 0x34) => "Ù",
+            (Layout::Fr, 0x35) => "²",
+            (Layout::Fr, 0x36) => ";",
+            (Layout::Fr, 0x37) => ":",
+            (Layout::Fr, 0x38) => "!",
+            (Layout::De | Layout::Fr, 0x64) => "<",
+            (Layout::De | Layout::Fr, 0xe6) => "Alt Gr",
+            _ => return None,
+        })
+    }
+}
+
+/// The input report layouts of one device.
+#[derive(Clone, Debug)]
+pub struct InputDecoder {
+    uses_report_ids: bool,
+    reports: BTreeMap<u8, Vec<Field>>,
+    layout: Layout,
+}
+
+impl InputDecoder {
+    /// `None` if the descriptor declares no input reports.
+    pub fn new(desc: &[u8]) -> Option<Self> {
+        let uses_report_ids = descriptor::uses_report_ids(desc);
+        let reports: BTreeMap<u8, Vec<Field>> = descriptor::report_sizes(desc).input.into_keys()
+            .map(|id| (id, descriptor::report_fields(desc, ReportKind::Input, id)))
+            .collect();
+        (!reports.is_empty()).then_some(InputDecoder { uses_report_ids, reports, layout: Layout::Us })
+    }
+
+    /// Name keys as on a `layout` keyboard instead of a US one.
+    pub fn with_layout(self, layout: Layout) -> Self {
+        InputDecoder { layout, ..self }
+    }
+
+    /// What `report`, report ID included if the device uses them, says, as
+    /// labelled readings such as `("keys", "Left Shift, A")` or `("X", "+5")`.
+    /// `None` if the descriptor doesn't describe the report.
+    pub fn decode(&self, report: &[u8]) -> Option<Vec<(String, String)>> {
+        let (id, payload) = match report.split_first() {
+            Some((&id, payload)) if self.uses_report_ids => (id, payload),
+            _ if self.uses_report_ids => return None,
+            _ => (0, report),
+        };
+        let fields = self.reports.get(&id)?;
+
+        let mut keys = None;
+        let mut buttons = None;
+        let mut readings = Vec::new();
+        for field in fields.iter().filter(|f| !f.constant) {
+            if !field.variable {
+                let active = array_usages(field, payload);
+                if field.usage_page == PAGE_KEYBOARD {
+                    keys.get_or_insert_with(Vec::new).extend(active.into_iter().map(|(_, usage)| key_name(usage, self.layout)));
+                } else if field.usage_page == PAGE_BUTTON {
+                    buttons.get_or_insert_with(Vec::new).extend(active.into_iter().map(|(_, usage)| usage.to_string()));
+                } else {
+                    let names: Vec<String> = active.into_iter().map(|(page, usage)| descriptor::usage_name(page, usage)).collect();
+                    readings.push((descriptor::usage_page_name(field.usage_page), list(&names)));
+                }
+                continue;
+            }
+            for i in 0..field.count {
+                let (Some(value), Some((page, usage))) = (field.value(payload, i), field.usage(i)) else {
+                    break;
+                };
+                match page {
+                    PAGE_KEYBOARD => {
+                        let held = keys.get_or_insert_with(Vec::new);
+                        if value != 0 {
+                            held.push(key_name(usage, self.layout));
+                        }
+                    }
+                    PAGE_BUTTON => {
+                        let pressed = buttons.get_or_insert_with(Vec::new);
+                        if value != 0 {
+                            pressed.push(usage.to_string());
+                        }
+                    }
+                    PAGE_GENERIC_DESKTOP if usage == USAGE_HAT_SWITCH => {
+                        readings.push((descriptor::usage_name(page, usage), hat_direction(field, value)));
+                    }
+                    _ if field.relative => readings.push((descriptor::usage_name(page, usage), format!("{:+}", value))),
+                    _ => readings.push((descriptor::usage_name(page, usage), value.to_string())),
+                }
+            }
+        }
+
+        let mut decoded = Vec::new();
+        if let Some(mut keys) = keys {
+            // A keyboard with too many keys down fills every slot with Error Roll Over
+            keys.dedup();
+            decoded.push(("keys".to_string(), list(&keys)));
+        }
+        if let Some(buttons) = buttons {
+            decoded.push(("buttons".to_string(), list(&buttons)));
+        }
+        decoded.extend(readings);
+        Some(decoded)
+    }
+}
+
+/// The usages an array field's values select. Index 0 of the usage range,
+/// usually "no event", and values outside the logical range select nothing.
+fn array_usages(field: &Field, payload: &[u8]) -> Vec<(u16, u16)> {
+    (0..field.count)
+        .map_while(|i| field.value(payload, i))
+        .filter(|&value| value >= i64::from(field.logical_min) && value <= i64::from(field.logical_max))
+        .filter_map(|value| field.usage((value - i64::from(field.logical_min)) as usize))
+        .filter(|&(_, usage)| usage != 0)
+        .collect()
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() { "none".to_string() } else { names.join(", ") }
+}
+
+/// Hat switches count clockwise from up; values outside the logical range
+/// mean the hat is centered.
+fn hat_direction(field: &Field, value: i64) -> String {
+    let (min, max) = (i64::from(field.logical_min), i64::from(field.logical_max));
+    if value < min || value > max {
+        return "centered".to_string();
+    }
+    let step = match max - min + 1 {
+        8 => 1,
+        4 => 2,
+        _ => return value.to_string(),
+    };
+    DIRECTIONS[((value - min) * step) as usize].to_string()
+}
+
+/// Name of a key on the Keyboard/Keypad page, as printed on a `layout`
+/// keyboard.
+pub fn key_name(usage: u16, layout: Layout) -> String {
+    if let Some(name) = layout.key(usage) {
+        return name.to_string();
+    }
+    let name = match usage {
+        0x01 => "Error Roll Over",
+        0x02 => "POST Fail",
+        0x03 => "Error Undefined",
+        0x04..=0x1d => return char::from(b'A' + (usage - 0x04) as u8).to_string(),
+        0x1e..=0x26 => return char::from(b'1' + (usage - 0x1e) as u8).to_string(),
+        0x27 => "0",
+        0x28 => "Enter",
+        0x29 => "Escape",
+        0x2a => "Backspace",
+        0x2b => "Tab",
+        0x2c => "Space",
+        0x2d => "-",
+        0x2e => "=",
+        0x2f => "[",
+        0x30 => "]",
+        0x31 => "\\",
+        0x32 => "Non-US #",
+        0x33 => ";",
+        0x34 => "'",
+        0x35 => "`",
+        0x36 => ",",
+        0x37 => ".",
+        0x38 => "/",
+        0x39 => "Caps Lock",
+        0x3a..=0x45 => return format!("F{}", usage - 0x39),
+        0x46 => "Print Screen",
+        0x47 => "Scroll Lock",
+        0x48 => "Pause",
+        0x49 => "Insert",
+        0x4a => "Home",
+        0x4b => "Page Up",
+        0x4c => "Delete",
+        0x4d => "End",
+        0x4e => "Page Down",
+        0x4f => "Right",
+        0x50 => "Left",
+        0x51 => "Down",
+        0x52 => "Up",
+        0x53 => "Num Lock",
+        0x54 => "Keypad /",
+        0x55 => "Keypad *",
+        0x56 => "Keypad -",
+        0x57 => "Keypad +",
+        0x58 => "Keypad Enter",
+        0x59..=0x61 => return format!("Keypad {}", usage - 0x58),
+        0x62 => "Keypad 0",
+        0x63 => "Keypad .",
+        0x64 => "Non-US \\",
+        0x65 => "Application",
+        0x66 => "Power",
+        0x67 => "Keypad =",
+        0x68..=0x73 => return format!("F{}", usage - 0x5b),
+        0x7f => "Mute",
+        0x80 => "Volume Up",
+        0x81 => "Volume Down",
+        0xe0 => "Left Ctrl",
+        0xe1 => "Left Shift",
+        0xe2 => "Left Alt",
+        0xe3 => "Left GUI",
+        0xe4 => "Right Ctrl",
+        0xe5 => "Right Shift",
+        0xe6 => "Right Alt",
+        0xe7 => "Right GUI",
+        _ => return format!("Key 0x{:02x}", usage),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The boot keyboard from the HID specification: a modifier byte, a
+    /// reserved byte and six key slots.
+    const KEYBOARD: &[u8] = &[
+        0x05, 0x01, // Usage Page (Generic Desktop)
+        0x09, 0x06, // Usage (Keyboard)
+        0xa1, 0x01, // Collection (Application)
+        0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+        0x19, 0xe0, //   Usage Minimum (0xe0)
+        0x29, 0xe7, //   Usage Maximum (0xe7)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x08, //   Report Count (8)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+        0x95, 0x01, //   Report Count (1)
+        0x75, 0x08, //   Report Size (8)
+        0x81, 0x01, //   Input (Constant)
+        0x95, 0x05, //   Report Count (5)
+        0x75, 0x01, //   Report Size (1)
+        0x05, 0x08, //   Usage Page (LEDs)
+        0x19, 0x01, //   Usage Minimum (1)
+        0x29, 0x05, //   Usage Maximum (5)
+        0x91, 0x02, //   Output (Data, Variable, Absolute)
+        0x95, 0x01, //   Report Count (1)
+        0x75, 0x03, //   Report Size (3)
+        0x91, 0x01, //   Output (Constant)
+        0x95, 0x06, //   Report Count (6)
+        0x75, 0x08, //   Report Size (8)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x65, //   Logical Maximum (0x65)
+        0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+        0x19, 0x00, //   Usage Minimum (0)
+        0x29, 0x65, //   Usage Maximum (0x65)
+        0x81, 0x00, //   Input (Data, Array)
+        0xc0, // End Collection
+    ];
+
+    /// The boot mouse: three buttons and relative X and Y.
+    const MOUSE: &[u8] = &[
+        0x05, 0x01, // Usage Page (Generic Desktop)
+        0x09, 0x02, // Usage (Mouse)
+        0xa1, 0x01, // Collection (Application)
+        0x09, 0x01, //   Usage (Pointer)
+        0xa1, 0x00, //   Collection (Physical)
+        0x05, 0x09, //     Usage Page (Button)
+        0x19, 0x01, //     Usage Minimum (1)
+        0x29, 0x03, //     Usage Maximum (3)
+        0x15, 0x00, //     Logical Minimum (0)
+        0x25, 0x01, //     Logical Maximum (1)
+        0x95, 0x03, //     Report Count (3)
+        0x75, 0x01, //     Report Size (1)
+        0x81, 0x02, //     Input (Data, Variable, Absolute)
+        0x95, 0x01, //     Report Count (1)
+        0x75, 0x05, //     Report Size (5)
+        0x81, 0x01, //     Input (Constant)
+        0x05, 0x01, //     Usage Page (Generic Desktop)
+        0x09, 0x30, //     Usage (X)
+        0x09, 0x31, //     Usage (Y)
+        0x15, 0x81, //     Logical Minimum (-127)
+        0x25, 0x7f, //     Logical Maximum (127)
+        0x75, 0x08, //     Report Size (8)
+        0x95, 0x02, //     Report Count (2)
+        0x81, 0x06, //     Input (Data, Variable, Relative)
+        0xc0, //   End Collection
+        0xc0, // End Collection
+    ];
+
+    /// Report 1: four buttons, an eight-way hat with a null state, and X.
+    const GAMEPAD: &[u8] = &[
+        0x05, 0x01, // Usage Page (Generic Desktop)
+        0x09, 0x05, // Usage (Game Pad)
+        0xa1, 0x01, // Collection (Application)
+        0x85, 0x01, //   Report ID (1)
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x01, //   Usage Minimum (1)
+        0x29, 0x04, //   Usage Maximum (4)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x04, //   Report Count (4)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+        0x05, 0x01, //   Usage Page (Generic Desktop)
+        0x09, 0x39, //   Usage (Hat Switch)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x07, //   Logical Maximum (7)
+        0x75, 0x04, //   Report Size (4)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x42, //   Input (Data, Variable, Absolute, Null State)
+        0x09, 0x30, //   Usage (X)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x26, 0xff, 0x00, //   Logical Maximum (255)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x02, //   Input (Data, Variable, Absolute)
+        0xc0, // End Collection
+    ];
+
+    fn decode(desc: &[u8], report: &[u8]) -> Vec<(String, String)> {
+        InputDecoder::new(desc).unwrap().decode(report).unwrap()
+    }
+
+    fn reading<'a>(decoded: &'a [(String, String)], label: &str) -> &'a str {
+        &decoded.iter().find(|(l, _)| l == label).unwrap_or_else(|| panic!("no {} in {:?}", label, decoded)).1
+    }
+
+    #[test]
+    fn keyboard_modifiers_and_keys() {
+        // Left Shift and Right Alt held, with A and Enter
+        let decoded = decode(KEYBOARD, &[0x42, 0x00, 0x04, 0x28, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(decoded, [("keys".to_string(), "Left Shift, Right Alt, A, Enter".to_string())]);
+        assert_eq!(reading(&decode(KEYBOARD, &[0; 8]), "keys"), "none");
+    }
+
+    #[test]
+    fn keyboard_roll_over_is_shown_once() {
+        assert_eq!(reading(&decode(KEYBOARD, &[0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01]), "keys"), "Error Roll Over");
+    }
+
+    #[test]
+    fn array_values_outside_the_logical_range_select_nothing() {
+        assert_eq!(reading(&decode(KEYBOARD, &[0x00, 0x00, 0x66, 0xff, 0x05, 0x00, 0x00, 0x00]), "keys"), "B");
+    }
+
+    #[test]
+    fn mouse_buttons_and_relative_motion() {
+        let decoded = decode(MOUSE, &[0x05, 0x05, 0xfd]);
+        assert_eq!(reading(&decoded, "buttons"), "1, 3");
+        assert_eq!(reading(&decoded, "X"), "+5");
+        assert_eq!(reading(&decoded, "Y"), "-3");
+        assert_eq!(reading(&decode(MOUSE, &[0x00, 0x00, 0x00]), "buttons"), "none");
+    }
+
+    #[test]
+    fn gamepad_hat_and_axis() {
+        let decoded = decode(GAMEPAD, &[0x01, 0x22, 0x80]);
+        assert_eq!(reading(&decoded, "buttons"), "2");
+        assert_eq!(reading(&decoded, "Hat Switch"), "right");
+        assert_eq!(reading(&decoded, "X"), "128");
+        // The null state is outside the logical range
+        assert_eq!(reading(&decode(GAMEPAD, &[0x01, 0xf0, 0x00]), "Hat Switch"), "centered");
+    }
+
+    #[test]
+    fn reports_the_descriptor_does_not_describe() {
+        let decoder = InputDecoder::new(GAMEPAD).unwrap();
+        assert!(decoder.decode(&[0x02, 0x00, 0x00]).is_none());
+        assert!(decoder.decode(&[]).is_none());
+        assert!(InputDecoder::new(&[0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0xc0]).is_none());
+    }
+
+    #[test]
+    fn hat_directions() {
+        let field = |logical_max| {
+            let decoded = descriptor::report_fields(GAMEPAD, ReportKind::Input, 1);
+            let mut hat = decoded.into_iter().find(|f| f.usage(0) == Some((PAGE_GENERIC_DESKTOP, USAGE_HAT_SWITCH))).unwrap();
+            hat.logical_max = logical_max;
+            hat
+        };
+        let eight = field(7);
+        assert_eq!((0..8).map(|v| hat_direction(&eight, v)).collect::<Vec<_>>(), DIRECTIONS);
+        assert_eq!(hat_direction(&eight, 8), "centered");
+        assert_eq!(hat_direction(&eight, -1), "centered");
+        let four = field(3);
+        assert_eq!((0..4).map(|v| hat_direction(&four, v)).collect::<Vec<_>>(), ["up", "right", "down", "left"]);
+        // Other resolutions show the raw value
+        assert_eq!(hat_direction(&field(15), 9), "9");
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(key_name(0x04, Layout::Us), "A");
+        assert_eq!(key_name(0x1d, Layout::Us), "Z");
+        assert_eq!(key_name(0x1e, Layout::Us), "1");
+        assert_eq!(key_name(0x26, Layout::Us), "9");
+        assert_eq!(key_name(0x27, Layout::Us), "0");
+        assert_eq!(key_name(0x3a, Layout::Us), "F1");
+        assert_eq!(key_name(0x45, Layout::Us), "F12");
+        assert_eq!(key_name(0x68, Layout::Us), "F13");
+        assert_eq!(key_name(0x73, Layout::Us), "F24");
+        assert_eq!(key_name(0x59, Layout::Us), "Keypad 1");
+        assert_eq!(key_name(0x61, Layout::Us), "Keypad 9");
+        assert_eq!(key_name(0xe7, Layout::Us), "Right GUI");
+        assert_eq!(key_name(0xa0, Layout::Us), "Key 0xa0");
+    }
+
+    #[test]
+    fn layouts_name_keys_by_their_keycaps() {
+        assert_eq!(key_name(0x1c, Layout::De), "Z");
+        assert_eq!(key_name(0x1d, Layout::De), "Y");
+        assert_eq!(key_name(0x33, Layout::De), "Ö");
+        assert_eq!(key_name(0x04, Layout::Fr), "Q");
+        assert_eq!(key_name(0x14, Layout::Fr), "A");
+        assert_eq!(key_name(0x33, Layout::Fr), "M");
+        assert_eq!(key_name(0x1e, Layout::Fr), "1");
+        // Keys in the same place on every layout
+        for layout in [Layout::Us, Layout::De, Layout::Fr] {
+            assert_eq!(key_name(0x28, layout), "Enter");
+            assert_eq!(key_name(0xe1, layout), "Left Shift");
+        }
+        assert_eq!(key_name(0xe6, Layout::Us), "Right Alt");
+        assert_eq!(key_name(0xe6, Layout::De), "Alt Gr");
+    }
+
+    #[test]
+    fn decoder_uses_its_layout() {
+        let decoder = InputDecoder::new(KEYBOARD).unwrap().with_layout(Layout::De);
+        let decoded = decoder.decode(&[0x00, 0x00, 0x1d, 0x1c, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(reading(&decoded, "keys"), "Y, Z");
+    }
+}
@@ -0,0 +1,189 @@
+//! USB interface layout of composite devices.
+//!
+//! hidapi only enumerates HID interfaces, so a device that also exposes a CCID
+//! smart-card reader or a CDC serial port shows up as just its HID entries. On
+//! Linux the full interface list is read from sysfs instead, starting from the
+//! hidraw node hidapi reports.
+
+use std::ffi::CStr;
+
+/// One interface of a USB device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsbInterface {
+    pub number: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<UsbEndpoint>,
+}
+
+/// One endpoint of a USB interface (endpoint 0 is not listed).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsbEndpoint {
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl UsbEndpoint {
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub fn transfer_type(&self) -> &'static str {
+        match self.attributes & 0x03 {
+            0 => "control",
+            1 => "isochronous",
+            2 => "bulk",
+            _ => "interrupt",
+        }
+    }
+}
+
+impl UsbInterface {
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class)
+    }
+}
+
+/// Human-readable name of a USB interface class code.
+pub fn class_name(class: u8) -> &'static str {
+    match class {
+        0x01 => "Audio",
+        0x02 => "CDC Control",
+        0x03 => "HID",
+        0x05 => "Physical",
+        0x06 => "Image",
+        0x07 => "Printer",
+        0x08 => "Mass Storage",
+        0x09 => "Hub",
+        0x0a => "CDC Data",
+        0x0b => "Smart Card (CCID)",
+        0x0d => "Content Security",
+        0x0e => "Video",
+        0x0f => "Personal Healthcare",
+        0x10 => "Audio/Video",
+        0x11 => "Billboard",
+        0xdc => "Diagnostic",
+        0xe0 => "Wireless Controller",
+        0xef => "Miscellaneous",
+        0xfe => "Application Specific",
+        0xff => "Vendor Specific",
+        _ => "Unknown",
+    }
+}
+
+/// Sysfs directory of the USB device behind the hidapi `path`.
+#[cfg(target_os = "linux")]
+fn usb_device_dir(path: &CStr) -> Option<std::path::PathBuf> {
+    use std::path::Path;
+
+    let node = Path::new(path.to_str().ok()?).file_name()?;
+    // .../<usb device>/<usb interface>/<hid device>/hidraw/hidrawN
+    let hid_dir = std::fs::canonicalize(Path::new("/sys/class/hidraw").join(node).join("device")).ok()?;
+    let usb_device = hid_dir.parent()?.parent()?;
+    // Bluetooth and I2C HID devices have no USB device directory two levels up
+    usb_device.join("idVendor").exists().then(|| usb_device.to_path_buf())
+}
+
+/// Read a hexadecimal sysfs attribute.
+#[cfg(target_os = "linux")]
+fn hex_attr<T: TryFrom<u32>>(dir: &std::path::Path, name: &str) -> Option<T> {
+    let value = std::fs::read_to_string(dir.join(name)).ok()?;
+    T::try_from(u32::from_str_radix(value.trim(), 16).ok()?).ok()
+}
+
+/// Stable identifier of the physical USB device behind the hidapi `path`
+/// (its bus-port name, e.g. `1-1.4`), shared by all of its interfaces.
+#[cfg(target_os = "linux")]
+pub fn usb_device_id(path: &CStr) -> Option<String> {
+    Some(usb_device_dir(path)?.file_name()?.to_str()?.to_string())
+}
+
+/// Bus number and device address of the USB device behind the hidapi `path`.
+#[cfg(target_os = "linux")]
+pub fn usb_address(path: &CStr) -> Option<(u16, u16)> {
+    let dir = usb_device_dir(path)?;
+    let attr = |name: &str| std::fs::read_to_string(dir.join(name)).ok()?.trim().parse().ok();
+    Some((attr("busnum")?, attr("devnum")?))
+}
+
+/// Bus number and device address of the USB device behind the hidapi `path`.
+///
+/// Only implemented on Linux; always `None` elsewhere.
+#[cfg(not(target_os = "linux"))]
+pub fn usb_address(_path: &CStr) -> Option<(u16, u16)> {
+    None
+}
+
+/// Stable identifier of the physical USB device behind the hidapi `path`.
+///
+/// Only implemented on Linux; always `None` elsewhere.
+#[cfg(not(target_os = "linux"))]
+pub fn usb_device_id(_path: &CStr) -> Option<String> {
+    None
+}
+
+/// All interfaces of the USB device behind the hidapi `path`, sorted by number.
+///
+/// Returns `None` when the layout can't be determined: on platforms other than
+/// Linux, for non-USB transports (Bluetooth, I2C), or with hidapi's libusb backend.
+#[cfg(target_os = "linux")]
+pub fn composite_interfaces(path: &CStr) -> Option<Vec<UsbInterface>> {
+    use std::fs;
+
+    let usb_device = usb_device_dir(path)?;
+    let prefix = format!("{}:", usb_device.file_name()?.to_str()?);
+
+    let mut interfaces: Vec<UsbInterface> = fs::read_dir(&usb_device).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|n| n.starts_with(&prefix)))
+        .filter_map(|entry| {
+            let dir = entry.path();
+            Some(UsbInterface {
+                number: hex_attr(&dir, "bInterfaceNumber")?,
+                class: hex_attr(&dir, "bInterfaceClass")?,
+                subclass: hex_attr(&dir, "bInterfaceSubClass")?,
+                protocol: hex_attr(&dir, "bInterfaceProtocol")?,
+                endpoints: endpoints(&dir),
+            })
+        })
+        .collect();
+
+    if interfaces.is_empty() {
+        return None;
+    }
+    interfaces.sort_by_key(|i| i.number);
+    Some(interfaces)
+}
+
+#[cfg(target_os = "linux")]
+fn endpoints(interface_dir: &std::path::Path) -> Vec<UsbEndpoint> {
+    let Ok(entries) = std::fs::read_dir(interface_dir) else {
+        return Vec::new();
+    };
+    let mut endpoints: Vec<UsbEndpoint> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|n| n.starts_with("ep_")))
+        .filter_map(|entry| {
+            let dir = entry.path();
+            Some(UsbEndpoint {
+                address: hex_attr(&dir, "bEndpointAddress")?,
+                attributes: hex_attr(&dir, "bmAttributes")?,
+                max_packet_size: hex_attr(&dir, "wMaxPacketSize")?,
+                interval: hex_attr(&dir, "bInterval")?,
+            })
+        })
+        .collect();
+    endpoints.sort_by_key(|e| e.address);
+    endpoints
+}
+
+/// All interfaces of the USB device behind the hidapi `path`, sorted by number.
+///
+/// Only implemented on Linux; always `None` elsewhere.
+#[cfg(not(target_os = "linux"))]
+pub fn composite_interfaces(_path: &CStr) -> Option<Vec<UsbInterface>> {
+    None
+}
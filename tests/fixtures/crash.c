@@ -0,0 +1,26 @@
+// Source of crash-dwarf{3,4,5}.elf, the symbol and line table fixtures of
+// src/crashdump.rs. Built from this directory, for each version:
+//
+//   gcc -gdwarf-$v -O0 -ffreestanding -nostdlib -static -fno-pie -no-pie \
+//       -fno-asynchronous-unwind-tables -ffile-prefix-map=$PWD=tests/fixtures \
+//       -Wl,-n -Wl,--build-id=none -o crash.full crash.c
+//   objcopy -R .comment -R .debug_info -R .debug_abbrev -R .debug_aranges \
+//       -R .debug_frame -R .note.gnu.property crash.full crash-dwarf$v.elf
+//
+// (-gdwarf-2 gives a version 3 line table.)
+
+int crash_here(int x) {
+    int y = x * 3;
+    return y + 1;
+}
+
+int middle(int a) {
+    int r = crash_here(a);
+    return r * 2;
+}
+
+void _start(void) {
+    middle(4);
+    for (;;) {
+    }
+}
@@ -0,0 +1,21 @@
+# Source of defmt.elf, the format string fixture of src/defmt.rs:
+#
+#   as --32 -o defmt.elf defmt.s
+#
+# A linked firmware image has its .defmt section at address 0, so that each
+# symbol's address is the index of its string; in an object file the offsets
+# into the section are the same thing.
+
+    .section .defmt,"",@progbits
+"{\"package\":\"app\",\"tag\":\"defmt_timestamp\",\"data\":\"{=u32:us}\",\"disambiguator\":\"1\"}":
+    .skip 1
+"{\"package\":\"app\",\"tag\":\"defmt_info\",\"data\":\"x={=u8:#x}\",\"disambiguator\":\"2\"}":
+    .skip 1
+"{\"package\":\"app\",\"tag\":\"defmt_println\",\"data\":\"say \\\"hi\\\"\\t{=str}\",\"disambiguator\":\"3\"}":
+    .skip 1
+_defmt_version_marker:
+    .skip 1
+
+    .text
+main:
+    ret